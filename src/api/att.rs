@@ -0,0 +1,189 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Raw ATT requests, for [`Peripheral::raw_att`](crate::api::Peripheral::raw_att).
+//!
+//! This is an unstable debugging aid, only available with the `unstable-raw-att` feature. It
+//! bypasses the platform's GATT client entirely, so nothing sent this way is reflected in the
+//! services, values or subscriptions btleplug knows about, and the types here may change in any
+//! release.
+
+use std::fmt::{self, Display, Formatter};
+
+/// The opcode of an Error Response PDU.
+pub(crate) const ERROR_RESPONSE: u8 = 0x01;
+const READ_REQUEST: u8 = 0x0a;
+const READ_BLOB_REQUEST: u8 = 0x0c;
+const WRITE_REQUEST: u8 = 0x12;
+
+/// A request to send to a device's ATT server as it is, without any of the GATT client's checks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AttRequest {
+    /// Reads the attribute with the given handle: a Read Request if `offset` is 0, or a Read Blob
+    /// Request starting at `offset` otherwise.
+    Read { handle: u16, offset: u16 },
+    /// Writes the attribute with the given handle, with a Write Request.
+    Write { handle: u16, value: Vec<u8> },
+    /// Any other request, given as its opcode and parameters. Only request opcodes which the device
+    /// answers with either the next opcode up or an Error Response can be sent this way.
+    Other { opcode: u8, parameters: Vec<u8> },
+}
+
+impl AttRequest {
+    /// Returns the opcode of the PDU for this request.
+    pub fn opcode(&self) -> u8 {
+        match self {
+            AttRequest::Read { offset: 0, .. } => READ_REQUEST,
+            AttRequest::Read { .. } => READ_BLOB_REQUEST,
+            AttRequest::Write { .. } => WRITE_REQUEST,
+            AttRequest::Other { opcode, .. } => *opcode,
+        }
+    }
+
+    /// Returns the PDU for this request, opcode first.
+    pub(crate) fn to_pdu(&self) -> Vec<u8> {
+        let mut pdu = vec![self.opcode()];
+        match self {
+            AttRequest::Read { handle, offset: 0 } => pdu.extend_from_slice(&handle.to_le_bytes()),
+            AttRequest::Read { handle, offset } => {
+                pdu.extend_from_slice(&handle.to_le_bytes());
+                pdu.extend_from_slice(&offset.to_le_bytes());
+            }
+            AttRequest::Write { handle, value } => {
+                pdu.extend_from_slice(&handle.to_le_bytes());
+                pdu.extend_from_slice(value);
+            }
+            AttRequest::Other { parameters, .. } => pdu.extend_from_slice(parameters),
+        }
+        pdu
+    }
+}
+
+/// The device's answer to an [`AttRequest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AttResponse {
+    /// The request succeeded. This is the response PDU's opcode and parameters, so for a read it
+    /// has the value read.
+    Success { opcode: u8, parameters: Vec<u8> },
+    /// The device sent an Error Response, with the error code exactly as it was sent.
+    Error {
+        /// The opcode of the request which failed.
+        request_opcode: u8,
+        /// The handle of the attribute which caused the error, or 0 if there isn't one.
+        handle: u16,
+        error: AttError,
+    },
+}
+
+impl AttResponse {
+    /// Parses a response PDU, or returns `None` if it is too short to be one.
+    pub(crate) fn from_pdu(pdu: &[u8]) -> Option<Self> {
+        match pdu {
+            [ERROR_RESPONSE, request_opcode, low, high, code, ..] => Some(AttResponse::Error {
+                request_opcode: *request_opcode,
+                handle: u16::from_le_bytes([*low, *high]),
+                error: AttError::from(*code),
+            }),
+            [ERROR_RESPONSE, ..] => None,
+            [opcode, parameters @ ..] => Some(AttResponse::Success {
+                opcode: *opcode,
+                parameters: parameters.to_vec(),
+            }),
+            [] => None,
+        }
+    }
+}
+
+/// An ATT error code, as defined in Core Specification Vol 3, Part F, section 3.4.1.1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AttError {
+    InvalidHandle,
+    ReadNotPermitted,
+    WriteNotPermitted,
+    InvalidPdu,
+    InsufficientAuthentication,
+    RequestNotSupported,
+    InvalidOffset,
+    InsufficientAuthorization,
+    PrepareQueueFull,
+    AttributeNotFound,
+    AttributeNotLong,
+    InsufficientEncryptionKeySize,
+    InvalidAttributeValueLength,
+    UnlikelyError,
+    InsufficientEncryption,
+    UnsupportedGroupType,
+    InsufficientResources,
+    DatabaseOutOfSync,
+    ValueNotAllowed,
+    /// Any other code, such as those reserved for applications (0x80 to 0x9f) or for common profile
+    /// and service errors (0xe0 to 0xff).
+    Other(u8),
+}
+
+impl From<u8> for AttError {
+    fn from(code: u8) -> Self {
+        match code {
+            0x01 => AttError::InvalidHandle,
+            0x02 => AttError::ReadNotPermitted,
+            0x03 => AttError::WriteNotPermitted,
+            0x04 => AttError::InvalidPdu,
+            0x05 => AttError::InsufficientAuthentication,
+            0x06 => AttError::RequestNotSupported,
+            0x07 => AttError::InvalidOffset,
+            0x08 => AttError::InsufficientAuthorization,
+            0x09 => AttError::PrepareQueueFull,
+            0x0a => AttError::AttributeNotFound,
+            0x0b => AttError::AttributeNotLong,
+            0x0c => AttError::InsufficientEncryptionKeySize,
+            0x0d => AttError::InvalidAttributeValueLength,
+            0x0e => AttError::UnlikelyError,
+            0x0f => AttError::InsufficientEncryption,
+            0x10 => AttError::UnsupportedGroupType,
+            0x11 => AttError::InsufficientResources,
+            0x12 => AttError::DatabaseOutOfSync,
+            0x13 => AttError::ValueNotAllowed,
+            code => AttError::Other(code),
+        }
+    }
+}
+
+impl From<AttError> for u8 {
+    fn from(error: AttError) -> Self {
+        match error {
+            AttError::InvalidHandle => 0x01,
+            AttError::ReadNotPermitted => 0x02,
+            AttError::WriteNotPermitted => 0x03,
+            AttError::InvalidPdu => 0x04,
+            AttError::InsufficientAuthentication => 0x05,
+            AttError::RequestNotSupported => 0x06,
+            AttError::InvalidOffset => 0x07,
+            AttError::InsufficientAuthorization => 0x08,
+            AttError::PrepareQueueFull => 0x09,
+            AttError::AttributeNotFound => 0x0a,
+            AttError::AttributeNotLong => 0x0b,
+            AttError::InsufficientEncryptionKeySize => 0x0c,
+            AttError::InvalidAttributeValueLength => 0x0d,
+            AttError::UnlikelyError => 0x0e,
+            AttError::InsufficientEncryption => 0x0f,
+            AttError::UnsupportedGroupType => 0x10,
+            AttError::InsufficientResources => 0x11,
+            AttError::DatabaseOutOfSync => 0x12,
+            AttError::ValueNotAllowed => 0x13,
+            AttError::Other(code) => code,
+        }
+    }
+}
+
+impl Display for AttError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:?} ({:#04x})", self, u8::from(*self))
+    }
+}
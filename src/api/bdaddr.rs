@@ -133,9 +133,13 @@ impl BDAddr {
         self.address
     }
 
-    /// Check if this address is a randomly generated.
+    /// Check if this is a valid random static address, i.e. the two most significant bits are set
+    /// and the remaining random bits are neither all zeros nor all ones.
     pub fn is_random_static(&self) -> bool {
-        self.address[5] & 0b11 == 0b11
+        let random_part = u64::from(*self) & 0x3fff_ffff_ffff;
+        self.address[0] & 0b1100_0000 == 0b1100_0000
+            && random_part != 0
+            && random_part != 0x3fff_ffff_ffff
     }
 
     /// Parses a Bluetooth address with colons `:` as delimiters.
@@ -427,6 +431,15 @@ mod tests {
         assert_eq!(format!("{}", ADDR.to_string_no_delim()), "1f2a00cc22f1");
     }
 
+    #[test]
+    fn random_static_addr() {
+        assert!(BDAddr::from([0xc0, 0x2a, 0x00, 0xcc, 0x22, 0xf1]).is_random_static());
+        assert!(BDAddr::from([0xff, 0x2a, 0x00, 0xcc, 0x22, 0xf1]).is_random_static());
+        assert!(!BDAddr::from([0x7f, 0x2a, 0x00, 0xcc, 0x22, 0xf3]).is_random_static());
+        assert!(!BDAddr::from([0xc0, 0x00, 0x00, 0x00, 0x00, 0x00]).is_random_static());
+        assert!(!BDAddr::from([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_random_static());
+    }
+
     #[test]
     fn u64_to_addr() {
         let hex_addr: BDAddr = HEX.try_into().unwrap();
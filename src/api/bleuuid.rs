@@ -1,5 +1,6 @@
 //! Utilities for dealing with BLE UUIDs, converting to and from their short formats.
 
+use std::fmt::{self, Debug, Display, Formatter};
 use uuid::Uuid;
 
 const BLUETOOTH_BASE_UUID: u128 = 0x00000000_0000_1000_8000_00805f9b34fb;
@@ -10,7 +11,7 @@ const BLUETOOTH_BASE_MASK_16: u128 = 0xffff0000_ffff_ffff_ffff_ffffffffffff;
 /// Convert a 32-bit BLE short UUID to a full 128-bit UUID by filling in the standard Bluetooth Base
 /// UUID.
 pub const fn uuid_from_u32(short: u32) -> Uuid {
-    Uuid::from_u128(BLUETOOTH_BASE_UUID | ((short as u128) << 96))
+    uuid_from_u32_with_base(short, Uuid::from_u128(BLUETOOTH_BASE_UUID))
 }
 
 /// Convert a 16-bit BLE short UUID to a full 128-bit UUID by filling in the standard Bluetooth Base
@@ -19,6 +20,19 @@ pub const fn uuid_from_u16(short: u16) -> Uuid {
     uuid_from_u32(short as u32)
 }
 
+/// Convert a 32-bit short UUID to a full 128-bit UUID by filling in the given base UUID, for
+/// vendors which use short forms of their own 128-bit base rather than the Bluetooth one. The first
+/// 32 bits of `base` are ignored.
+pub const fn uuid_from_u32_with_base(short: u32, base: Uuid) -> Uuid {
+    Uuid::from_u128((base.as_u128() & BLUETOOTH_BASE_MASK) | ((short as u128) << 96))
+}
+
+/// Convert a 16-bit short UUID to a full 128-bit UUID by filling in the given base UUID, like
+/// [`uuid_from_u32_with_base`].
+pub const fn uuid_from_u16_with_base(short: u16, base: Uuid) -> Uuid {
+    uuid_from_u32_with_base(short as u32, base)
+}
+
 /// An extension trait for `Uuid` which provides BLE-specific methods.
 pub trait BleUuid {
     /// If the UUID is a valid BLE short UUID then return its short form, otherwise return `None`.
@@ -28,23 +42,43 @@ pub trait BleUuid {
     /// `None`.
     fn to_ble_u16(&self) -> Option<u16>;
 
+    /// If the UUID is a short UUID relative to the given base UUID then return its short form,
+    /// otherwise return `None`. The first 32 bits of `base` are ignored.
+    fn to_u32_with_base(&self, base: &Uuid) -> Option<u32>;
+
+    /// If the UUID is a 16-bit short UUID relative to the given base UUID then return its short
+    /// form, otherwise return `None`. The first 32 bits of `base` are ignored.
+    fn to_u16_with_base(&self, base: &Uuid) -> Option<u16>;
+
     /// Convert the UUID to a string, using short format if applicable.
     fn to_short_string(&self) -> String;
+
+    /// If the UUID is one of the common services, characteristics or descriptors assigned by the
+    /// Bluetooth SIG then return its name, like "Battery Level", otherwise return `None`.
+    fn assigned_name(&self) -> Option<&'static str>;
 }
 
 impl BleUuid for Uuid {
     fn to_ble_u32(&self) -> Option<u32> {
+        self.to_u32_with_base(&Uuid::from_u128(BLUETOOTH_BASE_UUID))
+    }
+
+    fn to_ble_u16(&self) -> Option<u16> {
+        self.to_u16_with_base(&Uuid::from_u128(BLUETOOTH_BASE_UUID))
+    }
+
+    fn to_u32_with_base(&self, base: &Uuid) -> Option<u32> {
         let value = self.as_u128();
-        if value & BLUETOOTH_BASE_MASK == BLUETOOTH_BASE_UUID {
+        if value & BLUETOOTH_BASE_MASK == base.as_u128() & BLUETOOTH_BASE_MASK {
             Some((value >> 96) as u32)
         } else {
             None
         }
     }
 
-    fn to_ble_u16(&self) -> Option<u16> {
+    fn to_u16_with_base(&self, base: &Uuid) -> Option<u16> {
         let value = self.as_u128();
-        if value & BLUETOOTH_BASE_MASK_16 == BLUETOOTH_BASE_UUID {
+        if value & BLUETOOTH_BASE_MASK_16 == base.as_u128() & BLUETOOTH_BASE_MASK {
             Some((value >> 96) as u16)
         } else {
             None
@@ -60,6 +94,91 @@ impl BleUuid for Uuid {
             self.to_string()
         }
     }
+
+    fn assigned_name(&self) -> Option<&'static str> {
+        assigned_name(self.to_ble_u16()?)
+    }
+}
+
+/// Formats a UUID in short format, preceded by its assigned name if it has one, like
+/// "Battery Level (0x2a19)".
+pub(crate) struct NamedUuid<'a>(pub &'a Uuid);
+
+impl Display for NamedUuid<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.0.assigned_name() {
+            Some(name) => write!(f, "{} ({})", name, self.0.to_short_string()),
+            None => f.write_str(&self.0.to_short_string()),
+        }
+    }
+}
+
+impl Debug for NamedUuid<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// The names of the more common 16-bit UUIDs from the Bluetooth SIG's Assigned Numbers document.
+fn assigned_name(uuid: u16) -> Option<&'static str> {
+    Some(match uuid {
+        // Services
+        0x1800 => "Generic Access",
+        0x1801 => "Generic Attribute",
+        0x1802 => "Immediate Alert",
+        0x1803 => "Link Loss",
+        0x1804 => "Tx Power",
+        0x1805 => "Current Time",
+        0x1808 => "Glucose",
+        0x1809 => "Health Thermometer",
+        0x180a => "Device Information",
+        0x180d => "Heart Rate",
+        0x180f => "Battery",
+        0x1810 => "Blood Pressure",
+        0x1812 => "Human Interface Device",
+        0x1816 => "Cycling Speed and Cadence",
+        0x1818 => "Cycling Power",
+        0x1819 => "Location and Navigation",
+        0x181a => "Environmental Sensing",
+        0x181c => "User Data",
+        0x181d => "Weight Scale",
+        0x1826 => "Fitness Machine",
+        // Characteristics
+        0x2a00 => "Device Name",
+        0x2a01 => "Appearance",
+        0x2a04 => "Peripheral Preferred Connection Parameters",
+        0x2a05 => "Service Changed",
+        0x2a06 => "Alert Level",
+        0x2a07 => "Tx Power Level",
+        0x2a19 => "Battery Level",
+        0x2a23 => "System ID",
+        0x2a24 => "Model Number String",
+        0x2a25 => "Serial Number String",
+        0x2a26 => "Firmware Revision String",
+        0x2a27 => "Hardware Revision String",
+        0x2a28 => "Software Revision String",
+        0x2a29 => "Manufacturer Name String",
+        0x2a2b => "Current Time",
+        0x2a37 => "Heart Rate Measurement",
+        0x2a38 => "Body Sensor Location",
+        0x2a39 => "Heart Rate Control Point",
+        0x2a4d => "Report",
+        0x2a50 => "PnP ID",
+        0x2a6e => "Temperature",
+        0x2a6f => "Humidity",
+        0x2aa6 => "Central Address Resolution",
+        0x2b29 => "Client Supported Features",
+        0x2b2a => "Database Hash",
+        // Descriptors
+        0x2900 => "Characteristic Extended Properties",
+        0x2901 => "Characteristic User Description",
+        0x2902 => "Client Characteristic Configuration",
+        0x2903 => "Server Characteristic Configuration",
+        0x2904 => "Characteristic Presentation Format",
+        0x2905 => "Characteristic Aggregate Format",
+        0x2908 => "Report Reference",
+        _ => return None,
+    })
 }
 
 #[cfg(test)]
@@ -82,6 +201,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn uuid_with_custom_base() {
+        let base = Uuid::parse_str("00000000-1212-efde-1523-785feabcd123").unwrap();
+        let uuid = uuid_from_u16_with_base(0x1524, base);
+        assert_eq!(
+            uuid,
+            Uuid::parse_str("00001524-1212-efde-1523-785feabcd123").unwrap()
+        );
+        assert_eq!(uuid.to_u16_with_base(&base), Some(0x1524));
+        assert_eq!(uuid.to_u32_with_base(&base), Some(0x1524));
+        assert_eq!(uuid.to_ble_u16(), None);
+        assert_eq!(uuid_from_u16(0x1524).to_u16_with_base(&base), None);
+    }
+
     #[test]
     fn uuid_to_from_u16_success() {
         let uuid = Uuid::parse_str("00001234-0000-1000-8000-00805f9b34fb").unwrap();
@@ -134,6 +267,17 @@ mod tests {
         assert_eq!(uuid.to_short_string(), "0x11223344");
     }
 
+    #[test]
+    fn assigned_names() {
+        assert_eq!(uuid_from_u16(0x2a19).assigned_name(), Some("Battery Level"));
+        assert_eq!(uuid_from_u16(0x1122).assigned_name(), None);
+        assert_eq!(
+            NamedUuid(&uuid_from_u16(0x2902)).to_string(),
+            "Client Characteristic Configuration (0x2902)"
+        );
+        assert_eq!(NamedUuid(&uuid_from_u16(0x1122)).to_string(), "0x1122");
+    }
+
     #[test]
     fn to_short_string_long() {
         let uuid_str = "12345678-9000-1000-8000-00805f9b34fb";
@@ -0,0 +1,82 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{Characteristic, Service};
+use std::collections::{BTreeMap, BTreeSet};
+use uuid::Uuid;
+
+/// The characteristics of a peripheral, looked up by UUID, for apps which want handles to all of
+/// them up front. Returned by [`Peripheral::bootstrap`](super::Peripheral::bootstrap).
+///
+/// Several characteristics can share a UUID, such as the same characteristic in two services or
+/// two instances of it in one, so each UUID maps to all of them.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GattMap {
+    /// Each UUID's characteristics, ordered by service UUID and then instance ID.
+    characteristics: BTreeMap<Uuid, Vec<Characteristic>>,
+}
+
+impl GattMap {
+    pub(crate) fn new(services: BTreeSet<Service>) -> Self {
+        let mut characteristics: BTreeMap<Uuid, Vec<Characteristic>> = BTreeMap::new();
+        for characteristic in services
+            .into_iter()
+            .flat_map(|service| service.characteristics)
+        {
+            characteristics
+                .entry(characteristic.uuid)
+                .or_default()
+                .push(characteristic);
+        }
+        for characteristics in characteristics.values_mut() {
+            characteristics.sort();
+        }
+        GattMap { characteristics }
+    }
+
+    /// Returns the characteristic with the given UUID, or the first of them by service UUID and
+    /// instance ID if there are several.
+    pub fn get(&self, uuid: Uuid) -> Option<&Characteristic> {
+        self.get_all(uuid).first()
+    }
+
+    /// Returns every characteristic with the given UUID, in any service.
+    pub fn get_all(&self, uuid: Uuid) -> &[Characteristic] {
+        self.characteristics
+            .get(&uuid)
+            .map_or(&[], |characteristics| characteristics.as_slice())
+    }
+
+    /// Returns the characteristic with the given UUID in the given service, or the first of them
+    /// by instance ID if there are several.
+    pub fn get_in(&self, service: Uuid, uuid: Uuid) -> Option<&Characteristic> {
+        self.get_all(uuid)
+            .iter()
+            .find(|characteristic| characteristic.service_uuid == service)
+    }
+
+    /// Returns the characteristics of the given service.
+    pub fn service(&self, service: Uuid) -> impl Iterator<Item = &Characteristic> {
+        self.iter()
+            .filter(move |characteristic| characteristic.service_uuid == service)
+    }
+
+    /// Returns every characteristic, ordered by UUID.
+    pub fn iter(&self) -> impl Iterator<Item = &Characteristic> {
+        self.characteristics.values().flatten()
+    }
+
+    /// Returns how many characteristics there are, counting each of those sharing a UUID.
+    pub fn len(&self) -> usize {
+        self.characteristics.values().map(Vec::len).sum()
+    }
+
+    /// Returns whether there are no characteristics, as before services have been discovered.
+    pub fn is_empty(&self) -> bool {
+        self.characteristics.is_empty()
+    }
+}
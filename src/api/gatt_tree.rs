@@ -0,0 +1,131 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{CharPropFlags, Characteristic, Descriptor, Service};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_cr as serde;
+use std::collections::BTreeSet;
+use uuid::Uuid;
+
+/// The services of a peripheral, with their characteristics and descriptors, in one structure
+/// which can be walked or serialized, such as to dump a device's attribute table. Returned by
+/// [`Peripheral::gatt_tree`](super::Peripheral::gatt_tree).
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct GattTree {
+    /// The discovered services, ordered by UUID.
+    pub services: Vec<GattTreeService>,
+}
+
+/// A service in a [`GattTree`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct GattTreeService {
+    /// The UUID of the service.
+    pub uuid: Uuid,
+    /// Whether this is a primary service.
+    pub primary: bool,
+    /// See [`Service::start_handle`].
+    pub start_handle: Option<u16>,
+    /// See [`Service::end_handle`].
+    pub end_handle: Option<u16>,
+    /// The characteristics of the service, ordered by UUID and then handle.
+    pub characteristics: Vec<GattTreeCharacteristic>,
+}
+
+/// A characteristic in a [`GattTree`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct GattTreeCharacteristic {
+    /// The UUID of the characteristic.
+    pub uuid: Uuid,
+    /// The attribute handle of the characteristic, where the platform exposes it. This is `None`
+    /// on macOS and iOS, where CoreBluetooth hides handles.
+    pub handle: Option<u16>,
+    /// The operations the characteristic supports.
+    pub properties: CharPropFlags,
+    /// The descriptors of the characteristic, ordered by UUID.
+    pub descriptors: Vec<GattTreeDescriptor>,
+}
+
+/// A descriptor in a [`GattTree`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct GattTreeDescriptor {
+    /// The UUID of the descriptor.
+    pub uuid: Uuid,
+}
+
+impl GattTree {
+    pub(crate) fn new(services: BTreeSet<Service>) -> Self {
+        GattTree {
+            services: services.into_iter().map(GattTreeService::new).collect(),
+        }
+    }
+}
+
+impl GattTreeService {
+    fn new(service: Service) -> Self {
+        GattTreeService {
+            uuid: service.uuid,
+            primary: service.primary,
+            start_handle: service.start_handle,
+            end_handle: service.end_handle,
+            characteristics: service
+                .characteristics
+                .into_iter()
+                .map(GattTreeCharacteristic::new)
+                .collect(),
+        }
+    }
+}
+
+impl GattTreeCharacteristic {
+    fn new(characteristic: Characteristic) -> Self {
+        GattTreeCharacteristic {
+            uuid: characteristic.uuid,
+            // CoreBluetooth's instance IDs are indices rather than handles.
+            handle: (!cfg!(target_vendor = "apple")).then_some(characteristic.instance_id),
+            properties: characteristic.properties,
+            descriptors: characteristic
+                .descriptors
+                .into_iter()
+                .map(GattTreeDescriptor::new)
+                .collect(),
+        }
+    }
+}
+
+impl GattTreeDescriptor {
+    fn new(descriptor: Descriptor) -> Self {
+        GattTreeDescriptor {
+            uuid: descriptor.uuid,
+        }
+    }
+}
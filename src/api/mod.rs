@@ -21,25 +21,58 @@
 //! use btleplug::platform::{Adapter, Manager, Peripheral};
 //! ```
 
+#[cfg(feature = "unstable-raw-att")]
+pub mod att;
 pub(crate) mod bdaddr;
 pub mod bleuuid;
+pub(crate) mod gatt_map;
+pub(crate) mod gatt_tree;
+pub(crate) mod reliable_write;
+pub mod trace;
 
-use crate::Result;
+use self::bleuuid::NamedUuid;
+use crate::{
+    common::{
+        all_notifications::all_notifications,
+        coalesce::coalesce_notifications,
+        connect_first::{connect_first_with_service, refresh_properties, scan_until},
+        liveness::probe_liveness,
+        rssi_history::RssiHistory,
+        runtime,
+        subscriptions::{auto_subscribe, resubscribe_all},
+    },
+    Error, Result,
+};
 use async_trait::async_trait;
 use bitflags::bitflags;
-use futures::stream::Stream;
+use futures::{
+    future::{self, AbortHandle, Abortable, Either},
+    pin_mut,
+    stream::{Stream, StreamExt},
+};
+use log::debug;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "serde")]
 use serde_cr as serde;
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeSet, HashMap, HashSet},
     fmt::{self, Debug, Display, Formatter},
     pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 use uuid::Uuid;
 
+#[cfg(feature = "unstable-raw-att")]
+pub use self::att::{AttError, AttRequest, AttResponse};
 pub use self::bdaddr::{BDAddr, ParseBDAddrError};
+pub use self::gatt_map::GattMap;
+pub use self::gatt_tree::{GattTree, GattTreeCharacteristic, GattTreeDescriptor, GattTreeService};
+pub use self::reliable_write::ReliableWriteTransaction;
+pub use self::trace::TraceEvent;
+pub use crate::common::notification_receiver::NotificationReceiver;
+pub use crate::common::scan_subscriptions::ScanSubscription;
 
 use crate::platform::PeripheralId;
 
@@ -81,17 +114,100 @@ impl AddressType {
     }
 }
 
+/// Link Layer data lengths, as set by the LE Data Length Extension. These are separate from the ATT
+/// MTU, and limit how much data fits into a single packet on the air.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct DataLength {
+    /// The maximum number of payload octets in a transmitted packet.
+    pub max_tx_octets: u16,
+    /// The maximum time taken to transmit a packet, in microseconds.
+    pub max_tx_time: u16,
+    /// The maximum number of payload octets in a received packet.
+    pub max_rx_octets: u16,
+    /// The maximum time taken to receive a packet, in microseconds.
+    pub max_rx_time: u16,
+}
+
+/// A snapshot of the traffic to and from a peripheral, from [`Peripheral::metrics`].
+///
+/// The counters cover the lifetime of the peripheral handle and its clones, across connections,
+/// and are never reset. To measure a single connection, take the difference between snapshots made
+/// at its start and end.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub struct PeripheralMetrics {
+    /// The total length of the characteristic values read from the peripheral.
+    pub bytes_read: u64,
+    /// The total length of the characteristic values successfully written to the peripheral.
+    pub bytes_written: u64,
+    /// How many notifications and indications have been received from the peripheral. On Linux
+    /// and Android, each stream returned by [`Peripheral::notifications`] which receives one
+    /// counts it separately.
+    pub notifications_received: u64,
+    /// How many times the peripheral has been connected to again after the first connection.
+    pub reconnects: u64,
+    /// The ATT MTU of the current connection, or `None` if the peripheral isn't connected or the
+    /// platform doesn't expose it, as on Linux.
+    pub mtu: Option<u16>,
+}
+
+/// The radio transport over which a peripheral has been seen.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum Transport {
+    /// Bluetooth Low Energy only.
+    #[default]
+    Le,
+    /// Classic Bluetooth (BR/EDR) only. LE GATT operations won't work with such a device.
+    BrEdr,
+    /// The device has been seen on both LE and BR/EDR.
+    DualMode,
+}
+
 /// A notification sent from a peripheral due to a change in a value.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ValueNotification {
     /// UUID of the characteristic that fired the notification.
     pub uuid: Uuid,
+    /// UUID of the service of the characteristic that fired the notification.
+    pub service_uuid: Uuid,
+    /// The [`Characteristic::instance_id`] of the characteristic that fired the notification, to
+    /// tell apart the notifications of characteristics with the same UUID.
+    pub instance_id: u16,
     /// The new value of the characteristic.
     pub value: Vec<u8>,
 }
 
+impl ValueNotification {
+    /// Returns whether the notification was fired by the given characteristic.
+    pub fn is_from(&self, characteristic: &Characteristic) -> bool {
+        self.uuid == characteristic.uuid
+            && self.service_uuid == characteristic.service_uuid
+            && self.instance_id == characteristic.instance_id
+    }
+}
+
 bitflags! {
     /// A set of properties that indicate what operations are supported by a Characteristic.
+    #[cfg_attr(
+        feature = "serde",
+        derive(Serialize, Deserialize),
+        serde(crate = "serde_cr", transparent)
+    )]
     #[derive(Default, Debug, PartialEq, Eq, Ord, PartialOrd, Clone, Copy)]
     pub struct CharPropFlags: u8 {
         const BROADCAST = 0x01;
@@ -105,18 +221,72 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// The optional GATT features a client can tell a device it supports, by writing them to the
+    /// Client Supported Features characteristic with
+    /// [`Peripheral::write_client_supported_features`].
+    #[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+    pub struct ClientSupportedFeatures: u8 {
+        /// The client keeps track of the Database Hash, so that the device can tell it when its
+        /// attribute table has changed.
+        const ROBUST_CACHING = 0x01;
+        /// The client supports Enhanced ATT bearers.
+        const ENHANCED_ATT_BEARER = 0x02;
+        /// The client can receive Multiple Handle Value Notifications.
+        const MULTIPLE_HANDLE_VALUE_NOTIFICATIONS = 0x04;
+    }
+}
+
+/// Formats the flags like `READ | NOTIFY`.
+impl Display for CharPropFlags {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        bitflags::parser::to_writer(self, f)
+    }
+}
+
 /// A GATT service. Services are groups of characteristics, which may be standard or
 /// device-specific.
-#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
+#[derive(Ord, PartialOrd, Eq, PartialEq, Clone)]
 pub struct Service {
     /// The UUID for this service.
     pub uuid: Uuid,
     /// Whether this is a primary service.
     pub primary: bool,
+    /// The attribute handle of the service's declaration, which starts its range of handles, where
+    /// the platform exposes it. This is `None` on macOS and iOS, where CoreBluetooth hides handles.
+    pub start_handle: Option<u16>,
+    /// The handle of the last attribute of the service, where the platform exposes the handles of
+    /// its characteristics and descriptors. No platform reports the service's end group handle
+    /// itself, so this falls short of it if the device leaves handles unused at the end of the
+    /// service. This is `None` on macOS, iOS and Android.
+    pub end_handle: Option<u16>,
     /// The characteristics of this service.
     pub characteristics: BTreeSet<Characteristic>,
 }
 
+impl Display for Service {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}]",
+            NamedUuid(&self.uuid),
+            if self.primary { "primary" } else { "secondary" }
+        )
+    }
+}
+
+impl Debug for Service {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Service")
+            .field("uuid", &NamedUuid(&self.uuid))
+            .field("primary", &self.primary)
+            .field("start_handle", &self.start_handle)
+            .field("end_handle", &self.end_handle)
+            .field("characteristics", &self.characteristics)
+            .finish()
+    }
+}
+
 /// A Bluetooth characteristic. Characteristics are the main way you will interact with other
 /// bluetooth devices. Characteristics are identified by a UUID which may be standardized
 /// (like 0x2803, which identifies a characteristic for reading heart rate measurements) but more
@@ -125,12 +295,17 @@ pub struct Service {
 ///
 /// A characteristic may be interacted with in various ways depending on its properties. You may be
 /// able to write to it, read from it, set its notify or indicate status, or send a command to it.
-#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
+#[derive(Ord, PartialOrd, Eq, PartialEq, Clone)]
 pub struct Characteristic {
     /// The UUID for this characteristic. This uniquely identifies its behavior.
     pub uuid: Uuid,
     /// The UUID of the service this characteristic belongs to.
     pub service_uuid: Uuid,
+    /// Distinguishes this characteristic from any others with the same UUID in the same service.
+    /// This is the characteristic's attribute handle on Linux, Windows and Android, and its index
+    /// among the service's characteristics with the same UUID on macOS and iOS. It is only
+    /// meaningful until services are next discovered.
+    pub instance_id: u16,
     /// The set of properties for this characteristic, which indicate what functionality it
     /// supports. If you attempt an operation that is not supported by the characteristics (for
     /// example setting notify on one without the NOTIFY flag), that operation will fail.
@@ -141,16 +316,24 @@ pub struct Characteristic {
 
 impl Display for Characteristic {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(
-            f,
-            "uuid: {:?}, char properties: {:?}",
-            self.uuid, self.properties
-        )
+        write!(f, "{} [{}]", NamedUuid(&self.uuid), self.properties)
+    }
+}
+
+impl Debug for Characteristic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Characteristic")
+            .field("uuid", &NamedUuid(&self.uuid))
+            .field("service_uuid", &NamedUuid(&self.service_uuid))
+            .field("instance_id", &self.instance_id)
+            .field("properties", &format_args!("{}", self.properties))
+            .field("descriptors", &self.descriptors)
+            .finish()
     }
 }
 
 /// Add doc
-#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
+#[derive(Ord, PartialOrd, Eq, PartialEq, Clone)]
 pub struct Descriptor {
     /// The UUID for this descriptor. This uniquely identifies its behavior.
     pub uuid: Uuid,
@@ -158,11 +341,27 @@ pub struct Descriptor {
     pub service_uuid: Uuid,
     /// The UUID of the characteristic this descriptor belongs to.
     pub characteristic_uuid: Uuid,
+    /// The [`Characteristic::instance_id`] of the characteristic this descriptor belongs to.
+    pub characteristic_instance_id: u16,
 }
 
 impl Display for Descriptor {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "uuid: {:?}", self.uuid)
+        write!(f, "{}", NamedUuid(&self.uuid))
+    }
+}
+
+impl Debug for Descriptor {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Descriptor")
+            .field("uuid", &NamedUuid(&self.uuid))
+            .field("service_uuid", &NamedUuid(&self.service_uuid))
+            .field("characteristic_uuid", &NamedUuid(&self.characteristic_uuid))
+            .field(
+                "characteristic_instance_id",
+                &self.characteristic_instance_id,
+            )
+            .finish()
     }
 }
 
@@ -185,38 +384,700 @@ pub struct PeripheralProperties {
     pub tx_power_level: Option<i16>,
     /// The most recent Received Signal Strength Indicator for the device
     pub rssi: Option<i16>,
+    /// An exponential moving average of the signal strength of the device's advertisements, which
+    /// jitters less than [`PeripheralProperties::rssi`] for estimating how near the device is. How
+    /// much each advertisement counts is set by [`ScanOptions::rssi_smoothing`].
+    ///
+    /// BlueZ only reports the signal strength when it changes, so on Linux an advertisement with
+    /// the same strength as the one before doesn't count towards the average.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub smoothed_rssi: Option<i16>,
     /// Advertisement data specific to the device manufacturer. The keys of this map are
     /// 'manufacturer IDs', while the values are arbitrary data.
     pub manufacturer_data: HashMap<u16, Vec<u8>>,
     /// Advertisement data specific to a service. The keys of this map are
     /// 'Service UUIDs', while the values are arbitrary data.
     pub service_data: HashMap<Uuid, Vec<u8>>,
-    /// Advertised services for this device
-    pub services: Vec<Uuid>,
+    /// The service UUIDs the device has included in its advertisements. Devices often advertise
+    /// only a subset of their services, so this is not a substitute for the GATT services returned
+    /// by [`Peripheral::services`] after [`Peripheral::discover_services`].
+    #[cfg_attr(feature = "serde", serde(alias = "services"))]
+    pub advertised_services: Vec<Uuid>,
     pub class: Option<u32>,
+    /// The transport over which the device has been seen. On platforms which only scan for LE
+    /// devices this is always [`Transport::Le`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub transport: Transport,
+    /// When an advertisement from the device was last received, or `None` if none has been since
+    /// the `Adapter` was created. This is monotonic, so unaffected by changes to the wall clock. It
+    /// is not serialized, as an `Instant` is only meaningful within the process which took it.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub last_seen: Option<Instant>,
+    /// Whether a scan response has been received from the device, so that any properties it only
+    /// sends in its scan response, typically the local name, are known. Scan responses are only
+    /// requested by [`ScanType::Active`] scans. This is `None` on platforms which merge scan
+    /// responses into the advertisement without saying when one arrived: only Windows reports it.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub has_scan_response: Option<bool>,
+    /// Whether the device's advertisements say it accepts connections, or `None` if that isn't
+    /// known. Only macOS, iOS and Windows report it.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub connectable: Option<bool>,
+    /// The Advertising SID of the device's latest extended advertisement, which tells apart the
+    /// advertising sets of a device advertising several, and is needed to sync to a set's periodic
+    /// advertising with `Central::sync_periodic_advertising`. This is `None` for legacy
+    /// advertisements and those without an ADI field.
+    ///
+    /// Only reported on Linux with the `le5` feature, where BlueZ doesn't pass the SID on, so it is
+    /// read from raw HCI events once a scan is started. That needs the `CAP_NET_RAW` capability;
+    /// without it the SID is always `None`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub advertising_sid: Option<u8>,
+}
+
+/// An error parsing a [`PeripheralId`] from a string, such as one saved on a different platform.
+#[derive(Debug, thiserror::Error, Clone, PartialEq)]
+#[error("Invalid peripheral ID {id:?}, expected {expected}")]
+pub struct ParsePeripheralIdError {
+    id: String,
+    expected: &'static str,
+}
+
+impl ParsePeripheralIdError {
+    pub(crate) fn new(id: &str, expected: &'static str) -> Self {
+        Self {
+            id: id.to_string(),
+            expected,
+        }
+    }
+}
+
+/// A report of an advertisement from a device, from [`Central::observe`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Advertisement {
+    /// The ID of the device which sent the advertisement.
+    pub id: PeripheralId,
+    /// The address of the device which sent the advertisement.
+    pub address: BDAddr,
+    /// The device's local name, if it has been advertised.
+    pub local_name: Option<String>,
+    /// The signal strength the advertisement was received with.
+    pub rssi: Option<i16>,
+    /// The transmission power level the device advertised.
+    pub tx_power_level: Option<i16>,
+    /// Advertisement data specific to the device manufacturer, keyed by manufacturer ID.
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    /// Advertisement data specific to a service, keyed by service UUID.
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+    /// The service UUIDs the device advertised.
+    pub advertised_services: Vec<Uuid>,
+}
+
+impl Advertisement {
+    fn from_properties(id: PeripheralId, properties: PeripheralProperties) -> Self {
+        Self {
+            id,
+            address: properties.address,
+            local_name: properties.local_name,
+            rssi: properties.rssi,
+            tx_power_level: properties.tx_power_level,
+            manufacturer_data: properties.manufacturer_data,
+            service_data: properties.service_data,
+            advertised_services: properties.advertised_services,
+        }
+    }
+}
+
+/// A measurement of the signal strength of an advertisement, from [`Central::rssi_samples`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RssiSample {
+    /// The ID of the device which sent the advertisement.
+    pub id: PeripheralId,
+    /// The signal strength the advertisement was received with, in dBm.
+    pub rssi: i16,
+    /// The mean of the latest samples from the device, including this one, in dBm.
+    pub smoothed_rssi: i16,
+    /// When the advertisement was received.
+    pub received: Instant,
 }
 
 #[cfg_attr(
     feature = "serde",
     derive(Serialize, Deserialize),
-    serde(crate = "serde_cr")
+    serde(crate = "serde_cr", default)
 )]
-/// The filter used when scanning for BLE devices.
+/// The filter used when scanning for BLE devices. More options may be added in future, so use
+/// [`ScanFilter::builder`] or [`ScanFilter::default`] to construct one.
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
 pub struct ScanFilter {
     /// If the filter contains at least one service UUID, only devices supporting at least one of
     /// the given services will be available.
     pub services: Vec<Uuid>,
+    /// If the filter contains at least one entry, only devices advertising manufacturer data for
+    /// one of the given company IDs, starting with the corresponding prefix, will be available.
+    /// Only supported on Windows, and ignored elsewhere.
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    /// Only devices received with at least this signal strength will be available. Only supported
+    /// on Linux and Windows, and ignored elsewhere.
+    pub min_rssi: Option<i16>,
+    /// Whether to send scan requests to discovered devices. Only supported on Windows; other
+    /// platforms always scan actively.
+    pub scan_type: ScanType,
+    /// Whether to only make available devices whose advertisements say they accept connections,
+    /// leaving out beacons and the like. Devices for which [`PeripheralProperties::connectable`]
+    /// isn't known are let through, so this has no effect on Linux or Android.
+    pub connectable_only: bool,
+    /// Only devices whose name starts with this prefix will be available. The comparison is case
+    /// sensitive, as it is in BlueZ, which on Linux applies this as its `Pattern` discovery
+    /// filter and so also lets through devices whose address starts with it, like `"AA:BB"`.
+    /// Devices which haven't advertised a name yet are left out until they do. Ignored on Android.
+    pub name_prefix: Option<String>,
+}
+
+impl ScanFilter {
+    /// Returns a builder for a [`ScanFilter`].
+    ///
+    /// ```
+    /// use btleplug::api::{ScanFilter, ScanType};
+    /// use uuid::Uuid;
+    ///
+    /// let filter = ScanFilter::builder()
+    ///     .service(Uuid::from_u128(0x0000180d_0000_1000_8000_00805f9b34fb))
+    ///     .min_rssi(-80)
+    ///     .scan_type(ScanType::Passive)
+    ///     .build();
+    /// assert_eq!(filter.min_rssi, Some(-80));
+    /// ```
+    pub fn builder() -> ScanFilterBuilder {
+        ScanFilterBuilder::default()
+    }
+}
+
+/// A builder for [`ScanFilter`], created by [`ScanFilter::builder`].
+#[derive(Clone, Debug, Default)]
+pub struct ScanFilterBuilder {
+    filter: ScanFilter,
+}
+
+impl ScanFilterBuilder {
+    /// Adds a service UUID to [`ScanFilter::services`].
+    pub fn service(mut self, uuid: Uuid) -> Self {
+        self.filter.services.push(uuid);
+        self
+    }
+
+    /// Adds a company ID and data prefix to [`ScanFilter::manufacturer_data`].
+    pub fn manufacturer(mut self, company_id: u16, data_prefix: &[u8]) -> Self {
+        self.filter
+            .manufacturer_data
+            .insert(company_id, data_prefix.to_vec());
+        self
+    }
+
+    /// Sets [`ScanFilter::min_rssi`].
+    pub fn min_rssi(mut self, rssi: i16) -> Self {
+        self.filter.min_rssi = Some(rssi);
+        self
+    }
+
+    /// Sets [`ScanFilter::scan_type`].
+    pub fn scan_type(mut self, scan_type: ScanType) -> Self {
+        self.filter.scan_type = scan_type;
+        self
+    }
+
+    /// Sets [`ScanFilter::connectable_only`].
+    pub fn connectable_only(mut self, connectable_only: bool) -> Self {
+        self.filter.connectable_only = connectable_only;
+        self
+    }
+
+    /// Sets [`ScanFilter::name_prefix`].
+    pub fn name_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.filter.name_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Builds the [`ScanFilter`].
+    pub fn build(self) -> ScanFilter {
+        self.filter
+    }
+}
+
+/// How to scan for devices.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ScanType {
+    /// Send scan requests to discovered devices, to get their scan response data as well as their
+    /// advertisements.
+    #[default]
+    Active,
+    /// Only listen for advertisements. This uses less power, but devices may take longer to be
+    /// discovered and some of their properties may be missing.
+    Passive,
+}
+
+/// Options for [`Central::start_scan_with_options`], for tuning how the controller scans. More
+/// options may be added in future, so use [`ScanOptions::default`] and the setters to construct
+/// one.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct ScanOptions {
+    /// How long the controller listens for advertisements at a time, once every
+    /// [`ScanOptions::interval`]. A window as long as the interval scans all of the time, which
+    /// finds devices quickest at the cost of power. Must be between 2.5 ms and 10.24 s, and no
+    /// longer than the interval. Defaults to `None`, leaving it to the OS.
+    pub window: Option<Duration>,
+    /// How often the controller starts listening for advertisements. Must be between 2.5 ms and
+    /// 10.24 s. Defaults to `None`, leaving it to the OS.
+    pub interval: Option<Duration>,
+    /// The weight, as a percentage from 1 to 100, that each advertisement is given in
+    /// [`PeripheralProperties::smoothed_rssi`]. Lower values smooth out more jitter, but follow
+    /// real changes in signal strength more slowly; 100 does no smoothing. This applies to all of
+    /// the adapter's peripherals until another scan is started with different options. Defaults to
+    /// `None`, for 25%.
+    pub rssi_smoothing: Option<u8>,
+}
+
+impl ScanOptions {
+    /// The shortest and longest windows and intervals HCI allows.
+    const MIN: Duration = Duration::from_micros(2500);
+    const MAX: Duration = Duration::from_micros(10_240_000);
+
+    /// Sets [`ScanOptions::window`].
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// Sets [`ScanOptions::interval`].
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Sets [`ScanOptions::rssi_smoothing`].
+    pub fn rssi_smoothing(mut self, rssi_smoothing: u8) -> Self {
+        self.rssi_smoothing = Some(rssi_smoothing);
+        self
+    }
+
+    /// Checks that the window and interval are within range, the window is no longer than the
+    /// interval, and the RSSI smoothing is a percentage.
+    pub(crate) fn check(&self) -> Result<()> {
+        for (name, value) in [("window", self.window), ("interval", self.interval)] {
+            if let Some(value) = value {
+                if !(Self::MIN..=Self::MAX).contains(&value) {
+                    return Err(Error::Other(
+                        format!(
+                            "Scan {} of {:?} is not between {:?} and {:?}",
+                            name,
+                            value,
+                            Self::MIN,
+                            Self::MAX
+                        )
+                        .into(),
+                    ));
+                }
+            }
+        }
+        if let (Some(window), Some(interval)) = (self.window, self.interval) {
+            if window > interval {
+                return Err(Error::Other(
+                    format!(
+                        "Scan window of {:?} is longer than the interval of {:?}",
+                        window, interval
+                    )
+                    .into(),
+                ));
+            }
+        }
+        if let Some(rssi_smoothing) = self.rssi_smoothing {
+            if !(1..=100).contains(&rssi_smoothing) {
+                return Err(Error::Other(
+                    format!(
+                        "RSSI smoothing of {}% is not between 1% and 100%",
+                        rssi_smoothing
+                    )
+                    .into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Options for [`Peripheral::connect_with_options`]. More options may be added in future, so use
+/// [`ConnectOptions::default`] and the setters to construct one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct ConnectOptions {
+    /// Whether to discover the device's services while connecting, on the platforms which do so.
+    /// Turning this off makes connecting quicker, but [`Peripheral::discover_services`] must then
+    /// be called before any characteristics can be used. Defaults to `true`.
+    pub discover_services: bool,
+    /// The connection parameters to ask for from the start of the connection, rather than leaving
+    /// them to the platform and renegotiating afterwards. WinRT requests these before connecting
+    /// (Windows 11 and later only), and Android requests the matching connection priority straight
+    /// after connecting. BlueZ and CoreBluetooth don't let applications choose, so ignore this.
+    /// Defaults to `None`.
+    pub preferred_parameters: Option<PreferredConnectionParameters>,
+    /// The type of the device's address, for platforms which need it to find the device again.
+    /// WinRT fails to resolve random addresses without it, so uses this to connect to a peripheral
+    /// added with [`Central::add_peripheral`] from a saved address. Defaults to `None`, which means
+    /// the type seen in the device's advertisements, or public if it hasn't been seen. The other
+    /// platforms already know the address type, so ignore this.
+    pub address_type: Option<AddressType>,
+    /// The UUIDs of characteristics to subscribe to once connected, discovering services first if
+    /// need be, so that notifications start arriving straight away. Every characteristic with one
+    /// of these UUIDs is subscribed to. A subscription which fails is logged and doesn't fail the
+    /// connection; check [`Peripheral::subscriptions`] for those which succeeded. Defaults to
+    /// empty.
+    pub auto_subscribe: Vec<Uuid>,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            discover_services: true,
+            preferred_parameters: None,
+            address_type: None,
+            auto_subscribe: Vec::new(),
+        }
+    }
+}
+
+impl ConnectOptions {
+    /// Sets [`ConnectOptions::discover_services`].
+    pub fn discover_services(mut self, discover_services: bool) -> Self {
+        self.discover_services = discover_services;
+        self
+    }
+
+    /// Sets [`ConnectOptions::preferred_parameters`].
+    pub fn preferred_parameters(
+        mut self,
+        preferred_parameters: PreferredConnectionParameters,
+    ) -> Self {
+        self.preferred_parameters = Some(preferred_parameters);
+        self
+    }
+
+    /// Sets [`ConnectOptions::address_type`].
+    pub fn address_type(mut self, address_type: AddressType) -> Self {
+        self.address_type = Some(address_type);
+        self
+    }
+
+    /// Sets [`ConnectOptions::auto_subscribe`].
+    pub fn auto_subscribe(mut self, auto_subscribe: impl IntoIterator<Item = Uuid>) -> Self {
+        self.auto_subscribe = auto_subscribe.into_iter().collect();
+        self
+    }
+}
+
+/// A preset for the connection interval, latency and supervision timeout to ask for when
+/// connecting, with [`ConnectOptions::preferred_parameters`]. The device and the platform have the
+/// final say on what is actually used.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum PreferredConnectionParameters {
+    /// The platform's default balance between throughput, latency and power.
+    Balanced,
+    /// A short connection interval, for the most throughput and lowest latency at the cost of
+    /// power.
+    ThroughputOptimized,
+    /// A long connection interval, using the least power at the cost of throughput and latency.
+    PowerOptimized,
+}
+
+/// Options for [`Peripheral::discover_services_with_options`]. More options may be added in
+/// future, so use [`DiscoverOptions::default`] and the setters to construct one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct DiscoverOptions {
+    /// Whether to find each characteristic's descriptors as well. Turning this off saves a request
+    /// per characteristic, and [`Peripheral::descriptors`] is then empty until
+    /// [`Peripheral::discover_descriptors`] is called for the characteristic. Defaults to `true`.
+    pub descriptors: bool,
+    /// The UUIDs of the services to discover, or empty for all of them. CoreBluetooth and WinRT
+    /// ask the device for just these services, and BlueZ only looks up their characteristics,
+    /// while Android always discovers every service. Services which weren't asked for are left as
+    /// they were, so [`Peripheral::services`] may still list others. Defaults to empty.
+    pub services: Vec<Uuid>,
+}
+
+impl Default for DiscoverOptions {
+    fn default() -> Self {
+        Self {
+            descriptors: true,
+            services: Vec::new(),
+        }
+    }
+}
+
+impl DiscoverOptions {
+    /// Sets [`DiscoverOptions::descriptors`].
+    pub fn descriptors(mut self, descriptors: bool) -> Self {
+        self.descriptors = descriptors;
+        self
+    }
+
+    /// Sets [`DiscoverOptions::services`].
+    pub fn services(mut self, services: impl IntoIterator<Item = Uuid>) -> Self {
+        self.services = services.into_iter().collect();
+        self
+    }
+}
+
+/// Options for [`Peripheral::disconnect_with_options`]. More options may be added in future, so use
+/// [`DisconnectOptions::default`] and the setters to construct one.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct DisconnectOptions {
+    /// Whether to keep the device's discovered services, so that they can be used again straight
+    /// after reconnecting without calling [`Peripheral::discover_services`]. Only BlueZ keeps its
+    /// GATT objects across connections; other platforms always forget them on disconnecting.
+    /// Defaults to `false`.
+    pub keep_cache: bool,
+    /// Why btleplug itself is disconnecting, to be reported with
+    /// [`CentralEvent::DeviceDisconnectedWithReason`].
+    pub(crate) reason: Option<DisconnectReason>,
+}
+
+impl DisconnectOptions {
+    /// Sets [`DisconnectOptions::keep_cache`].
+    pub fn keep_cache(mut self, keep_cache: bool) -> Self {
+        self.keep_cache = keep_cache;
+        self
+    }
+
+    pub(crate) fn reason(mut self, reason: DisconnectReason) -> Self {
+        self.reason = Some(reason);
+        self
+    }
+}
+
+/// Keeps a liveness probe started with [`Peripheral::set_liveness_probe`] running. The probe stops
+/// when this is dropped.
+#[must_use = "the liveness probe stops when this is dropped"]
+#[derive(Debug)]
+pub struct LivenessProbe(AbortHandle);
+
+impl Drop for LivenessProbe {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
 }
 
 /// The type of write operation to use.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum WriteType {
     /// A write operation where the device is expected to respond with a confirmation or error. Also
-    /// known as a request.
+    /// known as a request. [`Peripheral::write`] waits for the response.
     WithResponse,
     /// A write-without-response, also known as a command.
     WithoutResponse,
+    /// A write-without-response authenticated with a signature made from the key exchanged when
+    /// pairing, for characteristics with [`CharPropFlags::AUTHENTICATED_SIGNED_WRITES`]. Only
+    /// supported on Linux and Android.
+    SignedWithoutResponse,
+}
+
+impl WriteType {
+    /// The property a characteristic must have to be written to this way.
+    pub(crate) fn required_property(self) -> CharPropFlags {
+        match self {
+            WriteType::WithResponse => CharPropFlags::WRITE,
+            WriteType::WithoutResponse => CharPropFlags::WRITE_WITHOUT_RESPONSE,
+            WriteType::SignedWithoutResponse => CharPropFlags::AUTHENTICATED_SIGNED_WRITES,
+        }
+    }
+}
+
+/// Chooses the write type for [`Peripheral::write_default`]: `preferred` if the characteristic
+/// supports it, or otherwise the first type it does support, trying writes with response first.
+fn choose_write_type(characteristic: &Characteristic, preferred: Option<WriteType>) -> WriteType {
+    let supported = |write_type: WriteType| {
+        characteristic
+            .properties
+            .contains(write_type.required_property())
+    };
+    if let Some(preferred) = preferred.filter(|&preferred| supported(preferred)) {
+        return preferred;
+    }
+    let fallback = [
+        WriteType::WithResponse,
+        WriteType::WithoutResponse,
+        WriteType::SignedWithoutResponse,
+    ]
+    .into_iter()
+    .find(|&write_type| supported(write_type))
+    // Let the write fail the same way it would if the type had been given explicitly.
+    .unwrap_or(preferred.unwrap_or(WriteType::WithResponse));
+    if let Some(preferred) = preferred {
+        debug!(
+            "Characteristic {} doesn't support {:?}, writing with {:?}",
+            characteristic.uuid, preferred, fallback
+        );
+    }
+    fallback
+}
+
+/// What became of a write, as reported by [`Peripheral::write_detailed`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct WriteResult {
+    /// The type the write was made with. This can differ from the one asked for: CoreBluetooth
+    /// writes with response to characteristics which don't allow writes without.
+    pub write_type: WriteType,
+    /// For a write without response, whether the OS had room for it in its flow-control window and
+    /// sent it straight away, rather than queuing it to be sent later. Only CoreBluetooth reports
+    /// this, so it is `None` elsewhere, and for writes with response, which the device confirms.
+    pub sent_immediately: Option<bool>,
+}
+
+/// What to ask a characteristic to send when subscribing to it with
+/// [`Peripheral::subscribe_with`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SubscriptionKind {
+    /// Notifications, which the device sends without waiting for them to be confirmed.
+    Notify,
+    /// Indications, which are confirmed on receipt.
+    Indicate,
+    /// Both notifications and indications at once, for the few devices which send some data one
+    /// way and the rest the other.
+    NotifyAndIndicate,
+}
+
+impl SubscriptionKind {
+    /// The properties a characteristic must have to be subscribed to this way.
+    fn required_properties(self) -> CharPropFlags {
+        match self {
+            SubscriptionKind::Notify => CharPropFlags::NOTIFY,
+            SubscriptionKind::Indicate => CharPropFlags::INDICATE,
+            SubscriptionKind::NotifyAndIndicate => CharPropFlags::NOTIFY | CharPropFlags::INDICATE,
+        }
+    }
+
+    /// Returns [`Error::NotSupported`] unless the characteristic can be subscribed to this way.
+    pub(crate) fn check(self, characteristic: &Characteristic) -> Result<()> {
+        if characteristic
+            .properties
+            .contains(self.required_properties())
+        {
+            Ok(())
+        } else {
+            Err(Error::NotSupported(format!(
+                "Subscribing with {:?} to characteristic {}, which has properties {:?}",
+                self, characteristic.uuid, characteristic.properties
+            )))
+        }
+    }
+
+    /// The value of the Client Characteristic Configuration descriptor which enables this.
+    pub(crate) fn cccd_value(self) -> u16 {
+        match self {
+            SubscriptionKind::Notify => 0x0001,
+            SubscriptionKind::Indicate => 0x0002,
+            SubscriptionKind::NotifyAndIndicate => 0x0003,
+        }
+    }
+}
+
+/// The ATT MTU a connection starts with, before the MTU is exchanged.
+pub(crate) const DEFAULT_ATT_MTU: u16 = 23;
+
+/// Returns the longest value which can be written with a single operation of the given type, on a
+/// connection with the given ATT MTU.
+pub(crate) fn max_write_len_for_mtu(mtu: u16, write_type: WriteType) -> usize {
+    let mtu = usize::from(mtu);
+    match write_type {
+        // Long writes are split over several requests, so are only limited by the longest value
+        // an attribute can have.
+        WriteType::WithResponse => 512,
+        // The opcode and attribute handle take 3 bytes of the packet...
+        WriteType::WithoutResponse => mtu.saturating_sub(3),
+        // ...and a signed write also needs 12 for the signature.
+        WriteType::SignedWithoutResponse => mtu.saturating_sub(15),
+    }
+}
+
+/// The UUID of the GAP service's Device Name characteristic.
+const DEVICE_NAME: Uuid = bleuuid::uuid_from_u16(0x2a00);
+
+/// The UUID of the GATT service's Client Supported Features characteristic.
+const CLIENT_SUPPORTED_FEATURES: Uuid = bleuuid::uuid_from_u16(0x2b29);
+
+/// The UUID of the GATT service's Database Hash characteristic.
+const DATABASE_HASH: Uuid = bleuuid::uuid_from_u16(0x2b2a);
+
+/// The UUID of the Client Characteristic Configuration descriptor.
+pub(crate) const CLIENT_CHARACTERISTIC_CONFIGURATION: Uuid = bleuuid::uuid_from_u16(0x2902);
+
+/// Finds the Client Characteristic Configuration descriptor of a characteristic, or returns
+/// [`Error::NotSupported`] if it has none.
+fn client_characteristic_configuration(characteristic: &Characteristic) -> Result<&Descriptor> {
+    characteristic
+        .descriptors
+        .iter()
+        .find(|descriptor| descriptor.uuid == CLIENT_CHARACTERISTIC_CONFIGURATION)
+        .ok_or_else(|| {
+            Error::NotSupported(format!(
+                "Characteristic {} has no Client Characteristic Configuration descriptor",
+                characteristic.uuid
+            ))
+        })
+}
+
+/// Implements [`Peripheral::subscribe_with`] for platforms which don't allow the Client
+/// Characteristic Configuration descriptor to be written, and enable notifications when a
+/// characteristic supports both notifications and indications.
+pub(crate) async fn subscribe_preferring_notify<P: Peripheral>(
+    peripheral: &P,
+    characteristic: &Characteristic,
+    kind: SubscriptionKind,
+) -> Result<()> {
+    kind.check(characteristic)?;
+    if kind != SubscriptionKind::Notify && characteristic.properties.contains(CharPropFlags::NOTIFY)
+    {
+        return Err(Error::NotSupported(format!(
+            "{:?} subscriptions to notifying characteristics are not supported on this platform",
+            kind
+        )));
+    }
+    peripheral.subscribe(characteristic).await
+}
+
+/// Reads the value of the GAP Device Name characteristic, for platforms which expose the GAP service
+/// like any other.
+pub(crate) async fn read_gap_device_name<P: Peripheral>(peripheral: &P) -> Result<String> {
+    let characteristic = find_standard_characteristic(peripheral, DEVICE_NAME)?;
+    let value = peripheral.read(&characteristic).await?;
+    // Some devices pad the name out to a fixed length.
+    Ok(String::from_utf8_lossy(&value)
+        .trim_end_matches('\0')
+        .to_string())
+}
+
+/// Finds the discovered characteristic with the given UUID, for the standard characteristics of
+/// which a device has at most one.
+fn find_standard_characteristic<P: Peripheral>(
+    peripheral: &P,
+    uuid: Uuid,
+) -> Result<Characteristic> {
+    peripheral
+        .characteristics()
+        .into_iter()
+        .find(|characteristic| characteristic.uuid == uuid)
+        .ok_or(Error::NoSuchCharacteristic)
 }
 
 /// Peripheral is the device that you would like to communicate with (the "server" of BLE). This
@@ -230,12 +1091,123 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     /// Returns the MAC address of the peripheral.
     fn address(&self) -> BDAddr;
 
+    /// Returns the longest value which can be written to a characteristic of this peripheral with a
+    /// single operation of the given type. Writes without response are limited by the ATT MTU
+    /// negotiated for the connection, while writes with response can be split into a long write,
+    /// so are limited to the 512 bytes an attribute value can hold. Where the negotiated MTU isn't
+    /// known, or before connecting, this assumes the minimum MTU of 23.
+    fn max_write_len(&self, write_type: WriteType) -> usize {
+        max_write_len_for_mtu(DEFAULT_ATT_MTU, write_type)
+    }
+
+    /// Asks for the ATT MTU of the connection to be raised to the given value, and returns the MTU
+    /// actually negotiated, which may be lower if the device doesn't support as large a one. The
+    /// peripheral must be connected.
+    ///
+    /// Only Android lets clients request an MTU. CoreBluetooth and WinRT negotiate the largest MTU
+    /// they can by themselves, so on macOS, iOS and Windows this just returns the current MTU. On
+    /// Linux, BlueZ doesn't expose the MTU to clients, so this returns [`Error::NotSupported`].
+    async fn request_mtu(&self, _mtu: u16) -> Result<u16> {
+        Err(Error::NotSupported(
+            "Requesting an MTU is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Returns the controller's HCI handle for the connection to this peripheral, or `None` if it
+    /// isn't connected or the platform doesn't expose it. This is only meant for diagnostics, such as
+    /// matching up a peripheral with the connections in a `btmon` trace, and is best-effort.
+    ///
+    /// Only supported on Linux, where it queries the kernel directly rather than going through
+    /// BlueZ. Other platforms always return `None`.
+    async fn connection_handle(&self) -> Result<Option<u16>> {
+        Ok(None)
+    }
+
+    /// Returns the Link Layer data lengths for the connection to this peripheral, or `None` if it
+    /// isn't connected or the platform doesn't expose them. Like [`Peripheral::connection_handle`],
+    /// this is a diagnostics aid.
+    ///
+    /// Only supported on Linux, where this is the maximum data length the controller supports, read
+    /// over HCI. The kernel doesn't keep track of the values negotiated for each connection, so these
+    /// are an upper bound on them. Sending HCI commands needs the `CAP_NET_RAW` capability. Other
+    /// platforms always return `None`.
+    async fn data_length(&self) -> Result<Option<DataLength>> {
+        Ok(None)
+    }
+
+    /// Reads the transmit power level, in dBm, which the local controller is currently using on the
+    /// connection to this peripheral. Unlike [`PeripheralProperties::tx_power_level`], which is
+    /// what the device advertises for itself, this is specific to the connection and may change as
+    /// the controller adjusts it.
+    ///
+    /// Only supported on Linux, where this sends the HCI Read Transmit Power Level command, which
+    /// needs the `CAP_NET_RAW` capability. Returns [`Error::NotConnected`] if there is no
+    /// connection. Other platforms return [`Error::NotSupported`].
+    async fn read_tx_power_level(&self) -> Result<i8> {
+        Err(Error::NotSupported(
+            "Reading the connection's transmit power level is not supported on this platform"
+                .to_string(),
+        ))
+    }
+
+    /// Sends a single raw ATT request to the device and returns its response, including the ATT
+    /// error code of an Error Response rather than translating it to an [`Error`]. This is an
+    /// unstable debugging aid for devices with nonstandard ATT behaviour, only available with the
+    /// `unstable-raw-att` feature; see the [`att`] module.
+    ///
+    /// Only supported on Linux, where the request goes over an ATT socket of btleplug's own, which
+    /// needs the `CAP_NET_RAW` capability. The kernel only allows one ATT bearer per connection, so
+    /// the device must not be connected through BlueZ at the time. Other platforms return
+    /// [`Error::NotSupported`].
+    #[cfg(feature = "unstable-raw-att")]
+    async fn raw_att(&self, _request: AttRequest) -> Result<AttResponse> {
+        Err(Error::NotSupported(
+            "Raw ATT requests are not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Returns how many GATT operations (reads, writes, subscriptions and descriptor accesses) made
+    /// through this peripheral are still in progress. Clones of a peripheral share the count, but
+    /// separate handles to the same device from [`Central::peripheral`] may not. Implementations
+    /// which don't track their operations report none.
+    fn pending_operations(&self) -> usize {
+        0
+    }
+
+    /// Returns how many characteristic writes made through this peripheral are still in progress,
+    /// counted like [`Peripheral::pending_operations`]. This can be used to apply backpressure when
+    /// streaming writes.
+    ///
+    /// Writes without response complete as soon as the platform has accepted them, rather than when
+    /// they have been sent, so are rarely pending for long.
+    fn pending_writes(&self) -> usize {
+        0
+    }
+
+    /// Makes every GATT operation counted by [`Peripheral::pending_operations`] return
+    /// [`Error::Cancelled`] straight away, rather than waiting for it to complete or time out, such
+    /// as when tearing down a device. Operations started afterwards aren't affected.
+    ///
+    /// This only stops waiting for the operations: none of the platforms can take back a request
+    /// which has been handed to the OS, so the device may still receive an abandoned write, or be
+    /// left subscribed after an abandoned subscription.
+    fn abort_pending(&self) {}
+
+    /// Returns a snapshot of the traffic counters of this peripheral, kept as operations complete.
+    /// Like [`Peripheral::pending_operations`], clones of a peripheral share the counters, but
+    /// separate handles to the same device from [`Central::peripheral`] may not. Implementations
+    /// which don't keep counters report zeroes.
+    fn metrics(&self) -> PeripheralMetrics {
+        PeripheralMetrics::default()
+    }
+
     /// Returns the set of properties associated with the peripheral. These may be updated over time
     /// as additional advertising reports are received.
     async fn properties(&self) -> Result<Option<PeripheralProperties>>;
 
     /// The set of services we've discovered for this device. This will be empty until
-    /// `discover_services` is called.
+    /// `discover_services` is called. Unlike [`PeripheralProperties::advertised_services`], this
+    /// is the device's actual GATT table.
     fn services(&self) -> BTreeSet<Service>;
 
     /// The set of characteristics we've discovered for this device. This will be empty until
@@ -247,6 +1219,38 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
             .collect()
     }
 
+    /// The descriptors of the given characteristic, as found by the last call to
+    /// `discover_services`. Every platform enumerates descriptors while discovering services, so
+    /// this includes any vendor-specific ones. This is empty if the characteristic wasn't found,
+    /// such as before `discover_services` is called, or if services were discovered without
+    /// descriptors and [`Peripheral::discover_descriptors`] hasn't been called for it since.
+    fn descriptors(&self, characteristic: &Characteristic) -> Vec<Descriptor> {
+        self.characteristics()
+            .into_iter()
+            .find(|c| {
+                c.uuid == characteristic.uuid
+                    && c.service_uuid == characteristic.service_uuid
+                    && c.instance_id == characteristic.instance_id
+            })
+            .map(|c| c.descriptors.into_iter().collect())
+            .unwrap_or_default()
+    }
+
+    /// The discovered services, characteristics and descriptors as one nested [`GattTree`], which
+    /// can be serialized with the `serde` feature, such as to export the device's attribute table.
+    /// Like [`services`](Peripheral::services), this is empty until `discover_services` is called.
+    fn gatt_tree(&self) -> GattTree {
+        GattTree::new(self.services())
+    }
+
+    /// The services found by the last call to `discover_services` whose characteristics couldn't
+    /// be enumerated. These are left out of [`services`](Peripheral::services) rather than failing
+    /// the whole discovery, so one misbehaving service doesn't stop the rest of the device from
+    /// being used. Always empty on platforms which get the GATT table from the OS as a whole.
+    fn failed_services(&self) -> BTreeSet<Uuid> {
+        BTreeSet::new()
+    }
+
     /// Returns true iff we are currently connected to the device.
     async fn is_connected(&self) -> Result<bool>;
 
@@ -255,14 +1259,191 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     /// attempt to communicate with a device will fail until it is connected.
     async fn connect(&self) -> Result<()>;
 
+    /// Connects to the device like [`Peripheral::connect`], with the given options.
+    ///
+    /// CoreBluetooth and WinRT discover all of the device's services while connecting, which
+    /// [`ConnectOptions::discover_services`] can skip. BlueZ always discovers services itself, and
+    /// Android only does so from [`Peripheral::discover_services`]. Only WinRT and Android make use
+    /// of [`ConnectOptions::preferred_parameters`], so on Linux the only option which makes a
+    /// difference is [`ConnectOptions::auto_subscribe`].
+    async fn connect_with_options(&self, options: ConnectOptions) -> Result<()> {
+        self.connect().await?;
+        auto_subscribe(self, &options.auto_subscribe).await;
+        Ok(())
+    }
+
+    /// Connects to the device and returns one of its characteristics, ready to be used, for code
+    /// which only needs a single known characteristic. This skips discovering services while
+    /// connecting on the platforms which do so (see [`ConnectOptions::discover_services`]), and
+    /// afterwards discovers just the one service where the platform can (see
+    /// [`DiscoverOptions::services`]).
+    ///
+    /// Returns [`Error::NoSuchCharacteristic`] if the device has no such characteristic, in which
+    /// case it is left connected.
+    async fn connect_and_get(&self, service: Uuid, characteristic: Uuid) -> Result<Characteristic> {
+        self.connect_with_options(ConnectOptions::default().discover_services(false))
+            .await?;
+        self.discover_services_with_options(DiscoverOptions::default().services([service]))
+            .await?;
+        self.characteristics()
+            .into_iter()
+            .find(|c| c.service_uuid == service && c.uuid == characteristic)
+            .ok_or(Error::NoSuchCharacteristic)
+    }
+
+    /// Connects to the device, discovers all of its services, and returns all of its
+    /// characteristics looked up by UUID, for apps which want every handle ready to use as soon as
+    /// they start. Like [`Peripheral::connect_and_get`], this discovers services once after
+    /// connecting rather than while connecting.
+    async fn bootstrap(&self) -> Result<GattMap> {
+        self.connect_with_options(ConnectOptions::default().discover_services(false))
+            .await?;
+        self.discover_services().await?;
+        Ok(GattMap::new(self.services()))
+    }
+
     /// Terminates a connection to the device.
     async fn disconnect(&self) -> Result<()>;
 
+    /// Terminates a connection to the device like [`Peripheral::disconnect`], with the given
+    /// options. The connection is always closed cleanly, with the platform telling the device that
+    /// the local host ended it.
+    ///
+    /// Plain [`Peripheral::disconnect`] keeps each platform's own behaviour, which on Linux is to
+    /// keep the discovered services.
+    async fn disconnect_with_options(&self, _options: DisconnectOptions) -> Result<()> {
+        self.disconnect().await
+    }
+
+    /// Disconnects from the device if connected and releases everything held for it, reporting any
+    /// error in doing so. Dropping the last handle to a peripheral tears it down too, but on some
+    /// platforms that involves blocking calls, so async code doing an orderly shutdown should
+    /// prefer this. Other clones of the peripheral remain usable and can connect again.
+    async fn close(self) -> Result<()> {
+        if self.is_connected().await? {
+            self.disconnect().await?;
+        }
+        Ok(())
+    }
+
+    /// Starts checking that the device is still there while connected, by reading `characteristic`
+    /// every `interval`. If a read fails, or doesn't complete within another `interval`, the device
+    /// is disconnected, so [`CentralEvent::DeviceDisconnected`] is emitted without waiting for the
+    /// OS to notice that the link has been lost. That can take the whole supervision timeout, which
+    /// is several seconds on some stacks. It comes with a
+    /// [`CentralEvent::DeviceDisconnectedWithReason`] of [`DisconnectReason::ProbeTimeout`], and
+    /// the failure is reported to the trace callback as [`trace::TraceEvent::LivenessProbeFailed`].
+    ///
+    /// The characteristic should be cheap for the device to read. The probe runs on an internal
+    /// task, and carries on through reconnections until the returned [`LivenessProbe`] is dropped.
+    fn set_liveness_probe(
+        &self,
+        interval: Duration,
+        characteristic: &Characteristic,
+    ) -> LivenessProbe
+    where
+        Self: 'static,
+    {
+        let (handle, registration) = AbortHandle::new_pair();
+        let probe = probe_liveness(self.clone(), interval, characteristic.clone());
+        runtime::spawn(async move {
+            let _ = Abortable::new(probe, registration).await;
+        });
+        LivenessProbe(handle)
+    }
+
     /// Discovers all services for the device, including their characteristics.
+    ///
+    /// This can be called again to pick up services the device has added or changed since, after
+    /// which their characteristics can be subscribed to. Existing subscriptions are kept for
+    /// characteristics the device still has.
     async fn discover_services(&self) -> Result<()>;
 
+    /// Discovers all services for the device like [`Peripheral::discover_services`], with the given
+    /// options.
+    ///
+    /// Skipping descriptors with [`DiscoverOptions::descriptors`] saves the most on CoreBluetooth
+    /// and WinRT, which ask the device for each characteristic's descriptors separately. BlueZ
+    /// finds descriptors itself while resolving services, so there it only saves D-Bus calls.
+    /// Android always discovers everything at once, so ignores the option.
+    async fn discover_services_with_options(&self, _options: DiscoverOptions) -> Result<()> {
+        self.discover_services().await
+    }
+
+    /// Discovers the descriptors of the given characteristic, for when services were discovered
+    /// without them (see [`DiscoverOptions::descriptors`]), and returns them. They are also
+    /// returned by [`Peripheral::descriptors`] from then on. The peripheral must be connected and
+    /// its services discovered.
+    async fn discover_descriptors(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Vec<Descriptor>> {
+        Ok(self.descriptors(characteristic))
+    }
+
+    /// Waits for the platform to finish discovering the connected device's services, then
+    /// discovers them as [`Peripheral::discover_services`] does, so that [`Peripheral::services`]
+    /// and [`Peripheral::characteristics`] are complete once this returns. Use this rather than
+    /// [`Peripheral::discover_services`] where discovery may still be in progress, such as straight
+    /// after connecting or after [`CentralEvent::ServicesChanged`].
+    ///
+    /// Returns [`Error::TimedOut`] if discovery doesn't finish within `timeout`. On BlueZ this waits
+    /// for the device's `ServicesResolved` property, and returns [`Error::NotConnected`] if the
+    /// device isn't connected or disconnects in the meantime. Other platforms only report services
+    /// once their discovery is complete, so this is [`Peripheral::discover_services`] with a
+    /// timeout.
+    async fn wait_services_resolved(&self, timeout: Duration) -> Result<()> {
+        let discover = self.discover_services();
+        let timeout_elapsed = runtime::sleep(timeout);
+        pin_mut!(discover, timeout_elapsed);
+        match future::select(discover, timeout_elapsed).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err(Error::TimedOut(timeout)),
+        }
+    }
+
+    /// Clears the platform's cache of the device's services, so that the next call to
+    /// [`Peripheral::discover_services`] reads them from the device rather than reusing what was
+    /// found before. This is for devices whose services have changed without them saying so, such as
+    /// after a firmware update, which would otherwise need unpairing to fix.
+    ///
+    /// On Windows the next discovery bypasses the cache. On Android the peripheral must be
+    /// connected. On Linux, BlueZ only forgets a device's services when the device itself is
+    /// removed, so this disconnects the peripheral and removes it from BlueZ. It then needs to be
+    /// found by scanning again before it can be connected to. Removing a paired device would also
+    /// remove the pairing, so for them this returns [`Error::NotSupported`] instead. CoreBluetooth
+    /// has no way to clear its cache, so on macOS and iOS this always returns
+    /// [`Error::NotSupported`].
+    async fn clear_gatt_cache(&self) -> Result<()> {
+        Err(Error::NotSupported(
+            "Clearing the GATT cache is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Makes sure the link to the device is encrypted, pairing with it first if need be, so that
+    /// security-sensitive code can fail early rather than part way through talking to the device.
+    /// The peripheral must be connected. Returns [`Error::NotPaired`] if pairing fails or is
+    /// refused.
+    ///
+    /// On Linux this pairs through BlueZ, which needs an agent registered for any pairing method
+    /// other than Just Works; BlueZ encrypts links to devices it is bonded with as soon as they
+    /// connect, so a device which is already paired is taken to be encrypted. On Windows the device
+    /// is paired with at least `DevicePairingProtectionLevel::Encryption`. CoreBluetooth only pairs
+    /// when the device refuses an operation until it does, and Android isn't supported yet, so on
+    /// those platforms this returns [`Error::NotSupported`].
+    async fn require_encryption(&self) -> Result<()> {
+        Err(Error::NotSupported(
+            "Requiring encryption is not supported on this platform".to_string(),
+        ))
+    }
+
     /// Write some data to the characteristic. Returns an error if the write couldn't be sent or (in
     /// the case of a write-with-response) if the device returns an error.
+    ///
+    /// A write-with-response only returns `Ok` once the device has acknowledged it with a Write
+    /// Response. The response carries no data, so there is nothing more to return; a protocol that
+    /// needs a reply with data has to get it by a read or notification. If the connection is lost
+    /// before the response arrives, the write fails.
     async fn write(
         &self,
         characteristic: &Characteristic,
@@ -270,22 +1451,413 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
         write_type: WriteType,
     ) -> Result<()>;
 
+    /// Returns the write type set with [`Peripheral::set_default_write_type`], if any.
+    fn default_write_type(&self) -> Option<WriteType> {
+        None
+    }
+
+    /// Sets the write type which [`Peripheral::write_default`] uses for characteristics which
+    /// support it. Throughput-oriented apps may prefer [`WriteType::WithoutResponse`], so as not to
+    /// wait for each write to be acknowledged. Clones of a peripheral share the setting.
+    /// Implementations which don't store it ignore this, and always choose from the properties.
+    fn set_default_write_type(&self, _write_type: WriteType) {}
+
+    /// Writes some data to the characteristic like [`Peripheral::write`], choosing the write type
+    /// from those the characteristic's properties allow. The type set with
+    /// [`Peripheral::set_default_write_type`] is used if the characteristic supports it. Otherwise,
+    /// or if no type has been set, this writes with response where supported and without response
+    /// where not.
+    async fn write_default(&self, characteristic: &Characteristic, data: &[u8]) -> Result<()> {
+        let write_type = choose_write_type(characteristic, self.default_write_type());
+        self.write(characteristic, data, write_type).await
+    }
+
+    /// Writes some data to the characteristic like [`Peripheral::write`], and reports how the write
+    /// was actually made, for code such as firmware updates which needs to know what went out.
+    async fn write_detailed(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<WriteResult> {
+        self.write(characteristic, data, write_type).await?;
+        Ok(WriteResult {
+            write_type,
+            sent_immediately: None,
+        })
+    }
+
+    /// Starts building a Reliable Write transaction, for writes to several characteristics which
+    /// the device must commit together or not at all:
+    ///
+    /// ```no_run
+    /// # use btleplug::api::{Characteristic, Peripheral};
+    /// # async fn example(peripheral: impl Peripheral, a: Characteristic, b: Characteristic) -> btleplug::Result<()> {
+    /// peripheral
+    ///     .reliable_write()
+    ///     .write(&a, &[1])
+    ///     .write(&b, &[2, 3])
+    ///     .execute()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// See [`Peripheral::execute_reliable_write`] for which platforms support this.
+    fn reliable_write(&self) -> ReliableWriteTransaction<'_, Self> {
+        ReliableWriteTransaction::new(self)
+    }
+
+    /// Writes each value to its characteristic as one Reliable Write transaction, which is what
+    /// [`ReliableWriteTransaction::execute`] does. If any write fails, the transaction is cancelled
+    /// so that none of them take effect.
+    ///
+    /// WinRT and Android support transactions across any characteristics of the device. BlueZ
+    /// only lets a transaction write to a single characteristic, and CoreBluetooth doesn't expose
+    /// transactions at all, so these return [`Error::NotSupported`] otherwise.
+    async fn execute_reliable_write(&self, _writes: &[(Characteristic, Vec<u8>)]) -> Result<()> {
+        Err(Error::NotSupported(
+            "Reliable writes are not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Writes `data` to the characteristic as a series of writes of up to
+    /// [`Peripheral::max_write_len`] bytes each, for values too long to write in one go such as
+    /// firmware images. The device sees each chunk as a separate write, so must be expecting the
+    /// data in pieces. After each chunk, `progress` is called with the number of bytes written so
+    /// far and the total, from the task awaiting this rather than another thread.
+    ///
+    /// Each chunk is written once the previous one has completed. If a write fails, the error is
+    /// returned and the remaining chunks aren't written.
+    async fn write_chunked_with_progress<F>(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+        mut progress: F,
+    ) -> Result<()>
+    where
+        F: FnMut(usize, usize) + Send,
+    {
+        let chunk_len = self.max_write_len(write_type).max(1);
+        let mut written = 0;
+        for chunk in data.chunks(chunk_len) {
+            self.write(characteristic, chunk, write_type).await?;
+            written += chunk.len();
+            progress(written, data.len());
+        }
+        Ok(())
+    }
+
     /// Sends a read request to the device. Returns either an error if the request was not accepted
     /// or the response from the device.
     async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>>;
 
+    /// Returns the value of the characteristic the platform has cached, if it has one, rather than
+    /// sending a read request. This is quicker and doesn't use the radio, but the value may be out
+    /// of date: it's whatever was last read or notified, which won't reflect changes the device
+    /// hasn't told us about. Use it for values which rarely change, and [`Peripheral::read`] for
+    /// those which need to be fresh.
+    ///
+    /// On Windows this reads with `BluetoothCacheMode::Cached`. On Linux it returns the value BlueZ
+    /// last received, which may have been read by another program, or reads from the device if it
+    /// is empty. Other platforms don't cache values, so this is the same as [`Peripheral::read`].
+    async fn read_cached(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.read(characteristic).await
+    }
+
+    /// Reads the device's name from the Device Name characteristic of its GAP service, and updates
+    /// [`PeripheralProperties::local_name`] to it. This is the device's full name, which may be
+    /// longer than, or different to, the one it advertises. The peripheral must be connected and
+    /// its services discovered. Returns [`Error::NoSuchCharacteristic`] if the device has no GAP
+    /// Device Name.
+    ///
+    /// BlueZ and CoreBluetooth read the name themselves when connecting, and don't expose the GAP
+    /// service, so on Linux, macOS and iOS this returns the name they read.
+    async fn read_device_name(&self) -> Result<String> {
+        read_gap_device_name(self).await
+    }
+
+    /// Reads the Database Hash characteristic of the device's GATT service, a hash of its
+    /// attribute table which changes whenever the table does. Comparing it with the hash from an
+    /// earlier connection tells whether services, characteristics and descriptors cached then can
+    /// be used again. The peripheral must be connected and its services discovered. Returns
+    /// [`Error::NoSuchCharacteristic`] if the device has no Database Hash, as is the case for
+    /// devices from before Bluetooth 5.1.
+    ///
+    /// BlueZ and CoreBluetooth use the hash to manage their own caches, and may not expose the GATT
+    /// service to applications, in which case this also returns [`Error::NoSuchCharacteristic`].
+    async fn read_database_hash(&self) -> Result<[u8; 16]> {
+        let characteristic = find_standard_characteristic(self, DATABASE_HASH)?;
+        let value = self.read(&characteristic).await?;
+        value.as_slice().try_into().map_err(|_| {
+            Error::Other(format!("Database Hash has invalid length {}", value.len()).into())
+        })
+    }
+
+    /// Tells the device which optional GATT features this client supports, by writing them to the
+    /// Client Supported Features characteristic of its GATT service. Enabling
+    /// [`ClientSupportedFeatures::ROBUST_CACHING`] makes the device report changes to its attribute
+    /// table, which a client caching it should do before relying on [`Peripheral::read_database_hash`].
+    /// Features can't be disabled once enabled for a bonded device. The peripheral must be
+    /// connected and its services discovered. Returns [`Error::NoSuchCharacteristic`] if the device
+    /// has no Client Supported Features, as with [`Peripheral::read_database_hash`].
+    async fn write_client_supported_features(
+        &self,
+        features: ClientSupportedFeatures,
+    ) -> Result<()> {
+        let characteristic = find_standard_characteristic(self, CLIENT_SUPPORTED_FEATURES)?;
+        self.write(&characteristic, &[features.bits()], WriteType::WithResponse)
+            .await
+    }
+
+    /// Reads every discovered characteristic which supports [`CharPropFlags::READ`], concurrently,
+    /// and returns each along with the result of reading it. A characteristic which can't be read,
+    /// e.g. because it requires pairing, has its error reported in its entry rather than failing
+    /// the whole call.
+    async fn read_all_readable(&self) -> Result<Vec<(Characteristic, Result<Vec<u8>>)>> {
+        if !self.is_connected().await? {
+            return Err(Error::NotConnected);
+        }
+        let readable: Vec<_> = self
+            .characteristics()
+            .into_iter()
+            .filter(|characteristic| characteristic.properties.contains(CharPropFlags::READ))
+            .collect();
+        let values = future::join_all(
+            readable
+                .iter()
+                .map(|characteristic| self.read(characteristic)),
+        )
+        .await;
+        Ok(readable.into_iter().zip(values).collect())
+    }
+
+    /// Reads every descriptor of every discovered characteristic, concurrently, and returns each
+    /// along with its characteristic and the result of reading it. Descriptors don't say whether
+    /// they can be read, so all of them are tried; one which can't be read, e.g. because it requires
+    /// pairing, has its error reported in its entry rather than failing the whole call.
+    async fn read_all_descriptors(
+        &self,
+    ) -> Result<Vec<(Characteristic, Descriptor, Result<Vec<u8>>)>> {
+        if !self.is_connected().await? {
+            return Err(Error::NotConnected);
+        }
+        let descriptors: Vec<_> = self
+            .characteristics()
+            .into_iter()
+            .flat_map(|characteristic| {
+                let descriptors = characteristic.descriptors.clone();
+                descriptors
+                    .into_iter()
+                    .map(move |descriptor| (characteristic.clone(), descriptor))
+            })
+            .collect();
+        let values = future::join_all(
+            descriptors
+                .iter()
+                .map(|(_, descriptor)| self.read_descriptor(descriptor)),
+        )
+        .await;
+        Ok(descriptors
+            .into_iter()
+            .zip(values)
+            .map(|((characteristic, descriptor), value)| (characteristic, descriptor, value))
+            .collect())
+    }
+
+    /// Reads every descriptor of the characteristic concurrently, like
+    /// [`Peripheral::read_all_descriptors`] does for the whole device, and returns each along with
+    /// the result of reading it. This gets e.g. the User Description and Presentation Format of a
+    /// characteristic in one call. A descriptor which can't be read, such as a Client
+    /// Characteristic Configuration which the device only lets paired clients read, has its error
+    /// reported in its entry rather than failing the whole call.
+    async fn read_descriptors(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Vec<(Descriptor, Result<Vec<u8>>)>> {
+        if !self.is_connected().await? {
+            return Err(Error::NotConnected);
+        }
+        let descriptors: Vec<_> = characteristic.descriptors.iter().cloned().collect();
+        let values = future::join_all(
+            descriptors
+                .iter()
+                .map(|descriptor| self.read_descriptor(descriptor)),
+        )
+        .await;
+        Ok(descriptors.into_iter().zip(values).collect())
+    }
+
     /// Enables either notify or indicate (depending on support) for the specified characteristic.
     async fn subscribe(&self, characteristic: &Characteristic) -> Result<()>;
 
+    /// Subscribes to the characteristic like [`Peripheral::subscribe`], but choosing whether it
+    /// sends notifications, indications or both, rather than leaving that to the platform. The
+    /// characteristic must have the [`CharPropFlags::NOTIFY`] and/or [`CharPropFlags::INDICATE`]
+    /// properties needed, or this returns [`Error::NotSupported`]. [`Peripheral::resubscribe_all`]
+    /// subscribes again with [`Peripheral::subscribe`].
+    ///
+    /// Where the platform's own subscription doesn't enable what was asked for, this writes the
+    /// Client Characteristic Configuration descriptor afterwards. BlueZ and CoreBluetooth don't
+    /// allow that descriptor to be written and enable notifications when a characteristic supports
+    /// both, so there only [`SubscriptionKind::Notify`] and indications on characteristics which
+    /// don't support notifications are supported.
+    async fn subscribe_with(
+        &self,
+        characteristic: &Characteristic,
+        kind: SubscriptionKind,
+    ) -> Result<()> {
+        kind.check(characteristic)?;
+        self.subscribe(characteristic).await?;
+        if kind == SubscriptionKind::Notify {
+            return Ok(());
+        }
+        let cccd = client_characteristic_configuration(characteristic)?;
+        self.write_descriptor(cccd, &kind.cccd_value().to_le_bytes())
+            .await
+    }
+
+    /// Reads the characteristic's Client Characteristic Configuration descriptor from the device,
+    /// and returns whether it has notifications or indications enabled. Unlike the subscriptions
+    /// this client has asked for, as returned by [`Peripheral::subscriptions`], this is the
+    /// device's own state, so shows if the device didn't accept a subscription or has since
+    /// cleared it, such as after another client wrote to the descriptor.
+    ///
+    /// Returns [`Error::NotSupported`] if the characteristic has no such descriptor. BlueZ
+    /// manages the descriptor itself and doesn't list it among the descriptors of a
+    /// characteristic, so that is always the case on Linux.
+    async fn is_notifying(&self, characteristic: &Characteristic) -> Result<bool> {
+        let cccd = client_characteristic_configuration(characteristic)?;
+        let value = self.read_descriptor(cccd).await?;
+        // The value is little-endian, and the bits which enable notifications and indications are
+        // in its first byte.
+        let enabled = SubscriptionKind::NotifyAndIndicate.cccd_value();
+        Ok(value
+            .first()
+            .is_some_and(|bits| u16::from(*bits) & enabled != 0))
+    }
+
+    /// Subscribes to the characteristic with the given UUID in the service with the given UUID,
+    /// like [`Peripheral::subscribe`], for well-known characteristics which don't need looking up
+    /// first. The device must be connected; its services are discovered only if the
+    /// characteristic isn't among those already known. If the device has several instances of the
+    /// characteristic, the first is used.
+    ///
+    /// Returns [`Error::NoSuchCharacteristic`] if the device has no such characteristic.
+    async fn subscribe_by_uuid(&self, service: Uuid, characteristic: Uuid) -> Result<()> {
+        let find = |characteristics: BTreeSet<Characteristic>| {
+            characteristics
+                .into_iter()
+                .find(|c| c.service_uuid == service && c.uuid == characteristic)
+        };
+        let found = match find(self.characteristics()) {
+            Some(found) => found,
+            None => {
+                self.discover_services().await?;
+                find(self.characteristics()).ok_or(Error::NoSuchCharacteristic)?
+            }
+        };
+        self.subscribe(&found).await
+    }
+
     /// Disables either notify or indicate (depending on support) for the specified characteristic.
     async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()>;
 
+    /// The characteristics which have been subscribed to and not since unsubscribed from through
+    /// this peripheral. These are remembered across disconnects, unlike the subscriptions
+    /// themselves, for [`Peripheral::resubscribe_all`].
+    fn subscriptions(&self) -> Vec<Characteristic> {
+        Vec::new()
+    }
+
+    /// Subscribes again to each of the [`Peripheral::subscriptions`], such as after reconnecting and
+    /// discovering services. A failure for one characteristic doesn't stop the rest being tried.
+    /// Returns the characteristics which couldn't be subscribed to, with why; those no longer in
+    /// [`Peripheral::characteristics`], e.g. because the device's services have changed, fail with
+    /// [`Error::NoSuchCharacteristic`].
+    async fn resubscribe_all(&self) -> Vec<(Characteristic, Error)> {
+        resubscribe_all(self).await
+    }
+
     /// Returns a stream of notifications for characteristic value updates. The stream will receive
     /// a notification when a value notification or indication is received from the device.
     /// The stream will remain valid across connections and can be queried before any connection
     /// is made.
     async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>>;
 
+    /// Returns the notifications like [`Peripheral::notifications`], in a receiver which can also
+    /// be polled without awaiting, for code which can't block on a stream.
+    async fn notification_receiver(&self) -> Result<NotificationReceiver> {
+        Ok(NotificationReceiver::new(self.notifications().await?))
+    }
+
+    /// Enables notifications on the given characteristic and returns a stream of its value
+    /// updates, coalesced so that at most one is delivered per `window`. The first notification
+    /// opens a window; further notifications within it only replace the pending value, and the
+    /// newest value is delivered once the window elapses.
+    ///
+    /// With the `tokio` feature the window is timed by Tokio's timer, so the returned stream must
+    /// be polled from within a Tokio runtime. Without it, btleplug's own timer thread is used.
+    async fn subscribe_coalesced(
+        &self,
+        characteristic: &Characteristic,
+        window: Duration,
+    ) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        // Get the stream before subscribing, so that the first notification can't be missed.
+        let notifications = self.notifications().await?;
+        self.subscribe(characteristic).await?;
+        let characteristic = characteristic.clone();
+        Ok(coalesce_notifications(
+            notifications
+                .filter(move |notification| future::ready(notification.is_from(&characteristic))),
+            window,
+        ))
+    }
+
+    /// Reads the current value of the characteristic and subscribes to it, returning the value
+    /// along with a stream of the values it is updated to afterwards, for gauge-like
+    /// characteristics which are both read and notified. The notifications are listened for before
+    /// reading, so that an update in between isn't missed.
+    ///
+    /// If the characteristic can only be notified or indicated, the value is `None`; if it can only
+    /// be read, the stream ends straight away. Returns [`Error::NotSupported`] if it can be neither.
+    async fn read_and_subscribe(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<(Option<Vec<u8>>, Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>)> {
+        let readable = characteristic.properties.contains(CharPropFlags::READ);
+        let subscribable = characteristic
+            .properties
+            .intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE);
+        if !readable && !subscribable {
+            return Err(Error::NotSupported(format!(
+                "Characteristic {} can't be read or subscribed to",
+                characteristic.uuid
+            )));
+        }
+        let updates: Pin<Box<dyn Stream<Item = Vec<u8>> + Send>> = if subscribable {
+            let notifications = self.notifications().await?;
+            self.subscribe(characteristic).await?;
+            let characteristic = characteristic.clone();
+            Box::pin(notifications.filter_map(move |notification| {
+                future::ready(
+                    notification
+                        .is_from(&characteristic)
+                        .then_some(notification.value),
+                )
+            }))
+        } else {
+            Box::pin(futures::stream::empty())
+        };
+        let value = if readable {
+            Some(self.read(characteristic).await?)
+        } else {
+            None
+        };
+        Ok((value, updates))
+    }
+
     /// Write some data to the descriptor. Returns an error if the write couldn't be sent or (in
     /// the case of a write-with-response) if the device returns an error.
     async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()>;
@@ -295,6 +1867,65 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>>;
 }
 
+/// What an adapter can do, from [`Central::adapter_capabilities`]. More capabilities may be added in
+/// future.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct AdapterCapabilities {
+    /// Whether the adapter can take the peripheral role, advertising and accepting connections as a
+    /// GATT server, rather than only the central role.
+    pub peripheral_role: bool,
+}
+
+bitflags! {
+    /// The Link Layer features an adapter's controller supports, as it reports them with the HCI LE
+    /// Read Local Supported Features command. Returned by [`Central::le_features`]. Bits which
+    /// aren't named here are kept as the controller reported them.
+    #[derive(Default, Debug, PartialEq, Eq, Clone, Copy, Hash)]
+    pub struct LeFeatures: u64 {
+        const ENCRYPTION = 1 << 0;
+        const CONNECTION_PARAMETERS_REQUEST = 1 << 1;
+        const EXTENDED_REJECT_INDICATION = 1 << 2;
+        const PERIPHERAL_INITIATED_FEATURES_EXCHANGE = 1 << 3;
+        const PING = 1 << 4;
+        /// The Data Length Extension.
+        const DATA_PACKET_LENGTH_EXTENSION = 1 << 5;
+        const LL_PRIVACY = 1 << 6;
+        const EXTENDED_SCANNER_FILTER_POLICIES = 1 << 7;
+        const PHY_2M = 1 << 8;
+        const STABLE_MODULATION_INDEX_TX = 1 << 9;
+        const STABLE_MODULATION_INDEX_RX = 1 << 10;
+        const PHY_CODED = 1 << 11;
+        const EXTENDED_ADVERTISING = 1 << 12;
+        const PERIODIC_ADVERTISING = 1 << 13;
+        const CHANNEL_SELECTION_ALGORITHM_2 = 1 << 14;
+        const POWER_CLASS_1 = 1 << 15;
+        const MINIMUM_NUMBER_OF_USED_CHANNELS = 1 << 16;
+        const CONNECTION_CTE_REQUEST = 1 << 17;
+        const CONNECTION_CTE_RESPONSE = 1 << 18;
+        const CONNECTIONLESS_CTE_TRANSMITTER = 1 << 19;
+        const CONNECTIONLESS_CTE_RECEIVER = 1 << 20;
+        const ANTENNA_SWITCHING_DURING_CTE_TRANSMISSION = 1 << 21;
+        const ANTENNA_SWITCHING_DURING_CTE_RECEPTION = 1 << 22;
+        const RECEIVING_CONSTANT_TONE_EXTENSIONS = 1 << 23;
+        const PERIODIC_ADVERTISING_SYNC_TRANSFER_SENDER = 1 << 24;
+        const PERIODIC_ADVERTISING_SYNC_TRANSFER_RECIPIENT = 1 << 25;
+        const SLEEP_CLOCK_ACCURACY_UPDATES = 1 << 26;
+        const REMOTE_PUBLIC_KEY_VALIDATION = 1 << 27;
+        const CONNECTED_ISOCHRONOUS_STREAM_CENTRAL = 1 << 28;
+        const CONNECTED_ISOCHRONOUS_STREAM_PERIPHERAL = 1 << 29;
+        const ISOCHRONOUS_BROADCASTER = 1 << 30;
+        const SYNCHRONIZED_RECEIVER = 1 << 31;
+
+        const _ = !0;
+    }
+}
+
 #[cfg_attr(
     feature = "serde",
     derive(Serialize, Deserialize),
@@ -319,6 +1950,20 @@ pub enum CentralEvent {
     DeviceUpdated(PeripheralId),
     DeviceConnected(PeripheralId),
     DeviceDisconnected(PeripheralId),
+    /// Emitted along with [`CentralEvent::DeviceDisconnected`] when btleplug disconnected the
+    /// device itself, to say why.
+    DeviceDisconnectedWithReason {
+        id: PeripheralId,
+        reason: DisconnectReason,
+    },
+    /// Emitted when a connected device has indicated, with its Service Changed characteristic
+    /// (0x2A05), that its services have changed. The OS subscribes to and handles the indication
+    /// itself; call [`Peripheral::discover_services`] to pick up the changes. Not emitted on Linux,
+    /// where BlueZ updates the services it exposes without saying so.
+    ServicesChanged(PeripheralId),
+    /// Emitted when a peripheral which isn't connected hasn't been seen for longer than the TTL set
+    /// with [`Central::set_peripheral_ttl`], and has been removed from [`Central::peripherals`].
+    DeviceLost(PeripheralId),
     /// Emitted when a Manufacturer Data advertisement has been received from a device
     ManufacturerDataAdvertisement {
         id: PeripheralId,
@@ -329,12 +1974,35 @@ pub enum CentralEvent {
         id: PeripheralId,
         service_data: HashMap<Uuid, Vec<u8>>,
     },
-    /// Emitted when the advertised services for a device has been updated
+    /// Emitted when the advertised services for a device has been updated. As with
+    /// [`PeripheralProperties::advertised_services`], this list may not cover every service the
+    /// device has.
     ServicesAdvertisement {
         id: PeripheralId,
         services: Vec<Uuid>,
     },
     StateUpdate(CentralState),
+    /// Emitted once the adapter has confirmed that it is actually scanning for devices. This may
+    /// arrive some time after [`Central::start_scan`] returns.
+    ScanStarted,
+    /// Emitted when the adapter has stopped scanning, either because [`Central::stop_scan`] was
+    /// called or because the scan was stopped by the OS.
+    ScanStopped,
+}
+
+/// Why btleplug disconnected from a device, as reported by
+/// [`CentralEvent::DeviceDisconnectedWithReason`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum DisconnectReason {
+    /// A read by the liveness probe set with [`Peripheral::set_liveness_probe`] failed or timed
+    /// out.
+    ProbeTimeout,
 }
 
 /// Central is the "client" of BLE. It's able to scan for and establish connections to peripherals.
@@ -347,6 +2015,45 @@ pub trait Central: Send + Sync + Clone {
     /// occur for this Central module. See [`CentralEvent`] for the full set of possible events.
     async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>>;
 
+    /// Waits for the first event for which `predicate` returns true, such as the
+    /// [`CentralEvent::DeviceConnected`] of a particular device. Only events emitted once the
+    /// returned future is first polled are considered, so start waiting (e.g. with `join!`) before
+    /// doing whatever should cause the event.
+    ///
+    /// Returns [`Error::TimedOut`] if no such event arrives within `timeout`.
+    async fn wait_for_event<F>(&self, predicate: F, timeout: Duration) -> Result<CentralEvent>
+    where
+        F: Fn(&CentralEvent) -> bool + Send,
+    {
+        let mut events = self.events().await?;
+        let wait = async move {
+            while let Some(event) = events.next().await {
+                if predicate(&event) {
+                    return Ok(event);
+                }
+            }
+            Err(Error::RuntimeError(
+                "Central event stream ended".to_string(),
+            ))
+        };
+        let timeout_elapsed = runtime::sleep(timeout);
+        pin_mut!(wait, timeout_elapsed);
+        match future::select(wait, timeout_elapsed).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err(Error::TimedOut(timeout)),
+        }
+    }
+
+    /// Returns whether the adapter is scanning right now, as reported by the OS rather than tracked
+    /// from [`CentralEvent::ScanStarted`] and [`CentralEvent::ScanStopped`], so this also tells
+    /// when a scan has ended by itself, such as after the adapter was reset. On BlueZ this is the
+    /// adapter's `Discovering` property, which is also true while another program is scanning.
+    async fn is_scanning(&self) -> Result<bool> {
+        Err(Error::NotSupported(
+            "Querying the scan state is not supported".to_string(),
+        ))
+    }
+
     /// Starts a scan for BLE devices. This scan will generally continue until explicitly stopped,
     /// although this may depend on your Bluetooth adapter. Discovered devices will be announced
     /// to subscribers of `events` and will be available via `peripherals()`.
@@ -356,19 +2063,280 @@ pub trait Central: Send + Sync + Clone {
     /// a filter, but must be able to handle devices, which do not fit into the filter.
     async fn start_scan(&self, filter: ScanFilter) -> Result<()>;
 
+    /// Starts a scan like [`Central::start_scan`], with the given options.
+    ///
+    /// Only BlueZ lets the scan window and interval be chosen, and needs the `CAP_NET_ADMIN`
+    /// capability to do so. They are set as the kernel's defaults for discovery on the adapter, so
+    /// they also apply to any later scans, including other programs', until changed again or the
+    /// adapter is reset. Other platforms check the window and interval but otherwise ignore them.
+    /// [`ScanOptions::rssi_smoothing`] applies everywhere.
+    async fn start_scan_with_options(
+        &self,
+        filter: ScanFilter,
+        options: ScanOptions,
+    ) -> Result<()> {
+        options.check()?;
+        self.start_scan(filter).await
+    }
+
     /// Stops scanning for BLE devices.
     async fn stop_scan(&self) -> Result<()>;
 
+    /// Stops the OS scan started by [`Central::start_scan`] for now, e.g. to leave the radio free
+    /// for a connection-sensitive operation, while keeping its filter for
+    /// [`Central::resume_scan`]. The devices already discovered are kept, so resuming doesn't
+    /// announce them again. This does nothing if no scan is running.
+    ///
+    /// The OS scan stopping and starting again is still reported with
+    /// [`CentralEvent::ScanStopped`] and [`CentralEvent::ScanStarted`]. Calling
+    /// [`Central::start_scan`] or [`Central::stop_scan`] while paused ends the pause.
+    async fn pause_scan(&self) -> Result<()> {
+        Err(Error::NotSupported(
+            "Pausing scans is not supported".to_string(),
+        ))
+    }
+
+    /// Starts the scan paused by [`Central::pause_scan`] again, with the same filter. This does
+    /// nothing if the scan isn't paused.
+    async fn resume_scan(&self) -> Result<()> {
+        Err(Error::NotSupported(
+            "Pausing scans is not supported".to_string(),
+        ))
+    }
+
+    /// Starts a scan for devices matching `filter`, which can run alongside others started the same
+    /// way, e.g. by independent parts of an application looking for different services. The
+    /// adapter scans for everything any of them is looking for, and each subscription is a stream
+    /// of only the events for the devices matching its own filter. It keeps scanning until the last
+    /// subscription has been dropped or stopped.
+    ///
+    /// Subscriptions manage the adapter's scan themselves, so shouldn't be mixed with calls to
+    /// [`Central::start_scan`] and [`Central::stop_scan`].
+    async fn subscribe_scan(&self, _filter: ScanFilter) -> Result<ScanSubscription> {
+        Err(Error::NotSupported(
+            "Scan subscriptions are not supported".to_string(),
+        ))
+    }
+
+    /// Scans for advertisements from devices matching `filter`, as a stream of reports, for apps
+    /// such as beacon monitors which never connect to anything. The scan continues until the
+    /// stream is dropped.
+    ///
+    /// On Windows this uses a scan of its own, and doesn't add the devices seen to
+    /// [`Central::peripherals`] or emit any [`CentralEvent`]s, so there is no per-device state to
+    /// maintain no matter how many devices are advertising. On other platforms the OS keeps track
+    /// of every device seen anyway, so this is built on [`Central::subscribe_scan`], with each
+    /// report reflecting everything the device has advertised so far.
+    async fn observe(
+        &self,
+        filter: ScanFilter,
+    ) -> Result<Pin<Box<dyn Stream<Item = Advertisement> + Send>>>
+    where
+        Self: 'static,
+    {
+        let scan = self.subscribe_scan(filter).await?;
+        let central = self.clone();
+        Ok(Box::pin(scan.filter_map(move |event| {
+            let central = central.clone();
+            async move {
+                let id = match event {
+                    CentralEvent::DeviceDiscovered(id) | CentralEvent::DeviceUpdated(id) => id,
+                    _ => return None,
+                };
+                let properties = central
+                    .peripheral(&id)
+                    .await
+                    .ok()?
+                    .properties()
+                    .await
+                    .ok()??;
+                Some(Advertisement::from_properties(id, properties))
+            }
+        })))
+    }
+
+    /// Scans for advertisements from devices matching `filter`, as a stream of the signal strength
+    /// each one was received with, for apps such as ranging which track how near devices are. Only
+    /// the devices in `devices` are sampled, or every device if it is empty. Each sample also has
+    /// the mean of the latest `window` samples from its device, to smooth out the jitter between
+    /// one advertisement and the next; a window of 0 or 1 does no smoothing. Advertisements
+    /// received without a signal strength are skipped. The signal strength is read from the
+    /// device's properties as each event is taken from the stream, so a stream which falls behind
+    /// will repeat the latest reading. The scan continues until the stream is dropped.
+    ///
+    /// Like [`Central::observe`], this is built on [`Central::subscribe_scan`]. All of the
+    /// platforms are asked to report every advertisement, rather than only those which change
+    /// something, so a device is sampled as often as it advertises.
+    async fn rssi_samples(
+        &self,
+        filter: ScanFilter,
+        devices: Vec<PeripheralId>,
+        window: usize,
+    ) -> Result<Pin<Box<dyn Stream<Item = RssiSample> + Send>>>
+    where
+        Self: 'static,
+    {
+        let scan = self.subscribe_scan(filter).await?;
+        let central = self.clone();
+        let devices: HashSet<_> = devices.into_iter().collect();
+        let mut history = RssiHistory::new(window);
+        Ok(Box::pin(
+            scan.filter_map(move |event| {
+                let id = match event {
+                    CentralEvent::DeviceDiscovered(id) | CentralEvent::DeviceUpdated(id)
+                        if devices.is_empty() || devices.contains(&id) =>
+                    {
+                        Some(id)
+                    }
+                    _ => None,
+                };
+                let central = central.clone();
+                async move {
+                    let id = id?;
+                    let properties = central
+                        .peripheral(&id)
+                        .await
+                        .ok()?
+                        .properties()
+                        .await
+                        .ok()??;
+                    let received = properties.last_seen.unwrap_or_else(Instant::now);
+                    Some((id, properties.rssi?, received))
+                }
+            })
+            .map(move |(id, rssi, received)| RssiSample {
+                smoothed_rssi: history.add(&id, rssi),
+                id,
+                rssi,
+                received,
+            }),
+        ))
+    }
+
+    /// Returns a stream of the devices found by the scan, yielding each one only the first time it
+    /// is seen, with its properties at that time. Later [`CentralEvent::DeviceUpdated`] events for
+    /// it are ignored. This starts over whenever a scan starts, as reported by
+    /// [`CentralEvent::ScanStarted`], so each device is yielded again once per scan; that includes
+    /// a scan resumed with [`Central::resume_scan`]. A device the OS already knew of from an
+    /// earlier scan counts as found the first time it is updated in the new one.
+    async fn discoveries(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = (PeripheralId, PeripheralProperties)> + Send>>>
+    where
+        Self: 'static,
+    {
+        let events = self.events().await?;
+        let central = self.clone();
+        let mut seen = HashSet::new();
+        Ok(Box::pin(events.filter_map(move |event| {
+            let id = match event {
+                CentralEvent::ScanStarted => {
+                    seen.clear();
+                    None
+                }
+                CentralEvent::DeviceDiscovered(id) | CentralEvent::DeviceUpdated(id) => {
+                    seen.insert(id.clone()).then_some(id)
+                }
+                _ => None,
+            };
+            let central = central.clone();
+            async move {
+                let id = id?;
+                let properties = central
+                    .peripheral(&id)
+                    .await
+                    .ok()?
+                    .properties()
+                    .await
+                    .ok()??;
+                Some((id, properties))
+            }
+        })))
+    }
+
     /// Returns the list of [`Peripheral`]s that have been discovered so far. Note that this list
     /// may contain peripherals that are no longer available.
     async fn peripherals(&self) -> Result<Vec<Self::Peripheral>>;
 
+    /// Returns a single stream of the notifications from every peripheral connected to this
+    /// adapter, each tagged with the ID of the peripheral it came from. A peripheral's
+    /// notifications are included from when it connects until it disconnects, for whichever of its
+    /// characteristics have been subscribed to. Dropping the stream doesn't unsubscribe from
+    /// anything.
+    async fn all_notifications(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = (PeripheralId, ValueNotification)> + Send>>>
+    where
+        Self: 'static,
+    {
+        // Get the events first, so that no connection can be missed.
+        let events = self.events().await?;
+        let connected = self.connected().await?;
+        Ok(all_notifications(self.clone(), events, connected))
+    }
+
+    /// Returns the properties of every peripheral discovered so far, as [`Central::peripherals`]
+    /// would list them, in one call. These come from what the adapter has already recorded of
+    /// each device's advertisements, rather than from asking the OS about each device in turn; on
+    /// BlueZ all devices are fetched with a single D-Bus call.
+    async fn peripherals_snapshot(&self) -> Result<Vec<(PeripheralId, PeripheralProperties)>> {
+        let mut snapshot = Vec::new();
+        for peripheral in self.peripherals().await? {
+            if let Some(properties) = peripheral.properties().await? {
+                snapshot.push((peripheral.id(), properties));
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Returns the peripherals this adapter is currently connected to. This is answered from the
+    /// connection state btleplug already tracks, without querying each device. On Linux, BlueZ
+    /// tracks connections for the whole system, so this includes devices connected by other
+    /// programs.
+    async fn connected(&self) -> Result<Vec<Self::Peripheral>> {
+        let mut connected = Vec::new();
+        for peripheral in self.peripherals().await? {
+            if peripheral.is_connected().await? {
+                connected.push(peripheral);
+            }
+        }
+        Ok(connected)
+    }
+
+    /// Returns the devices the OS is bonded (paired) with, whether or not they are in range, so
+    /// that an app can offer to reconnect to them without scanning first. Any not already in
+    /// [`Central::peripherals`] are added to it, without a [`CentralEvent::DeviceDiscovered`].
+    ///
+    /// macOS and iOS keep no list of bonded devices, so this returns [`Error::NotSupported`] there.
+    async fn bonded_devices(&self) -> Result<Vec<Self::Peripheral>> {
+        Err(Error::NotSupported(
+            "Listing bonded devices is not supported on this platform".to_string(),
+        ))
+    }
+
     /// Returns a particular [`Peripheral`] by its address if it has been discovered.
     async fn peripheral(&self, id: &PeripheralId) -> Result<Self::Peripheral>;
 
     /// Add a [`Peripheral`] from a MAC address without a scan result. Not supported on all Bluetooth systems.
     async fn add_peripheral(&self, address: &PeripheralId) -> Result<Self::Peripheral>;
 
+    /// Returns whether the OS still knows the peripheral with the given ID, e.g. from an earlier
+    /// scan, connection or pairing, even if it isn't in range now. A device which isn't known has
+    /// been forgotten, such as by being unpaired or removed, and must be discovered again before it
+    /// can be connected to.
+    ///
+    /// On BlueZ this is whether the device object exists. CoreBluetooth is asked with
+    /// `retrievePeripheralsWithIdentifiers:`, and Windows with whether the address can be resolved
+    /// to a device, so both also know of devices only seen before btleplug started. Android only
+    /// knows the peripherals discovered since the adapter was created.
+    async fn is_known(&self, id: &PeripheralId) -> Result<bool> {
+        match self.peripheral(id).await {
+            Ok(_) => Ok(true),
+            Err(Error::DeviceNotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Get information about the Bluetooth adapter being used, such as the model or type.
     ///
     /// The details of this are platform-specific andyou should not attempt to parse it, but it may
@@ -377,6 +2345,210 @@ pub trait Central: Send + Sync + Clone {
 
     /// Get information about the Bluetooth adapter state.
     async fn adapter_state(&self) -> Result<CentralState>;
+
+    /// Returns what the adapter is capable of. Capabilities the platform doesn't report are
+    /// `false`.
+    ///
+    /// On Linux the peripheral role comes from the adapter's `Roles` property, which needs BlueZ
+    /// 5.56 or later. On Windows it is for the default adapter, regardless of which radio this is.
+    /// Android reports whether the adapter supports advertising, and macOS and iOS always support
+    /// the peripheral role.
+    async fn adapter_capabilities(&self) -> Result<AdapterCapabilities> {
+        Ok(AdapterCapabilities::default())
+    }
+
+    /// Returns the Link Layer features the adapter's controller supports, such as the 2M PHY or
+    /// extended advertising, for code which needs to know exactly what the controller can do.
+    ///
+    /// On Linux this sends the HCI LE Read Local Supported Features command, which needs the
+    /// `CAP_NET_RAW` capability. Windows only tells whether the default adapter supports extended
+    /// advertising, so that is the only feature set there. CoreBluetooth and Android don't report
+    /// controller features, so return [`Error::NotSupported`].
+    async fn le_features(&self) -> Result<LeFeatures> {
+        Err(Error::NotSupported(
+            "Reading the controller's LE features is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Synchronizes with the periodic advertising train with the given Advertising SID from the
+    /// device with the given address, and returns a stream of the data of each periodic
+    /// advertisement received, reassembled from the fragments it arrives in. The stream ends if
+    /// synchronization is lost, and dropping it terminates the sync. A scan must be running for the
+    /// controller to find the train. Only available with the `le5` feature.
+    ///
+    /// Only supported on Linux, where this sends HCI LE Periodic Advertising Create Sync, which
+    /// needs the `CAP_NET_RAW` capability and a controller with
+    /// [`LeFeatures::PERIODIC_ADVERTISING`]. The device must have been seen by a scan, so that its
+    /// address type is known, or this returns [`Error::DeviceNotFound`]. Some kernels terminate
+    /// periodic advertising syncs which no ISO socket was waiting for, in which case the stream
+    /// ends straight away. Other platforms return [`Error::NotSupported`].
+    #[cfg(feature = "le5")]
+    async fn sync_periodic_advertising(
+        &self,
+        _address: BDAddr,
+        _sid: u8,
+    ) -> Result<Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>> {
+        Err(Error::NotSupported(
+            "Periodic advertising sync is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Set the random static address the adapter uses for LE, so that it no longer uses its public
+    /// address. The address must be a valid random static address, see
+    /// [`BDAddr::is_random_static`].
+    ///
+    /// Only supported on Linux, where the adapter must be powered off and the process needs the
+    /// `CAP_NET_ADMIN` capability.
+    async fn set_random_address(&self, _address: BDAddr) -> Result<()> {
+        Err(Error::NotSupported(
+            "Setting the adapter address is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Returns whether the adapter is discoverable, so that other devices scanning for it can find
+    /// it. Only supported on Linux.
+    async fn is_discoverable(&self) -> Result<bool> {
+        Err(Error::NotSupported(
+            "Reading whether the adapter is discoverable is not supported on this platform"
+                .to_string(),
+        ))
+    }
+
+    /// Makes the adapter discoverable or not. With a `timeout`, the adapter stops being
+    /// discoverable by itself once it elapses, rounded up to a whole number of seconds; without
+    /// one it stays discoverable until this is called again.
+    ///
+    /// Only supported on Linux, where this sets BlueZ's `Discoverable` and `DiscoverableTimeout`
+    /// adapter properties, and the adapter must be powered on. Windows and macOS don't let apps
+    /// make the adapter discoverable; that needs the peripheral role, which btleplug doesn't
+    /// implement yet.
+    async fn set_discoverable(
+        &self,
+        _discoverable: bool,
+        _timeout: Option<Duration>,
+    ) -> Result<()> {
+        Err(Error::NotSupported(
+            "Making the adapter discoverable is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Returns whether the adapter accepts incoming connections. Only supported on Linux.
+    async fn is_connectable(&self) -> Result<bool> {
+        Err(Error::NotSupported(
+            "Reading whether the adapter is connectable is not supported on this platform"
+                .to_string(),
+        ))
+    }
+
+    /// Sets whether the adapter accepts incoming connections. Making the adapter non-connectable
+    /// also makes it non-discoverable.
+    ///
+    /// Only supported on Linux, where this sets BlueZ's `Connectable` adapter property. That needs
+    /// BlueZ 5.66 or later.
+    async fn set_connectable(&self, _connectable: bool) -> Result<()> {
+        Err(Error::NotSupported(
+            "Making the adapter connectable is not supported on this platform".to_string(),
+        ))
+    }
+
+    /// Sets how many times a read, write, subscribe or unsubscribe on a peripheral of this central
+    /// is retried, after a short delay, when it fails with a transient error. Errors which won't go
+    /// away by themselves are never retried. Defaults to 3; set it to 0 to disable retries.
+    ///
+    /// Only BlueZ reports such errors, when another operation on the device is still in progress.
+    /// Other platforms queue operations themselves, so this does nothing there.
+    fn set_max_retries(&self, _retries: u32) {}
+
+    /// Sets how long a peripheral may go without advertising before it is forgotten, or `None` (the
+    /// default) to keep peripherals until they disconnect. Peripherals which are connected are never
+    /// forgotten. Forgotten peripherals are no longer returned by [`Central::peripherals`], and
+    /// [`CentralEvent::DeviceLost`] is emitted for each; they are rediscovered if they advertise
+    /// again. This is based on [`PeripheralProperties::last_seen`], checked every second or so.
+    ///
+    /// BlueZ already forgets devices it hasn't seen for a while by itself (see `TemporaryTimeout` in
+    /// its `main.conf`), so this does nothing on Linux.
+    fn set_peripheral_ttl(&self, _ttl: Option<Duration>) {}
+
+    /// Sets how many peripherals may be kept at once, or `None` (the default) for no limit, so that
+    /// a long-running scan in a busy place uses a bounded amount of memory. Whenever there are
+    /// more, the least recently seen are forgotten, as they would be by
+    /// [`Central::set_peripheral_ttl`]: [`CentralEvent::DeviceLost`] is emitted for each, and they
+    /// are rediscovered if they advertise again. Peripherals which are connected are never
+    /// forgotten, so there may be more than `max` if that many are connected.
+    ///
+    /// As with the TTL, BlueZ keeps its own record of the devices it has seen, so this does nothing
+    /// on Linux.
+    fn set_max_peripherals(&self, _max: Option<usize>) {}
+
+    /// Sets how many connection attempts to peripherals of this central may be in progress at once.
+    /// Any further calls to [`Peripheral::connect`] or [`Peripheral::connect_with_options`] wait
+    /// for an earlier attempt to finish, rather than failing because the controller can't
+    /// establish that many connections at a time. Connections which have already been established
+    /// don't count towards the limit. Set it to 0 to remove the limit.
+    ///
+    /// Defaults to 1 on Linux and Android, which fail connection attempts made while another is in
+    /// progress, and to 4 on other platforms.
+    fn set_max_concurrent_connects(&self, _max: usize) {}
+
+    /// Scans for devices advertising the given service and connects to one, returning it once it
+    /// is connected. If several devices are found within about half a second of each other, the
+    /// one with the strongest signal is picked. If the adapter isn't already scanning, a scan is
+    /// started for the purpose and stopped before connecting, and if this fails, times out or is
+    /// cancelled. A failure or time-out only returns once that scan has stopped. A scan which was
+    /// already running, e.g. from [`Central::start_scan`], is used as it is and left running, so
+    /// its filter must let the devices advertising `service` through.
+    ///
+    /// Returns [`Error::TimedOut`] if no device is found and connected to within `timeout`.
+    async fn connect_first_with_service(
+        &self,
+        service: Uuid,
+        timeout: Duration,
+    ) -> Result<Self::Peripheral>
+    where
+        Self: 'static,
+    {
+        connect_first_with_service(self, service, timeout).await
+    }
+
+    /// Scans with the given filter until a peripheral whose properties satisfy `predicate` is
+    /// found, and returns it without connecting. The predicate is checked against the peripherals
+    /// already known, and again with the latest properties each time an advertisement is received,
+    /// so it can match on anything advertised, like part of the local name or a byte of
+    /// manufacturer data. Scanning is stopped before this returns, including when it fails or
+    /// times out, and in the background if it is cancelled.
+    ///
+    /// Returns [`Error::TimedOut`] if no matching device is found within `timeout`.
+    async fn scan_until<F>(
+        &self,
+        filter: ScanFilter,
+        predicate: F,
+        timeout: Duration,
+    ) -> Result<Self::Peripheral>
+    where
+        F: Fn(&PeripheralProperties) -> bool + Send + Sync,
+        Self: 'static,
+    {
+        scan_until(self, filter, predicate, timeout).await
+    }
+
+    /// Waits for the next advertisement from the given peripheral and returns its properties as of
+    /// then, for when a device is known to have changed what it advertises, e.g. a beacon which has
+    /// just been reconfigured over GATT. If a scan is already running, it is left alone and its
+    /// advertisements are waited for; otherwise a scan is started for the purpose, and stopped
+    /// before this returns, even if it times out, or in the background if it is cancelled. None of
+    /// the platforms can filter a scan by address, so such a scan is for everything nearby.
+    ///
+    /// Returns [`Error::TimedOut`] if the device doesn't advertise within `timeout`.
+    async fn refresh_properties(
+        &self,
+        id: &PeripheralId,
+        timeout: Duration,
+    ) -> Result<PeripheralProperties>
+    where
+        Self: 'static,
+    {
+        refresh_properties(self, id, timeout).await
+    }
 }
 
 /// The Manager is the entry point to the library, providing access to all the Bluetooth adapters on
@@ -404,4 +2576,18 @@ pub trait Manager {
 
     /// Get a list of all Bluetooth adapters on the system. Each adapter implements [`Central`].
     async fn adapters(&self) -> Result<Vec<Self::Adapter>>;
+
+    /// Sets a callback to receive structured [`TraceEvent`]s for key operations, like connecting
+    /// and service discovery, alongside the output btleplug sends to the `log` crate. Like the
+    /// `log` facade, there is one callback for the whole process, which replaces any set before.
+    ///
+    /// The callback is called from whichever thread the operation is running on, so should return
+    /// quickly.
+    fn set_trace_callback<F>(&self, callback: F)
+    where
+        F: Fn(TraceEvent) + Send + Sync + 'static,
+        Self: Sized,
+    {
+        trace::set_callback(Arc::new(callback));
+    }
 }
@@ -0,0 +1,44 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{Characteristic, Peripheral};
+use crate::Result;
+
+/// Writes to one or more characteristics of a peripheral which the device commits all together or
+/// not at all, as a Reliable Write transaction of Prepare Write requests followed by an Execute
+/// Write. Created by [`Peripheral::reliable_write`].
+#[must_use = "nothing is written until the transaction is executed"]
+pub struct ReliableWriteTransaction<'a, P> {
+    peripheral: &'a P,
+    writes: Vec<(Characteristic, Vec<u8>)>,
+}
+
+impl<'a, P: Peripheral> ReliableWriteTransaction<'a, P> {
+    pub(crate) fn new(peripheral: &'a P) -> Self {
+        ReliableWriteTransaction {
+            peripheral,
+            writes: Vec::new(),
+        }
+    }
+
+    /// Adds a write of `data` to `characteristic` to the transaction. Writes are made in the order
+    /// they are added.
+    pub fn write(mut self, characteristic: &Characteristic, data: &[u8]) -> Self {
+        self.writes.push((characteristic.clone(), data.to_vec()));
+        self
+    }
+
+    /// Makes the writes and commits them. If any of them fails, the device is told to cancel the
+    /// transaction, so none of them take effect, and the error is returned. A transaction with no
+    /// writes does nothing.
+    pub async fn execute(self) -> Result<()> {
+        if self.writes.is_empty() {
+            return Ok(());
+        }
+        self.peripheral.execute_reliable_write(&self.writes).await
+    }
+}
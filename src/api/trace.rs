@@ -0,0 +1,95 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Structured diagnostics, for routing what btleplug is doing into telemetry without relying on
+//! the `log` facade. See [`Manager::set_trace_callback`](crate::api::Manager::set_trace_callback).
+
+use super::Peripheral;
+use crate::{platform::PeripheralId, Result};
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+/// An event reported to the callback set with
+/// [`Manager::set_trace_callback`](crate::api::Manager::set_trace_callback).
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TraceEvent {
+    /// Started connecting to a peripheral.
+    ConnectStarted { id: PeripheralId },
+    /// Finished connecting to a peripheral. `error` describes why the connection failed, if it
+    /// did.
+    ConnectFinished {
+        id: PeripheralId,
+        error: Option<String>,
+    },
+    /// Finished discovering the services of a peripheral, with either the number of services found
+    /// or a description of why discovery failed.
+    ServicesDiscovered {
+        id: PeripheralId,
+        result: std::result::Result<usize, String>,
+    },
+    /// A notification or indication was delivered for a characteristic of a peripheral. On Linux
+    /// and Android this is reported for each stream returned by [`Peripheral::notifications`]
+    /// which receives it.
+    NotificationDelivered {
+        id: PeripheralId,
+        characteristic: Uuid,
+    },
+    /// A read by the liveness probe set with [`Peripheral::set_liveness_probe`] failed or timed
+    /// out, so the peripheral is being disconnected.
+    LivenessProbeFailed { id: PeripheralId, error: String },
+}
+
+type TraceCallback = Arc<dyn Fn(TraceEvent) + Send + Sync>;
+
+static CALLBACK: RwLock<Option<TraceCallback>> = RwLock::new(None);
+
+pub(crate) fn set_callback(callback: TraceCallback) {
+    *CALLBACK.write().unwrap() = Some(callback);
+}
+
+/// Reports an event to the callback, if one is set. The event is only built if it will be used.
+pub(crate) fn emit(event: impl FnOnce() -> TraceEvent) {
+    // Don't hold the lock while calling out, in case the callback sets a new callback.
+    let callback = CALLBACK.read().unwrap().clone();
+    if let Some(callback) = callback {
+        callback(event());
+    }
+}
+
+/// Reports the start and outcome of connecting to the given peripheral.
+pub(crate) async fn traced_connect<P: Peripheral>(
+    peripheral: &P,
+    connect: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    emit(|| TraceEvent::ConnectStarted {
+        id: peripheral.id(),
+    });
+    let result = connect.await;
+    emit(|| TraceEvent::ConnectFinished {
+        id: peripheral.id(),
+        error: result.as_ref().err().map(ToString::to_string),
+    });
+    result
+}
+
+/// Reports the outcome of discovering the services of the given peripheral.
+pub(crate) async fn traced_discover_services<P: Peripheral>(
+    peripheral: &P,
+    discover_services: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    let result = discover_services.await;
+    emit(|| TraceEvent::ServicesDiscovered {
+        id: peripheral.id(),
+        result: match &result {
+            Ok(()) => Ok(peripheral.services().len()),
+            Err(e) => Err(e.to_string()),
+        },
+    });
+    result
+}
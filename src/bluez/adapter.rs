@@ -1,24 +1,293 @@
-use super::peripheral::{Peripheral, PeripheralId};
-use crate::api::{Central, CentralEvent, CentralState, ScanFilter};
+use super::{
+    mgmt,
+    peripheral::{properties_from_info, Peripheral, PeripheralId},
+};
+use crate::api::{
+    AdapterCapabilities, BDAddr, Central, CentralEvent, CentralState, DisconnectReason, LeFeatures,
+    PeripheralProperties, ScanFilter, ScanOptions, ScanSubscription,
+};
+use crate::common::{
+    connect_limit::ConnectLimit,
+    rssi_history::{RssiAverage, RssiSmoothing},
+    runtime,
+    scan_state::ScanState,
+    scan_subscriptions::{subscribe_scan, ScanSubscriptions},
+};
 use crate::{Error, Result};
 use async_trait::async_trait;
 use bluez_async::{
     AdapterEvent, AdapterId, BluetoothError, BluetoothEvent, BluetoothSession, DeviceEvent,
-    DiscoveryFilter, Transport,
+    DeviceId, DeviceInfo, DiscoveryFilter, Transport,
 };
+use futures::future::ready;
 use futures::stream::{self, Stream, StreamExt};
+use log::debug;
+use std::collections::{HashMap, HashSet};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::BroadcastStream;
 
 /// Implementation of [api::Central](crate::api::Central).
 #[derive(Clone, Debug)]
 pub struct Adapter {
     session: BluetoothSession,
     adapter: AdapterId,
+    /// Devices which peripherals have found to have been removed by BlueZ. There's no D-Bus event
+    /// for these which we can turn into a `DeviceDisconnected`.
+    removed_devices: broadcast::Sender<DeviceId>,
+    /// Why peripherals have disconnected their devices themselves, to go with the
+    /// `DeviceDisconnected` from D-Bus.
+    disconnect_reasons: broadcast::Sender<(DeviceId, DisconnectReason)>,
+    /// Shared with all peripherals of the adapter.
+    max_retries: Arc<AtomicU32>,
+    /// Shared with all peripherals of the adapter.
+    connect_limit: Arc<ConnectLimit>,
+    /// When each device was last advertised. BlueZ doesn't keep track of this, so it is recorded
+    /// from D-Bus events once a scan has been started.
+    last_seen: Arc<Mutex<HashMap<DeviceId, Seen>>>,
+    tracking_last_seen: Arc<AtomicBool>,
+    /// The devices which are connected, so that peripherals can refuse GATT operations without a
+    /// D-Bus call. Kept up to date from D-Bus events once a peripheral has been got.
+    connected: Arc<Mutex<HashSet<DeviceId>>>,
+    tracking_connections: Arc<AtomicBool>,
+    /// The Advertising SID each device's extended advertisements were last seen with, which only
+    /// raw HCI events carry. Only recorded with the `le5` feature.
+    advertising_sids: Arc<Mutex<HashMap<BDAddr, u8>>>,
+    rssi_smoothing: Arc<RssiSmoothing>,
+    scan_state: Arc<ScanState>,
+    scans: Arc<ScanSubscriptions>,
 }
 
+/// How many times operations are retried by default if BlueZ reports another one is in progress.
+const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// How many devices' advertisements are remembered before the least recently seen are forgotten.
+/// bluez-async has no event for BlueZ removing a device, so without a bound a long scan would
+/// remember every random address it ever saw.
+const MAX_SEEN_DEVICES: usize = 1024;
+
+/// What has been recorded from the advertisements of a device.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Seen {
+    pub at: Instant,
+    /// Only changes of the signal strength are reported by BlueZ, so this averages those.
+    pub rssi: RssiAverage,
+}
+
+/// The D-Bus interface of BlueZ adapters, for the properties bluez-async doesn't handle.
+const ADAPTER_INTERFACE: &str = "org.bluez.Adapter1";
+
 impl Adapter {
-    pub(crate) fn new(session: BluetoothSession, adapter: AdapterId) -> Self {
-        Self { session, adapter }
+    pub(crate) fn new(
+        session: BluetoothSession,
+        adapter: AdapterId,
+        removed_devices: broadcast::Sender<DeviceId>,
+        disconnect_reasons: broadcast::Sender<(DeviceId, DisconnectReason)>,
+    ) -> Self {
+        Self {
+            session,
+            adapter,
+            removed_devices,
+            disconnect_reasons,
+            max_retries: Arc::new(AtomicU32::new(DEFAULT_MAX_RETRIES)),
+            connect_limit: Default::default(),
+            last_seen: Default::default(),
+            tracking_last_seen: Default::default(),
+            connected: Default::default(),
+            tracking_connections: Default::default(),
+            advertising_sids: Default::default(),
+            rssi_smoothing: Default::default(),
+            scan_state: Default::default(),
+            scans: Default::default(),
+        }
+    }
+
+    /// Makes a peripheral from freshly fetched information about the device. Connection tracking
+    /// must have been started before it was fetched, so that no change can be missed in between.
+    fn peripheral_from_info(&self, device: DeviceInfo) -> Peripheral {
+        {
+            let mut connected = self.connected.lock().unwrap();
+            if device.connected {
+                connected.insert(device.id.clone());
+            } else {
+                connected.remove(&device.id);
+            }
+        }
+        Peripheral::new(
+            self.session.clone(),
+            device,
+            self.removed_devices.clone(),
+            self.disconnect_reasons.clone(),
+            self.max_retries.clone(),
+            self.connect_limit.clone(),
+            self.last_seen.clone(),
+            self.connected.clone(),
+            self.advertising_sids.clone(),
+        )
+    }
+
+    /// Starts BlueZ discovering devices with the given filter.
+    async fn start_discovery(&self, filter: ScanFilter) -> Result<()> {
+        let filter = DiscoveryFilter {
+            service_uuids: filter.services,
+            rssi_threshold: filter.min_rssi,
+            pattern: filter.name_prefix,
+            duplicate_data: Some(true),
+            transport: Some(Transport::Auto),
+            ..Default::default()
+        };
+        self.session
+            .start_discovery_on_adapter_with_filter(&self.adapter, &filter)
+            .await?;
+        Ok(())
+    }
+
+    /// Starts recording when devices are advertised, if that isn't already happening.
+    async fn track_last_seen(&self) -> Result<()> {
+        if self.tracking_last_seen.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let events = match self.session.adapter_event_stream(&self.adapter).await {
+            Ok(events) => events,
+            Err(e) => {
+                self.tracking_last_seen.store(false, Ordering::SeqCst);
+                return Err(e.into());
+            }
+        };
+        runtime::spawn(record_last_seen(
+            events,
+            self.removed_devices.subscribe(),
+            Arc::downgrade(&self.last_seen),
+            self.rssi_smoothing.clone(),
+        ));
+        // Opening a raw HCI socket may need more privileges than the rest of btleplug, and SIDs
+        // are a nice-to-have, so scanning goes ahead without them.
+        #[cfg(feature = "le5")]
+        if let Err(e) =
+            mgmt::watch_advertising_sids(&self.adapter, Arc::downgrade(&self.advertising_sids))
+        {
+            debug!("Not recording advertising SIDs: {:?}", e);
+        }
+        Ok(())
+    }
+
+    /// Starts keeping track of which devices are connected, if that isn't already happening.
+    async fn track_connections(&self) -> Result<()> {
+        if self.tracking_connections.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let events = match self.session.adapter_event_stream(&self.adapter).await {
+            Ok(events) => events,
+            Err(e) => {
+                self.tracking_connections.store(false, Ordering::SeqCst);
+                return Err(e.into());
+            }
+        };
+        runtime::spawn(record_connections(events, Arc::downgrade(&self.connected)));
+        Ok(())
+    }
+
+    /// Gets one of the adapter's D-Bus properties which bluez-async doesn't expose.
+    async fn get_adapter_property<T>(&self, property: &'static str) -> Result<T>
+    where
+        T: for<'b> ::dbus::arg::Get<'b> + Send + 'static,
+    {
+        super::dbus::get_property(self.adapter.clone().into(), ADAPTER_INTERFACE, property).await
+    }
+
+    /// Sets one of the adapter's D-Bus properties which bluez-async can't set.
+    async fn set_adapter_property<T>(&self, property: &'static str, value: T) -> Result<()>
+    where
+        T: ::dbus::arg::Arg + ::dbus::arg::Append + Send + 'static,
+    {
+        super::dbus::set_property(
+            self.adapter.clone().into(),
+            ADAPTER_INTERFACE,
+            property,
+            value,
+        )
+        .await
+    }
+}
+
+/// Records when each device was last advertised, and averages its signal strength, until the
+/// adapter is dropped. Devices which peripherals find to have been removed are forgotten.
+async fn record_last_seen(
+    events: impl Stream<Item = BluetoothEvent> + Unpin,
+    removed_devices: broadcast::Receiver<DeviceId>,
+    last_seen: Weak<Mutex<HashMap<DeviceId, Seen>>>,
+    rssi_smoothing: Arc<RssiSmoothing>,
+) {
+    let removed_devices =
+        BroadcastStream::new(removed_devices).filter_map(|device| ready(device.ok()));
+    let mut events = stream::select(events.map(Ok), removed_devices.map(Err));
+    while let Some(event) = events.next().await {
+        let Some(last_seen) = last_seen.upgrade() else {
+            break;
+        };
+        let event = match event {
+            Ok(event) => event,
+            Err(removed) => {
+                last_seen.lock().unwrap().remove(&removed);
+                continue;
+            }
+        };
+        if let BluetoothEvent::Device {
+            id,
+            event:
+                event @ (DeviceEvent::Discovered
+                | DeviceEvent::Rssi { .. }
+                | DeviceEvent::ManufacturerData { .. }
+                | DeviceEvent::ServiceData { .. }
+                | DeviceEvent::Services { .. }),
+        } = event
+        {
+            let mut last_seen = last_seen.lock().unwrap();
+            if !last_seen.contains_key(&id) && last_seen.len() >= MAX_SEEN_DEVICES {
+                let oldest = last_seen
+                    .iter()
+                    .min_by_key(|(_, seen)| seen.at)
+                    .map(|(id, _)| id.clone());
+                if let Some(oldest) = oldest {
+                    last_seen.remove(&oldest);
+                }
+            }
+            let seen = last_seen.entry(id).or_insert(Seen {
+                at: Instant::now(),
+                rssi: RssiAverage::default(),
+            });
+            seen.at = Instant::now();
+            if let DeviceEvent::Rssi { rssi } = event {
+                seen.rssi.add(rssi, rssi_smoothing.weight());
+            }
+        }
+    }
+}
+
+/// Records which devices connect and disconnect, until the adapter is dropped.
+async fn record_connections(
+    mut events: impl Stream<Item = BluetoothEvent> + Unpin,
+    connected: Weak<Mutex<HashSet<DeviceId>>>,
+) {
+    while let Some(event) = events.next().await {
+        let Some(connected) = connected.upgrade() else {
+            break;
+        };
+        if let BluetoothEvent::Device {
+            id,
+            event: DeviceEvent::Connected { connected: now },
+        } = event
+        {
+            let mut connected = connected.lock().unwrap();
+            if now {
+                connected.insert(id);
+            } else {
+                connected.remove(&id);
+            }
+        }
     }
 }
 
@@ -38,6 +307,8 @@ impl Central for Adapter {
         // Get the stream first, on the basis that it's better to have a duplicate DeviceDiscovered
         // event than to miss one. It's unlikely to happen in any case.
         let events = self.session.adapter_event_stream(&self.adapter).await?;
+        let removed_devices = BroadcastStream::new(self.removed_devices.subscribe());
+        let disconnect_reasons = BroadcastStream::new(self.disconnect_reasons.subscribe());
 
         // Synthesise `DeviceDiscovered' and `DeviceConnected` events for existing peripherals.
         let devices = self.session.get_devices().await?;
@@ -59,20 +330,39 @@ impl Central for Adapter {
         let adapter_id = self.adapter.clone();
         let events = events
             .filter_map(move |event| central_event(event, session.clone(), adapter_id.clone()));
+        let adapter_id = self.adapter.clone();
+        let removed_devices = removed_devices.filter_map(move |device| {
+            ready(match device {
+                Ok(device) if device.adapter() == adapter_id => {
+                    Some(CentralEvent::DeviceDisconnected(device.into()))
+                }
+                _ => None,
+            })
+        });
+
+        let adapter_id = self.adapter.clone();
+        let disconnect_reasons = disconnect_reasons.filter_map(move |disconnect| {
+            ready(match disconnect {
+                Ok((device, reason)) if device.adapter() == adapter_id => {
+                    Some(CentralEvent::DeviceDisconnectedWithReason {
+                        id: device.into(),
+                        reason,
+                    })
+                }
+                _ => None,
+            })
+        });
 
-        Ok(Box::pin(initial_events.chain(events)))
+        Ok(Box::pin(initial_events.chain(stream::select(
+            events,
+            stream::select(removed_devices, disconnect_reasons),
+        ))))
     }
 
     async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
-        let filter = DiscoveryFilter {
-            service_uuids: filter.services,
-            duplicate_data: Some(true),
-            transport: Some(Transport::Auto),
-            ..Default::default()
-        };
-        self.session
-            .start_discovery_on_adapter_with_filter(&self.adapter, &filter)
-            .await?;
+        self.track_last_seen().await?;
+        self.start_discovery(filter.clone()).await?;
+        self.scan_state.started(filter);
         Ok(())
     }
 
@@ -80,26 +370,108 @@ impl Central for Adapter {
         self.session
             .stop_discovery_on_adapter(&self.adapter)
             .await?;
+        self.scan_state.stopped();
+        Ok(())
+    }
+
+    async fn start_scan_with_options(
+        &self,
+        filter: ScanFilter,
+        options: ScanOptions,
+    ) -> Result<()> {
+        options.check()?;
+        self.rssi_smoothing.set(options.rssi_smoothing);
+        // In units of 0.625 ms.
+        let units = |duration: Duration| (duration.as_micros() / 625) as u16;
+        mgmt::set_discovery_scan_parameters(
+            &self.adapter,
+            options.interval.map(units),
+            options.window.map(units),
+        )
+        .await?;
+        self.start_scan(filter).await
+    }
+
+    async fn pause_scan(&self) -> Result<()> {
+        if self.scan_state.is_running() {
+            self.session
+                .stop_discovery_on_adapter(&self.adapter)
+                .await?;
+            self.scan_state.paused();
+        }
         Ok(())
     }
 
+    async fn resume_scan(&self) -> Result<()> {
+        if let Some(filter) = self.scan_state.paused_filter() {
+            self.start_discovery(filter).await?;
+            self.scan_state.resumed();
+        }
+        Ok(())
+    }
+
+    async fn subscribe_scan(&self, filter: ScanFilter) -> Result<ScanSubscription> {
+        subscribe_scan(self, &self.scans, filter).await
+    }
+
     async fn peripherals(&self) -> Result<Vec<Peripheral>> {
+        self.track_connections().await?;
         let devices = self.session.get_devices_on_adapter(&self.adapter).await?;
         Ok(devices
             .into_iter()
-            .map(|device| Peripheral::new(self.session.clone(), device))
+            .map(|device| self.peripheral_from_info(device))
+            .collect())
+    }
+
+    async fn peripherals_snapshot(&self) -> Result<Vec<(PeripheralId, PeripheralProperties)>> {
+        // One call for every device, rather than one per device as `properties` would make.
+        let devices = self.session.get_devices_on_adapter(&self.adapter).await?;
+        let last_seen = self.last_seen.lock().unwrap();
+        let advertising_sids = self.advertising_sids.lock().unwrap();
+        Ok(devices
+            .into_iter()
+            .map(|device| {
+                let seen = last_seen.get(&device.id).copied();
+                let sid = advertising_sids.get(&device.mac_address.into()).copied();
+                (
+                    device.id.clone().into(),
+                    properties_from_info(device, seen, sid),
+                )
+            })
+            .collect())
+    }
+
+    async fn connected(&self) -> Result<Vec<Peripheral>> {
+        self.track_connections().await?;
+        let devices = self.session.get_devices_on_adapter(&self.adapter).await?;
+        Ok(devices
+            .into_iter()
+            .filter(|device| device.connected)
+            .map(|device| self.peripheral_from_info(device))
+            .collect())
+    }
+
+    async fn bonded_devices(&self) -> Result<Vec<Peripheral>> {
+        self.track_connections().await?;
+        let devices = self.session.get_devices_on_adapter(&self.adapter).await?;
+        Ok(devices
+            .into_iter()
+            .filter(|device| device.paired)
+            .map(|device| self.peripheral_from_info(device))
             .collect())
     }
 
     async fn peripheral(&self, id: &PeripheralId) -> Result<Peripheral> {
-        let device = self.session.get_device_info(&id.0).await.map_err(|e| {
-            if let BluetoothError::DbusError(_) = e {
-                Error::DeviceNotFound
-            } else {
-                e.into()
-            }
-        })?;
-        Ok(Peripheral::new(self.session.clone(), device))
+        self.track_connections().await?;
+        // The ID may come from a string rather than from BlueZ, so find the device it names.
+        let device = self
+            .session
+            .get_devices()
+            .await?
+            .into_iter()
+            .find(|device| PeripheralId::from(device.id.clone()) == *id)
+            .ok_or(Error::DeviceNotFound)?;
+        Ok(self.peripheral_from_info(device))
     }
 
     async fn add_peripheral(&self, _address: &PeripheralId) -> Result<Peripheral> {
@@ -120,6 +492,93 @@ impl Central for Adapter {
         }
         Ok(get_central_state(powered))
     }
+
+    async fn is_scanning(&self) -> Result<bool> {
+        Ok(self
+            .session
+            .get_adapter_info(&self.adapter)
+            .await?
+            .discovering)
+    }
+
+    async fn adapter_capabilities(&self) -> Result<AdapterCapabilities> {
+        // Older versions of BlueZ don't have the property, so just don't report the roles.
+        let roles: Vec<String> = self
+            .get_adapter_property("Roles")
+            .await
+            .unwrap_or_else(|e| {
+                debug!("Couldn't get the roles of {}: {:?}", self.adapter, e);
+                Vec::new()
+            });
+        Ok(AdapterCapabilities {
+            peripheral_role: roles.iter().any(|role| role == "peripheral"),
+        })
+    }
+
+    async fn le_features(&self) -> Result<LeFeatures> {
+        Ok(LeFeatures::from_bits_retain(
+            mgmt::le_features(&self.adapter).await?,
+        ))
+    }
+
+    #[cfg(feature = "le5")]
+    async fn sync_periodic_advertising(
+        &self,
+        address: BDAddr,
+        sid: u8,
+    ) -> Result<Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>> {
+        let device = self
+            .session
+            .get_devices_on_adapter(&self.adapter)
+            .await?
+            .into_iter()
+            .find(|device| BDAddr::from(device.mac_address) == address)
+            .ok_or(Error::DeviceNotFound)?;
+        let reports =
+            mgmt::sync_periodic_advertising(&self.adapter, address, device.address_type, sid)
+                .await?;
+        Ok(Box::pin(reports))
+    }
+
+    async fn set_random_address(&self, address: BDAddr) -> Result<()> {
+        if !address.is_random_static() {
+            return Err(Error::Other(
+                format!("{} is not a valid random static address", address).into(),
+            ));
+        }
+        mgmt::set_static_address(&self.adapter, address).await
+    }
+
+    async fn is_discoverable(&self) -> Result<bool> {
+        self.get_adapter_property("Discoverable").await
+    }
+
+    async fn set_discoverable(&self, discoverable: bool, timeout: Option<Duration>) -> Result<()> {
+        if discoverable {
+            // BlueZ takes the timeout in seconds, and 0 to mean no timeout. The cast saturates.
+            let timeout = timeout.map_or(0, |timeout| (timeout.as_secs_f64().ceil() as u32).max(1));
+            self.set_adapter_property("DiscoverableTimeout", timeout)
+                .await?;
+        }
+        self.set_adapter_property("Discoverable", discoverable)
+            .await
+    }
+
+    async fn is_connectable(&self) -> Result<bool> {
+        self.get_adapter_property("Connectable").await
+    }
+
+    async fn set_connectable(&self, connectable: bool) -> Result<()> {
+        self.set_adapter_property("Connectable", connectable).await
+    }
+
+    fn set_max_retries(&self, retries: u32) {
+        self.max_retries.store(retries, Ordering::Relaxed);
+    }
+
+    fn set_max_concurrent_connects(&self, max: usize) {
+        self.connect_limit.set_max(max);
+    }
 }
 
 impl From<BluetoothError> for Error {
@@ -185,6 +644,13 @@ async fn central_event(
                 let state = get_central_state(powered);
                 Some(CentralEvent::StateUpdate(state))
             }
+            AdapterEvent::Discovering { discovering } => {
+                if discovering {
+                    Some(CentralEvent::ScanStarted)
+                } else {
+                    Some(CentralEvent::ScanStopped)
+                }
+            }
             _ => None,
         },
         _ => None,
@@ -0,0 +1,211 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Raw ATT requests over an L2CAP socket on the fixed ATT channel, bypassing BlueZ's GATT client.
+
+use crate::{
+    api::{att::ERROR_RESPONSE, AttRequest, AttResponse, BDAddr},
+    common::runtime,
+    Error, Result,
+};
+use bluez_async::AddressType;
+use std::convert::TryInto;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+
+const BTPROTO_L2CAP: libc::c_int = 0;
+const ATT_CID: u16 = 4;
+const BDADDR_LE_PUBLIC: u8 = 1;
+const BDADDR_LE_RANDOM: u8 = 2;
+
+const HANDLE_VALUE_INDICATION: u8 = 0x1d;
+const HANDLE_VALUE_CONFIRMATION: u8 = 0x1e;
+/// Set in the opcodes of commands, which unlike requests get no response.
+const COMMAND_FLAG: u8 = 0x40;
+const REQUEST_NOT_SUPPORTED: u8 = 0x06;
+
+/// The ATT transaction timeout, which also bounds how long connecting may take.
+const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The kernel's `struct sockaddr_l2`.
+#[repr(C)]
+struct SockaddrL2 {
+    l2_family: libc::sa_family_t,
+    l2_psm: u16,
+    l2_bdaddr: [u8; 6],
+    l2_cid: u16,
+    l2_bdaddr_type: u8,
+}
+
+impl SockaddrL2 {
+    fn new(address: BDAddr, address_type: AddressType) -> Self {
+        // Addresses go over the wire least significant byte first.
+        let mut l2_bdaddr = address.into_inner();
+        l2_bdaddr.reverse();
+        SockaddrL2 {
+            l2_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+            l2_psm: 0,
+            l2_bdaddr,
+            l2_cid: ATT_CID.to_le(),
+            l2_bdaddr_type: match address_type {
+                AddressType::Public => BDADDR_LE_PUBLIC,
+                AddressType::Random => BDADDR_LE_RANDOM,
+            },
+        }
+    }
+}
+
+/// Connects from the given adapter to the ATT server of the given device, sends it the request and
+/// waits for its response.
+pub(crate) async fn request(
+    adapter: (BDAddr, AddressType),
+    device: (BDAddr, AddressType),
+    request: AttRequest,
+) -> Result<AttResponse> {
+    runtime::spawn_blocking(move || {
+        let socket = connect(adapter, device)?;
+        let pdu = request.to_pdu();
+        send(&socket, &pdu)?;
+
+        let opcode = request.opcode();
+        let mut buffer = [0u8; 517];
+        loop {
+            let read = unsafe {
+                libc::read(
+                    socket.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                )
+            };
+            if read < 0 {
+                return Err(io_error(io::Error::last_os_error()));
+            }
+            // The device has closed the link.
+            if read == 0 {
+                return Err(Error::NotConnected);
+            }
+            let pdu = &buffer[..read as usize];
+            // The device may send notifications, indications or requests of its own before it
+            // responds. Indications must be confirmed and requests answered before it will send
+            // anything else.
+            match pdu {
+                [HANDLE_VALUE_INDICATION, ..] => {
+                    send(&socket, &[HANDLE_VALUE_CONFIRMATION])?;
+                    continue;
+                }
+                [ERROR_RESPONSE, request_opcode, ..] if *request_opcode == opcode => {}
+                [response_opcode, ..] if *response_opcode == opcode.wrapping_add(1) => {}
+                [request_opcode, ..] if is_request(*request_opcode) => {
+                    send(
+                        &socket,
+                        &[ERROR_RESPONSE, *request_opcode, 0, 0, REQUEST_NOT_SUPPORTED],
+                    )?;
+                    continue;
+                }
+                _ => continue,
+            }
+            if let Some(response) = AttResponse::from_pdu(pdu) {
+                return Ok(response);
+            }
+        }
+    })
+    .await?
+}
+
+/// Whether a PDU with the given opcode is a request, which must be answered. Requests have even
+/// opcodes, other than the Handle Value Confirmation, and aren't commands.
+fn is_request(opcode: u8) -> bool {
+    opcode & 1 == 0 && opcode & COMMAND_FLAG == 0 && opcode != HANDLE_VALUE_CONFIRMATION
+}
+
+fn send(socket: &OwnedFd, pdu: &[u8]) -> Result<()> {
+    let written = unsafe {
+        libc::write(
+            socket.as_raw_fd(),
+            pdu.as_ptr() as *const libc::c_void,
+            pdu.len(),
+        )
+    };
+    if written < 0 {
+        return Err(io_error(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+fn connect(adapter: (BDAddr, AddressType), device: (BDAddr, AddressType)) -> Result<OwnedFd> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_BLUETOOTH,
+            libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+            BTPROTO_L2CAP,
+        )
+    };
+    if fd < 0 {
+        return Err(io_error(io::Error::last_os_error()));
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let timeout = libc::timeval {
+        tv_sec: TRANSACTION_TIMEOUT.as_secs().try_into().unwrap(),
+        tv_usec: 0,
+    };
+    for option in [libc::SO_RCVTIMEO, libc::SO_SNDTIMEO] {
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                option,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io_error(io::Error::last_os_error()));
+        }
+    }
+
+    let local = SockaddrL2::new(adapter.0, adapter.1);
+    let result = unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            &local as *const SockaddrL2 as *const libc::sockaddr,
+            mem::size_of::<SockaddrL2>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io_error(io::Error::last_os_error()));
+    }
+
+    let remote = SockaddrL2::new(device.0, device.1);
+    let result = unsafe {
+        libc::connect(
+            socket.as_raw_fd(),
+            &remote as *const SockaddrL2 as *const libc::sockaddr,
+            mem::size_of::<SockaddrL2>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        let error = io::Error::last_os_error();
+        return Err(match error.raw_os_error() {
+            Some(libc::EBUSY) => Error::RuntimeError(
+                "The device already has an ATT bearer; disconnect it from BlueZ first".to_string(),
+            ),
+            _ => io_error(error),
+        });
+    }
+    Ok(socket)
+}
+
+fn io_error(error: io::Error) -> Error {
+    match error.kind() {
+        io::ErrorKind::PermissionDenied => Error::PermissionDenied,
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::TimedOut(TRANSACTION_TIMEOUT),
+        _ => Error::Other(Box::new(error)),
+    }
+}
@@ -0,0 +1,84 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Direct D-Bus calls to BlueZ, for the few methods and properties which bluez-async doesn't
+//! expose. These go over a separate blocking connection to the system bus.
+
+use crate::{common::runtime, Result};
+use bluez_async::BluetoothError;
+use dbus::{
+    arg::{Append, AppendAll, Arg, Get, ReadAll},
+    blocking::{stdintf::org_freedesktop_dbus::Properties, Connection},
+    Path,
+};
+use std::time::Duration;
+
+/// How long to wait for BlueZ to reply.
+const METHOD_CALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Calls a method on a BlueZ object.
+pub(crate) async fn call_method<A, R>(
+    path: Path<'static>,
+    interface: &'static str,
+    method: &'static str,
+    args: A,
+) -> Result<R>
+where
+    A: AppendAll + Send + 'static,
+    R: ReadAll + Send + 'static,
+{
+    runtime::spawn_blocking(move || {
+        let connection = Connection::new_system().map_err(BluetoothError::from)?;
+        let reply = connection
+            .with_proxy("org.bluez", path, METHOD_CALL_TIMEOUT)
+            .method_call(interface, method, args)
+            .map_err(BluetoothError::from)?;
+        Ok(reply)
+    })
+    .await?
+}
+
+/// Gets the value of a property of a BlueZ object.
+pub(crate) async fn get_property<T>(
+    path: Path<'static>,
+    interface: &'static str,
+    property: &'static str,
+) -> Result<T>
+where
+    T: for<'b> Get<'b> + Send + 'static,
+{
+    runtime::spawn_blocking(move || {
+        let connection = Connection::new_system().map_err(BluetoothError::from)?;
+        let value = connection
+            .with_proxy("org.bluez", path, METHOD_CALL_TIMEOUT)
+            .get(interface, property)
+            .map_err(BluetoothError::from)?;
+        Ok(value)
+    })
+    .await?
+}
+
+/// Sets the value of a property of a BlueZ object.
+pub(crate) async fn set_property<T>(
+    path: Path<'static>,
+    interface: &'static str,
+    property: &'static str,
+    value: T,
+) -> Result<()>
+where
+    T: Arg + Append + Send + 'static,
+{
+    runtime::spawn_blocking(move || {
+        let connection = Connection::new_system().map_err(BluetoothError::from)?;
+        connection
+            .with_proxy("org.bluez", path, METHOD_CALL_TIMEOUT)
+            .set(interface, property, value)
+            .map_err(BluetoothError::from)?;
+        Ok(())
+    })
+    .await?
+}
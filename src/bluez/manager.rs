@@ -1,18 +1,32 @@
 use super::adapter::Adapter;
-use crate::{api, Result};
+use crate::{api, api::DisconnectReason, Result};
 use async_trait::async_trait;
-use bluez_async::BluetoothSession;
+use bluez_async::{BluetoothSession, DeviceId};
+use tokio::sync::broadcast;
 
 /// Implementation of [api::Manager](crate::api::Manager).
 #[derive(Clone, Debug)]
 pub struct Manager {
     session: BluetoothSession,
+    removed_devices: broadcast::Sender<DeviceId>,
+    disconnect_reasons: broadcast::Sender<(DeviceId, DisconnectReason)>,
 }
 
 impl Manager {
+    /// Connects to BlueZ over the D-Bus system bus. The bus is found like any other D-Bus client
+    /// finds it, so a different one can be used by setting the `DBUS_SYSTEM_BUS_ADDRESS`
+    /// environment variable, such as where a container or sandbox makes the host's bus available
+    /// at another path. The bus policy must let the process call methods on `org.bluez` and
+    /// receive its signals.
     pub async fn new() -> Result<Self> {
         let (_, session) = BluetoothSession::new().await?;
-        Ok(Self { session })
+        let (removed_devices, _) = broadcast::channel(16);
+        let (disconnect_reasons, _) = broadcast::channel(16);
+        Ok(Self {
+            session,
+            removed_devices,
+            disconnect_reasons,
+        })
     }
 }
 
@@ -24,7 +38,14 @@ impl api::Manager for Manager {
         let adapters = self.session.get_adapters().await?;
         Ok(adapters
             .into_iter()
-            .map(|adapter| Adapter::new(self.session.clone(), adapter.id))
+            .map(|adapter| {
+                Adapter::new(
+                    self.session.clone(),
+                    adapter.id,
+                    self.removed_devices.clone(),
+                    self.disconnect_reasons.clone(),
+                )
+            })
             .collect())
     }
 }
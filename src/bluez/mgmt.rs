@@ -0,0 +1,695 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! A minimal client for the BlueZ management API (see `doc/mgmt-api.txt` in the BlueZ tree) and raw
+//! HCI sockets, used for the few operations which BlueZ doesn't expose over D-Bus.
+
+use crate::{
+    api::{BDAddr, DataLength},
+    common::runtime,
+    Error, Result,
+};
+use bluez_async::AdapterId;
+#[cfg(feature = "le5")]
+use bluez_async::AddressType;
+#[cfg(feature = "le5")]
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+#[cfg(feature = "le5")]
+use {
+    log::debug,
+    std::sync::{Mutex, Weak},
+    std::time::Instant,
+};
+
+const BTPROTO_HCI: libc::c_int = 1;
+const HCI_DEV_NONE: u16 = 0xffff;
+const HCI_CHANNEL_RAW: u16 = 0;
+const HCI_CHANNEL_CONTROL: u16 = 3;
+
+/// `_IOR('H', 213, int)`
+const HCIGETCONNINFO: libc::Ioctl = 0x800448d5u32 as libc::Ioctl;
+const ACL_LINK: u8 = 0x01;
+const LE_LINK: u8 = 0x80;
+
+const SOL_HCI: libc::c_int = 0;
+const HCI_FILTER: libc::c_int = 2;
+const HCI_COMMAND_PKT: u8 = 0x01;
+const HCI_EVENT_PKT: u8 = 0x04;
+const EVT_CMD_COMPLETE: u8 = 0x0e;
+const EVT_CMD_STATUS: u8 = 0x0f;
+/// HCI_LE_Read_Maximum_Data_Length: OGF 0x08, OCF 0x002f.
+const HCI_OP_LE_READ_MAX_DATA_LEN: u16 = 0x202f;
+const HCI_OP_READ_TX_POWER_LEVEL: u16 = 0x0c2d;
+/// HCI_LE_Read_Local_Supported_Features: OGF 0x08, OCF 0x0003.
+const HCI_OP_LE_READ_LOCAL_FEATURES: u16 = 0x2003;
+#[cfg(feature = "le5")]
+const HCI_OP_LE_PA_CREATE_SYNC: u16 = 0x2044;
+#[cfg(feature = "le5")]
+const HCI_OP_LE_PA_CREATE_SYNC_CANCEL: u16 = 0x2045;
+#[cfg(feature = "le5")]
+const HCI_OP_LE_PA_TERMINATE_SYNC: u16 = 0x2046;
+#[cfg(feature = "le5")]
+const EVT_LE_META: u8 = 0x3e;
+#[cfg(feature = "le5")]
+const LE_EVT_EXT_ADV_REPORT: u8 = 0x0d;
+#[cfg(feature = "le5")]
+const LE_EVT_PA_SYNC_ESTABLISHED: u8 = 0x0e;
+#[cfg(feature = "le5")]
+const LE_EVT_PA_REPORT: u8 = 0x0f;
+#[cfg(feature = "le5")]
+const LE_EVT_PA_SYNC_LOST: u8 = 0x10;
+/// The bit of an extended advertising report's Event_Type which marks a legacy advertisement.
+#[cfg(feature = "le5")]
+const EXT_ADV_LEGACY: u16 = 0x0010;
+/// The Advertising_SID of an extended advertising report without an ADI field.
+#[cfg(feature = "le5")]
+const EXT_ADV_NO_SID: u8 = 0xff;
+/// The length of each extended advertising report up to its advertising data.
+#[cfg(feature = "le5")]
+const EXT_ADV_REPORT_HEADER_LEN: usize = 24;
+/// The Data_Status of a periodic advertising report which is to be continued by the next one.
+#[cfg(feature = "le5")]
+const PA_DATA_INCOMPLETE: u8 = 0x01;
+/// How long the controller and we wait for the periodic advertising train, and how long it may
+/// then go unheard before the sync is lost.
+#[cfg(feature = "le5")]
+const PA_SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+/// The Type parameter of Read Transmit Power Level asking for the current rather than the maximum
+/// level.
+const TX_POWER_LEVEL_CURRENT: u8 = 0x00;
+
+const MGMT_OP_SET_STATIC_ADDRESS: u16 = 0x002b;
+const MGMT_OP_SET_DEF_SYSTEM_CONFIG: u16 = 0x004c;
+const SYSTEM_CONFIG_LE_SCAN_INTERVAL_DISCOVERY: u16 = 0x0011;
+const SYSTEM_CONFIG_LE_SCAN_WINDOW_DISCOVERY: u16 = 0x0012;
+const MGMT_EV_CMD_COMPLETE: u16 = 0x0001;
+const MGMT_EV_CMD_STATUS: u16 = 0x0002;
+
+const MGMT_HEADER_SIZE: usize = 6;
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[repr(C)]
+struct SockaddrHci {
+    hci_family: libc::sa_family_t,
+    hci_dev: u16,
+    hci_channel: u16,
+}
+
+/// The kernel's `struct hci_conn_info`.
+#[repr(C)]
+#[derive(Default)]
+struct HciConnInfo {
+    handle: u16,
+    bdaddr: [u8; 6],
+    link_type: u8,
+    out: u8,
+    state: u16,
+    link_mode: u32,
+}
+
+/// The kernel's `struct hci_conn_info_req`, with room for the single `hci_conn_info` it returns.
+#[repr(C)]
+struct HciConnInfoReq {
+    bdaddr: [u8; 6],
+    link_type: u8,
+    conn_info: HciConnInfo,
+}
+
+/// The kernel's `struct hci_filter`.
+#[repr(C)]
+struct HciFilter {
+    type_mask: u32,
+    event_mask: [u32; 2],
+    opcode: u16,
+}
+
+/// Sets the static random address of the given controller. The controller must be powered off.
+pub(crate) async fn set_static_address(adapter: &AdapterId, address: BDAddr) -> Result<()> {
+    let index = controller_index(adapter)?;
+    // Addresses go over the wire least significant byte first.
+    let mut params = address.into_inner();
+    params.reverse();
+    runtime::spawn_blocking(move || {
+        send_command(index, MGMT_OP_SET_STATIC_ADDRESS, &params).map(|_| ())
+    })
+    .await?
+}
+
+/// Sets the LE scan interval and window, in units of 0.625 ms, which the kernel uses when the given
+/// controller discovers devices. Either can be left as it is.
+pub(crate) async fn set_discovery_scan_parameters(
+    adapter: &AdapterId,
+    interval: Option<u16>,
+    window: Option<u16>,
+) -> Result<()> {
+    let index = controller_index(adapter)?;
+    // A list of type, length and value entries.
+    let mut params = Vec::new();
+    for (config_type, value) in [
+        (SYSTEM_CONFIG_LE_SCAN_INTERVAL_DISCOVERY, interval),
+        (SYSTEM_CONFIG_LE_SCAN_WINDOW_DISCOVERY, window),
+    ] {
+        if let Some(value) = value {
+            params.extend_from_slice(&config_type.to_le_bytes());
+            params.push(2);
+            params.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    if params.is_empty() {
+        return Ok(());
+    }
+    runtime::spawn_blocking(move || {
+        send_command(index, MGMT_OP_SET_DEF_SYSTEM_CONFIG, &params).map(|_| ())
+    })
+    .await?
+}
+
+/// Gets the HCI handle of the given controller's connection to the device with the given address, if
+/// it has one, first over LE and then over BR/EDR.
+pub(crate) async fn connection_handle(adapter: &AdapterId, address: BDAddr) -> Result<Option<u16>> {
+    let index = controller_index(adapter)?;
+    let mut address = address.into_inner();
+    address.reverse();
+    runtime::spawn_blocking(move || {
+        let socket = open_socket(index, HCI_CHANNEL_RAW).map_err(io_error)?;
+        for link_type in [LE_LINK, ACL_LINK] {
+            let mut request = HciConnInfoReq {
+                bdaddr: address,
+                link_type,
+                conn_info: HciConnInfo::default(),
+            };
+            let result = unsafe {
+                libc::ioctl(
+                    socket.as_raw_fd(),
+                    HCIGETCONNINFO,
+                    &mut request as *mut HciConnInfoReq,
+                )
+            };
+            if result == 0 {
+                return Ok(Some(request.conn_info.handle));
+            }
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() != Some(libc::ENOENT) {
+                return Err(io_error(error));
+            }
+        }
+        Ok(None)
+    })
+    .await?
+}
+
+/// Reads the maximum Link Layer data lengths the given controller supports, or `None` if it is too
+/// old to support the Data Length Extension.
+pub(crate) async fn max_data_length(adapter: &AdapterId) -> Result<Option<DataLength>> {
+    let index = controller_index(adapter)?;
+    runtime::spawn_blocking(move || {
+        let reply = match send_hci_command(index, HCI_OP_LE_READ_MAX_DATA_LEN, &[]) {
+            Ok(reply) => reply,
+            Err(Error::NotSupported(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if reply.len() < 8 {
+            return Err(Error::Other("HCI reply too short".into()));
+        }
+        let field = |offset: usize| u16::from_le_bytes([reply[offset], reply[offset + 1]]);
+        Ok(Some(DataLength {
+            max_tx_octets: field(0),
+            max_tx_time: field(2),
+            max_rx_octets: field(4),
+            max_rx_time: field(6),
+        }))
+    })
+    .await?
+}
+
+/// Reads the transmit power level, in dBm, the given controller is currently using for the
+/// connection with the given handle.
+pub(crate) async fn tx_power_level(adapter: &AdapterId, handle: u16) -> Result<i8> {
+    let index = controller_index(adapter)?;
+    runtime::spawn_blocking(move || {
+        let mut params = handle.to_le_bytes().to_vec();
+        params.push(TX_POWER_LEVEL_CURRENT);
+        let reply = send_hci_command(index, HCI_OP_READ_TX_POWER_LEVEL, &params)?;
+        // The connection handle, then the level.
+        match reply[..] {
+            [_, _, level, ..] => Ok(level as i8),
+            _ => Err(Error::Other("HCI reply too short".into())),
+        }
+    })
+    .await?
+}
+
+/// Reads the bitmask of LE features the given controller supports.
+pub(crate) async fn le_features(adapter: &AdapterId) -> Result<u64> {
+    let index = controller_index(adapter)?;
+    runtime::spawn_blocking(move || {
+        let reply = send_hci_command(index, HCI_OP_LE_READ_LOCAL_FEATURES, &[])?;
+        let features: [u8; 8] = reply
+            .get(..8)
+            .and_then(|features| features.try_into().ok())
+            .ok_or_else(|| Error::Other("HCI reply too short".into()))?;
+        Ok(u64::from_le_bytes(features))
+    })
+    .await?
+}
+
+/// Synchronizes the given controller with the periodic advertising train with the given SID from
+/// the device with the given address, then sends the data of every periodic advertisement
+/// received on the returned channel, until the sync is lost or the receiver is dropped.
+#[cfg(feature = "le5")]
+pub(crate) async fn sync_periodic_advertising(
+    adapter: &AdapterId,
+    address: BDAddr,
+    address_type: AddressType,
+    sid: u8,
+) -> Result<futures::channel::mpsc::UnboundedReceiver<Vec<u8>>> {
+    let index = controller_index(adapter)?;
+    let (socket, sync_handle) =
+        runtime::spawn_blocking(move || create_sync(index, address, address_type, sid)).await??;
+    let (sender, receiver) = futures::channel::mpsc::unbounded();
+    std::thread::spawn(move || {
+        let mut data = Vec::new();
+        let mut buffer = [0u8; 260];
+        while !sender.is_closed() {
+            let event = match read_packet(&socket, &mut buffer) {
+                Ok(event) => event,
+                // Time out every so often to check whether the receiver is still there.
+                Err(Error::TimedOut(_)) => continue,
+                Err(e) => {
+                    debug!("Reading periodic advertising reports failed: {:?}", e);
+                    break;
+                }
+            };
+            match event {
+                [HCI_EVENT_PKT, EVT_LE_META, _, LE_EVT_PA_REPORT, low, high, _tx_power, _rssi, _cte_type, status, length, report @ ..]
+                    if u16::from_le_bytes([*low, *high]) == sync_handle =>
+                {
+                    data.extend_from_slice(&report[..(*length as usize).min(report.len())]);
+                    // Truncated data is passed on as far as it goes.
+                    if *status != PA_DATA_INCOMPLETE {
+                        let _ = sender.unbounded_send(mem::take(&mut data));
+                    }
+                }
+                [HCI_EVENT_PKT, EVT_LE_META, _, LE_EVT_PA_SYNC_LOST, low, high, ..]
+                    if u16::from_le_bytes([*low, *high]) == sync_handle =>
+                {
+                    return;
+                }
+                _ => {}
+            }
+        }
+        if let Err(e) = send_hci_command(
+            index,
+            HCI_OP_LE_PA_TERMINATE_SYNC,
+            &sync_handle.to_le_bytes(),
+        ) {
+            debug!("Terminating periodic advertising sync failed: {:?}", e);
+        }
+    });
+    Ok(receiver)
+}
+
+/// Records the Advertising SID of every extended advertisement the given controller receives in
+/// `sids`, keyed by the address of the advertiser, on a thread of its own until `sids` is dropped.
+/// A legacy advertisement, or one without an ADI field, removes its advertiser from `sids`.
+#[cfg(feature = "le5")]
+pub(crate) fn watch_advertising_sids(
+    adapter: &AdapterId,
+    sids: Weak<Mutex<HashMap<BDAddr, u8>>>,
+) -> Result<()> {
+    let index = controller_index(adapter)?;
+    let socket = open_socket(index, HCI_CHANNEL_RAW).map_err(io_error)?;
+    set_filter(
+        &socket,
+        &HciFilter {
+            type_mask: 1 << HCI_EVENT_PKT,
+            event_mask: [0, 1 << (EVT_LE_META - 32)],
+            opcode: 0,
+        },
+    )?;
+    std::thread::spawn(move || {
+        let mut buffer = [0u8; 260];
+        loop {
+            let event = match read_packet(&socket, &mut buffer) {
+                Ok(event) => event,
+                // Time out every so often to check whether the SIDs are still wanted.
+                Err(Error::TimedOut(_)) => &[],
+                Err(e) => {
+                    debug!("Reading extended advertising reports failed: {:?}", e);
+                    return;
+                }
+            };
+            let Some(sids) = sids.upgrade() else {
+                return;
+            };
+            if let [HCI_EVENT_PKT, EVT_LE_META, _, LE_EVT_EXT_ADV_REPORT, reports @ ..] = event {
+                let mut sids = sids.lock().unwrap();
+                for (address, sid) in extended_advertising_sids(reports) {
+                    match sid {
+                        Some(sid) => sids.insert(address, sid),
+                        None => sids.remove(&address),
+                    };
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Gets the address and Advertising SID of each report in the parameters of an LE Extended
+/// Advertising Report event, with `None` for the SID of legacy advertisements and those without an
+/// ADI field.
+#[cfg(feature = "le5")]
+fn extended_advertising_sids(reports: &[u8]) -> Vec<(BDAddr, Option<u8>)> {
+    let Some((&count, mut rest)) = reports.split_first() else {
+        return Vec::new();
+    };
+    let mut sids = Vec::new();
+    for _ in 0..count {
+        if rest.len() < EXT_ADV_REPORT_HEADER_LEN {
+            break;
+        }
+        // Event_Type, Address_Type and Address, then Primary_PHY and Secondary_PHY before the SID.
+        let event_type = u16::from_le_bytes([rest[0], rest[1]]);
+        let mut address: [u8; 6] = rest[3..9].try_into().unwrap();
+        address.reverse();
+        let sid = rest[11];
+        let sid = (event_type & EXT_ADV_LEGACY == 0 && sid != EXT_ADV_NO_SID).then_some(sid);
+        sids.push((address.into(), sid));
+        let data_len = usize::from(rest[EXT_ADV_REPORT_HEADER_LEN - 1]);
+        rest = rest
+            .get(EXT_ADV_REPORT_HEADER_LEN + data_len..)
+            .unwrap_or_default();
+    }
+    sids
+}
+
+/// Starts synchronizing with a periodic advertising train and waits until the sync is established,
+/// returning the socket to receive its reports on and the sync handle.
+#[cfg(feature = "le5")]
+fn create_sync(
+    index: u16,
+    address: BDAddr,
+    address_type: AddressType,
+    sid: u8,
+) -> Result<(OwnedFd, u16)> {
+    let socket = open_socket(index, HCI_CHANNEL_RAW).map_err(io_error)?;
+    set_filter(
+        &socket,
+        &HciFilter {
+            type_mask: 1 << HCI_EVENT_PKT,
+            event_mask: [1 << EVT_CMD_STATUS, 1 << (EVT_LE_META - 32)],
+            opcode: HCI_OP_LE_PA_CREATE_SYNC.to_le(),
+        },
+    )?;
+
+    let mut advertiser = address.into_inner();
+    advertiser.reverse();
+    let timeout = (PA_SYNC_TIMEOUT.as_millis() / 10) as u16;
+    let address_type = match address_type {
+        AddressType::Public => 0x00,
+        AddressType::Random => 0x01,
+    };
+    let mut params = vec![0x00, sid, address_type];
+    params.extend_from_slice(&advertiser);
+    // Skip no reports, and sync whatever Constant Tone Extension the train has.
+    params.extend_from_slice(&0u16.to_le_bytes());
+    params.extend_from_slice(&timeout.to_le_bytes());
+    params.push(0x00);
+    write_hci_command(&socket, HCI_OP_LE_PA_CREATE_SYNC, &params)?;
+
+    let deadline = Instant::now() + PA_SYNC_TIMEOUT;
+    let mut buffer = [0u8; 260];
+    while Instant::now() < deadline {
+        let event = match read_packet(&socket, &mut buffer) {
+            Ok(event) => event,
+            Err(Error::TimedOut(_)) => continue,
+            Err(e) => return Err(e),
+        };
+        match event {
+            [HCI_EVENT_PKT, EVT_CMD_STATUS, _, status, _, low, high, ..]
+                if u16::from_le_bytes([*low, *high]) == HCI_OP_LE_PA_CREATE_SYNC
+                    && *status != 0x00 =>
+            {
+                return Err(hci_status_error(*status));
+            }
+            [HCI_EVENT_PKT, EVT_LE_META, _, LE_EVT_PA_SYNC_ESTABLISHED, status, low, high, event_sid, _, event_address @ .., _phy, _, _, _]
+                if *event_sid == sid && event_address == advertiser =>
+            {
+                return match status {
+                    0x00 => Ok((socket, u16::from_le_bytes([*low, *high]))),
+                    status => Err(hci_status_error(*status)),
+                };
+            }
+            _ => continue,
+        }
+    }
+    let _ = send_hci_command(index, HCI_OP_LE_PA_CREATE_SYNC_CANCEL, &[]);
+    Err(Error::TimedOut(PA_SYNC_TIMEOUT))
+}
+
+/// Gets the controller index the management API uses from a BlueZ adapter ID like `hci0`.
+fn controller_index(adapter: &AdapterId) -> Result<u16> {
+    adapter
+        .to_string()
+        .strip_prefix("hci")
+        .and_then(|index| index.parse().ok())
+        .ok_or_else(|| Error::Other(format!("Unexpected adapter ID {}", adapter).into()))
+}
+
+/// Sends a command to the given controller and waits for it to complete, returning its reply
+/// parameters.
+fn send_command(index: u16, opcode: u16, params: &[u8]) -> Result<Vec<u8>> {
+    let socket = open_socket(HCI_DEV_NONE, HCI_CHANNEL_CONTROL).map_err(io_error)?;
+
+    let mut command = Vec::with_capacity(MGMT_HEADER_SIZE + params.len());
+    command.extend_from_slice(&opcode.to_le_bytes());
+    command.extend_from_slice(&index.to_le_bytes());
+    command.extend_from_slice(&(params.len() as u16).to_le_bytes());
+    command.extend_from_slice(params);
+    let written = unsafe {
+        libc::write(
+            socket.as_raw_fd(),
+            command.as_ptr() as *const libc::c_void,
+            command.len(),
+        )
+    };
+    if written < 0 {
+        return Err(io_error(io::Error::last_os_error()));
+    }
+
+    // The control channel also delivers events which have nothing to do with our command, so skip
+    // over anything that isn't its reply.
+    let mut buffer = [0u8; 1024];
+    loop {
+        let read = unsafe {
+            libc::read(
+                socket.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        };
+        if read < 0 {
+            let error = io::Error::last_os_error();
+            return Err(match error.kind() {
+                io::ErrorKind::WouldBlock => Error::TimedOut(REPLY_TIMEOUT),
+                _ => io_error(error),
+            });
+        }
+        let event = &buffer[..read as usize];
+        if event.len() < MGMT_HEADER_SIZE + 3 {
+            continue;
+        }
+        let code = u16::from_le_bytes([event[0], event[1]]);
+        let event_index = u16::from_le_bytes([event[2], event[3]]);
+        let event_opcode = u16::from_le_bytes([event[6], event[7]]);
+        if (code != MGMT_EV_CMD_COMPLETE && code != MGMT_EV_CMD_STATUS)
+            || event_index != index
+            || event_opcode != opcode
+        {
+            continue;
+        }
+        return match event[8] {
+            0x00 => Ok(event[MGMT_HEADER_SIZE + 3..].to_vec()),
+            status => Err(status_error(status)),
+        };
+    }
+}
+
+/// Sends an HCI command to the given controller and waits for it to complete, returning its return
+/// parameters after the status.
+fn send_hci_command(index: u16, opcode: u16, params: &[u8]) -> Result<Vec<u8>> {
+    let socket = open_socket(index, HCI_CHANNEL_RAW).map_err(io_error)?;
+
+    // Only let through the events which can complete our command.
+    set_filter(
+        &socket,
+        &HciFilter {
+            type_mask: 1 << HCI_EVENT_PKT,
+            event_mask: [(1 << EVT_CMD_COMPLETE) | (1 << EVT_CMD_STATUS), 0],
+            opcode: opcode.to_le(),
+        },
+    )?;
+    write_hci_command(&socket, opcode, params)?;
+
+    let mut buffer = [0u8; 260];
+    loop {
+        let event = read_packet(&socket, &mut buffer)?;
+        // Packet type, event code and parameter length, then the event parameters.
+        match event {
+            [HCI_EVENT_PKT, EVT_CMD_COMPLETE, _, _, low, high, status, reply @ ..]
+                if u16::from_le_bytes([*low, *high]) == opcode =>
+            {
+                return match status {
+                    0x00 => Ok(reply.to_vec()),
+                    status => Err(hci_status_error(*status)),
+                };
+            }
+            [HCI_EVENT_PKT, EVT_CMD_STATUS, _, status, _, low, high, ..]
+                if u16::from_le_bytes([*low, *high]) == opcode && *status != 0x00 =>
+            {
+                return Err(hci_status_error(*status));
+            }
+            _ => continue,
+        }
+    }
+}
+
+fn set_filter(socket: &OwnedFd, filter: &HciFilter) -> Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            SOL_HCI,
+            HCI_FILTER,
+            filter as *const HciFilter as *const libc::c_void,
+            mem::size_of::<HciFilter>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io_error(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+fn write_hci_command(socket: &OwnedFd, opcode: u16, params: &[u8]) -> Result<()> {
+    let mut command = Vec::with_capacity(4 + params.len());
+    command.push(HCI_COMMAND_PKT);
+    command.extend_from_slice(&opcode.to_le_bytes());
+    command.push(params.len() as u8);
+    command.extend_from_slice(params);
+    let written = unsafe {
+        libc::write(
+            socket.as_raw_fd(),
+            command.as_ptr() as *const libc::c_void,
+            command.len(),
+        )
+    };
+    if written < 0 {
+        return Err(io_error(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+/// Reads the next packet from an HCI socket, or returns [`Error::TimedOut`] if none arrives before
+/// the socket's receive timeout.
+fn read_packet<'a>(socket: &OwnedFd, buffer: &'a mut [u8]) -> Result<&'a [u8]> {
+    let read = unsafe {
+        libc::read(
+            socket.as_raw_fd(),
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            buffer.len(),
+        )
+    };
+    if read < 0 {
+        let error = io::Error::last_os_error();
+        return Err(match error.kind() {
+            io::ErrorKind::WouldBlock => Error::TimedOut(REPLY_TIMEOUT),
+            _ => io_error(error),
+        });
+    }
+    Ok(&buffer[..read as usize])
+}
+
+fn open_socket(index: u16, channel: u16) -> io::Result<OwnedFd> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_BLUETOOTH,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            BTPROTO_HCI,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let address = SockaddrHci {
+        hci_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+        hci_dev: index,
+        hci_channel: channel,
+    };
+    let result = unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            &address as *const SockaddrHci as *const libc::sockaddr,
+            mem::size_of::<SockaddrHci>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let timeout = libc::timeval {
+        tv_sec: REPLY_TIMEOUT.as_secs().try_into().unwrap(),
+        tv_usec: 0,
+    };
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVTIMEO,
+            &timeout as *const libc::timeval as *const libc::c_void,
+            mem::size_of::<libc::timeval>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+fn io_error(error: io::Error) -> Error {
+    match error.kind() {
+        io::ErrorKind::PermissionDenied => Error::PermissionDenied,
+        _ => Error::Other(Box::new(error)),
+    }
+}
+
+fn status_error(status: u8) -> Error {
+    match status {
+        0x0b => Error::RuntimeError(
+            "Command rejected by the adapter; it may need to be powered off first".to_string(),
+        ),
+        0x0c => Error::NotSupported("Command not supported by the adapter".to_string()),
+        0x14 => Error::PermissionDenied,
+        status => {
+            Error::Other(format!("Management command failed with status {status:#04x}").into())
+        }
+    }
+}
+
+fn hci_status_error(status: u8) -> Error {
+    match status {
+        0x01 => Error::NotSupported("HCI command not supported by the adapter".to_string()),
+        status => Error::Other(format!("HCI command failed with status {status:#04x}").into()),
+    }
+}
@@ -1,3 +1,7 @@
 pub mod adapter;
+#[cfg(feature = "unstable-raw-att")]
+mod att;
+mod dbus;
 pub mod manager;
+mod mgmt;
 pub mod peripheral;
@@ -1,26 +1,45 @@
 use async_trait::async_trait;
 use bluez_async::{
-    BluetoothEvent, BluetoothSession, CharacteristicEvent, CharacteristicFlags, CharacteristicId,
-    CharacteristicInfo, DescriptorInfo, DeviceId, DeviceInfo, MacAddress, ServiceInfo,
-    WriteOptions,
+    BluetoothError, BluetoothEvent, BluetoothSession, CharacteristicEvent, CharacteristicFlags,
+    CharacteristicId, CharacteristicInfo, DescriptorInfo, DeviceEvent, DeviceId, DeviceInfo,
+    MacAddress, ServiceId, ServiceInfo, WriteOptions,
 };
-use futures::future::{join_all, ready};
+use futures::future::{join_all, ready, select, Either};
+use futures::pin_mut;
 use futures::stream::{Stream, StreamExt};
+use log::debug;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "serde")]
 use serde_cr as serde;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt::{self, Display, Formatter};
+use std::future::Future;
+use std::iter;
 use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
 use uuid::Uuid;
 
+use super::adapter::Seen;
 use crate::api::{
-    self, AddressType, BDAddr, CharPropFlags, Characteristic, Descriptor, PeripheralProperties,
-    Service, ValueNotification, WriteType,
+    self,
+    trace::{self, traced_connect, traced_discover_services},
+    AddressType, BDAddr, CharPropFlags, Characteristic, DataLength, Descriptor, DisconnectOptions,
+    DisconnectReason, DiscoverOptions, ParsePeripheralIdError, PeripheralMetrics,
+    PeripheralProperties, Service, SubscriptionKind, TraceEvent, Transport, ValueNotification,
+    WriteType,
+};
+use crate::{
+    common::{
+        connect_limit::ConnectLimit, metrics::Metrics, pending::PendingOperations, runtime,
+        subscriptions::Subscriptions,
+    },
+    ConnectFailureReason, Error, Result,
 };
-use crate::{Error, Result};
 
 #[derive(Clone, Debug)]
 struct CharacteristicInternal {
@@ -37,7 +56,8 @@ impl CharacteristicInternal {
 #[derive(Clone, Debug)]
 struct ServiceInternal {
     info: ServiceInfo,
-    characteristics: HashMap<Uuid, CharacteristicInternal>,
+    /// Keyed by UUID and handle, as a service may have several characteristics with the same UUID.
+    characteristics: HashMap<(Uuid, u16), CharacteristicInternal>,
 }
 
 #[cfg_attr(
@@ -46,11 +66,20 @@ struct ServiceInternal {
     serde(crate = "serde_cr")
 )]
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub struct PeripheralId(pub(crate) DeviceId);
+pub struct PeripheralId {
+    /// The device's BlueZ object path, like `/org/bluez/hci0/dev_00_11_22_33_44_55`. This is kept
+    /// rather than a [`DeviceId`], which bluez-async provides no way to build from a string.
+    object_path: String,
+}
 
 impl Display for PeripheralId {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        self.0.fmt(f)
+        let object_path = &self.object_path;
+        f.write_str(
+            object_path
+                .strip_prefix("/org/bluez/")
+                .unwrap_or(object_path),
+        )
     }
 }
 
@@ -60,21 +89,127 @@ pub struct Peripheral {
     session: BluetoothSession,
     device: DeviceId,
     mac_address: BDAddr,
-    services: Arc<Mutex<HashMap<Uuid, ServiceInternal>>>,
+    services: Arc<Mutex<HashMap<(Uuid, u16), ServiceInternal>>>,
+    removed: Arc<AtomicBool>,
+    removed_devices: broadcast::Sender<DeviceId>,
+    disconnect_reasons: broadcast::Sender<(DeviceId, DisconnectReason)>,
+    max_retries: Arc<AtomicU32>,
+    connect_limit: Arc<ConnectLimit>,
+    last_seen: Arc<Mutex<HashMap<DeviceId, Seen>>>,
+    /// The devices of the adapter which are connected, as tracked by the adapter.
+    connected: Arc<Mutex<HashSet<DeviceId>>>,
+    advertising_sids: Arc<Mutex<HashMap<BDAddr, u8>>>,
+    pending: Arc<PendingOperations>,
+    metrics: Arc<Metrics>,
+    default_write_type: Arc<Mutex<Option<WriteType>>>,
+    subscriptions: Arc<Subscriptions>,
 }
 
+/// How long to wait before the first retry of an operation, increasing linearly for later ones.
+const RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Returns whether the error is BlueZ telling us that the D-Bus object a call was made on doesn't
+/// exist (any more).
+fn is_unknown_object(error: &BluetoothError) -> bool {
+    match error {
+        BluetoothError::DbusError(error) => matches!(
+            error.name(),
+            Some("org.freedesktop.DBus.Error.UnknownObject") | Some("org.bluez.Error.DoesNotExist")
+        ),
+        _ => false,
+    }
+}
+
+/// Returns whether the error is BlueZ telling us that the call overlapped with another operation on
+/// the same object, so may well succeed if tried again shortly.
+fn is_in_progress(error: &BluetoothError) -> bool {
+    match error {
+        BluetoothError::DbusError(error) => error.name() == Some("org.bluez.Error.InProgress"),
+        _ => false,
+    }
+}
+
+/// Classifies the error BlueZ gave for a failed `Connect`, if it is one which says why. Since 5.62
+/// BlueZ names the kernel's reason in the message, like `le-connection-abort-by-local`; older
+/// versions pass on the kernel's error string, like `Host is down`.
+fn connect_failure_reason(error: &BluetoothError) -> Option<ConnectFailureReason> {
+    let BluetoothError::DbusError(error) = error else {
+        return None;
+    };
+    if error.name() == Some("org.freedesktop.DBus.Error.NoReply") {
+        return Some(ConnectFailureReason::Timeout);
+    }
+    if !matches!(
+        error.name(),
+        Some("org.bluez.Error.Failed") | Some("org.bluez.Error.NotAvailable")
+    ) {
+        return None;
+    }
+    let message = error.message()?;
+    Some(
+        // The kernel aborts LE connection attempts which get no answer, so this usually means the
+        // device wasn't advertising.
+        if message.contains("page-timeout")
+            || message.contains("le-connection-abort-by-local")
+            || message.contains("connection-timeout")
+            || message.contains("Host is down")
+            || message.contains("timed out")
+            || message.contains("Software caused connection abort")
+        {
+            ConnectFailureReason::Timeout
+        } else if message.contains("refused") || message.contains("key-missing") {
+            ConnectFailureReason::Rejected
+        } else if message.contains("profile-unavailable") || message.contains("not supported") {
+            ConnectFailureReason::UnsupportedFeature
+        } else if message.contains("canceled") {
+            ConnectFailureReason::Cancelled
+        } else {
+            ConnectFailureReason::Unknown
+        },
+    )
+}
+
+/// Gets the handle of a GATT attribute from its object path, which BlueZ names after it, like
+/// `.../service0010/char0012/desc0014`.
+fn attribute_handle(path: impl Display, prefix: &str) -> Option<u16> {
+    path.to_string()
+        .rsplit('/')
+        .next()
+        .and_then(|name| name.strip_prefix(prefix))
+        .and_then(|handle| u16::from_str_radix(handle, 16).ok())
+}
+
+fn characteristic_handle(id: &CharacteristicId) -> u16 {
+    attribute_handle(id, "char").unwrap_or_default()
+}
+
+fn service_handle(id: &ServiceId) -> u16 {
+    attribute_handle(id, "service").unwrap_or_default()
+}
+
+/// Finds a characteristic by its service's UUID, and its own UUID and handle. A device may have
+/// several services with the same UUID, but characteristics' handles are unique across them.
 fn get_characteristic<'a>(
-    services: &'a HashMap<Uuid, ServiceInternal>,
+    services: &'a HashMap<(Uuid, u16), ServiceInternal>,
     service_uuid: &Uuid,
     characteristic_uuid: &Uuid,
+    instance_id: u16,
 ) -> Result<&'a CharacteristicInternal> {
+    let mut services = services
+        .iter()
+        .filter(|((uuid, _), _)| uuid == service_uuid)
+        .peekable();
+    if services.peek().is_none() {
+        return Err(Error::Other(
+            format!("Service with UUID {} not found.", service_uuid).into(),
+        ));
+    }
     services
-        .get(service_uuid)
-        .ok_or_else(|| {
-            Error::Other(format!("Service with UUID {} not found.", service_uuid).into())
-        })?
-        .characteristics
-        .get(characteristic_uuid)
+        .find_map(|(_, service)| {
+            service
+                .characteristics
+                .get(&(*characteristic_uuid, instance_id))
+        })
         .ok_or_else(|| {
             Error::Other(
                 format!(
@@ -87,12 +222,140 @@ fn get_characteristic<'a>(
 }
 
 impl Peripheral {
-    pub(crate) fn new(session: BluetoothSession, device: DeviceInfo) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        session: BluetoothSession,
+        device: DeviceInfo,
+        removed_devices: broadcast::Sender<DeviceId>,
+        disconnect_reasons: broadcast::Sender<(DeviceId, DisconnectReason)>,
+        max_retries: Arc<AtomicU32>,
+        connect_limit: Arc<ConnectLimit>,
+        last_seen: Arc<Mutex<HashMap<DeviceId, Seen>>>,
+        connected: Arc<Mutex<HashSet<DeviceId>>>,
+        advertising_sids: Arc<Mutex<HashMap<BDAddr, u8>>>,
+    ) -> Self {
         Peripheral {
             session,
             device: device.id,
             mac_address: device.mac_address.into(),
             services: Arc::new(Mutex::new(HashMap::new())),
+            removed: Arc::new(AtomicBool::new(false)),
+            removed_devices,
+            disconnect_reasons,
+            max_retries,
+            connect_limit,
+            last_seen,
+            connected,
+            advertising_sids,
+            pending: Default::default(),
+            metrics: Default::default(),
+            default_write_type: Default::default(),
+            subscriptions: Default::default(),
+        }
+    }
+
+    /// Runs a BlueZ call, retrying it with a short backoff while it fails because another
+    /// operation is in progress, up to the adapter's retry limit. The final result goes through
+    /// [`Self::check`].
+    async fn retry<T, F, Fut>(&self, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = std::result::Result<T, BluetoothError>>,
+    {
+        let mut attempts = 0;
+        loop {
+            match operation().await {
+                Err(error)
+                    if is_in_progress(&error)
+                        && attempts < self.max_retries.load(Ordering::Relaxed) =>
+                {
+                    attempts += 1;
+                    debug!("{} in progress, retrying ({})", self.device, attempts);
+                    runtime::sleep(RETRY_DELAY * attempts).await;
+                }
+                result => return self.check(result).await,
+            }
+        }
+    }
+
+    /// Runs a GATT operation on the device, reporting it as [`Error::NotConnected`] without
+    /// touching BlueZ if the device isn't connected as far as the adapter has tracked. The device
+    /// may disconnect before the operation gets to it though, which BlueZ reports in several
+    /// different ways, or by the characteristic no longer existing, so the connection state is
+    /// also checked after a failure.
+    async fn or_not_connected<T>(&self, operation: impl Future<Output = Result<T>>) -> Result<T> {
+        if !self.tracked_connected() {
+            return Err(Error::NotConnected);
+        }
+        match operation.await {
+            Err(error) if !matches!(error, Error::NotConnected | Error::DeviceNotFound) => {
+                match self.session.get_device_info(&self.device).await {
+                    Ok(device_info) if !device_info.connected => {
+                        self.set_connected(false);
+                        Err(Error::NotConnected)
+                    }
+                    _ => Err(error),
+                }
+            }
+            result => result,
+        }
+    }
+
+    fn tracked_connected(&self) -> bool {
+        self.connected.lock().unwrap().contains(&self.device)
+    }
+
+    fn set_connected(&self, connected: bool) {
+        let mut tracked = self.connected.lock().unwrap();
+        if connected {
+            tracked.insert(self.device.clone());
+        } else {
+            tracked.remove(&self.device);
+        }
+    }
+
+    /// Converts the result of a BlueZ call made for this device. BlueZ removes devices which
+    /// haven't been seen for a while, along with all their D-Bus objects, after which every call
+    /// fails with `UnknownObject`. Report that as `DeviceNotFound`, and treat the device as having
+    /// disconnected. The device will get the same object path if it's discovered again, so there's
+    /// nothing to re-resolve.
+    async fn check<T>(&self, result: std::result::Result<T, BluetoothError>) -> Result<T> {
+        match result {
+            Err(error) if is_unknown_object(&error) => {
+                // It may just be a characteristic or descriptor that has gone away, so check the
+                // device itself.
+                match self.session.get_device_info(&self.device).await {
+                    Err(device_error) if is_unknown_object(&device_error) => {
+                        self.on_removed();
+                        Err(Error::DeviceNotFound)
+                    }
+                    Ok(_) => {
+                        self.on_found();
+                        Err(error.into())
+                    }
+                    Err(_) => Err(error.into()),
+                }
+            }
+            Ok(value) => {
+                self.on_found();
+                Ok(value)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Notes that the device is known to BlueZ, as it may have been rediscovered since it was
+    /// removed, so that it is reported as disconnected if it is removed again.
+    fn on_found(&self) {
+        self.removed.store(false, Ordering::Relaxed);
+    }
+
+    fn on_removed(&self) {
+        self.services.lock().unwrap().clear();
+        self.set_connected(false);
+        if !self.removed.swap(true, Ordering::Relaxed) {
+            // Nothing may be listening for events, which is fine.
+            let _ = self.removed_devices.send(self.device.clone());
         }
     }
 
@@ -102,6 +365,7 @@ impl Peripheral {
             &services,
             &characteristic.service_uuid,
             &characteristic.uuid,
+            characteristic.instance_id,
         )
         .map(|c| &c.info)
         .cloned()
@@ -113,6 +377,7 @@ impl Peripheral {
             &services,
             &descriptor.service_uuid,
             &descriptor.characteristic_uuid,
+            descriptor.characteristic_instance_id,
         )?;
         characteristic
             .descriptors
@@ -124,33 +389,69 @@ impl Peripheral {
     }
 
     async fn device_info(&self) -> Result<DeviceInfo> {
-        Ok(self.session.get_device_info(&self.device).await?)
+        match self.session.get_device_info(&self.device).await {
+            Ok(device_info) => {
+                self.on_found();
+                Ok(device_info)
+            }
+            Err(error) if is_unknown_object(&error) => {
+                self.on_removed();
+                Err(Error::DeviceNotFound)
+            }
+            Err(error) => Err(error.into()),
+        }
     }
 }
 
 #[async_trait]
 impl api::Peripheral for Peripheral {
     fn id(&self) -> PeripheralId {
-        PeripheralId(self.device.to_owned())
+        PeripheralId::from(self.device.to_owned())
     }
 
     fn address(&self) -> BDAddr {
         self.mac_address
     }
 
+    fn pending_operations(&self) -> usize {
+        self.pending.operations()
+    }
+
+    fn pending_writes(&self) -> usize {
+        self.pending.writes()
+    }
+
+    fn abort_pending(&self) {
+        self.pending.abort();
+    }
+
+    fn metrics(&self) -> PeripheralMetrics {
+        // BlueZ doesn't expose the MTU to clients.
+        self.metrics.snapshot(None)
+    }
+
+    fn default_write_type(&self) -> Option<WriteType> {
+        *self.default_write_type.lock().unwrap()
+    }
+
+    fn set_default_write_type(&self, write_type: WriteType) {
+        *self.default_write_type.lock().unwrap() = Some(write_type);
+    }
+
+    fn subscriptions(&self) -> Vec<Characteristic> {
+        self.subscriptions.get()
+    }
+
     async fn properties(&self) -> Result<Option<PeripheralProperties>> {
         let device_info = self.device_info().await?;
-        Ok(Some(PeripheralProperties {
-            address: device_info.mac_address.into(),
-            address_type: Some(device_info.address_type.into()),
-            local_name: device_info.name,
-            tx_power_level: device_info.tx_power,
-            rssi: device_info.rssi,
-            manufacturer_data: device_info.manufacturer_data,
-            service_data: device_info.service_data,
-            services: device_info.services,
-            class: device_info.class,
-        }))
+        let seen = self.last_seen.lock().unwrap().get(&self.device).copied();
+        let sid = self
+            .advertising_sids
+            .lock()
+            .unwrap()
+            .get(&self.mac_address)
+            .copied();
+        Ok(Some(properties_from_info(device_info, seen, sid)))
     }
 
     fn services(&self) -> BTreeSet<Service> {
@@ -163,118 +464,456 @@ impl api::Peripheral for Peripheral {
     }
 
     async fn is_connected(&self) -> Result<bool> {
-        let device_info = self.device_info().await?;
-        Ok(device_info.connected)
+        match self.device_info().await {
+            Ok(device_info) => {
+                self.set_connected(device_info.connected);
+                Ok(device_info.connected)
+            }
+            Err(Error::DeviceNotFound) => Ok(false),
+            Err(error) => Err(error),
+        }
     }
 
     async fn connect(&self) -> Result<()> {
-        self.session.connect(&self.device).await?;
-        Ok(())
+        let _permit = self.connect_limit.acquire().await;
+        traced_connect(self, async {
+            let result = self.session.connect(&self.device).await;
+            if let Err(error) = &result {
+                if let Some(reason) = connect_failure_reason(error) {
+                    return Err(Error::ConnectionFailed(reason, error.to_string()));
+                }
+            }
+            self.check(result).await.inspect(|()| {
+                self.set_connected(true);
+                self.metrics.connected();
+            })
+        })
+        .await
     }
 
     async fn disconnect(&self) -> Result<()> {
-        self.session.disconnect(&self.device).await?;
+        self.check(self.session.disconnect(&self.device).await)
+            .await
+            .inspect(|()| self.set_connected(false))
+    }
+
+    async fn disconnect_with_options(&self, options: DisconnectOptions) -> Result<()> {
+        self.disconnect().await?;
+        if !options.keep_cache {
+            self.services.lock().unwrap().clear();
+        }
+        if let Some(reason) = options.reason {
+            // Nothing may be listening for events, which is fine.
+            let _ = self.disconnect_reasons.send((self.device.clone(), reason));
+        }
         Ok(())
     }
 
     async fn discover_services(&self) -> Result<()> {
-        let mut services_internal = HashMap::new();
-        let services = self.session.get_services(&self.device).await?;
-        for service in services {
-            let characteristics = self.session.get_characteristics(&service.id).await?;
-            let characteristics =
-                join_all(characteristics.into_iter().map(|characteristic| async {
-                    let descriptors = self
-                        .session
-                        .get_descriptors(&characteristic.id)
-                        .await
-                        .unwrap_or(Vec::new())
-                        .into_iter()
-                        .map(|descriptor| (descriptor.uuid, descriptor))
-                        .collect();
-                    CharacteristicInternal::new(characteristic, descriptors)
-                }))
-                .await;
-            services_internal.insert(
-                service.uuid,
-                ServiceInternal {
-                    info: service,
-                    characteristics: characteristics
-                        .into_iter()
-                        .map(|characteristic| (characteristic.info.uuid, characteristic))
-                        .collect(),
-                },
-            );
+        self.discover_services_with_options(DiscoverOptions::default())
+            .await
+    }
+
+    async fn discover_services_with_options(&self, options: DiscoverOptions) -> Result<()> {
+        traced_discover_services(self, async {
+            let mut services_internal = HashMap::new();
+            let services = self
+                .check(self.session.get_services(&self.device).await)
+                .await?;
+            for service in services {
+                if !options.services.is_empty() && !options.services.contains(&service.uuid) {
+                    continue;
+                }
+                let characteristics = self
+                    .check(self.session.get_characteristics(&service.id).await)
+                    .await?;
+                let characteristics =
+                    join_all(characteristics.into_iter().map(|characteristic| async {
+                        if !options.descriptors {
+                            return CharacteristicInternal::new(characteristic, HashMap::new());
+                        }
+                        let descriptors = self
+                            .session
+                            .get_descriptors(&characteristic.id)
+                            .await
+                            .unwrap_or(Vec::new())
+                            .into_iter()
+                            .map(|descriptor| (descriptor.uuid, descriptor))
+                            .collect();
+                        CharacteristicInternal::new(characteristic, descriptors)
+                    }))
+                    .await;
+                services_internal.insert(
+                    (service.uuid, service_handle(&service.id)),
+                    ServiceInternal {
+                        info: service,
+                        characteristics: characteristics
+                            .into_iter()
+                            .map(|characteristic| {
+                                let handle = characteristic_handle(&characteristic.info.id);
+                                ((characteristic.info.uuid, handle), characteristic)
+                            })
+                            .collect(),
+                    },
+                );
+            }
+            let mut services = self.services.lock().unwrap();
+            if options.services.is_empty() {
+                *services = services_internal;
+            } else {
+                // Services which weren't asked for are left as they were.
+                services.retain(|(uuid, _), _| !options.services.contains(uuid));
+                services.extend(services_internal);
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn discover_descriptors(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Vec<Descriptor>> {
+        let info = self.characteristic_info(characteristic)?;
+        let descriptors: HashMap<Uuid, DescriptorInfo> = self
+            .check(self.session.get_descriptors(&info.id).await)
+            .await?
+            .into_iter()
+            .map(|descriptor| (descriptor.uuid, descriptor))
+            .collect();
+        let result: BTreeSet<_> = descriptors
+            .values()
+            .map(|descriptor| make_descriptor(descriptor, &info, characteristic.service_uuid))
+            .collect();
+        let key = (characteristic.uuid, characteristic.instance_id);
+        if let Some(internal) = self
+            .services
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter(|((uuid, _), _)| *uuid == characteristic.service_uuid)
+            .find_map(|(_, service)| service.characteristics.get_mut(&key))
+        {
+            internal.descriptors = descriptors;
         }
-        *self.services.lock().unwrap() = services_internal;
+        Ok(result.into_iter().collect())
+    }
+
+    async fn wait_services_resolved(&self, timeout: Duration) -> Result<()> {
+        let wait = async {
+            // Get the events first, so that ServicesResolved can't be missed in between.
+            let mut events = self.session.device_event_stream(&self.device).await?;
+            let device_info = self.device_info().await?;
+            if !device_info.connected {
+                return Err(Error::NotConnected);
+            }
+            if !device_info.services_resolved {
+                while let Some(event) = events.next().await {
+                    match event {
+                        BluetoothEvent::Device {
+                            event: DeviceEvent::ServicesResolved,
+                            ..
+                        } => break,
+                        BluetoothEvent::Device {
+                            event: DeviceEvent::Connected { connected: false },
+                            ..
+                        } => return Err(Error::NotConnected),
+                        _ => {}
+                    }
+                }
+            }
+            self.discover_services().await
+        };
+        let timeout_elapsed = runtime::sleep(timeout);
+        pin_mut!(wait, timeout_elapsed);
+        match select(wait, timeout_elapsed).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err(Error::TimedOut(timeout)),
+        }
+    }
+
+    async fn clear_gatt_cache(&self) -> Result<()> {
+        let device_info = self.device_info().await?;
+        if device_info.paired {
+            return Err(Error::NotSupported(
+                "BlueZ can't clear the GATT cache of a paired device without unpairing it"
+                    .to_string(),
+            ));
+        }
+        if device_info.connected {
+            self.disconnect().await?;
+        }
+        let device = self.device.clone();
+        // bluez-async doesn't expose removing a device.
+        let adapter = device.adapter().into();
+        super::dbus::call_method::<_, ()>(
+            adapter,
+            "org.bluez.Adapter1",
+            "RemoveDevice",
+            (dbus::Path::from(device),),
+        )
+        .await?;
+        self.on_removed();
         Ok(())
     }
 
+    async fn require_encryption(&self) -> Result<()> {
+        let device_info = self.device_info().await?;
+        if !device_info.connected {
+            return Err(Error::NotConnected);
+        }
+        if device_info.paired {
+            return Ok(());
+        }
+        // bluez-async doesn't expose pairing.
+        let result = super::dbus::call_method::<_, ()>(
+            self.device.clone().into(),
+            "org.bluez.Device1",
+            "Pair",
+            (),
+        )
+        .await;
+        if let Err(error) = result {
+            let already_paired = matches!(
+                &error,
+                Error::Other(error) if matches!(
+                    error.downcast_ref::<BluetoothError>(),
+                    Some(BluetoothError::DbusError(error))
+                        if error.name() == Some("org.bluez.Error.AlreadyExists")
+                )
+            );
+            if !already_paired {
+                debug!("Pairing with {:?} failed: {}", self.device, error);
+                return Err(Error::NotPaired);
+            }
+        }
+        if self.device_info().await?.paired {
+            Ok(())
+        } else {
+            Err(Error::NotPaired)
+        }
+    }
+
     async fn write(
         &self,
         characteristic: &Characteristic,
         data: &[u8],
         write_type: WriteType,
     ) -> Result<()> {
-        let characteristic_info = self.characteristic_info(characteristic)?;
-        let options = WriteOptions {
-            write_type: Some(write_type.into()),
-            ..Default::default()
+        self.pending
+            .track_write(self.or_not_connected(async {
+                let characteristic_info = self.characteristic_info(characteristic)?;
+                if write_type == WriteType::SignedWithoutResponse
+                    && !characteristic
+                        .properties
+                        .contains(CharPropFlags::AUTHENTICATED_SIGNED_WRITES)
+                {
+                    return Err(Error::NotSupported(
+                        "Characteristic does not support signed writes".to_string(),
+                    ));
+                }
+                let options = WriteOptions {
+                    write_type: Some(write_type.into()),
+                    ..Default::default()
+                };
+                self.retry(|| {
+                    self.session.write_characteristic_value_with_options(
+                        &characteristic_info.id,
+                        data,
+                        options,
+                    )
+                })
+                .await
+            }))
+            .await
+            .inspect(|()| self.metrics.written(data.len()))
+    }
+
+    async fn execute_reliable_write(&self, writes: &[(Characteristic, Vec<u8>)]) -> Result<()> {
+        // BlueZ prepares and executes the writes to a characteristic itself, so can't be asked to
+        // include any other characteristic in the transaction.
+        if writes.is_empty() {
+            return Ok(());
+        }
+        let [(characteristic, data)] = writes else {
+            return Err(Error::NotSupported(
+                "Reliable writes to more than one characteristic are not supported on this platform"
+                    .to_string(),
+            ));
         };
-        Ok(self
-            .session
-            .write_characteristic_value_with_options(&characteristic_info.id, data, options)
-            .await?)
+        self.pending
+            .track_write(self.or_not_connected(async {
+                let characteristic_info = self.characteristic_info(characteristic)?;
+                let options = WriteOptions {
+                    write_type: Some(bluez_async::WriteType::Reliable),
+                    ..Default::default()
+                };
+                self.retry(|| {
+                    self.session.write_characteristic_value_with_options(
+                        &characteristic_info.id,
+                        data.as_slice(),
+                        options,
+                    )
+                })
+                .await
+            }))
+            .await
     }
 
     async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
-        let characteristic_info = self.characteristic_info(characteristic)?;
-        Ok(self
-            .session
-            .read_characteristic_value(&characteristic_info.id)
-            .await?)
+        self.pending
+            .track(self.or_not_connected(async {
+                let characteristic_info = self.characteristic_info(characteristic)?;
+                self.retry(|| {
+                    self.session
+                        .read_characteristic_value(&characteristic_info.id)
+                })
+                .await
+            }))
+            .await
+            .inspect(|value| self.metrics.read(value.len()))
+    }
+
+    async fn read_cached(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        let id = self.characteristic_info(characteristic)?.id;
+        // bluez-async only exposes reading the value from the device.
+        let value: Vec<u8> =
+            super::dbus::get_property(id.into(), "org.bluez.GattCharacteristic1", "Value").await?;
+        if value.is_empty() {
+            // BlueZ hasn't received a value yet.
+            return self.read(characteristic).await;
+        }
+        Ok(value)
+    }
+
+    async fn read_device_name(&self) -> Result<String> {
+        self.device_info()
+            .await?
+            .name
+            .ok_or(Error::NoSuchCharacteristic)
+    }
+
+    async fn subscribe_with(
+        &self,
+        characteristic: &Characteristic,
+        kind: SubscriptionKind,
+    ) -> Result<()> {
+        api::subscribe_preferring_notify(self, characteristic, kind).await
     }
 
     async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        let characteristic_info = self.characteristic_info(characteristic)?;
-        Ok(self.session.start_notify(&characteristic_info.id).await?)
+        self.subscriptions
+            .subscribe(
+                characteristic,
+                self.pending.track(self.or_not_connected(async {
+                    let characteristic_info = self.characteristic_info(characteristic)?;
+                    self.retry(|| self.session.start_notify(&characteristic_info.id))
+                        .await
+                })),
+            )
+            .await
     }
 
     async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        let characteristic_info = self.characteristic_info(characteristic)?;
-        Ok(self.session.stop_notify(&characteristic_info.id).await?)
+        self.subscriptions
+            .unsubscribe(
+                characteristic,
+                self.pending.track(self.or_not_connected(async {
+                    let characteristic_info = self.characteristic_info(characteristic)?;
+                    self.retry(|| self.session.stop_notify(&characteristic_info.id))
+                        .await
+                })),
+            )
+            .await
     }
 
     async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
         let device_id = self.device.clone();
         let events = self.session.device_event_stream(&device_id).await?;
         let services = self.services.clone();
+        let metrics = self.metrics.clone();
         Ok(Box::pin(events.filter_map(move |event| {
-            ready(value_notification(event, &device_id, services.clone()))
+            ready(value_notification(
+                event,
+                &device_id,
+                services.clone(),
+                &metrics,
+            ))
         })))
     }
 
     async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
-        let descriptor_info = self.descriptor_info(descriptor)?;
-        Ok(self
-            .session
-            .write_descriptor_value(&descriptor_info.id, data)
-            .await?)
+        self.pending
+            .track(self.or_not_connected(async {
+                let descriptor_info = self.descriptor_info(descriptor)?;
+                self.check(
+                    self.session
+                        .write_descriptor_value(&descriptor_info.id, data)
+                        .await,
+                )
+                .await
+            }))
+            .await
     }
 
     async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
-        let descriptor_info = self.descriptor_info(descriptor)?;
-        Ok(self
+        self.pending
+            .track(self.or_not_connected(async {
+                let descriptor_info = self.descriptor_info(descriptor)?;
+                self.check(
+                    self.session
+                        .read_descriptor_value(&descriptor_info.id)
+                        .await,
+                )
+                .await
+            }))
+            .await
+    }
+
+    async fn connection_handle(&self) -> Result<Option<u16>> {
+        super::mgmt::connection_handle(&self.device.adapter(), self.mac_address).await
+    }
+
+    async fn data_length(&self) -> Result<Option<DataLength>> {
+        let adapter = self.device.adapter();
+        if super::mgmt::connection_handle(&adapter, self.mac_address)
+            .await?
+            .is_none()
+        {
+            return Ok(None);
+        }
+        super::mgmt::max_data_length(&adapter).await
+    }
+
+    async fn read_tx_power_level(&self) -> Result<i8> {
+        let adapter = self.device.adapter();
+        let handle = super::mgmt::connection_handle(&adapter, self.mac_address)
+            .await?
+            .ok_or(Error::NotConnected)?;
+        super::mgmt::tx_power_level(&adapter, handle).await
+    }
+
+    #[cfg(feature = "unstable-raw-att")]
+    async fn raw_att(&self, request: api::AttRequest) -> Result<api::AttResponse> {
+        let adapter_info = self
             .session
-            .read_descriptor_value(&descriptor_info.id)
-            .await?)
+            .get_adapter_info(&self.device.adapter())
+            .await?;
+        let device_info = self.device_info().await?;
+        super::att::request(
+            (adapter_info.mac_address.into(), adapter_info.address_type),
+            (self.mac_address, device_info.address_type),
+            request,
+        )
+        .await
     }
 }
 
 fn value_notification(
     event: BluetoothEvent,
     device_id: &DeviceId,
-    services: Arc<Mutex<HashMap<Uuid, ServiceInternal>>>,
+    services: Arc<Mutex<HashMap<(Uuid, u16), ServiceInternal>>>,
+    metrics: &Metrics,
 ) -> Option<ValueNotification> {
     match event {
         BluetoothEvent::Characteristic {
@@ -282,21 +921,33 @@ fn value_notification(
             event: CharacteristicEvent::Value { value },
         } if id.service().device() == *device_id => {
             let services = services.lock().unwrap();
-            let uuid = find_characteristic_by_id(&services, id)?.uuid;
-            Some(ValueNotification { uuid, value })
+            let (service_uuid, info) = find_characteristic_by_id(&services, id)?;
+            let uuid = info.uuid;
+            trace::emit(|| TraceEvent::NotificationDelivered {
+                id: PeripheralId::from(device_id.clone()),
+                characteristic: uuid,
+            });
+            metrics.notification();
+            Some(ValueNotification {
+                uuid,
+                service_uuid,
+                instance_id: characteristic_handle(&info.id),
+                value,
+            })
         }
         _ => None,
     }
 }
 
+/// Finds the characteristic with the given ID, and the UUID of its service.
 fn find_characteristic_by_id(
-    services: &HashMap<Uuid, ServiceInternal>,
+    services: &HashMap<(Uuid, u16), ServiceInternal>,
     characteristic_id: CharacteristicId,
-) -> Option<&CharacteristicInfo> {
-    for service in services.values() {
+) -> Option<(Uuid, &CharacteristicInfo)> {
+    for (&(service_uuid, _), service) in services {
         for characteristic in service.characteristics.values() {
             if characteristic.info.id == characteristic_id {
-                return Some(&characteristic.info);
+                return Some((service_uuid, &characteristic.info));
             }
         }
     }
@@ -308,6 +959,9 @@ impl From<WriteType> for bluez_async::WriteType {
         match write_type {
             WriteType::WithoutResponse => bluez_async::WriteType::WithoutResponse,
             WriteType::WithResponse => bluez_async::WriteType::WithResponse,
+            // BlueZ signs write commands itself for characteristics which support it, when the
+            // link isn't already encrypted.
+            WriteType::SignedWithoutResponse => bluez_async::WriteType::WithoutResponse,
         }
     }
 }
@@ -320,7 +974,66 @@ impl From<MacAddress> for BDAddr {
 
 impl From<DeviceId> for PeripheralId {
     fn from(device_id: DeviceId) -> Self {
-        PeripheralId(device_id)
+        PeripheralId {
+            object_path: dbus::Path::from(device_id).to_string(),
+        }
+    }
+}
+
+impl FromStr for PeripheralId {
+    type Err = ParsePeripheralIdError;
+
+    /// Parses an ID in the form it is displayed, the device's BlueZ object path without the
+    /// `/org/bluez/` prefix, like `hci0/dev_00_11_22_33_44_55`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let error =
+            || ParsePeripheralIdError::new(s, "a BlueZ device like hci0/dev_00_11_22_33_44_55");
+        let (adapter, device) = s.split_once('/').ok_or_else(error)?;
+        let address = device.strip_prefix("dev_").ok_or_else(error)?;
+        if adapter.is_empty() || BDAddr::from_str_delim(&address.replace('_', ":")).is_err() {
+            return Err(error());
+        }
+        Ok(PeripheralId {
+            object_path: format!("/org/bluez/{}", s),
+        })
+    }
+}
+
+/// BlueZ doesn't tell us directly which bearers a device has been seen on, so infer it: a class of
+/// device only comes from BR/EDR inquiry, while an appearance or a random address only comes from
+/// LE advertising.
+pub(crate) fn properties_from_info(
+    device_info: DeviceInfo,
+    seen: Option<Seen>,
+    advertising_sid: Option<u8>,
+) -> PeripheralProperties {
+    let transport = transport(&device_info);
+    PeripheralProperties {
+        address: device_info.mac_address.into(),
+        address_type: Some(device_info.address_type.into()),
+        local_name: device_info.name,
+        tx_power_level: device_info.tx_power,
+        rssi: device_info.rssi,
+        smoothed_rssi: seen.and_then(|seen| seen.rssi.get()).or(device_info.rssi),
+        manufacturer_data: device_info.manufacturer_data,
+        service_data: device_info.service_data,
+        advertised_services: device_info.services,
+        class: device_info.class,
+        transport,
+        last_seen: seen.map(|seen| seen.at),
+        has_scan_response: None,
+        connectable: None,
+        advertising_sid,
+    }
+}
+
+fn transport(device_info: &DeviceInfo) -> Transport {
+    let le = device_info.appearance.is_some()
+        || device_info.address_type == bluez_async::AddressType::Random;
+    match (device_info.class.is_some(), le) {
+        (true, true) => Transport::DualMode,
+        (true, false) => Transport::BrEdr,
+        (false, _) => Transport::Le,
     }
 }
 
@@ -335,12 +1048,13 @@ impl From<bluez_async::AddressType> for AddressType {
 
 fn make_descriptor(
     info: &DescriptorInfo,
-    characteristic_uuid: Uuid,
+    characteristic: &CharacteristicInfo,
     service_uuid: Uuid,
 ) -> Descriptor {
     Descriptor {
         uuid: info.uuid,
-        characteristic_uuid,
+        characteristic_uuid: characteristic.uuid,
+        characteristic_instance_id: characteristic_handle(&characteristic.id),
         service_uuid,
     }
 }
@@ -352,10 +1066,11 @@ fn make_characteristic(
     let CharacteristicInternal { info, descriptors } = characteristic;
     Characteristic {
         uuid: info.uuid,
+        instance_id: characteristic_handle(&info.id),
         properties: info.flags.into(),
         descriptors: descriptors
-            .iter()
-            .map(|(_, descriptor)| make_descriptor(descriptor, info.uuid, service_uuid))
+            .values()
+            .map(|descriptor| make_descriptor(descriptor, info, service_uuid))
             .collect(),
         service_uuid,
     }
@@ -363,9 +1078,28 @@ fn make_characteristic(
 
 impl From<&ServiceInternal> for Service {
     fn from(service: &ServiceInternal) -> Self {
+        let start_handle = attribute_handle(&service.info.id, "service");
+        // The characteristic's object is named after its declaration, which is followed by its
+        // value and then its descriptors.
+        let end_handle = service
+            .characteristics
+            .values()
+            .flat_map(|characteristic| {
+                let handle = attribute_handle(&characteristic.info.id, "char");
+                let descriptors = characteristic
+                    .descriptors
+                    .values()
+                    .map(|descriptor| attribute_handle(&descriptor.id, "desc"));
+                iter::once(handle.map(|handle| handle.saturating_add(1))).chain(descriptors)
+            })
+            .chain(iter::once(start_handle))
+            .max()
+            .flatten();
         Service {
             uuid: service.info.uuid,
             primary: service.info.primary,
+            start_handle,
+            end_handle,
             characteristics: service
                 .characteristics
                 .values()
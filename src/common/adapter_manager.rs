@@ -11,12 +11,16 @@
 // following copyright:
 //
 // Copyright (c) 2014 The Rust Project Developers
+use super::{connect_limit::ConnectLimit, rssi_history::RssiSmoothing, runtime};
 use crate::api::{CentralEvent, Peripheral};
 use crate::platform::PeripheralId;
-use dashmap::{mapref::one::RefMut, DashMap};
+use dashmap::{mapref::one::RefMut, DashMap, DashSet};
 use futures::stream::{Stream, StreamExt};
 use log::trace;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::broadcast;
 use tokio_stream::wrappers::BroadcastStream;
 
@@ -26,15 +30,42 @@ where
     PeripheralType: Peripheral,
 {
     peripherals: DashMap<PeripheralId, PeripheralType>,
+    /// The peripherals we've seen a `DeviceConnected` event for, and no `DeviceDisconnected` since.
+    connected: DashSet<PeripheralId>,
     events_channel: broadcast::Sender<CentralEvent>,
+    /// When each peripheral was last added or reported as advertising, for evicting the least
+    /// recently seen when there are more than `max_peripherals`.
+    last_seen: DashMap<PeripheralId, Instant>,
+    /// How many peripherals may be kept before the least recently seen are evicted, if there's a
+    /// limit.
+    max_peripherals: Mutex<Option<usize>>,
+    /// How long a peripheral may go unseen before it is evicted, if at all.
+    ttl: Mutex<Option<Duration>>,
+    /// Whether the task evicting stale peripherals is running.
+    sweeping: AtomicBool,
+    /// Shared with all peripherals of the adapter.
+    connect_limit: Arc<ConnectLimit>,
+    /// How much the peripherals smooth their signal strengths.
+    rssi_smoothing: RssiSmoothing,
 }
 
+/// The longest time between sweeps for stale peripherals. Sweeps happen more often than this if the
+/// TTL is shorter.
+const MAX_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
 impl<PeripheralType: Peripheral + 'static> Default for AdapterManager<PeripheralType> {
     fn default() -> Self {
         let (broadcast_sender, _) = broadcast::channel(16);
         AdapterManager {
             peripherals: DashMap::new(),
+            connected: DashSet::new(),
             events_channel: broadcast_sender,
+            last_seen: DashMap::new(),
+            max_peripherals: Mutex::new(None),
+            ttl: Mutex::new(None),
+            sweeping: AtomicBool::new(false),
+            connect_limit: Default::default(),
+            rssi_smoothing: Default::default(),
         }
     }
 }
@@ -44,8 +75,30 @@ where
     PeripheralType: Peripheral + 'static,
 {
     pub fn emit(&self, event: CentralEvent) {
-        if let CentralEvent::DeviceDisconnected(ref id) = event {
-            self.peripherals.remove(id);
+        match &event {
+            CentralEvent::DeviceConnected(id) => {
+                self.connected.insert(id.clone());
+            }
+            CentralEvent::DeviceDisconnected(id) => {
+                self.connected.remove(id);
+                self.peripherals.remove(id);
+                self.last_seen.remove(id);
+            }
+            CentralEvent::DeviceLost(id) => {
+                self.peripherals.remove(id);
+                self.last_seen.remove(id);
+            }
+            CentralEvent::DeviceDiscovered(id)
+            | CentralEvent::DeviceUpdated(id)
+            | CentralEvent::ManufacturerDataAdvertisement { id, .. }
+            | CentralEvent::ServiceDataAdvertisement { id, .. }
+            | CentralEvent::ServicesAdvertisement { id, .. } => {
+                // Backends may emit while holding an entry of `peripherals`, so don't look at it.
+                if let Some(mut last_seen) = self.last_seen.get_mut(id) {
+                    *last_seen = Instant::now();
+                }
+            }
+            _ => {}
         }
 
         if let Err(lost) = self.events_channel.send(event) {
@@ -63,7 +116,9 @@ where
             !self.peripherals.contains_key(&peripheral.id()),
             "Adding a peripheral that's already in the map."
         );
+        self.last_seen.insert(peripheral.id(), Instant::now());
         self.peripherals.insert(peripheral.id(), peripheral);
+        self.evict_excess();
     }
 
     pub fn peripherals(&self) -> Vec<PeripheralType> {
@@ -73,14 +128,113 @@ where
             .collect()
     }
 
+    pub fn connected_peripherals(&self) -> Vec<PeripheralType> {
+        self.peripherals
+            .iter()
+            .filter(|val| self.connected.contains(val.key()))
+            .map(|val| val.value().clone())
+            .collect()
+    }
+
+    // Only used by the platform backends, not the mock one.
+    #[cfg_attr(all(target_os = "linux", feature = "mock"), allow(dead_code))]
     pub fn peripheral_mut(
         &self,
         id: &PeripheralId,
-    ) -> Option<RefMut<PeripheralId, PeripheralType>> {
+    ) -> Option<RefMut<'_, PeripheralId, PeripheralType>> {
         self.peripherals.get_mut(id)
     }
 
     pub fn peripheral(&self, id: &PeripheralId) -> Option<PeripheralType> {
         self.peripherals.get(id).map(|val| val.value().clone())
     }
+
+    /// Returns the limit on concurrent connection attempts, for a new peripheral to share.
+    pub fn connect_limit(&self) -> Arc<ConnectLimit> {
+        self.connect_limit.clone()
+    }
+
+    /// Returns the weight the peripherals give new signal strengths in their smoothed ones.
+    pub fn rssi_smoothing(&self) -> &RssiSmoothing {
+        &self.rssi_smoothing
+    }
+
+    /// Sets how many peripherals may be kept, evicting the least recently seen of those which
+    /// aren't connected whenever there are more. `None` removes the limit.
+    pub fn set_max_peripherals(&self, max: Option<usize>) {
+        *self.max_peripherals.lock().unwrap() = max;
+        self.evict_excess();
+    }
+
+    /// Removes the least recently seen peripherals which are not connected until there are no more
+    /// than `max_peripherals`, emitting [`CentralEvent::DeviceLost`] for each.
+    fn evict_excess(&self) {
+        let Some(max) = *self.max_peripherals.lock().unwrap() else {
+            return;
+        };
+        let excess = self.peripherals.len().saturating_sub(max);
+        if excess == 0 {
+            return;
+        }
+        let mut evictable: Vec<_> = self
+            .last_seen
+            .iter()
+            .filter(|entry| !self.connected.contains(entry.key()))
+            .map(|entry| (*entry.value(), entry.key().clone()))
+            .collect();
+        evictable.sort();
+        for (_, id) in evictable.into_iter().take(excess) {
+            trace!(
+                "Evicting peripheral {:?}, as there are more than {}",
+                id,
+                max
+            );
+            self.emit(CentralEvent::DeviceLost(id));
+        }
+    }
+
+    /// Sets how long peripherals may go unseen before being evicted, starting a background sweep
+    /// for them if one isn't already running. `None` stops the sweep.
+    pub fn set_peripheral_ttl(self: &Arc<Self>, ttl: Option<Duration>) {
+        *self.ttl.lock().unwrap() = ttl;
+        if ttl.is_some() && !self.sweeping.swap(true, Ordering::SeqCst) {
+            runtime::spawn(Self::sweep(Arc::downgrade(self)));
+        }
+    }
+
+    async fn sweep(manager: std::sync::Weak<Self>) {
+        loop {
+            let Some(manager) = manager.upgrade() else {
+                break;
+            };
+            let ttl = *manager.ttl.lock().unwrap();
+            let Some(ttl) = ttl else {
+                manager.sweeping.store(false, Ordering::SeqCst);
+                break;
+            };
+            manager.evict_stale(ttl).await;
+            drop(manager);
+            runtime::sleep(ttl.min(MAX_SWEEP_INTERVAL)).await;
+        }
+    }
+
+    /// Removes the peripherals which are not connected and haven't been seen for at least `ttl`,
+    /// emitting [`CentralEvent::DeviceLost`] for each.
+    async fn evict_stale(&self, ttl: Duration) {
+        let now = Instant::now();
+        for peripheral in self.peripherals() {
+            let id = peripheral.id();
+            if self.connected.contains(&id) || peripheral.is_connected().await.unwrap_or(true) {
+                continue;
+            }
+            let last_seen = match peripheral.properties().await {
+                Ok(Some(properties)) => properties.last_seen,
+                _ => None,
+            };
+            if matches!(last_seen, Some(last_seen) if now.duration_since(last_seen) >= ttl) {
+                trace!("Evicting peripheral {:?}, not seen for {:?}", id, ttl);
+                self.emit(CentralEvent::DeviceLost(id));
+            }
+        }
+    }
 }
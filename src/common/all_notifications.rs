@@ -0,0 +1,169 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::api::{Central, CentralEvent, Peripheral, ValueNotification};
+use crate::platform::PeripheralId;
+use futures::future::{AbortHandle, Abortable, Aborted, BoxFuture};
+use futures::stream::{FusedStream, FuturesUnordered, Stream, StreamExt};
+use futures::FutureExt;
+use log::warn;
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+type Notifications = Pin<Box<dyn Stream<Item = ValueNotification> + Send>>;
+
+/// The outcome of setting up a peripheral's notification stream: its ID, and the peripheral and
+/// stream unless setting up failed.
+type Setup<P> = (PeripheralId, Option<(P, Notifications)>);
+
+/// Merges the notifications of every peripheral connected to `central` into one stream, tagged with
+/// the ID of the peripheral each came from. `connected` are the peripherals already connected
+/// when `events` was obtained.
+pub fn all_notifications<C: Central + 'static>(
+    central: C,
+    events: Pin<Box<dyn Stream<Item = CentralEvent> + Send>>,
+    connected: Vec<C::Peripheral>,
+) -> Pin<Box<dyn Stream<Item = (PeripheralId, ValueNotification)> + Send>> {
+    let mut all = AllNotifications {
+        central,
+        events: events.fuse(),
+        known: HashMap::new(),
+        setups: HashMap::new(),
+        pending: FuturesUnordered::new(),
+        streams: VecDeque::new(),
+    };
+    for peripheral in connected {
+        let id = peripheral.id();
+        all.set_up(id, async move { Some(peripheral) }.boxed());
+    }
+    Box::pin(all)
+}
+
+struct AllNotifications<C: Central> {
+    central: C,
+    events: futures::stream::Fuse<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>>,
+    /// Every peripheral whose notifications have been merged, so that one can be found again if it
+    /// reconnects after the central has forgotten it, as some platforms do on disconnecting.
+    known: HashMap<PeripheralId, C::Peripheral>,
+    /// Cancels the setup of each peripheral in `pending`, if it disconnects before that completes.
+    setups: HashMap<PeripheralId, AbortHandle>,
+    /// Notification streams still being set up for newly connected peripherals.
+    pending: FuturesUnordered<BoxFuture<'static, Result<Setup<C::Peripheral>, Aborted>>>,
+    /// The notification streams of connected peripherals. Whichever yields a notification is moved
+    /// to the back, so that a busy peripheral can't starve the others.
+    streams: VecDeque<(PeripheralId, Notifications)>,
+}
+
+// The central is never pinned, everything else is already `Unpin`.
+impl<C: Central> Unpin for AllNotifications<C> {}
+
+impl<C: Central + 'static> AllNotifications<C> {
+    /// Starts setting up the notification stream of the peripheral which `peripheral` finds,
+    /// replacing any setup of it already in progress.
+    fn set_up(&mut self, id: PeripheralId, peripheral: BoxFuture<'static, Option<C::Peripheral>>) {
+        let (handle, registration) = AbortHandle::new_pair();
+        if let Some(previous) = self.setups.insert(id.clone(), handle) {
+            previous.abort();
+        }
+        let setup = async move {
+            let Some(peripheral) = peripheral.await else {
+                return (id, None);
+            };
+            match peripheral.notifications().await {
+                Ok(notifications) => (id, Some((peripheral, notifications))),
+                Err(e) => {
+                    warn!("Not merging the notifications of {:?}: {}", id, e);
+                    (id, None)
+                }
+            }
+        };
+        self.pending
+            .push(Abortable::new(setup, registration).boxed());
+    }
+
+    /// Looks the newly connected peripheral up, falling back to the handle we had for it if the
+    /// central doesn't know it any more.
+    fn find(&self, id: PeripheralId) -> BoxFuture<'static, Option<C::Peripheral>> {
+        let central = self.central.clone();
+        let known = self.known.get(&id).cloned();
+        async move {
+            match central.peripheral(&id).await {
+                Ok(peripheral) => Some(peripheral),
+                Err(e) => {
+                    if known.is_none() {
+                        warn!("Not merging the notifications of {:?}: {}", id, e);
+                    }
+                    known
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+impl<C: Central + 'static> Stream for AllNotifications<C> {
+    type Item = (PeripheralId, ValueNotification);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        while let Poll::Ready(Some(event)) = this.events.poll_next_unpin(cx) {
+            match event {
+                CentralEvent::DeviceConnected(id) => {
+                    let peripheral = this.find(id.clone());
+                    this.set_up(id, peripheral);
+                }
+                CentralEvent::DeviceDisconnected(id) => {
+                    if let Some(setup) = this.setups.remove(&id) {
+                        setup.abort();
+                    }
+                    this.streams.retain(|(stream_id, _)| *stream_id != id);
+                }
+                CentralEvent::DeviceLost(id) => {
+                    this.known.remove(&id);
+                }
+                _ => {}
+            }
+        }
+
+        while let Poll::Ready(Some(result)) = this.pending.poll_next_unpin(cx) {
+            // Aborted setups have already been replaced or removed from `setups`.
+            let Ok((id, setup)) = result else {
+                continue;
+            };
+            this.setups.remove(&id);
+            if let Some((peripheral, notifications)) = setup {
+                // Some platforms report a connection more than once, so don't merge the same
+                // peripheral's notifications twice.
+                this.streams.retain(|(stream_id, _)| *stream_id != id);
+                this.streams.push_back((id.clone(), notifications));
+                this.known.insert(id, peripheral);
+            }
+        }
+        let mut index = 0;
+        while index < this.streams.len() {
+            match this.streams[index].1.poll_next_unpin(cx) {
+                Poll::Ready(Some(notification)) => {
+                    let (id, notifications) = this.streams.remove(index).unwrap();
+                    this.streams.push_back((id.clone(), notifications));
+                    return Poll::Ready(Some((id, notification)));
+                }
+                Poll::Ready(None) => {
+                    this.streams.remove(index);
+                }
+                Poll::Pending => index += 1,
+            }
+        }
+
+        if this.events.is_terminated() && this.pending.is_empty() && this.streams.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
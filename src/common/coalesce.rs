@@ -0,0 +1,42 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::runtime;
+use crate::api::ValueNotification;
+use futures::future::FutureExt;
+use futures::select_biased;
+use futures::stream::{self, Stream, StreamExt};
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Coalesces a stream of notifications so that at most one is delivered per `window`: the first
+/// notification opens a window, and the latest notification received by the time it closes is
+/// delivered. Notifications should all be for the same characteristic.
+pub fn coalesce_notifications(
+    notifications: impl Stream<Item = ValueNotification> + Send + 'static,
+    window: Duration,
+) -> Pin<Box<dyn Stream<Item = ValueNotification> + Send>> {
+    let notifications = Box::pin(notifications.fuse());
+    Box::pin(stream::unfold(
+        notifications,
+        move |mut notifications| async move {
+            let mut latest = notifications.next().await?;
+            let mut deadline = Box::pin(runtime::sleep(window).fuse());
+            // If the underlying stream ends mid-window, still deliver the value we have.
+            loop {
+                select_biased! {
+                    notification = notifications.next() => match notification {
+                        Some(notification) => latest = notification,
+                        None => break,
+                    },
+                    _ = deadline => break,
+                }
+            }
+            Some((latest, notifications))
+        },
+    ))
+}
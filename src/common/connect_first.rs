@@ -0,0 +1,300 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::runtime;
+use crate::api::{Central, CentralEvent, Peripheral, PeripheralProperties, ScanFilter};
+use crate::platform::PeripheralId;
+use crate::{Error, Result};
+use futures::stream::{Stream, StreamExt};
+use futures::{pin_mut, select, FutureExt};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::time::Duration;
+use uuid::Uuid;
+
+type Events = Pin<Box<dyn Stream<Item = CentralEvent> + Send>>;
+
+/// How long to keep listening for other advertisers once the first one is found, so that the
+/// closest of several nearby devices can be picked.
+const SELECTION_WINDOW: Duration = Duration::from_millis(500);
+
+/// Scans for devices advertising `service`, and connects to the one with the strongest signal. If
+/// the adapter isn't already scanning, a scan is started for the purpose, and stopped before
+/// connecting, or before returning if this fails or times out, or in the background if this is
+/// dropped. A scan which was already running is used as it is and left running.
+pub async fn connect_first_with_service<C: Central + 'static>(
+    central: &C,
+    service: Uuid,
+    timeout: Duration,
+) -> Result<C::Peripheral> {
+    // Get the events first, so that no advertisement can be missed.
+    let events = central.events().await?;
+    let mut scan = ScanGuard(None);
+    if !central.is_scanning().await? {
+        central
+            .start_scan(ScanFilter {
+                services: vec![service],
+                ..Default::default()
+            })
+            .await?;
+        scan.0 = Some(central.clone());
+    }
+
+    let result = {
+        let connect = async {
+            let peripheral = strongest_advertiser(central, events, service).await?;
+            if scan.0.take().is_some() {
+                central.stop_scan().await?;
+            }
+            peripheral.connect().await?;
+            Ok(peripheral)
+        }
+        .fuse();
+        let timeout_elapsed = runtime::sleep(timeout).fuse();
+        pin_mut!(connect, timeout_elapsed);
+        select! {
+            result = connect => result,
+            _ = timeout_elapsed => Err(Error::TimedOut(timeout)),
+        }
+    };
+    scan.stop().await;
+    result
+}
+
+/// Scans with the given filter until a peripheral whose properties satisfy `predicate` is found,
+/// and returns it. The scan is stopped before returning, or in the background if this is dropped.
+pub async fn scan_until<C, F>(
+    central: &C,
+    filter: ScanFilter,
+    predicate: F,
+    timeout: Duration,
+) -> Result<C::Peripheral>
+where
+    C: Central + 'static,
+    F: Fn(&PeripheralProperties) -> bool,
+{
+    // Get the events first, so that no advertisement can be missed.
+    let events = central.events().await?;
+    central.start_scan(filter).await?;
+    let mut scan = ScanGuard(Some(central.clone()));
+
+    let result = {
+        let find = async {
+            let peripheral = first_match(central, events, &predicate).await?;
+            scan.0 = None;
+            central.stop_scan().await?;
+            Ok(peripheral)
+        }
+        .fuse();
+        let timeout_elapsed = runtime::sleep(timeout).fuse();
+        pin_mut!(find, timeout_elapsed);
+        select! {
+            result = find => result,
+            _ = timeout_elapsed => Err(Error::TimedOut(timeout)),
+        }
+    };
+    scan.stop().await;
+    result
+}
+
+/// Waits for the next advertisement from the given peripheral, and returns its properties as of
+/// then. If the adapter isn't already scanning, a scan is started for the purpose, and stopped
+/// again before returning, or in the background if this is dropped.
+pub async fn refresh_properties<C: Central + 'static>(
+    central: &C,
+    id: &PeripheralId,
+    timeout: Duration,
+) -> Result<PeripheralProperties> {
+    // Get the events first, so that no advertisement can be missed.
+    let mut events = central.events().await?;
+    let mut scan = ScanGuard(None);
+    if !central.is_scanning().await? {
+        central.start_scan(ScanFilter::default()).await?;
+        scan.0 = Some(central.clone());
+    }
+
+    let result = {
+        let refresh = async {
+            while let Some(event) = events.next().await {
+                if advertisement_id(event).as_ref() != Some(id) {
+                    continue;
+                }
+                let Some(properties) = central.peripheral(id).await?.properties().await? else {
+                    continue;
+                };
+                if scan.0.take().is_some() {
+                    central.stop_scan().await?;
+                }
+                return Ok(properties);
+            }
+            Err(Error::RuntimeError(
+                "Central event stream ended".to_string(),
+            ))
+        }
+        .fuse();
+        let timeout_elapsed = runtime::sleep(timeout).fuse();
+        pin_mut!(refresh, timeout_elapsed);
+        select! {
+            result = refresh => result,
+            _ = timeout_elapsed => Err(Error::TimedOut(timeout)),
+        }
+    };
+    scan.stop().await;
+    result
+}
+
+/// Stops scanning when dropped, unless it has been disarmed by taking the central out of it.
+struct ScanGuard<C: Central + 'static>(Option<C>);
+
+impl<C: Central + 'static> ScanGuard<C> {
+    /// Stops scanning now if the guard is still armed, so that the scan has stopped by the time
+    /// the caller sees the result, and can't stop a scan the caller starts next. Dropping the
+    /// guard only stops the scan in the background, which is left for when the search is
+    /// cancelled. Any error stopping the scan is ignored in favour of the result being returned.
+    async fn stop(mut self) {
+        if let Some(central) = self.0.take() {
+            let _ = central.stop_scan().await;
+        }
+    }
+}
+
+impl<C: Central + 'static> Drop for ScanGuard<C> {
+    fn drop(&mut self) {
+        if let Some(central) = self.0.take() {
+            runtime::spawn(async move {
+                let _ = central.stop_scan().await;
+            });
+        }
+    }
+}
+
+async fn strongest_advertiser<C: Central>(
+    central: &C,
+    events: Events,
+    service: Uuid,
+) -> Result<C::Peripheral> {
+    let mut events = events.fuse();
+    let mut candidates = HashMap::new();
+    for peripheral in central.peripherals().await? {
+        if advertises(&peripheral, service).await {
+            candidates.insert(peripheral.id(), peripheral);
+        }
+    }
+    while candidates.is_empty() {
+        match events.next().await {
+            Some(event) => add_candidate(central, &mut candidates, event, service).await,
+            None => {
+                return Err(Error::RuntimeError(
+                    "Central event stream ended".to_string(),
+                ))
+            }
+        }
+    }
+
+    let window = runtime::sleep(SELECTION_WINDOW).fuse();
+    pin_mut!(window);
+    loop {
+        select! {
+            _ = window => break,
+            event = events.next() => match event {
+                Some(event) => add_candidate(central, &mut candidates, event, service).await,
+                None => break,
+            },
+        }
+    }
+
+    let mut strongest: Option<(i16, C::Peripheral)> = None;
+    for peripheral in candidates.into_values() {
+        let rssi = match peripheral.properties().await {
+            Ok(Some(properties)) => properties.rssi.unwrap_or(i16::MIN),
+            _ => i16::MIN,
+        };
+        if strongest
+            .as_ref()
+            .is_none_or(|(strongest, _)| rssi > *strongest)
+        {
+            strongest = Some((rssi, peripheral));
+        }
+    }
+    Ok(strongest.unwrap().1)
+}
+
+async fn first_match<C: Central>(
+    central: &C,
+    mut events: Events,
+    predicate: impl Fn(&PeripheralProperties) -> bool,
+) -> Result<C::Peripheral> {
+    for peripheral in central.peripherals().await? {
+        if matches(&peripheral, &predicate).await {
+            return Ok(peripheral);
+        }
+    }
+    while let Some(event) = events.next().await {
+        let Some(id) = advertisement_id(event) else {
+            continue;
+        };
+        if let Ok(peripheral) = central.peripheral(&id).await {
+            // Looked up on every report, so that the predicate sees the latest properties.
+            if matches(&peripheral, &predicate).await {
+                return Ok(peripheral);
+            }
+        }
+    }
+    Err(Error::RuntimeError(
+        "Central event stream ended".to_string(),
+    ))
+}
+
+async fn matches<P: Peripheral>(
+    peripheral: &P,
+    predicate: impl Fn(&PeripheralProperties) -> bool,
+) -> bool {
+    match peripheral.properties().await {
+        Ok(Some(properties)) => predicate(&properties),
+        _ => false,
+    }
+}
+
+/// Returns the peripheral an event reports an advertisement from, if it does.
+fn advertisement_id(event: CentralEvent) -> Option<PeripheralId> {
+    match event {
+        CentralEvent::DeviceDiscovered(id)
+        | CentralEvent::DeviceUpdated(id)
+        | CentralEvent::ManufacturerDataAdvertisement { id, .. }
+        | CentralEvent::ServiceDataAdvertisement { id, .. }
+        | CentralEvent::ServicesAdvertisement { id, .. } => Some(id),
+        _ => None,
+    }
+}
+
+async fn add_candidate<C: Central>(
+    central: &C,
+    candidates: &mut HashMap<PeripheralId, C::Peripheral>,
+    event: CentralEvent,
+    service: Uuid,
+) {
+    let Some(id) = advertisement_id(event) else {
+        return;
+    };
+    if let Ok(peripheral) = central.peripheral(&id).await {
+        if advertises(&peripheral, service).await {
+            candidates.insert(id, peripheral);
+        }
+    }
+}
+
+/// Returns whether the peripheral has advertised the given service, either in its list of services
+/// or with service data. Not every platform applies the scan filter, so this must be checked.
+async fn advertises<P: Peripheral>(peripheral: &P, service: Uuid) -> bool {
+    match peripheral.properties().await {
+        Ok(Some(properties)) => {
+            properties.advertised_services.contains(&service)
+                || properties.service_data.contains_key(&service)
+        }
+        _ => false,
+    }
+}
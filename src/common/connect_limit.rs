@@ -0,0 +1,85 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+/// How many connections an adapter tries to establish at once by default. BlueZ and Android fail
+/// connection attempts made while another is still in progress, rather than queueing them.
+const DEFAULT_MAX_CONCURRENT_CONNECTS: usize =
+    if cfg!(any(target_os = "linux", target_os = "android")) {
+        1
+    } else {
+        4
+    };
+
+/// Limits how many connection attempts to the peripherals of an adapter are in progress at once,
+/// making any others wait their turn. This is shared by the adapter and all of its peripherals.
+#[derive(Debug)]
+pub(crate) struct ConnectLimit {
+    state: Mutex<State>,
+    released: Notify,
+}
+
+#[derive(Debug)]
+struct State {
+    in_progress: usize,
+    /// The most attempts to allow at once, or 0 for no limit.
+    max: usize,
+}
+
+impl ConnectLimit {
+    pub fn new(max: usize) -> Self {
+        ConnectLimit {
+            state: Mutex::new(State {
+                in_progress: 0,
+                max,
+            }),
+            released: Notify::new(),
+        }
+    }
+
+    /// Changes the limit. Attempts already in progress carry on even if there are now more of them
+    /// than allowed.
+    pub fn set_max(&self, max: usize) {
+        self.state.lock().unwrap().max = max;
+        self.released.notify_waiters();
+    }
+
+    /// Waits until fewer than the maximum connection attempts are in progress, then counts one more
+    /// until the returned permit is dropped.
+    pub async fn acquire(&self) -> ConnectPermit<'_> {
+        loop {
+            // Created before checking, so that a release in between isn't missed.
+            let released = self.released.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.max == 0 || state.in_progress < state.max {
+                    state.in_progress += 1;
+                    return ConnectPermit(self);
+                }
+            }
+            released.await;
+        }
+    }
+}
+
+impl Default for ConnectLimit {
+    fn default() -> Self {
+        ConnectLimit::new(DEFAULT_MAX_CONCURRENT_CONNECTS)
+    }
+}
+
+/// Counts as one attempt in progress until dropped, including when the attempt is cancelled.
+pub(crate) struct ConnectPermit<'a>(&'a ConnectLimit);
+
+impl Drop for ConnectPermit<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().in_progress -= 1;
+        self.0.released.notify_waiters();
+    }
+}
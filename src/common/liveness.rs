@@ -0,0 +1,51 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The task behind [`Peripheral::set_liveness_probe`](crate::api::Peripheral::set_liveness_probe).
+
+use super::runtime;
+use crate::api::{
+    trace::{self, TraceEvent},
+    Characteristic, DisconnectOptions, DisconnectReason, Peripheral,
+};
+use crate::Error;
+use futures::future::{self, Either};
+use futures::pin_mut;
+use std::time::Duration;
+
+/// Every `interval` while the peripheral is connected, reads the characteristic, and disconnects
+/// with [`DisconnectReason::ProbeTimeout`] if the read fails or doesn't complete within another
+/// `interval`. Runs until it is aborted.
+pub(crate) async fn probe_liveness<P: Peripheral>(
+    peripheral: P,
+    interval: Duration,
+    characteristic: Characteristic,
+) {
+    loop {
+        runtime::sleep(interval).await;
+        if !peripheral.is_connected().await.unwrap_or(false) {
+            continue;
+        }
+        let read = peripheral.read(&characteristic);
+        let timeout_elapsed = runtime::sleep(interval);
+        pin_mut!(read, timeout_elapsed);
+        let error = match future::select(read, timeout_elapsed).await {
+            Either::Left((Ok(_), _)) => continue,
+            Either::Left((Err(e), _)) => e,
+            Either::Right(_) => Error::TimedOut(interval),
+        };
+        trace::emit(|| TraceEvent::LivenessProbeFailed {
+            id: peripheral.id(),
+            error: error.to_string(),
+        });
+        // Keep the services on BlueZ, as a plain disconnect does there.
+        let options = DisconnectOptions::default()
+            .keep_cache(true)
+            .reason(DisconnectReason::ProbeTimeout);
+        let _ = peripheral.disconnect_with_options(options).await;
+    }
+}
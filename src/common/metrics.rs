@@ -0,0 +1,56 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Counts the traffic to and from a peripheral, for
+//! [`Peripheral::metrics`](crate::api::Peripheral::metrics).
+
+use crate::api::PeripheralMetrics;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    notifications: AtomicU64,
+    connections: AtomicU64,
+}
+
+impl Metrics {
+    /// Counts a characteristic value of `len` bytes read from the peripheral.
+    pub fn read(&self, len: usize) {
+        add(&self.bytes_read, len);
+    }
+
+    /// Counts a characteristic value of `len` bytes written to the peripheral.
+    pub fn written(&self, len: usize) {
+        add(&self.bytes_written, len);
+    }
+
+    /// Counts a notification or indication received from the peripheral.
+    pub fn notification(&self) {
+        add(&self.notifications, 1);
+    }
+
+    /// Counts a successful connection to the peripheral.
+    pub fn connected(&self) {
+        add(&self.connections, 1);
+    }
+
+    pub fn snapshot(&self, mtu: Option<u16>) -> PeripheralMetrics {
+        PeripheralMetrics {
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            notifications_received: self.notifications.load(Ordering::Relaxed),
+            reconnects: self.connections.load(Ordering::Relaxed).saturating_sub(1),
+            mtu,
+        }
+    }
+}
+
+fn add(counter: &AtomicU64, n: usize) {
+    counter.fetch_add(n as u64, Ordering::Relaxed);
+}
@@ -1,2 +1,17 @@
+#[cfg(any(not(target_os = "linux"), feature = "mock"))]
 pub mod adapter_manager;
+pub mod all_notifications;
+pub mod coalesce;
+pub mod connect_first;
+pub(crate) mod connect_limit;
+pub(crate) mod liveness;
+pub(crate) mod metrics;
+pub(crate) mod notification_receiver;
+pub(crate) mod pending;
+pub(crate) mod rssi_history;
+pub(crate) mod runtime;
+pub(crate) mod scan_state;
+pub(crate) mod scan_subscriptions;
+pub(crate) mod subscriptions;
+#[cfg(any(not(target_os = "linux"), feature = "mock"))]
 pub mod util;
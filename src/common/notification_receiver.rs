@@ -0,0 +1,54 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::api::ValueNotification;
+use futures::future::FutureExt;
+use futures::stream::{Stream, StreamExt};
+use std::fmt::{self, Debug, Formatter};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// A peripheral's notifications, from [`Peripheral::notification_receiver`], which can be polled
+/// without awaiting as well as used as a stream. Both take from the same buffer, so each
+/// notification is delivered once, by whichever of them is called first.
+///
+/// [`Peripheral::notification_receiver`]: crate::api::Peripheral::notification_receiver
+pub struct NotificationReceiver {
+    notifications: Pin<Box<dyn Stream<Item = ValueNotification> + Send>>,
+}
+
+impl NotificationReceiver {
+    pub(crate) fn new(
+        notifications: Pin<Box<dyn Stream<Item = ValueNotification> + Send>>,
+    ) -> Self {
+        Self { notifications }
+    }
+
+    /// Returns the next buffered notification if there is one, or `None` straight away if not.
+    /// This is for synchronous code such as a frame loop, which can't wait on the stream.
+    pub fn try_next_notification(&mut self) -> Option<ValueNotification> {
+        self.notifications.next().now_or_never().flatten()
+    }
+}
+
+impl Stream for NotificationReceiver {
+    type Item = ValueNotification;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<ValueNotification>> {
+        self.notifications.poll_next_unpin(cx)
+    }
+}
+
+impl Debug for NotificationReceiver {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("NotificationReceiver")
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,76 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Counts the GATT operations outstanding on a peripheral, for
+//! [`Peripheral::pending_operations`](crate::api::Peripheral::pending_operations) and
+//! [`Peripheral::pending_writes`](crate::api::Peripheral::pending_writes), and abandons them for
+//! [`Peripheral::abort_pending`](crate::api::Peripheral::abort_pending).
+
+use crate::{Error, Result};
+use futures::future::{select, Either};
+use futures::pin_mut;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::Notify;
+
+#[derive(Debug, Default)]
+pub(crate) struct PendingOperations {
+    operations: AtomicUsize,
+    writes: AtomicUsize,
+    aborted: Notify,
+}
+
+impl PendingOperations {
+    /// Runs a GATT operation, counting it as pending until it completes or is dropped. If
+    /// [`Self::abort`] is called first, the operation is dropped and this returns
+    /// [`Error::Cancelled`].
+    pub async fn track<T>(&self, operation: impl Future<Output = Result<T>>) -> Result<T> {
+        let _guard = Guard::new(&self.operations);
+        // Created before the operation starts, so that an abort while it is running isn't missed.
+        let aborted = self.aborted.notified();
+        pin_mut!(operation, aborted);
+        match select(operation, aborted).await {
+            Either::Left((result, _)) => result,
+            Either::Right(((), _)) => Err(Error::Cancelled),
+        }
+    }
+
+    /// Like [`Self::track`], but also counts the operation as a pending characteristic write.
+    pub async fn track_write<T>(&self, operation: impl Future<Output = Result<T>>) -> Result<T> {
+        let _guard = Guard::new(&self.writes);
+        self.track(operation).await
+    }
+
+    /// Makes every operation currently being tracked return [`Error::Cancelled`].
+    pub fn abort(&self) {
+        self.aborted.notify_waiters();
+    }
+
+    pub fn operations(&self) -> usize {
+        self.operations.load(Ordering::Relaxed)
+    }
+
+    pub fn writes(&self) -> usize {
+        self.writes.load(Ordering::Relaxed)
+    }
+}
+
+/// Decrements a counter when dropped, so that cancelled operations stop being counted.
+struct Guard<'a>(&'a AtomicUsize);
+
+impl<'a> Guard<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
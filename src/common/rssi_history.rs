@@ -0,0 +1,95 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Keeps the latest signal strengths received from each device, to smooth them with a moving
+//! average for [`Central::rssi_samples`](crate::api::Central::rssi_samples), or an exponential one
+//! for [`PeripheralProperties::smoothed_rssi`](crate::api::PeripheralProperties::smoothed_rssi).
+
+use crate::platform::PeripheralId;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// The weight given to each new sample by [`RssiAverage`], in percent, unless set otherwise with
+/// [`ScanOptions::rssi_smoothing`](crate::api::ScanOptions::rssi_smoothing).
+pub(crate) const DEFAULT_RSSI_SMOOTHING: u8 = 25;
+
+#[derive(Debug)]
+pub(crate) struct RssiHistory {
+    window: usize,
+    samples: HashMap<PeripheralId, VecDeque<i16>>,
+}
+
+impl RssiHistory {
+    /// Averages over up to `window` samples per device. A window of 0 is taken to be 1, so does no
+    /// smoothing.
+    pub fn new(window: usize) -> Self {
+        RssiHistory {
+            window: window.max(1),
+            samples: HashMap::new(),
+        }
+    }
+
+    /// Records a sample for the device, and returns the mean of its latest samples, rounded to the
+    /// nearest dBm.
+    pub fn add(&mut self, id: &PeripheralId, rssi: i16) -> i16 {
+        let samples = self.samples.entry(id.clone()).or_default();
+        if samples.len() == self.window {
+            samples.pop_front();
+        }
+        samples.push_back(rssi);
+        let sum: i32 = samples.iter().copied().map(i32::from).sum();
+        (f64::from(sum) / samples.len() as f64).round() as i16
+    }
+}
+
+/// An exponential moving average of the signal strength of a device's advertisements. The average
+/// is kept unrounded, so that it doesn't get stuck short of a long run of equal samples.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct RssiAverage(Option<f32>);
+
+impl RssiAverage {
+    /// Adds a sample, with `weight` percent of the weight of the new average, and returns the new
+    /// average rounded to the nearest dBm. The first sample is taken as it is.
+    pub fn add(&mut self, rssi: i16, weight: u8) -> i16 {
+        let rssi = f32::from(rssi);
+        let weight = f32::from(weight.clamp(1, 100)) / 100.0;
+        let average = match self.0 {
+            Some(average) => average + weight * (rssi - average),
+            None => rssi,
+        };
+        self.0 = Some(average);
+        average.round() as i16
+    }
+
+    /// The current average, rounded to the nearest dBm, if there have been any samples.
+    pub fn get(&self) -> Option<i16> {
+        self.0.map(|average| average.round() as i16)
+    }
+}
+
+/// The weight [`RssiAverage`] gives new samples for the peripherals of an adapter, as set by the
+/// options of the last scan started.
+#[derive(Debug)]
+pub(crate) struct RssiSmoothing(AtomicU8);
+
+impl Default for RssiSmoothing {
+    fn default() -> Self {
+        RssiSmoothing(AtomicU8::new(DEFAULT_RSSI_SMOOTHING))
+    }
+}
+
+impl RssiSmoothing {
+    /// Sets the weight, or goes back to the default for `None`.
+    pub fn set(&self, weight: Option<u8>) {
+        self.0
+            .store(weight.unwrap_or(DEFAULT_RSSI_SMOOTHING), Ordering::Relaxed);
+    }
+
+    pub fn weight(&self) -> u8 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
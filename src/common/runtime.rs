@@ -0,0 +1,141 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The few things btleplug needs from an async runtime, beyond futures and Tokio's channels (which
+//! work on any executor). With the `tokio` feature these must be called from within a Tokio
+//! runtime. Without it they fall back to plain threads, so btleplug can be driven by any executor:
+//! each spawned task and blocking call gets a thread of its own, and sleeps share one timer thread.
+
+use crate::{Error, Result};
+use std::future::Future;
+use std::time::Duration;
+
+/// Spawns a background task which runs until `future` completes.
+pub(crate) fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    #[cfg(feature = "tokio")]
+    tokio::task::spawn(future);
+    #[cfg(not(feature = "tokio"))]
+    std::thread::spawn(move || futures::executor::block_on(future));
+}
+
+/// Runs a blocking function without blocking the calling task, and waits for its result.
+#[cfg_attr(not(any(target_os = "linux", target_os = "windows")), allow(dead_code))]
+pub(crate) async fn spawn_blocking<F, R>(f: F) -> Result<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    #[cfg(feature = "tokio")]
+    {
+        tokio::task::spawn_blocking(f)
+            .await
+            .map_err(|e| Error::RuntimeError(e.to_string()))
+    }
+    #[cfg(not(feature = "tokio"))]
+    {
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(f());
+        });
+        receiver
+            .await
+            .map_err(|_| Error::RuntimeError("Blocking task panicked".to_string()))
+    }
+}
+
+/// Waits until `duration` has elapsed.
+pub(crate) async fn sleep(duration: Duration) {
+    #[cfg(feature = "tokio")]
+    tokio::time::sleep(duration).await;
+    #[cfg(not(feature = "tokio"))]
+    {
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        timer::add(std::time::Instant::now() + duration, sender);
+        let _ = receiver.await;
+    }
+}
+
+/// One thread which wakes every pending [`sleep`] at its deadline, so that sleeps don't each need
+/// a thread of their own.
+#[cfg(not(feature = "tokio"))]
+mod timer {
+    use futures::channel::oneshot;
+    use std::{
+        cmp::{Ordering, Reverse},
+        collections::{binary_heap::PeekMut, BinaryHeap},
+        sync::{
+            mpsc::{self, RecvTimeoutError},
+            Mutex, OnceLock,
+        },
+        time::Instant,
+    };
+
+    struct Timer {
+        deadline: Instant,
+        sender: oneshot::Sender<()>,
+    }
+
+    impl PartialEq for Timer {
+        fn eq(&self, other: &Self) -> bool {
+            self.deadline == other.deadline
+        }
+    }
+
+    impl Eq for Timer {}
+
+    impl PartialOrd for Timer {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Timer {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.deadline.cmp(&other.deadline)
+        }
+    }
+
+    /// Sends on `sender` once `deadline` has passed, starting the timer thread if need be.
+    pub(super) fn add(deadline: Instant, sender: oneshot::Sender<()>) {
+        static TIMERS: OnceLock<Mutex<mpsc::Sender<Timer>>> = OnceLock::new();
+        let timers = TIMERS.get_or_init(|| {
+            let (timers, receiver) = mpsc::channel();
+            std::thread::Builder::new()
+                .name("btleplug-timer".to_string())
+                .spawn(move || run(receiver))
+                .expect("Failed to spawn the btleplug timer thread");
+            Mutex::new(timers)
+        });
+        let _ = timers.lock().unwrap().send(Timer { deadline, sender });
+    }
+
+    fn run(receiver: mpsc::Receiver<Timer>) {
+        // Earliest deadline first.
+        let mut timers: BinaryHeap<Reverse<Timer>> = BinaryHeap::new();
+        loop {
+            let now = Instant::now();
+            while let Some(timer) = timers.peek_mut() {
+                if timer.0.deadline > now {
+                    break;
+                }
+                let _ = PeekMut::pop(timer).0.sender.send(());
+            }
+            let next = match timers.peek() {
+                Some(Reverse(timer)) => receiver.recv_timeout(timer.deadline - now),
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match next {
+                Ok(timer) => timers.push(Reverse(timer)),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::api::ScanFilter;
+use std::sync::Mutex;
+
+/// Remembers the filter of the scan an adapter was last asked to run, so that
+/// [`Central::pause_scan`](crate::api::Central::pause_scan) can stop the OS scan and
+/// [`Central::resume_scan`](crate::api::Central::resume_scan) can start it again the same way.
+#[derive(Debug, Default)]
+pub(crate) struct ScanState(Mutex<State>);
+
+#[derive(Debug, Default)]
+enum State {
+    #[default]
+    Stopped,
+    Scanning(ScanFilter),
+    Paused(ScanFilter),
+}
+
+impl ScanState {
+    /// Records that a scan was started with the given filter, ending any pause.
+    pub fn started(&self, filter: ScanFilter) {
+        *self.0.lock().unwrap() = State::Scanning(filter);
+    }
+
+    /// Records that the scan was stopped, ending any pause.
+    pub fn stopped(&self) {
+        *self.0.lock().unwrap() = State::Stopped;
+    }
+
+    /// Returns whether a scan is running, so that pausing should stop the OS scan. Call
+    /// [`ScanState::paused`] once that has succeeded.
+    pub fn is_running(&self) -> bool {
+        matches!(*self.0.lock().unwrap(), State::Scanning(_))
+    }
+
+    /// Records that the running scan has been paused.
+    pub fn paused(&self) {
+        let mut state = self.0.lock().unwrap();
+        if let State::Scanning(filter) = std::mem::take(&mut *state) {
+            *state = State::Paused(filter);
+        }
+    }
+
+    /// Returns the filter to restart the OS scan with to resume it, or `None` if the scan isn't
+    /// paused. Call [`ScanState::resumed`] once restarting it has succeeded.
+    pub fn paused_filter(&self) -> Option<ScanFilter> {
+        match &*self.0.lock().unwrap() {
+            State::Paused(filter) => Some(filter.clone()),
+            _ => None,
+        }
+    }
+
+    /// Records that the paused scan is running again.
+    pub fn resumed(&self) {
+        let mut state = self.0.lock().unwrap();
+        if let State::Paused(filter) = std::mem::take(&mut *state) {
+            *state = State::Scanning(filter);
+        }
+    }
+}
@@ -0,0 +1,283 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::runtime;
+use crate::api::{Central, CentralEvent, Peripheral, PeripheralProperties, ScanFilter, ScanType};
+use crate::platform::PeripheralId;
+use crate::Result;
+use futures::future::BoxFuture;
+use futures::stream::{Stream, StreamExt};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Debug, Formatter};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+/// The scans started with [`Central::subscribe_scan`] on an adapter, which share its single scan.
+#[derive(Debug, Default)]
+pub(crate) struct ScanSubscriptions {
+    /// Keyed by ID, so that the combined filter lists services in the order they were asked for.
+    filters: Mutex<BTreeMap<u64, ScanFilter>>,
+    next_id: AtomicU64,
+    /// The filter the adapter is scanning with, or `None` if it isn't. This is held while the scan
+    /// is being changed, so that concurrent changes are applied in turn.
+    scanning: tokio::sync::Mutex<Option<ScanFilter>>,
+}
+
+impl ScanSubscriptions {
+    /// Starts, changes or stops the adapter's scan to cover all current subscriptions.
+    async fn apply<C: Central>(&self, central: &C) -> Result<()> {
+        let mut scanning = self.scanning.lock().await;
+        let wanted = {
+            let filters = self.filters.lock().unwrap();
+            if filters.is_empty() {
+                None
+            } else {
+                Some(union(filters.values()))
+            }
+        };
+        if *scanning == wanted {
+            return Ok(());
+        }
+        // Not every platform can change the filter of a scan in progress, so restart it.
+        if scanning.is_some() {
+            central.stop_scan().await?;
+            *scanning = None;
+        }
+        if let Some(filter) = wanted {
+            central.start_scan(filter.clone()).await?;
+            *scanning = Some(filter);
+        }
+        Ok(())
+    }
+}
+
+/// Adds a scan subscription with the given filter, starting or widening the adapter's scan to
+/// include it.
+pub(crate) async fn subscribe_scan<C: Central + 'static>(
+    central: &C,
+    scans: &Arc<ScanSubscriptions>,
+    filter: ScanFilter,
+) -> Result<ScanSubscription> {
+    // Get the events first, so that no advertisement can be missed.
+    let events = central.events().await?;
+    let id = scans.next_id.fetch_add(1, Ordering::Relaxed);
+    scans.filters.lock().unwrap().insert(id, filter.clone());
+    if let Err(e) = scans.apply(central).await {
+        scans.filters.lock().unwrap().remove(&id);
+        return Err(e);
+    }
+
+    let matched = Arc::new(Mutex::new(HashSet::new()));
+    let events = {
+        let central = central.clone();
+        events.filter_map(move |event| {
+            let central = central.clone();
+            let filter = filter.clone();
+            let matched = matched.clone();
+            async move { filter_event(&central, &filter, &matched, event).await }
+        })
+    };
+    let central = central.clone();
+    let scans = scans.clone();
+    Ok(ScanSubscription {
+        events: Box::pin(events),
+        stop: Some(Box::new(move || {
+            Box::pin(async move {
+                scans.filters.lock().unwrap().remove(&id);
+                scans.apply(&central).await
+            })
+        })),
+    })
+}
+
+/// Passes on the advertisement events for devices matching the filter, and
+/// [`CentralEvent::DeviceLost`] for those which matched before.
+async fn filter_event<C: Central>(
+    central: &C,
+    filter: &ScanFilter,
+    matched: &Mutex<HashSet<PeripheralId>>,
+    event: CentralEvent,
+) -> Option<CentralEvent> {
+    let id = match &event {
+        CentralEvent::DeviceDiscovered(id)
+        | CentralEvent::DeviceUpdated(id)
+        | CentralEvent::ManufacturerDataAdvertisement { id, .. }
+        | CentralEvent::ServiceDataAdvertisement { id, .. }
+        | CentralEvent::ServicesAdvertisement { id, .. } => id,
+        CentralEvent::DeviceLost(id) => {
+            return matched.lock().unwrap().remove(id).then_some(event);
+        }
+        _ => return None,
+    };
+    let properties = central
+        .peripheral(id)
+        .await
+        .ok()?
+        .properties()
+        .await
+        .ok()??;
+    if !matches(filter, &properties) {
+        return None;
+    }
+    matched.lock().unwrap().insert(id.clone());
+    Some(event)
+}
+
+/// Returns whether a device with the given properties is one the filter is scanning for.
+fn matches(filter: &ScanFilter, properties: &PeripheralProperties) -> bool {
+    let services_match = filter.services.is_empty()
+        || filter.services.iter().any(|service| {
+            properties.advertised_services.contains(service)
+                || properties.service_data.contains_key(service)
+        });
+    let manufacturer_data_matches = filter.manufacturer_data.is_empty()
+        || filter.manufacturer_data.iter().any(|(company_id, prefix)| {
+            properties
+                .manufacturer_data
+                .get(company_id)
+                .is_some_and(|data| data.starts_with(prefix))
+        });
+    // Not every platform reports the signal strength, in which case the OS filter is all there is.
+    let rssi_matches = filter
+        .min_rssi
+        .is_none_or(|min_rssi| properties.rssi.is_none_or(|rssi| rssi >= min_rssi));
+    let connectable_matches = !filter.connectable_only || properties.connectable != Some(false);
+    let name_matches = filter.name_prefix.as_ref().is_none_or(|prefix| {
+        properties
+            .local_name
+            .as_ref()
+            .is_some_and(|name| name.starts_with(prefix))
+    });
+    services_match
+        && manufacturer_data_matches
+        && rssi_matches
+        && connectable_matches
+        && name_matches
+}
+
+/// Returns a filter which lets through every device that any of the given filters would.
+fn union<'a>(filters: impl Iterator<Item = &'a ScanFilter> + Clone) -> ScanFilter {
+    let mut services = Vec::new();
+    if filters.clone().all(|filter| !filter.services.is_empty()) {
+        for service in filters.clone().flat_map(|filter| &filter.services) {
+            if !services.contains(service) {
+                services.push(*service);
+            }
+        }
+    }
+    let mut manufacturer_data: HashMap<u16, Vec<u8>> = HashMap::new();
+    if filters
+        .clone()
+        .all(|filter| !filter.manufacturer_data.is_empty())
+    {
+        for (company_id, prefix) in filters.clone().flat_map(|filter| &filter.manufacturer_data) {
+            // Where two filters want different data from the same company, only require what they
+            // have in common.
+            manufacturer_data
+                .entry(*company_id)
+                .and_modify(|common| {
+                    let len = common
+                        .iter()
+                        .zip(prefix)
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    common.truncate(len);
+                })
+                .or_insert_with(|| prefix.clone());
+        }
+    }
+    let min_rssi = filters
+        .clone()
+        .map(|filter| filter.min_rssi)
+        .reduce(|a, b| a.zip(b).map(|(a, b)| a.min(b)))
+        .flatten();
+    let scan_type = if filters
+        .clone()
+        .any(|filter| filter.scan_type == ScanType::Active)
+    {
+        ScanType::Active
+    } else {
+        ScanType::Passive
+    };
+    let connectable_only = filters.clone().all(|filter| filter.connectable_only);
+    // Where the filters want different names, only require the start they have in common.
+    let name_prefix = filters
+        .clone()
+        .map(|filter| filter.name_prefix.clone())
+        .reduce(|a, b| {
+            let (a, b) = a.zip(b)?;
+            let len = a
+                .char_indices()
+                .zip(b.chars())
+                .take_while(|((_, a), b)| a == b)
+                .last()
+                .map_or(0, |((i, a), _)| i + a.len_utf8());
+            Some(a[..len].to_string())
+        })
+        .flatten();
+    ScanFilter {
+        services,
+        manufacturer_data,
+        min_rssi,
+        scan_type,
+        connectable_only,
+        name_prefix,
+    }
+}
+
+type StopScan = Box<dyn FnOnce() -> BoxFuture<'static, Result<()>> + Send>;
+
+/// One of possibly several scans sharing an adapter, from [`Central::subscribe_scan`]. This is a
+/// stream of the advertisement events for the devices matching its filter, along with
+/// [`CentralEvent::DeviceLost`] for those devices.
+///
+/// The subscription ends when it is dropped or [`stop`](Self::stop)ped, and the adapter stops
+/// scanning once every subscription has ended. Dropping it only changes the scan in the background,
+/// so use [`stop`](Self::stop) where the scan must have stopped before going on, e.g. before
+/// starting another.
+pub struct ScanSubscription {
+    events: Pin<Box<dyn Stream<Item = CentralEvent> + Send>>,
+    stop: Option<StopScan>,
+}
+
+impl ScanSubscription {
+    /// Ends the subscription, narrowing or stopping the adapter's scan as needed. Unlike dropping
+    /// the subscription, this waits for the scan to have been changed and reports any error in
+    /// doing so.
+    pub async fn stop(mut self) -> Result<()> {
+        match self.stop.take() {
+            Some(stop) => stop().await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Stream for ScanSubscription {
+    type Item = CentralEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<CentralEvent>> {
+        self.events.poll_next_unpin(cx)
+    }
+}
+
+impl Drop for ScanSubscription {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            runtime::spawn(async move {
+                let _ = stop().await;
+            });
+        }
+    }
+}
+
+impl Debug for ScanSubscription {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("ScanSubscription").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,117 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Remembers the characteristics a peripheral has been subscribed to, for
+//! [`Peripheral::subscriptions`](crate::api::Peripheral::subscriptions) and
+//! [`Peripheral::resubscribe_all`](crate::api::Peripheral::resubscribe_all).
+
+use crate::api::{Characteristic, Peripheral};
+use crate::{Error, Result};
+use log::warn;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+type CharacteristicKey = (Uuid, Uuid, u16);
+
+fn key(characteristic: &Characteristic) -> CharacteristicKey {
+    (
+        characteristic.service_uuid,
+        characteristic.uuid,
+        characteristic.instance_id,
+    )
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Subscriptions(Mutex<BTreeMap<CharacteristicKey, Characteristic>>);
+
+impl Subscriptions {
+    /// Runs a subscribe operation, remembering the characteristic if it succeeds.
+    pub async fn subscribe(
+        &self,
+        characteristic: &Characteristic,
+        subscribe: impl Future<Output = Result<()>>,
+    ) -> Result<()> {
+        subscribe.await?;
+        self.0
+            .lock()
+            .unwrap()
+            .insert(key(characteristic), characteristic.clone());
+        Ok(())
+    }
+
+    /// Runs an unsubscribe operation, forgetting the characteristic if it succeeds.
+    pub async fn unsubscribe(
+        &self,
+        characteristic: &Characteristic,
+        unsubscribe: impl Future<Output = Result<()>>,
+    ) -> Result<()> {
+        unsubscribe.await?;
+        self.0.lock().unwrap().remove(&key(characteristic));
+        Ok(())
+    }
+
+    pub fn get(&self) -> Vec<Characteristic> {
+        self.0.lock().unwrap().values().cloned().collect()
+    }
+}
+
+/// Subscribes again to each of the peripheral's remembered subscriptions, carrying on past any
+/// which fail.
+pub(crate) async fn resubscribe_all<P: Peripheral>(peripheral: &P) -> Vec<(Characteristic, Error)> {
+    let characteristics = peripheral.characteristics();
+    let mut failures = Vec::new();
+    for remembered in peripheral.subscriptions() {
+        match characteristics
+            .iter()
+            .find(|characteristic| key(characteristic) == key(&remembered))
+        {
+            Some(characteristic) => {
+                if let Err(e) = peripheral.subscribe(characteristic).await {
+                    failures.push((characteristic.clone(), e));
+                }
+            }
+            None => failures.push((remembered, Error::NoSuchCharacteristic)),
+        }
+    }
+    failures
+}
+
+/// Subscribes to every characteristic of the peripheral with one of the given UUIDs, discovering
+/// services first if none are known yet, for [`ConnectOptions::auto_subscribe`]. Failures are
+/// logged rather than returned, so that they don't fail the connection.
+///
+/// [`ConnectOptions::auto_subscribe`]: crate::api::ConnectOptions::auto_subscribe
+pub(crate) async fn auto_subscribe<P: Peripheral>(peripheral: &P, uuids: &[Uuid]) {
+    if uuids.is_empty() {
+        return;
+    }
+    if peripheral.characteristics().is_empty() {
+        if let Err(e) = peripheral.discover_services().await {
+            warn!("Could not discover services to subscribe to: {}", e);
+            return;
+        }
+    }
+    let characteristics = peripheral.characteristics();
+    for uuid in uuids {
+        let mut found = false;
+        for characteristic in characteristics.iter().filter(|c| c.uuid == *uuid) {
+            found = true;
+            if let Err(e) = peripheral.subscribe(characteristic).await {
+                warn!("Could not subscribe to {}: {}", characteristic, e);
+            }
+        }
+        if !found {
+            warn!(
+                "Could not subscribe to {}: {}",
+                uuid,
+                Error::NoSuchCharacteristic
+            );
+        }
+    }
+}
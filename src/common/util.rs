@@ -6,7 +6,11 @@
 // for full license information.
 
 use crate::api::ValueNotification;
+#[cfg(any(target_vendor = "apple", feature = "mock"))]
+use crate::api::{Characteristic, Descriptor, Service};
 use futures::stream::{Stream, StreamExt};
+#[cfg(any(target_vendor = "apple", feature = "mock"))]
+use std::collections::BTreeSet;
 use std::pin::Pin;
 use tokio::sync::broadcast::Receiver;
 use tokio_stream::wrappers::BroadcastStream;
@@ -16,3 +20,29 @@ pub fn notifications_stream_from_broadcast_receiver(
 ) -> Pin<Box<dyn Stream<Item = ValueNotification> + Send>> {
     Box::pin(BroadcastStream::new(receiver).filter_map(|x| async move { x.ok() }))
 }
+
+/// Replaces the descriptors of the given characteristic in `services`, for backends which discover
+/// them separately from services. Returns the updated characteristic, or `None` if it isn't there.
+#[cfg(any(target_vendor = "apple", feature = "mock"))]
+pub fn replace_descriptors(
+    services: &mut BTreeSet<Service>,
+    characteristic: &Characteristic,
+    descriptors: BTreeSet<Descriptor>,
+) -> Option<Characteristic> {
+    let is_same = |c: &Characteristic| {
+        c.uuid == characteristic.uuid
+            && c.service_uuid == characteristic.service_uuid
+            && c.instance_id == characteristic.instance_id
+    };
+    let mut service = services
+        .iter()
+        .find(|service| service.characteristics.iter().any(is_same))?
+        .clone();
+    let mut updated = service.characteristics.iter().find(|c| is_same(c))?.clone();
+    services.remove(&service);
+    service.characteristics.remove(&updated);
+    updated.descriptors = descriptors;
+    service.characteristics.insert(updated.clone());
+    services.insert(service);
+    Some(updated)
+}
@@ -2,9 +2,18 @@ use super::internal::{
     run_corebluetooth_thread, CoreBluetoothEvent, CoreBluetoothMessage, CoreBluetoothReply,
     CoreBluetoothReplyFuture,
 };
+use super::manager::ManagerOptions;
 use super::peripheral::{Peripheral, PeripheralId};
-use crate::api::{Central, CentralEvent, CentralState, ScanFilter};
-use crate::common::adapter_manager::AdapterManager;
+use crate::api::{
+    AdapterCapabilities, Central, CentralEvent, CentralState, ScanFilter, ScanOptions,
+    ScanSubscription,
+};
+use crate::common::{
+    adapter_manager::AdapterManager,
+    runtime,
+    scan_state::ScanState,
+    scan_subscriptions::{subscribe_scan, ScanSubscriptions},
+};
 use crate::{Error, Result};
 use async_trait::async_trait;
 use futures::channel::mpsc::{self, Sender};
@@ -14,13 +23,15 @@ use log::*;
 use objc2_core_bluetooth::CBManagerState;
 use std::pin::Pin;
 use std::sync::Arc;
-use tokio::task;
+use std::time::Duration;
 
 /// Implementation of [api::Central](crate::api::Central).
 #[derive(Clone, Debug)]
 pub struct Adapter {
     manager: Arc<AdapterManager<Peripheral>>,
     sender: Sender<CoreBluetoothMessage>,
+    scan_state: Arc<ScanState>,
+    scans: Arc<ScanSubscriptions>,
 }
 
 fn get_central_state(state: CBManagerState) -> CentralState {
@@ -32,9 +43,9 @@ fn get_central_state(state: CBManagerState) -> CentralState {
 }
 
 impl Adapter {
-    pub(crate) async fn new() -> Result<Self> {
+    pub(crate) async fn new(options: ManagerOptions) -> Result<Self> {
         let (sender, mut receiver) = mpsc::channel(256);
-        let adapter_sender = run_corebluetooth_thread(sender)?;
+        let adapter_sender = run_corebluetooth_thread(sender, options)?;
         // Since init currently blocked until the state update, we know the
         // receiver is dropped after that. We can pick it up here and make it
         // part of our event loop to update our peripherals.
@@ -52,37 +63,69 @@ impl Adapter {
 
         let manager_clone = manager.clone();
         let adapter_sender_clone = adapter_sender.clone();
-        task::spawn(async move {
+        runtime::spawn(async move {
             while let Some(msg) = receiver.next().await {
                 match msg {
                     CoreBluetoothEvent::DeviceDiscovered {
                         uuid,
                         name,
+                        connectable,
                         event_receiver,
                     } => {
                         manager_clone.add_peripheral(Peripheral::new(
                             uuid,
                             name,
+                            connectable,
                             Arc::downgrade(&manager_clone),
+                            manager_clone.connect_limit(),
                             event_receiver,
                             adapter_sender_clone.clone(),
                         ));
                         manager_clone.emit(CentralEvent::DeviceDiscovered(uuid.into()));
                     }
-                    CoreBluetoothEvent::DeviceUpdated { uuid, name } => {
+                    CoreBluetoothEvent::DeviceUpdated {
+                        uuid,
+                        name,
+                        connectable,
+                    } => {
                         let id = uuid.into();
                         if let Some(entry) = manager_clone.peripheral_mut(&id) {
-                            entry.value().update_name(&name);
+                            entry.value().update_advertisement(name, connectable);
                             manager_clone.emit(CentralEvent::DeviceUpdated(id));
                         }
                     }
                     CoreBluetoothEvent::DeviceDisconnected { uuid } => {
                         manager_clone.emit(CentralEvent::DeviceDisconnected(uuid.into()));
                     }
+                    CoreBluetoothEvent::ServicesChanged { uuid } => {
+                        manager_clone.emit(CentralEvent::ServicesChanged(uuid.into()));
+                    }
                     CoreBluetoothEvent::DidUpdateState { state } => {
                         let central_state = get_central_state(state);
                         manager_clone.emit(CentralEvent::StateUpdate(central_state));
                     }
+                    CoreBluetoothEvent::ScanStarted => {
+                        manager_clone.emit(CentralEvent::ScanStarted);
+                    }
+                    CoreBluetoothEvent::ScanStopped => {
+                        manager_clone.emit(CentralEvent::ScanStopped);
+                    }
+                }
+            }
+        });
+
+        // CoreBluetooth would otherwise keep treating peripherals evicted for not advertising as
+        // known, and never report them as discovered again.
+        let mut events = manager.event_stream();
+        let mut forget_sender = adapter_sender.clone();
+        runtime::spawn(async move {
+            while let Some(event) = events.next().await {
+                if let CentralEvent::DeviceLost(id) = event {
+                    let _ = forget_sender
+                        .send(CoreBluetoothMessage::ForgetPeripheral {
+                            peripheral_uuid: id.0,
+                        })
+                        .await;
                 }
             }
         });
@@ -90,6 +133,8 @@ impl Adapter {
         Ok(Adapter {
             manager,
             sender: adapter_sender,
+            scan_state: Default::default(),
+            scans: Default::default(),
         })
     }
 }
@@ -105,27 +150,87 @@ impl Central for Adapter {
     async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
         self.sender
             .to_owned()
-            .send(CoreBluetoothMessage::StartScanning { filter })
+            .send(CoreBluetoothMessage::StartScanning {
+                filter: filter.clone(),
+            })
             .await?;
+        self.scan_state.started(filter);
         Ok(())
     }
 
+    async fn start_scan_with_options(
+        &self,
+        filter: ScanFilter,
+        options: ScanOptions,
+    ) -> Result<()> {
+        options.check()?;
+        self.manager.rssi_smoothing().set(options.rssi_smoothing);
+        self.start_scan(filter).await
+    }
+
     async fn stop_scan(&self) -> Result<()> {
         self.sender
             .to_owned()
             .send(CoreBluetoothMessage::StopScanning)
             .await?;
+        self.scan_state.stopped();
         Ok(())
     }
 
+    async fn pause_scan(&self) -> Result<()> {
+        if self.scan_state.is_running() {
+            self.sender
+                .to_owned()
+                .send(CoreBluetoothMessage::StopScanning)
+                .await?;
+            self.scan_state.paused();
+        }
+        Ok(())
+    }
+
+    async fn resume_scan(&self) -> Result<()> {
+        if let Some(filter) = self.scan_state.paused_filter() {
+            self.sender
+                .to_owned()
+                .send(CoreBluetoothMessage::StartScanning { filter })
+                .await?;
+            self.scan_state.resumed();
+        }
+        Ok(())
+    }
+
+    async fn subscribe_scan(&self, filter: ScanFilter) -> Result<ScanSubscription> {
+        subscribe_scan(self, &self.scans, filter).await
+    }
+
     async fn peripherals(&self) -> Result<Vec<Peripheral>> {
         Ok(self.manager.peripherals())
     }
 
+    async fn connected(&self) -> Result<Vec<Peripheral>> {
+        Ok(self.manager.connected_peripherals())
+    }
+
     async fn peripheral(&self, id: &PeripheralId) -> Result<Peripheral> {
         self.manager.peripheral(id).ok_or(Error::DeviceNotFound)
     }
 
+    async fn is_known(&self, id: &PeripheralId) -> Result<bool> {
+        let fut = CoreBluetoothReplyFuture::default();
+        self.sender
+            .to_owned()
+            .send(CoreBluetoothMessage::IsKnown {
+                peripheral_uuid: id.0,
+                future: fut.get_state_clone(),
+            })
+            .await?;
+
+        match fut.await {
+            CoreBluetoothReply::Known(known) => Ok(known),
+            _ => panic!("Shouldn't get anything but a Known!"),
+        }
+    }
+
     async fn add_peripheral(&self, _address: &PeripheralId) -> Result<Peripheral> {
         Err(Error::NotSupported(
             "Can't add a Peripheral from a PeripheralId".to_string(),
@@ -154,4 +259,38 @@ impl Central for Adapter {
             _ => panic!("Shouldn't get anything but a AdapterState!"),
         }
     }
+
+    async fn is_scanning(&self) -> Result<bool> {
+        let fut = CoreBluetoothReplyFuture::default();
+        self.sender
+            .to_owned()
+            .send(CoreBluetoothMessage::IsScanning {
+                future: fut.get_state_clone(),
+            })
+            .await?;
+
+        match fut.await {
+            CoreBluetoothReply::Scanning(scanning) => Ok(scanning),
+            _ => panic!("Shouldn't get anything but a Scanning!"),
+        }
+    }
+
+    async fn adapter_capabilities(&self) -> Result<AdapterCapabilities> {
+        // CBPeripheralManager is always available.
+        Ok(AdapterCapabilities {
+            peripheral_role: true,
+        })
+    }
+
+    fn set_peripheral_ttl(&self, ttl: Option<Duration>) {
+        self.manager.set_peripheral_ttl(ttl);
+    }
+
+    fn set_max_peripherals(&self, max: Option<usize>) {
+        self.manager.set_max_peripherals(max);
+    }
+
+    fn set_max_concurrent_connects(&self, max: usize) {
+        self.manager.connect_limit().set_max(max);
+    }
 }
@@ -17,17 +17,22 @@
 // according to those terms.
 
 use super::utils::nsstring_to_string;
-use super::utils::{core_bluetooth::cbuuid_to_uuid, nsuuid_to_uuid};
+use super::utils::{
+    core_bluetooth::{cbuuid_to_uuid, characteristic_instance_id, service_instance_id},
+    nsuuid_to_uuid,
+};
+use crate::ConnectFailureReason;
 use futures::channel::mpsc::Sender;
 use futures::sink::SinkExt;
 use log::{error, trace};
 use objc2::runtime::{AnyObject, ProtocolObject};
 use objc2::{declare_class, msg_send_id, mutability, rc::Retained, ClassType, DeclaredClass};
 use objc2_core_bluetooth::{
-    CBAdvertisementDataLocalNameKey, CBAdvertisementDataManufacturerDataKey,
-    CBAdvertisementDataServiceDataKey, CBAdvertisementDataServiceUUIDsKey, CBCentralManager,
-    CBCentralManagerDelegate, CBCharacteristic, CBDescriptor, CBManagerState, CBPeripheral,
-    CBPeripheralDelegate, CBService, CBUUID,
+    CBAdvertisementDataIsConnectable, CBAdvertisementDataLocalNameKey,
+    CBAdvertisementDataManufacturerDataKey, CBAdvertisementDataServiceDataKey,
+    CBAdvertisementDataServiceUUIDsKey, CBCentralManager, CBCentralManagerDelegate,
+    CBCharacteristic, CBDescriptor, CBManagerState, CBPeripheral, CBPeripheralDelegate, CBService,
+    CBUUID,
 };
 use objc2_foundation::{
     NSArray, NSData, NSDictionary, NSError, NSNumber, NSObject, NSObjectProtocol, NSString,
@@ -47,10 +52,12 @@ pub enum CentralDelegateEvent {
     DiscoveredPeripheral {
         cbperipheral: Retained<CBPeripheral>,
         local_name: Option<String>,
+        connectable: Option<bool>,
     },
     DiscoveredServices {
         peripheral_uuid: Uuid,
-        services: HashMap<Uuid, Retained<CBService>>,
+        /// Service UUID and instance ID to CBService
+        services: HashMap<(Uuid, u16), Retained<CBService>>,
     },
     ManufacturerData {
         peripheral_uuid: Uuid,
@@ -72,20 +79,26 @@ pub enum CentralDelegateEvent {
     DiscoveredCharacteristics {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
-        /// Characteristic UUID to CBCharacteristic
-        characteristics: HashMap<Uuid, Retained<CBCharacteristic>>,
+        service_instance: u16,
+        /// Characteristic UUID and instance ID to CBCharacteristic
+        characteristics: HashMap<(Uuid, u16), Retained<CBCharacteristic>>,
     },
     DiscoveredCharacteristicDescriptors {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         descriptors: HashMap<Uuid, Retained<CBDescriptor>>,
     },
+    ServicesModified {
+        peripheral_uuid: Uuid,
+    },
     ConnectedDevice {
         peripheral_uuid: Uuid,
     },
     ConnectionFailed {
         peripheral_uuid: Uuid,
+        reason: ConnectFailureReason,
         error_description: Option<String>,
     },
     DisconnectedDevice {
@@ -95,27 +108,33 @@ pub enum CentralDelegateEvent {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
     },
     CharacteristicUnsubscribed {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
     },
     CharacteristicNotified {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         data: Vec<u8>,
     },
     CharacteristicWritten {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
+        error: Option<String>,
     },
     DescriptorNotified {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         descriptor_uuid: Uuid,
         data: Vec<u8>,
     },
@@ -123,6 +142,7 @@ pub enum CentralDelegateEvent {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         descriptor_uuid: Uuid,
     },
 }
@@ -137,10 +157,12 @@ impl Debug for CentralDelegateEvent {
             CentralDelegateEvent::DiscoveredPeripheral {
                 cbperipheral,
                 local_name,
+                connectable,
             } => f
                 .debug_struct("CentralDelegateEvent")
                 .field("cbperipheral", cbperipheral.deref())
                 .field("local_name", local_name)
+                .field("connectable", connectable)
                 .finish(),
             CentralDelegateEvent::DiscoveredServices {
                 peripheral_uuid,
@@ -153,11 +175,13 @@ impl Debug for CentralDelegateEvent {
             CentralDelegateEvent::DiscoveredCharacteristics {
                 peripheral_uuid,
                 service_uuid,
+                service_instance,
                 characteristics,
             } => f
                 .debug_struct("DiscoveredCharacteristics")
                 .field("peripheral_uuid", peripheral_uuid)
                 .field("service_uuid", service_uuid)
+                .field("service_instance", service_instance)
                 .field(
                     "characteristics",
                     &characteristics.keys().collect::<Vec<_>>(),
@@ -167,24 +191,32 @@ impl Debug for CentralDelegateEvent {
                 peripheral_uuid,
                 service_uuid,
                 characteristic_uuid,
+                characteristic_instance,
                 descriptors,
             } => f
                 .debug_struct("DiscoveredCharacteristicDescriptors")
                 .field("peripheral_uuid", peripheral_uuid)
                 .field("service_uuid", service_uuid)
                 .field("characteristic_uuid", characteristic_uuid)
+                .field("characteristic_instance", characteristic_instance)
                 .field("descriptors", &descriptors.keys().collect::<Vec<_>>())
                 .finish(),
+            CentralDelegateEvent::ServicesModified { peripheral_uuid } => f
+                .debug_struct("ServicesModified")
+                .field("peripheral_uuid", peripheral_uuid)
+                .finish(),
             CentralDelegateEvent::ConnectedDevice { peripheral_uuid } => f
                 .debug_struct("ConnectedDevice")
                 .field("peripheral_uuid", peripheral_uuid)
                 .finish(),
             CentralDelegateEvent::ConnectionFailed {
                 peripheral_uuid,
+                reason,
                 error_description,
             } => f
                 .debug_struct("ConnectionFailed")
                 .field("peripheral_uuid", peripheral_uuid)
+                .field("reason", reason)
                 .field("error_description", error_description)
                 .finish(),
             CentralDelegateEvent::DisconnectedDevice { peripheral_uuid } => f
@@ -195,43 +227,53 @@ impl Debug for CentralDelegateEvent {
                 peripheral_uuid,
                 service_uuid,
                 characteristic_uuid,
+                characteristic_instance,
             } => f
                 .debug_struct("CharacteristicSubscribed")
                 .field("peripheral_uuid", peripheral_uuid)
                 .field("service_uuid", service_uuid)
                 .field("characteristic_uuid", characteristic_uuid)
+                .field("characteristic_instance", characteristic_instance)
                 .finish(),
             CentralDelegateEvent::CharacteristicUnsubscribed {
                 peripheral_uuid,
                 service_uuid,
                 characteristic_uuid,
+                characteristic_instance,
             } => f
                 .debug_struct("CharacteristicUnsubscribed")
                 .field("peripheral_uuid", peripheral_uuid)
                 .field("service_uuid", service_uuid)
                 .field("characteristic_uuid", characteristic_uuid)
+                .field("characteristic_instance", characteristic_instance)
                 .finish(),
             CentralDelegateEvent::CharacteristicNotified {
                 peripheral_uuid,
                 service_uuid,
                 characteristic_uuid,
+                characteristic_instance,
                 data,
             } => f
                 .debug_struct("CharacteristicNotified")
                 .field("peripheral_uuid", peripheral_uuid)
                 .field("service_uuid", service_uuid)
                 .field("characteristic_uuid", characteristic_uuid)
+                .field("characteristic_instance", characteristic_instance)
                 .field("data", data)
                 .finish(),
             CentralDelegateEvent::CharacteristicWritten {
                 peripheral_uuid,
                 service_uuid,
                 characteristic_uuid,
+                characteristic_instance,
+                error,
             } => f
                 .debug_struct("CharacteristicWritten")
                 .field("service_uuid", service_uuid)
                 .field("peripheral_uuid", peripheral_uuid)
                 .field("characteristic_uuid", characteristic_uuid)
+                .field("characteristic_instance", characteristic_instance)
+                .field("error", error)
                 .finish(),
             CentralDelegateEvent::ManufacturerData {
                 peripheral_uuid,
@@ -269,6 +311,7 @@ impl Debug for CentralDelegateEvent {
                 peripheral_uuid,
                 service_uuid,
                 characteristic_uuid,
+                characteristic_instance,
                 descriptor_uuid,
                 data,
             } => f
@@ -276,6 +319,7 @@ impl Debug for CentralDelegateEvent {
                 .field("peripheral_uuid", peripheral_uuid)
                 .field("service_uuid", service_uuid)
                 .field("characteristic_uuid", characteristic_uuid)
+                .field("characteristic_instance", characteristic_instance)
                 .field("descriptor_uuid", descriptor_uuid)
                 .field("data", data)
                 .finish(),
@@ -283,12 +327,14 @@ impl Debug for CentralDelegateEvent {
                 peripheral_uuid,
                 service_uuid,
                 characteristic_uuid,
+                characteristic_instance,
                 descriptor_uuid,
             } => f
                 .debug_struct("DescriptorWritten")
                 .field("service_uuid", service_uuid)
                 .field("peripheral_uuid", peripheral_uuid)
                 .field("characteristic_uuid", characteristic_uuid)
+                .field("characteristic_instance", characteristic_instance)
                 .field("descriptor_uuid", descriptor_uuid)
                 .finish(),
         }
@@ -335,7 +381,6 @@ declare_class!(
                 peripheral_debug(peripheral)
             );
             unsafe { peripheral.setDelegate(Some(ProtocolObject::from_ref(self))) };
-            unsafe { peripheral.discoverServices(None) }
             let peripheral_uuid = nsuuid_to_uuid(unsafe { &peripheral.identifier() });
             self.send_event(CentralDelegateEvent::ConnectedDevice { peripheral_uuid });
         }
@@ -364,9 +409,11 @@ declare_class!(
         ) {
             trace!("delegate_centralmanager_didfailtoconnectperipheral_error");
             let peripheral_uuid = nsuuid_to_uuid(unsafe { &peripheral.identifier() });
+            let reason = error.map_or(ConnectFailureReason::Unknown, connect_failure_reason);
             let error_description = error.map(|error| error.localizedDescription().to_string());
             self.send_event(CentralDelegateEvent::ConnectionFailed {
                 peripheral_uuid,
+                reason,
                 error_description,
             });
         }
@@ -389,9 +436,19 @@ declare_class!(
                 .map(|name| (name as *const AnyObject as *const NSString))
                 .and_then(|name| unsafe { nsstring_to_string(name) });
 
+            let connectable = adv_data
+                .get(unsafe { CBAdvertisementDataIsConnectable })
+                .map(|connectable| {
+                    // SAFETY: connectable is `NSNumber`
+                    let connectable: *const AnyObject = connectable;
+                    let connectable: *const NSNumber = connectable.cast();
+                    unsafe { &*connectable }.as_bool()
+                });
+
             self.send_event(CentralDelegateEvent::DiscoveredPeripheral {
                 cbperipheral: peripheral.retain(),
                 local_name,
+                connectable,
             });
 
             let rssi_value = rssi.as_i16();
@@ -483,7 +540,7 @@ declare_class!(
 
                     // Create the map entry we'll need to export.
                     let uuid = cbuuid_to_uuid(unsafe { &s.UUID() });
-                    service_map.insert(uuid, s);
+                    service_map.insert((uuid, service_instance_id(&s)), s);
                 }
                 let peripheral_uuid = nsuuid_to_uuid(unsafe { &peripheral.identifier() });
                 self.send_event(CentralDelegateEvent::DiscoveredServices {
@@ -493,6 +550,20 @@ declare_class!(
             }
         }
 
+        #[method(peripheral:didModifyServices:)]
+        fn delegate_peripheral_didmodifyservices(
+            &self,
+            peripheral: &CBPeripheral,
+            _invalidated_services: &NSArray<CBService>,
+        ) {
+            trace!(
+                "delegate_peripheral_didmodifyservices {}",
+                peripheral_debug(peripheral)
+            );
+            let peripheral_uuid = nsuuid_to_uuid(unsafe { &peripheral.identifier() });
+            self.send_event(CentralDelegateEvent::ServicesModified { peripheral_uuid });
+        }
+
         #[method(peripheral:didDiscoverIncludedServicesForService:error:)]
         fn delegate_peripheral_diddiscoverincludedservicesforservice_error(
             &self,
@@ -531,16 +602,16 @@ declare_class!(
                 let mut characteristics = HashMap::new();
                 let chars = unsafe { service.characteristics() }.unwrap_or_default();
                 for c in chars {
-                    unsafe { peripheral.discoverDescriptorsForCharacteristic(&c) };
                     // Create the map entry we'll need to export.
                     let uuid = cbuuid_to_uuid(unsafe { &c.UUID() });
-                    characteristics.insert(uuid, c);
+                    characteristics.insert((uuid, characteristic_instance_id(&c)), c);
                 }
                 let peripheral_uuid = nsuuid_to_uuid(unsafe { &peripheral.identifier() });
                 let service_uuid = cbuuid_to_uuid(unsafe { &service.UUID() });
                 self.send_event(CentralDelegateEvent::DiscoveredCharacteristics {
                     peripheral_uuid,
                     service_uuid,
+                    service_instance: service_instance_id(service),
                     characteristics,
                 });
             }
@@ -571,10 +642,12 @@ declare_class!(
                 let service = unsafe { characteristic.service() }.unwrap();
                 let service_uuid = cbuuid_to_uuid(unsafe { &service.UUID() });
                 let characteristic_uuid = cbuuid_to_uuid(unsafe { &characteristic.UUID() });
+                let characteristic_instance = characteristic_instance_id(characteristic);
                 self.send_event(CentralDelegateEvent::DiscoveredCharacteristicDescriptors {
                     peripheral_uuid,
                     service_uuid,
                     characteristic_uuid,
+                    characteristic_instance,
                     descriptors,
                 });
             }
@@ -599,6 +672,7 @@ declare_class!(
                     peripheral_uuid: nsuuid_to_uuid(unsafe { &peripheral.identifier() }),
                     service_uuid: cbuuid_to_uuid(unsafe { &service.UUID() }),
                     characteristic_uuid: cbuuid_to_uuid(unsafe { &characteristic.UUID() }),
+                    characteristic_instance: characteristic_instance_id(&characteristic),
                     data: get_characteristic_value(characteristic),
                 });
                 // Notify BluetoothGATTCharacteristic::read_value that read was successful.
@@ -618,14 +692,16 @@ declare_class!(
                 characteristic_debug(characteristic),
                 localized_description(error)
             );
-            if error.is_none() {
-                let service = unsafe { characteristic.service() }.unwrap();
-                self.send_event(CentralDelegateEvent::CharacteristicWritten {
-                    peripheral_uuid: nsuuid_to_uuid(unsafe { &peripheral.identifier() }),
-                    service_uuid: cbuuid_to_uuid(unsafe { &service.UUID() }),
-                    characteristic_uuid: cbuuid_to_uuid(unsafe { &characteristic.UUID() }),
-                });
-            }
+            // Sent on failure too, so that the write waiting on the device's response isn't left
+            // hanging.
+            let service = unsafe { characteristic.service() }.unwrap();
+            self.send_event(CentralDelegateEvent::CharacteristicWritten {
+                peripheral_uuid: nsuuid_to_uuid(unsafe { &peripheral.identifier() }),
+                service_uuid: cbuuid_to_uuid(unsafe { &service.UUID() }),
+                characteristic_uuid: cbuuid_to_uuid(unsafe { &characteristic.UUID() }),
+                characteristic_instance: characteristic_instance_id(&characteristic),
+                error: error.map(|error| error.localizedDescription().to_string()),
+            });
         }
 
         #[method(peripheral:didUpdateNotificationStateForCharacteristic:error:)]
@@ -641,17 +717,20 @@ declare_class!(
             let service = unsafe { characteristic.service() }.unwrap();
             let service_uuid = cbuuid_to_uuid(unsafe { &service.UUID() });
             let characteristic_uuid = cbuuid_to_uuid(unsafe { &characteristic.UUID() });
+            let characteristic_instance = characteristic_instance_id(characteristic);
             if unsafe { characteristic.isNotifying() } {
                 self.send_event(CentralDelegateEvent::CharacteristicSubscribed {
                     peripheral_uuid,
                     service_uuid,
                     characteristic_uuid,
+                    characteristic_instance,
                 });
             } else {
                 self.send_event(CentralDelegateEvent::CharacteristicUnsubscribed {
                     peripheral_uuid,
                     service_uuid,
                     characteristic_uuid,
+                    characteristic_instance,
                 });
             }
         }
@@ -690,6 +769,7 @@ declare_class!(
                     peripheral_uuid: nsuuid_to_uuid(unsafe { &peripheral.identifier() }),
                     service_uuid: cbuuid_to_uuid(unsafe { &service.UUID() }),
                     characteristic_uuid: cbuuid_to_uuid(unsafe { &characteristic.UUID() }),
+                    characteristic_instance: characteristic_instance_id(&characteristic),
                     descriptor_uuid: cbuuid_to_uuid(unsafe { &descriptor.UUID() }),
                     data: get_descriptor_value(&descriptor),
                 });
@@ -717,6 +797,7 @@ declare_class!(
                     peripheral_uuid: nsuuid_to_uuid(unsafe { &peripheral.identifier() }),
                     service_uuid: cbuuid_to_uuid(unsafe { &service.UUID() }),
                     characteristic_uuid: cbuuid_to_uuid(unsafe { &characteristic.UUID() }),
+                    characteristic_instance: characteristic_instance_id(&characteristic),
                     descriptor_uuid: cbuuid_to_uuid(unsafe { &descriptor.UUID() }),
                 });
             }
@@ -813,3 +894,21 @@ fn descriptor_debug(descriptor: &CBDescriptor) -> String {
     let uuid = unsafe { descriptor.UUID().UUIDString() };
     format!("CBDescriptor({})", uuid)
 }
+
+/// Classifies the error CoreBluetooth gave for a failed connection by its `CBError` code.
+fn connect_failure_reason(error: &NSError) -> ConnectFailureReason {
+    if error.domain().to_string() != "CBErrorDomain" {
+        return ConnectFailureReason::Unknown;
+    }
+    match error.code() {
+        // CBErrorOperationCancelled
+        5 => ConnectFailureReason::Cancelled,
+        // CBErrorConnectionTimeout and CBErrorEncryptionTimedOut
+        6 | 15 => ConnectFailureReason::Timeout,
+        // CBErrorOperationNotSupported
+        13 => ConnectFailureReason::UnsupportedFeature,
+        // CBErrorPeerRemovedPairingInformation
+        14 => ConnectFailureReason::Rejected,
+        _ => ConnectFailureReason::Unknown,
+    }
+}
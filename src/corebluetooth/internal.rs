@@ -12,26 +12,32 @@ use super::{
     central_delegate::{CentralDelegate, CentralDelegateEvent},
     ffi,
     future::{BtlePlugFuture, BtlePlugFutureStateShared},
+    manager::ManagerOptions,
     utils::{
         core_bluetooth::{cbuuid_to_uuid, uuid_to_cbuuid},
-        nsuuid_to_uuid,
+        nsuuid_to_uuid, uuid_to_nsuuid,
     },
 };
 use crate::api::{CharPropFlags, Characteristic, Descriptor, ScanFilter, Service, WriteType};
-use crate::Error;
+use crate::{ConnectFailureReason, Error};
 use futures::channel::mpsc::{self, Receiver, Sender};
 use futures::select;
 use futures::sink::SinkExt;
 use futures::stream::{Fuse, StreamExt};
 use log::{error, trace, warn};
 use objc2::{msg_send_id, ClassType};
-use objc2::{rc::Retained, runtime::AnyObject};
+use objc2::{
+    rc::Retained,
+    runtime::{AnyObject, NSObjectProtocol, ProtocolObject},
+};
 use objc2_core_bluetooth::{
     CBCentralManager, CBCentralManagerScanOptionAllowDuplicatesKey, CBCharacteristic,
     CBCharacteristicProperties, CBCharacteristicWriteType, CBDescriptor, CBManager,
     CBManagerAuthorization, CBManagerState, CBPeripheral, CBPeripheralState, CBService, CBUUID,
 };
-use objc2_foundation::{NSArray, NSData, NSMutableDictionary, NSNumber};
+use objc2_foundation::{
+    NSActivityOptions, NSArray, NSData, NSMutableDictionary, NSNumber, NSProcessInfo, NSString,
+};
 use std::{
     collections::{BTreeSet, HashMap, VecDeque},
     ffi::CString,
@@ -39,7 +45,6 @@ use std::{
     ops::Deref,
     thread,
 };
-use tokio::runtime;
 use uuid::Uuid;
 
 struct DescriptorInternal {
@@ -70,6 +75,7 @@ struct CharacteristicInternal {
     pub write_future_state: VecDeque<CoreBluetoothReplyStateShared>,
     pub subscribe_future_state: VecDeque<CoreBluetoothReplyStateShared>,
     pub unsubscribe_future_state: VecDeque<CoreBluetoothReplyStateShared>,
+    pub descriptors_future_state: VecDeque<CoreBluetoothReplyStateShared>,
     pub discovered: bool,
 }
 
@@ -83,6 +89,7 @@ impl Debug for CharacteristicInternal {
             .field("write_future_state", &self.write_future_state)
             .field("subscribe_future_state", &self.subscribe_future_state)
             .field("unsubscribe_future_state", &self.unsubscribe_future_state)
+            .field("descriptors_future_state", &self.descriptors_future_state)
             .finish()
     }
 }
@@ -108,6 +115,7 @@ impl CharacteristicInternal {
             write_future_state: VecDeque::with_capacity(10),
             subscribe_future_state: VecDeque::with_capacity(10),
             unsubscribe_future_state: VecDeque::with_capacity(10),
+            descriptors_future_state: VecDeque::with_capacity(10),
             discovered: false,
         }
     }
@@ -147,17 +155,28 @@ impl CharacteristicInternal {
 #[derive(Clone, Debug)]
 pub enum CoreBluetoothReply {
     AdapterState(CBManagerState),
+    Scanning(bool),
+    Known(bool),
     ReadResult(Vec<u8>),
-    Connected(BTreeSet<Service>),
+    /// The UUIDs of the descriptors discovered for a characteristic.
+    Descriptors(Vec<Uuid>),
+    /// The discovered services, and the maximum write lengths with and without response.
+    Connected(BTreeSet<Service>, usize, usize),
     State(CBPeripheralState),
+    Name(Option<String>),
+    /// A write without response was handed to CoreBluetooth, which did or didn't have room to send
+    /// it straight away.
+    WrittenWithoutResponse(bool),
     Ok,
     Err(String),
+    ConnectionFailed(ConnectFailureReason, String),
 }
 
 #[derive(Debug)]
 pub enum PeripheralEventInternal {
     Disconnected,
-    Notification(Uuid, Vec<u8>),
+    /// A notification from the characteristic with the given service UUID, UUID and instance ID.
+    Notification(Uuid, Uuid, u16, Vec<u8>),
     ManufacturerData(u16, Vec<u8>, i16),
     ServiceData(HashMap<Uuid, Vec<u8>>, i16),
     Services(Vec<Uuid>, i16),
@@ -168,16 +187,23 @@ pub type CoreBluetoothReplyFuture = BtlePlugFuture<CoreBluetoothReply>;
 
 struct ServiceInternal {
     cbservice: Retained<CBService>,
-    characteristics: HashMap<Uuid, CharacteristicInternal>,
+    /// Keyed by UUID and instance ID, as a service may have several characteristics with the same
+    /// UUID.
+    characteristics: HashMap<(Uuid, u16), CharacteristicInternal>,
     pub discovered: bool,
 }
 
 struct PeripheralInternal {
     pub peripheral: Retained<CBPeripheral>,
-    services: HashMap<Uuid, ServiceInternal>,
+    /// Keyed by UUID and instance ID, as a peripheral may have several services with the same UUID.
+    services: HashMap<(Uuid, u16), ServiceInternal>,
     pub event_sender: Sender<PeripheralEventInternal>,
     pub disconnected_future_state: Option<CoreBluetoothReplyStateShared>,
     pub connected_future_state: Option<CoreBluetoothReplyStateShared>,
+    /// Whether to discover services once the pending connection is made.
+    discover_on_connect: bool,
+    /// Whether the pending discovery also discovers each characteristic's descriptors.
+    discover_descriptors: bool,
 }
 
 impl Debug for PeripheralInternal {
@@ -189,7 +215,7 @@ impl Debug for PeripheralInternal {
                 &self
                     .services
                     .iter()
-                    .map(|(service_uuid, service)| (service_uuid, service.characteristics.len()))
+                    .map(|(service, internal)| (service, internal.characteristics.len()))
                     .collect::<HashMap<_, _>>(),
             )
             .field("event_sender", &self.event_sender)
@@ -198,6 +224,21 @@ impl Debug for PeripheralInternal {
     }
 }
 
+/// Finds the service with the given UUID which has the given characteristic. Characteristics'
+/// instance IDs tell them apart across all the services with the same UUID.
+fn service_mut<'a>(
+    services: &'a mut HashMap<(Uuid, u16), ServiceInternal>,
+    service_uuid: Uuid,
+    characteristic: &(Uuid, u16),
+) -> Option<&'a mut ServiceInternal> {
+    services
+        .iter_mut()
+        .find(|((uuid, _), service)| {
+            *uuid == service_uuid && service.characteristics.contains_key(characteristic)
+        })
+        .map(|(_, service)| service)
+}
+
 impl PeripheralInternal {
     pub fn new(
         peripheral: Retained<CBPeripheral>,
@@ -209,29 +250,41 @@ impl PeripheralInternal {
             event_sender,
             connected_future_state: None,
             disconnected_future_state: None,
+            discover_on_connect: true,
+            discover_descriptors: true,
         }
     }
 
     pub fn set_characteristics(
         &mut self,
-        service_uuid: Uuid,
-        characteristics: HashMap<Uuid, Retained<CBCharacteristic>>,
+        service: (Uuid, u16),
+        characteristics: HashMap<(Uuid, u16), Retained<CBCharacteristic>>,
     ) {
+        let discover_descriptors = self.discover_descriptors;
         let characteristics = characteristics
             .into_iter()
-            .map(|(characteristic_uuid, characteristic)| {
-                (
-                    characteristic_uuid,
-                    CharacteristicInternal::new(characteristic),
-                )
+            .map(|(key, cbcharacteristic)| {
+                let mut characteristic = CharacteristicInternal::new(cbcharacteristic);
+                if discover_descriptors {
+                    unsafe {
+                        self.peripheral
+                            .discoverDescriptorsForCharacteristic(&characteristic.characteristic)
+                    };
+                } else {
+                    // Left for discover_descriptors, so they aren't taken from CoreBluetooth's
+                    // cache meanwhile either.
+                    characteristic.descriptors.clear();
+                    characteristic.discovered = true;
+                }
+                (key, characteristic)
             })
             .collect();
         let service = self
             .services
-            .get_mut(&service_uuid)
+            .get_mut(&service)
             .expect("Got characteristics for a service we don't know about");
         service.characteristics = characteristics;
-        if service.characteristics.is_empty() {
+        if service.characteristics.is_empty() || !discover_descriptors {
             service.discovered = true;
             self.check_discovered();
         }
@@ -241,6 +294,7 @@ impl PeripheralInternal {
         &mut self,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         descriptors: HashMap<Uuid, Retained<CBDescriptor>>,
     ) {
         let descriptors = descriptors
@@ -249,16 +303,28 @@ impl PeripheralInternal {
                 (descriptor_uuid, DescriptorInternal::new(descriptor))
             })
             .collect();
-        let service = self
-            .services
-            .get_mut(&service_uuid)
-            .expect("Got descriptors for a service we don't know about");
+        let service = service_mut(
+            &mut self.services,
+            service_uuid,
+            &(characteristic_uuid, characteristic_instance),
+        )
+        .expect("Got descriptors for a service we don't know about");
         let characteristic = service
             .characteristics
-            .get_mut(&characteristic_uuid)
+            .get_mut(&(characteristic_uuid, characteristic_instance))
             .expect("Got descriptors for a characteristic we don't know about");
         characteristic.descriptors = descriptors;
-        characteristic.discovered = true;
+        let uuids: Vec<_> = characteristic.descriptors.keys().copied().collect();
+        for future in characteristic.descriptors_future_state.drain(..) {
+            future
+                .lock()
+                .unwrap()
+                .set_reply(CoreBluetoothReply::Descriptors(uuids.clone()));
+        }
+        // Descriptors discovered on their own, after the services, don't complete a discovery.
+        if std::mem::replace(&mut characteristic.discovered, true) {
+            return;
+        }
 
         if !service
             .characteristics
@@ -280,47 +346,70 @@ impl PeripheralInternal {
         // back a Connected reply to the waiting future with all of the
         // characteristic info in it.
         if !self.services.values().any(|service| !service.discovered) {
-            if self.connected_future_state.is_none() {
-                panic!("We should still have a future at this point!");
-            }
-            let services = self
-                .services
-                .iter()
-                .map(|(&service_uuid, service)| Service {
-                    uuid: service_uuid,
-                    primary: unsafe { service.cbservice.isPrimary() },
-                    characteristics: service
-                        .characteristics
-                        .iter()
-                        .map(|(&characteristic_uuid, characteristic)| {
-                            let descriptors = characteristic
-                                .descriptors
-                                .iter()
-                                .map(|(&descriptor_uuid, _)| Descriptor {
-                                    uuid: descriptor_uuid,
-                                    service_uuid,
-                                    characteristic_uuid,
-                                })
-                                .collect();
-                            Characteristic {
-                                uuid: characteristic_uuid,
-                                service_uuid,
-                                descriptors,
-                                properties: characteristic.properties,
-                            }
-                        })
-                        .collect(),
-                })
-                .collect();
-            self.connected_future_state
-                .take()
-                .unwrap()
-                .lock()
-                .unwrap()
-                .set_reply(CoreBluetoothReply::Connected(services));
+            self.reply_connected();
         }
     }
 
+    /// Fulfills the pending connection or discovery future with the services we know about.
+    fn reply_connected(&mut self) {
+        if self.connected_future_state.is_none() {
+            panic!("We should still have a future at this point!");
+        }
+        let services = self
+            .services
+            .iter()
+            .map(|(&(service_uuid, _), service)| Service {
+                uuid: service_uuid,
+                primary: unsafe { service.cbservice.isPrimary() },
+                start_handle: None,
+                end_handle: None,
+                characteristics: service
+                    .characteristics
+                    .iter()
+                    .map(|(&(characteristic_uuid, instance_id), characteristic)| {
+                        let descriptors = characteristic
+                            .descriptors
+                            .iter()
+                            .map(|(&descriptor_uuid, _)| Descriptor {
+                                uuid: descriptor_uuid,
+                                service_uuid,
+                                characteristic_uuid,
+                                characteristic_instance_id: instance_id,
+                            })
+                            .collect();
+                        Characteristic {
+                            uuid: characteristic_uuid,
+                            service_uuid,
+                            instance_id,
+                            descriptors,
+                            properties: characteristic.properties,
+                        }
+                    })
+                    .collect(),
+            })
+            .collect();
+        let (max_write_len, max_write_without_response_len) = unsafe {
+            (
+                self.peripheral.maximumWriteValueLengthForType(
+                    CBCharacteristicWriteType::CBCharacteristicWriteWithResponse,
+                ),
+                self.peripheral.maximumWriteValueLengthForType(
+                    CBCharacteristicWriteType::CBCharacteristicWriteWithoutResponse,
+                ),
+            )
+        };
+        self.connected_future_state
+            .take()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .set_reply(CoreBluetoothReply::Connected(
+                services,
+                max_write_len,
+                max_write_without_response_len,
+            ));
+    }
+
     pub fn confirm_disconnect(&mut self) {
         // Fulfill the disconnected future, if there is one.
         // There might not be a future if the device disconnects unexpectedly.
@@ -344,6 +433,14 @@ struct CoreBluetoothInternal {
     // task::block this when sending even though it'll never actually block.
     event_sender: Sender<CoreBluetoothEvent>,
     message_receiver: Fuse<Receiver<CoreBluetoothMessage>>,
+    // Whether the current scan leaves out devices which say they aren't connectable.
+    connectable_only: bool,
+    // The prefix the current scan requires devices' names to start with, if any.
+    name_prefix: Option<String>,
+    // A scan requested while the central wasn't powered on, to start once it is.
+    pending_scan: Option<ScanFilter>,
+    // Held to keep App Nap disabled while this manager is alive, if requested.
+    _activity: Option<Retained<ProtocolObject<dyn NSObjectProtocol>>>,
 }
 
 impl Debug for CoreBluetoothInternal {
@@ -355,6 +452,8 @@ impl Debug for CoreBluetoothInternal {
             .field("delegate_receiver", &self.delegate_receiver)
             .field("event_sender", &self.event_sender)
             .field("message_receiver", &self.message_receiver)
+            .field("connectable_only", &self.connectable_only)
+            .field("name_prefix", &self.name_prefix)
             .finish()
     }
 }
@@ -364,28 +463,52 @@ pub enum CoreBluetoothMessage {
     GetAdapterState {
         future: CoreBluetoothReplyStateShared,
     },
+    IsScanning {
+        future: CoreBluetoothReplyStateShared,
+    },
+    IsKnown {
+        peripheral_uuid: Uuid,
+        future: CoreBluetoothReplyStateShared,
+    },
     StartScanning {
         filter: ScanFilter,
     },
     StopScanning,
     ConnectDevice {
         peripheral_uuid: Uuid,
+        discover_services: bool,
         future: CoreBluetoothReplyStateShared,
     },
     DisconnectDevice {
         peripheral_uuid: Uuid,
         future: CoreBluetoothReplyStateShared,
     },
+    DiscoverServices {
+        peripheral_uuid: Uuid,
+        discover_descriptors: bool,
+        /// The services to discover, or empty for all of them.
+        services: Vec<Uuid>,
+        future: CoreBluetoothReplyStateShared,
+    },
+    DiscoverDescriptors {
+        peripheral_uuid: Uuid,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        characteristic_instance: u16,
+        future: CoreBluetoothReplyStateShared,
+    },
     ReadValue {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         future: CoreBluetoothReplyStateShared,
     },
     WriteValue {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         data: Vec<u8>,
         write_type: WriteType,
         future: CoreBluetoothReplyStateShared,
@@ -394,22 +517,34 @@ pub enum CoreBluetoothMessage {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         future: CoreBluetoothReplyStateShared,
     },
     Unsubscribe {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         future: CoreBluetoothReplyStateShared,
     },
     IsConnected {
         peripheral_uuid: Uuid,
         future: CoreBluetoothReplyStateShared,
     },
+    GetName {
+        peripheral_uuid: Uuid,
+        future: CoreBluetoothReplyStateShared,
+    },
+    /// Forgets a peripheral which has been evicted for not advertising, so that it is discovered
+    /// afresh if it advertises again.
+    ForgetPeripheral {
+        peripheral_uuid: Uuid,
+    },
     ReadDescriptorValue {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         descriptor_uuid: Uuid,
         future: CoreBluetoothReplyStateShared,
     },
@@ -417,6 +552,7 @@ pub enum CoreBluetoothMessage {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         descriptor_uuid: Uuid,
         data: Vec<u8>,
         future: CoreBluetoothReplyStateShared,
@@ -431,21 +567,29 @@ pub enum CoreBluetoothEvent {
     DeviceDiscovered {
         uuid: Uuid,
         name: Option<String>,
+        connectable: Option<bool>,
         event_receiver: Receiver<PeripheralEventInternal>,
     },
     DeviceUpdated {
         uuid: Uuid,
-        name: String,
+        name: Option<String>,
+        connectable: Option<bool>,
     },
     DeviceDisconnected {
         uuid: Uuid,
     },
+    ServicesChanged {
+        uuid: Uuid,
+    },
+    ScanStarted,
+    ScanStopped,
 }
 
 impl CoreBluetoothInternal {
     pub fn new(
         message_receiver: Receiver<CoreBluetoothMessage>,
         event_sender: Sender<CoreBluetoothEvent>,
+        options: ManagerOptions,
     ) -> Self {
         // Pretty sure these come preallocated?
         let (sender, receiver) = mpsc::channel::<CentralDelegateEvent>(256);
@@ -460,13 +604,28 @@ impl CoreBluetoothInternal {
             msg_send_id![CBCentralManager::alloc(), initWithDelegate: &*delegate, queue: queue]
         };
 
+        let activity = options.prevent_app_nap.then(|| {
+            trace!("Disabling App Nap");
+            let reason = NSString::from_str("Receiving Bluetooth LE notifications");
+            unsafe {
+                NSProcessInfo::processInfo().beginActivityWithOptions_reason(
+                    NSActivityOptions::UserInitiatedAllowingIdleSystemSleep,
+                    &reason,
+                )
+            }
+        });
+
         Self {
             manager,
             peripherals: HashMap::new(),
             delegate_receiver: receiver.fuse(),
             event_sender,
             message_receiver: message_receiver.fuse(),
+            connectable_only: false,
+            name_prefix: None,
+            pending_scan: None,
             delegate,
+            _activity: activity,
         }
     }
 
@@ -539,11 +698,26 @@ impl CoreBluetoothInternal {
         &mut self,
         peripheral: Retained<CBPeripheral>,
         local_name: Option<String>,
+        connectable: Option<bool>,
     ) {
+        if self.connectable_only && connectable == Some(false) {
+            return;
+        }
         let uuid = nsuuid_to_uuid(unsafe { &peripheral.identifier() });
-        let peripheral_name = unsafe { peripheral.name() };
+        let peripheral_name = unsafe { peripheral.name() }.map(|n| n.to_string());
+        if let Some(prefix) = &self.name_prefix {
+            // CoreBluetooth can't filter by name, so either the advertised name or the one it
+            // remembers for the device has to match.
+            let matches = [&peripheral_name, &local_name]
+                .into_iter()
+                .flatten()
+                .any(|name| name.starts_with(prefix.as_str()));
+            if !matches {
+                return;
+            }
+        }
 
-        let name = match (peripheral_name.map(|n| n.to_string()), local_name) {
+        let name = match (peripheral_name, local_name) {
             (Some(p_name), Some(l_name)) if p_name != l_name => {
                 Some(format!("{p_name} [{l_name}]"))
             }
@@ -554,10 +728,11 @@ impl CoreBluetoothInternal {
         };
 
         if self.peripherals.contains_key(&uuid) {
-            if let Some(name) = name {
+            if name.is_some() || connectable.is_some() {
                 self.dispatch_event(CoreBluetoothEvent::DeviceUpdated {
                     uuid,
-                    name: name.to_string(),
+                    name,
+                    connectable,
                 })
                 .await;
             }
@@ -568,7 +743,8 @@ impl CoreBluetoothInternal {
                 .insert(uuid, PeripheralInternal::new(peripheral, event_sender));
             self.dispatch_event(CoreBluetoothEvent::DeviceDiscovered {
                 uuid,
-                name: name.map(|name| name.to_string()),
+                name,
+                connectable,
                 event_receiver,
             })
             .await;
@@ -578,18 +754,18 @@ impl CoreBluetoothInternal {
     fn on_discovered_services(
         &mut self,
         peripheral_uuid: Uuid,
-        service_map: HashMap<Uuid, Retained<CBService>>,
+        service_map: HashMap<(Uuid, u16), Retained<CBService>>,
     ) {
         trace!("Found services!");
-        for id in service_map.keys() {
-            trace!("{}", id);
+        for (uuid, instance_id) in service_map.keys() {
+            trace!("{} ({})", uuid, instance_id);
         }
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
             let services = service_map
                 .into_iter()
-                .map(|(service_uuid, cbservice)| {
+                .map(|(service, cbservice)| {
                     (
-                        service_uuid,
+                        service,
                         ServiceInternal {
                             cbservice,
                             characteristics: HashMap::new(),
@@ -599,6 +775,10 @@ impl CoreBluetoothInternal {
                 })
                 .collect();
             p.services = services;
+            // A device with no services gets no characteristics callbacks to complete discovery.
+            if p.connected_future_state.is_some() {
+                p.check_discovered();
+            }
         }
     }
 
@@ -606,18 +786,20 @@ impl CoreBluetoothInternal {
         &mut self,
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
-        characteristics: HashMap<Uuid, Retained<CBCharacteristic>>,
+        service_instance: u16,
+        characteristics: HashMap<(Uuid, u16), Retained<CBCharacteristic>>,
     ) {
         trace!(
-            "Found characteristics for peripheral {} service {}:",
+            "Found characteristics for peripheral {} service {} ({}):",
             peripheral_uuid,
-            service_uuid
+            service_uuid,
+            service_instance
         );
-        for id in characteristics.keys() {
-            trace!("{}", id);
+        for (uuid, instance_id) in characteristics.keys() {
+            trace!("{} ({})", uuid, instance_id);
         }
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
-            p.set_characteristics(service_uuid, characteristics);
+            p.set_characteristics((service_uuid, service_instance), characteristics);
         }
     }
 
@@ -626,30 +808,46 @@ impl CoreBluetoothInternal {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         descriptors: HashMap<Uuid, Retained<CBDescriptor>>,
     ) {
         trace!(
-            "Found descriptors for peripheral {} service {} characteristic {}:",
+            "Found descriptors for peripheral {} service {} characteristic {} ({}):",
             peripheral_uuid,
             service_uuid,
             characteristic_uuid,
+            characteristic_instance,
         );
         for id in descriptors.keys() {
             trace!("{}", id);
         }
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
-            p.set_characteristic_descriptors(service_uuid, characteristic_uuid, descriptors);
+            p.set_characteristic_descriptors(
+                service_uuid,
+                characteristic_uuid,
+                characteristic_instance,
+                descriptors,
+            );
         }
     }
 
-    fn on_peripheral_connect(&mut self, _peripheral_uuid: Uuid) {
-        // Don't actually do anything here. The peripheral will fire the future
-        // itself when it receives all of its service/characteristic info.
+    fn on_peripheral_connect(&mut self, peripheral_uuid: Uuid) {
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            if p.discover_on_connect {
+                // The peripheral will fire the future itself when it receives all of its
+                // service/characteristic info.
+                unsafe { p.peripheral.discoverServices(None) };
+            } else if p.connected_future_state.is_some() {
+                p.services.clear();
+                p.reply_connected();
+            }
+        }
     }
 
     fn on_peripheral_connection_failed(
         &mut self,
         peripheral_uuid: Uuid,
+        reason: ConnectFailureReason,
         error_description: Option<String>,
     ) {
         trace!("Got connection fail event!");
@@ -665,7 +863,7 @@ impl CoreBluetoothInternal {
                 .unwrap()
                 .lock()
                 .unwrap()
-                .set_reply(CoreBluetoothReply::Err(error));
+                .set_reply(CoreBluetoothReply::ConnectionFailed(reason, error));
         }
     }
 
@@ -702,13 +900,16 @@ impl CoreBluetoothInternal {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
     ) -> Option<&mut CharacteristicInternal> {
-        self.peripherals
-            .get_mut(&peripheral_uuid)?
-            .services
-            .get_mut(&service_uuid)?
-            .characteristics
-            .get_mut(&characteristic_uuid)
+        let peripheral = self.peripherals.get_mut(&peripheral_uuid)?;
+        service_mut(
+            &mut peripheral.services,
+            service_uuid,
+            &(characteristic_uuid, characteristic_instance),
+        )?
+        .characteristics
+        .get_mut(&(characteristic_uuid, characteristic_instance))
     }
 
     /// Get the CBDescriptor for the given descriptor of the given peripheral, if it exists.
@@ -717,11 +918,17 @@ impl CoreBluetoothInternal {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         descriptor_uuid: Uuid,
     ) -> Option<&mut DescriptorInternal> {
-        self.get_characteristic(peripheral_uuid, service_uuid, characteristic_uuid)?
-            .descriptors
-            .get_mut(&descriptor_uuid)
+        self.get_characteristic(
+            peripheral_uuid,
+            service_uuid,
+            characteristic_uuid,
+            characteristic_instance,
+        )?
+        .descriptors
+        .get_mut(&descriptor_uuid)
     }
 
     fn on_characteristic_subscribed(
@@ -729,10 +936,14 @@ impl CoreBluetoothInternal {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
     ) {
-        if let Some(characteristic) =
-            self.get_characteristic(peripheral_uuid, service_uuid, characteristic_uuid)
-        {
+        if let Some(characteristic) = self.get_characteristic(
+            peripheral_uuid,
+            service_uuid,
+            characteristic_uuid,
+            characteristic_instance,
+        ) {
             trace!("Got subscribed event!");
             let state = characteristic.subscribe_future_state.pop_back().unwrap();
             state.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
@@ -744,10 +955,14 @@ impl CoreBluetoothInternal {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
     ) {
-        if let Some(characteristic) =
-            self.get_characteristic(peripheral_uuid, service_uuid, characteristic_uuid)
-        {
+        if let Some(characteristic) = self.get_characteristic(
+            peripheral_uuid,
+            service_uuid,
+            characteristic_uuid,
+            characteristic_instance,
+        ) {
             trace!("Got unsubscribed event!");
             let state = characteristic.unsubscribe_future_state.pop_back().unwrap();
             state.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
@@ -759,11 +974,18 @@ impl CoreBluetoothInternal {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         data: Vec<u8>,
     ) {
         if let Some(peripheral) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(service) = peripheral.services.get_mut(&service_uuid) {
-                if let Some(characteristic) = service.characteristics.get_mut(&characteristic_uuid)
+            if let Some(service) = service_mut(
+                &mut peripheral.services,
+                service_uuid,
+                &(characteristic_uuid, characteristic_instance),
+            ) {
+                if let Some(characteristic) = service
+                    .characteristics
+                    .get_mut(&(characteristic_uuid, characteristic_instance))
                 {
                     trace!("Got read event!");
 
@@ -784,7 +1006,9 @@ impl CoreBluetoothInternal {
                     } else if let Err(e) = peripheral
                         .event_sender
                         .send(PeripheralEventInternal::Notification(
+                            service_uuid,
                             characteristic_uuid,
+                            characteristic_instance,
                             data,
                         ))
                         .await
@@ -801,21 +1025,36 @@ impl CoreBluetoothInternal {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
+        error: Option<String>,
     ) {
-        if let Some(characteristic) =
-            self.get_characteristic(peripheral_uuid, service_uuid, characteristic_uuid)
-        {
+        if let Some(characteristic) = self.get_characteristic(
+            peripheral_uuid,
+            service_uuid,
+            characteristic_uuid,
+            characteristic_instance,
+        ) {
             trace!("Got written event!");
             let state = characteristic.write_future_state.pop_back().unwrap();
-            state.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
+            state.lock().unwrap().set_reply(match error {
+                None => CoreBluetoothReply::Ok,
+                Some(msg) => CoreBluetoothReply::Err(msg),
+            });
         }
     }
 
-    fn connect_peripheral(&mut self, peripheral_uuid: Uuid, fut: CoreBluetoothReplyStateShared) {
+    fn connect_peripheral(
+        &mut self,
+        peripheral_uuid: Uuid,
+        discover_services: bool,
+        fut: CoreBluetoothReplyStateShared,
+    ) {
         trace!("Trying to connect peripheral!");
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
             trace!("Connecting peripheral!");
             p.connected_future_state = Some(fut);
+            p.discover_on_connect = discover_services;
+            p.discover_descriptors = true;
             unsafe { self.manager.connectPeripheral_options(&p.peripheral, None) };
         }
     }
@@ -829,13 +1068,121 @@ impl CoreBluetoothInternal {
         }
     }
 
-    fn is_connected(&mut self, peripheral_uuid: Uuid, fut: CoreBluetoothReplyStateShared) {
+    fn discover_services(
+        &mut self,
+        peripheral_uuid: Uuid,
+        discover_descriptors: bool,
+        services: Vec<Uuid>,
+        fut: CoreBluetoothReplyStateShared,
+    ) {
+        trace!("Trying to rediscover services!");
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
-            let state = unsafe { p.peripheral.state() };
-            trace!("Connected state {:?} ", state);
-            fut.lock()
-                .unwrap()
-                .set_reply(CoreBluetoothReply::State(state));
+            if unsafe { p.peripheral.state() } != CBPeripheralState::Connected {
+                fut.lock().unwrap().set_reply(CoreBluetoothReply::Err(
+                    "Peripheral is not connected".to_string(),
+                ));
+                return;
+            }
+            // Only one discovery can be answered at a time, and the one in progress, or the
+            // connection which started it, would otherwise never get a reply.
+            if p.connected_future_state.is_some() {
+                fut.lock().unwrap().set_reply(CoreBluetoothReply::Err(
+                    "Discovery already in progress".to_string(),
+                ));
+                return;
+            }
+            // This goes through the same discovery as connecting, which replaces the services we
+            // know about and fulfills the future once everything has been discovered again.
+            p.connected_future_state = Some(fut);
+            p.discover_descriptors = discover_descriptors;
+            let services = uuids_to_cbuuids(services);
+            unsafe { p.peripheral.discoverServices(services.as_deref()) };
+        }
+    }
+
+    fn discover_descriptors(
+        &mut self,
+        peripheral_uuid: Uuid,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        characteristic_instance: u16,
+        fut: CoreBluetoothReplyStateShared,
+    ) {
+        if let Some(peripheral) = self.peripherals.get_mut(&peripheral_uuid) {
+            if let Some(service) = service_mut(
+                &mut peripheral.services,
+                service_uuid,
+                &(characteristic_uuid, characteristic_instance),
+            ) {
+                if let Some(characteristic) = service
+                    .characteristics
+                    .get_mut(&(characteristic_uuid, characteristic_instance))
+                {
+                    trace!("Discovering descriptors!");
+                    unsafe {
+                        peripheral
+                            .peripheral
+                            .discoverDescriptorsForCharacteristic(&characteristic.characteristic);
+                    }
+                    characteristic.descriptors_future_state.push_back(fut);
+                    return;
+                }
+            }
+        }
+        fut.lock().unwrap().set_reply(CoreBluetoothReply::Err(
+            "Characteristic not found for discovery".to_string(),
+        ));
+    }
+
+    fn is_connected(&mut self, peripheral_uuid: Uuid, fut: CoreBluetoothReplyStateShared) {
+        // A peripheral we've forgotten can't be connected.
+        let state = self
+            .peripherals
+            .get_mut(&peripheral_uuid)
+            .map_or(CBPeripheralState::Disconnected, |p| unsafe {
+                p.peripheral.state()
+            });
+        trace!("Connected state {:?} ", state);
+        fut.lock()
+            .unwrap()
+            .set_reply(CoreBluetoothReply::State(state));
+    }
+
+    /// Replies with whether CoreBluetooth still knows the peripheral, even if it hasn't been seen
+    /// since btleplug started.
+    fn is_known(&mut self, peripheral_uuid: Uuid, fut: CoreBluetoothReplyStateShared) {
+        let known = self.peripherals.contains_key(&peripheral_uuid) || {
+            let identifiers = NSArray::from_vec(vec![uuid_to_nsuuid(peripheral_uuid)]);
+            let peripherals = unsafe {
+                self.manager
+                    .retrievePeripheralsWithIdentifiers(&identifiers)
+            };
+            peripherals.count() > 0
+        };
+        fut.lock()
+            .unwrap()
+            .set_reply(CoreBluetoothReply::Known(known));
+    }
+
+    fn get_name(&mut self, peripheral_uuid: Uuid, fut: CoreBluetoothReplyStateShared) {
+        // Once connected, CoreBluetooth reads the name from the GAP service itself.
+        let name = self
+            .peripherals
+            .get(&peripheral_uuid)
+            .and_then(|p| unsafe { p.peripheral.name() })
+            .map(|name| name.to_string());
+        trace!("Name {:?}", name);
+        fut.lock()
+            .unwrap()
+            .set_reply(CoreBluetoothReply::Name(name));
+    }
+
+    fn forget_peripheral(&mut self, peripheral_uuid: Uuid) {
+        if let Some(p) = self.peripherals.get(&peripheral_uuid) {
+            if unsafe { p.peripheral.state() } == CBPeripheralState::Disconnected {
+                trace!("Forgetting peripheral {}", peripheral_uuid);
+                self.peripherals.remove(&peripheral_uuid);
+            }
         }
     }
 
@@ -844,33 +1191,53 @@ impl CoreBluetoothInternal {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         data: Vec<u8>,
         kind: WriteType,
         fut: CoreBluetoothReplyStateShared,
     ) {
         if let Some(peripheral) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(service) = peripheral.services.get_mut(&service_uuid) {
-                if let Some(characteristic) = service.characteristics.get_mut(&characteristic_uuid)
+            if let Some(service) = service_mut(
+                &mut peripheral.services,
+                service_uuid,
+                &(characteristic_uuid, characteristic_instance),
+            ) {
+                if let Some(characteristic) = service
+                    .characteristics
+                    .get_mut(&(characteristic_uuid, characteristic_instance))
                 {
                     trace!("Writing value! With kind {:?}", kind);
+                    let write_type = match kind {
+                        WriteType::WithResponse => {
+                            CBCharacteristicWriteType::CBCharacteristicWriteWithResponse
+                        }
+                        WriteType::WithoutResponse => {
+                            CBCharacteristicWriteType::CBCharacteristicWriteWithoutResponse
+                        }
+                        WriteType::SignedWithoutResponse => {
+                            fut.lock().unwrap().set_reply(CoreBluetoothReply::Err(
+                                "Signed writes are not supported by CoreBluetooth".to_string(),
+                            ));
+                            return;
+                        }
+                    };
+                    // Once the flow-control window is full, CoreBluetooth queues writes without
+                    // response until `peripheralIsReadyToSendWriteWithoutResponse`.
+                    let can_send = kind == WriteType::WithoutResponse
+                        && unsafe { peripheral.peripheral.canSendWriteWithoutResponse() };
                     unsafe {
                         peripheral.peripheral.writeValue_forCharacteristic_type(
                             &NSData::from_vec(data),
                             &characteristic.characteristic,
-                            match kind {
-                                WriteType::WithResponse => {
-                                    CBCharacteristicWriteType::CBCharacteristicWriteWithResponse
-                                }
-                                WriteType::WithoutResponse => {
-                                    CBCharacteristicWriteType::CBCharacteristicWriteWithoutResponse
-                                }
-                            },
+                            write_type,
                         );
                     }
                     // WriteWithoutResponse does not call the corebluetooth
                     // callback, it just always succeeds silently.
                     if kind == WriteType::WithoutResponse {
-                        fut.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
+                        fut.lock()
+                            .unwrap()
+                            .set_reply(CoreBluetoothReply::WrittenWithoutResponse(can_send));
                     } else {
                         characteristic.write_future_state.push_front(fut);
                     }
@@ -884,11 +1251,18 @@ impl CoreBluetoothInternal {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         fut: CoreBluetoothReplyStateShared,
     ) {
         if let Some(peripheral) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(service) = peripheral.services.get_mut(&service_uuid) {
-                if let Some(characteristic) = service.characteristics.get_mut(&characteristic_uuid)
+            if let Some(service) = service_mut(
+                &mut peripheral.services,
+                service_uuid,
+                &(characteristic_uuid, characteristic_instance),
+            ) {
+                if let Some(characteristic) = service
+                    .characteristics
+                    .get_mut(&(characteristic_uuid, characteristic_instance))
                 {
                     trace!("Reading value!");
                     unsafe {
@@ -907,11 +1281,18 @@ impl CoreBluetoothInternal {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         fut: CoreBluetoothReplyStateShared,
     ) {
         if let Some(peripheral) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(service) = peripheral.services.get_mut(&service_uuid) {
-                if let Some(characteristic) = service.characteristics.get_mut(&characteristic_uuid)
+            if let Some(service) = service_mut(
+                &mut peripheral.services,
+                service_uuid,
+                &(characteristic_uuid, characteristic_instance),
+            ) {
+                if let Some(characteristic) = service
+                    .characteristics
+                    .get_mut(&(characteristic_uuid, characteristic_instance))
                 {
                     trace!("Setting subscribe!");
                     unsafe {
@@ -930,11 +1311,18 @@ impl CoreBluetoothInternal {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         fut: CoreBluetoothReplyStateShared,
     ) {
         if let Some(peripheral) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(service) = peripheral.services.get_mut(&service_uuid) {
-                if let Some(characteristic) = service.characteristics.get_mut(&characteristic_uuid)
+            if let Some(service) = service_mut(
+                &mut peripheral.services,
+                service_uuid,
+                &(characteristic_uuid, characteristic_instance),
+            ) {
+                if let Some(characteristic) = service
+                    .characteristics
+                    .get_mut(&(characteristic_uuid, characteristic_instance))
                 {
                     trace!("Setting subscribe!");
                     unsafe {
@@ -954,13 +1342,20 @@ impl CoreBluetoothInternal {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         descriptor_uuid: Uuid,
         data: Vec<u8>,
         fut: CoreBluetoothReplyStateShared,
     ) {
         if let Some(peripheral) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(service) = peripheral.services.get_mut(&service_uuid) {
-                if let Some(characteristic) = service.characteristics.get_mut(&characteristic_uuid)
+            if let Some(service) = service_mut(
+                &mut peripheral.services,
+                service_uuid,
+                &(characteristic_uuid, characteristic_instance),
+            ) {
+                if let Some(characteristic) = service
+                    .characteristics
+                    .get_mut(&(characteristic_uuid, characteristic_instance))
                 {
                     if let Some(descriptor) = characteristic.descriptors.get_mut(&descriptor_uuid) {
                         trace!("Writing descriptor value!");
@@ -982,12 +1377,19 @@ impl CoreBluetoothInternal {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         descriptor_uuid: Uuid,
         fut: CoreBluetoothReplyStateShared,
     ) {
         if let Some(peripheral) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(service) = peripheral.services.get_mut(&service_uuid) {
-                if let Some(characteristic) = service.characteristics.get_mut(&characteristic_uuid)
+            if let Some(service) = service_mut(
+                &mut peripheral.services,
+                service_uuid,
+                &(characteristic_uuid, characteristic_instance),
+            ) {
+                if let Some(characteristic) = service
+                    .characteristics
+                    .get_mut(&(characteristic_uuid, characteristic_instance))
                 {
                     if let Some(descriptor) = characteristic.descriptors.get_mut(&descriptor_uuid) {
                         trace!("Reading descriptor value!");
@@ -1008,12 +1410,19 @@ impl CoreBluetoothInternal {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         descriptor_uuid: Uuid,
         data: Vec<u8>,
     ) {
         if let Some(peripheral) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(service) = peripheral.services.get_mut(&service_uuid) {
-                if let Some(characteristic) = service.characteristics.get_mut(&characteristic_uuid)
+            if let Some(service) = service_mut(
+                &mut peripheral.services,
+                service_uuid,
+                &(characteristic_uuid, characteristic_instance),
+            ) {
+                if let Some(characteristic) = service
+                    .characteristics
+                    .get_mut(&(characteristic_uuid, characteristic_instance))
                 {
                     if let Some(descriptor) = characteristic.descriptors.get_mut(&descriptor_uuid) {
                         trace!("Got read event!");
@@ -1038,12 +1447,14 @@ impl CoreBluetoothInternal {
         peripheral_uuid: Uuid,
         service_uuid: Uuid,
         characteristic_uuid: Uuid,
+        characteristic_instance: u16,
         descriptor_uuid: Uuid,
     ) {
         if let Some(descriptor) = self.get_descriptor(
             peripheral_uuid,
             service_uuid,
             characteristic_uuid,
+            characteristic_instance,
             descriptor_uuid,
         ) {
             trace!("Got written event!");
@@ -1059,26 +1470,27 @@ impl CoreBluetoothInternal {
                     // TODO We should probably also register some sort of
                     // "ready" variable in our adapter that will cause scans/etc
                     // to fail if this hasn't updated.
-                    CentralDelegateEvent::DidUpdateState{state} => {
-                        self.dispatch_event(CoreBluetoothEvent::DidUpdateState{state}).await
-                    }
-                    CentralDelegateEvent::DiscoveredPeripheral{cbperipheral, local_name} => {
-                        self.on_discovered_peripheral(cbperipheral, local_name).await
+                    CentralDelegateEvent::DidUpdateState{state} => self.on_state_updated(state).await,
+                    CentralDelegateEvent::DiscoveredPeripheral{cbperipheral, local_name, connectable} => {
+                        self.on_discovered_peripheral(cbperipheral, local_name, connectable).await
                     }
                     CentralDelegateEvent::DiscoveredServices{peripheral_uuid, services} => {
                         self.on_discovered_services(peripheral_uuid, services)
                     }
-                    CentralDelegateEvent::DiscoveredCharacteristics{peripheral_uuid, service_uuid, characteristics} => {
-                        self.on_discovered_characteristics(peripheral_uuid, service_uuid, characteristics)
+                    CentralDelegateEvent::ServicesModified{peripheral_uuid} => {
+                        self.dispatch_event(CoreBluetoothEvent::ServicesChanged{uuid: peripheral_uuid}).await
+                    }
+                    CentralDelegateEvent::DiscoveredCharacteristics{peripheral_uuid, service_uuid, service_instance, characteristics} => {
+                        self.on_discovered_characteristics(peripheral_uuid, service_uuid, service_instance, characteristics)
                     }
-                    CentralDelegateEvent::DiscoveredCharacteristicDescriptors{peripheral_uuid, service_uuid, characteristic_uuid, descriptors} => {
-                        self.on_discovered_characteristic_descriptors(peripheral_uuid, service_uuid, characteristic_uuid, descriptors)
+                    CentralDelegateEvent::DiscoveredCharacteristicDescriptors{peripheral_uuid, service_uuid, characteristic_uuid, characteristic_instance, descriptors} => {
+                        self.on_discovered_characteristic_descriptors(peripheral_uuid, service_uuid, characteristic_uuid, characteristic_instance, descriptors)
                     }
                     CentralDelegateEvent::ConnectedDevice{peripheral_uuid} => {
                             self.on_peripheral_connect(peripheral_uuid)
                     },
-                    CentralDelegateEvent::ConnectionFailed{peripheral_uuid, error_description} => {
-                        self.on_peripheral_connection_failed(peripheral_uuid, error_description)
+                    CentralDelegateEvent::ConnectionFailed{peripheral_uuid, reason, error_description} => {
+                        self.on_peripheral_connection_failed(peripheral_uuid, reason, error_description)
                     },
                     CentralDelegateEvent::DisconnectedDevice{peripheral_uuid} => {
                         self.on_peripheral_disconnect(peripheral_uuid).await
@@ -1087,23 +1499,28 @@ impl CoreBluetoothInternal {
                         peripheral_uuid,
                         service_uuid,
                         characteristic_uuid,
-                     } => self.on_characteristic_subscribed(peripheral_uuid, service_uuid, characteristic_uuid),
+                        characteristic_instance,
+                     } => self.on_characteristic_subscribed(peripheral_uuid, service_uuid, characteristic_uuid, characteristic_instance),
                     CentralDelegateEvent::CharacteristicUnsubscribed{
                         peripheral_uuid,
                         service_uuid,
                         characteristic_uuid,
-                     } => self.on_characteristic_unsubscribed(peripheral_uuid, service_uuid,characteristic_uuid),
+                        characteristic_instance,
+                     } => self.on_characteristic_unsubscribed(peripheral_uuid, service_uuid,characteristic_uuid, characteristic_instance),
                     CentralDelegateEvent::CharacteristicNotified{
                         peripheral_uuid,
                         service_uuid,
                         characteristic_uuid,
+                        characteristic_instance,
                         data,
-                     } => self.on_characteristic_read(peripheral_uuid, service_uuid,characteristic_uuid, data).await,
+                     } => self.on_characteristic_read(peripheral_uuid, service_uuid,characteristic_uuid, characteristic_instance, data).await,
                     CentralDelegateEvent::CharacteristicWritten{
                         peripheral_uuid,
                         service_uuid,
                         characteristic_uuid,
-                    } => self.on_characteristic_written(peripheral_uuid, service_uuid, characteristic_uuid),
+                        characteristic_instance,
+                        error,
+                    } => self.on_characteristic_written(peripheral_uuid, service_uuid, characteristic_uuid, characteristic_instance, error),
                     CentralDelegateEvent::ManufacturerData{peripheral_uuid, manufacturer_id, data, rssi} => {
                         self.on_manufacturer_data(peripheral_uuid, manufacturer_id, data, rssi).await
                     },
@@ -1117,15 +1534,17 @@ impl CoreBluetoothInternal {
                         peripheral_uuid,
                         service_uuid,
                         characteristic_uuid,
+                        characteristic_instance,
                         descriptor_uuid,
                         data,
-                     } => self.on_descriptor_read(peripheral_uuid, service_uuid, characteristic_uuid, descriptor_uuid, data).await,
+                     } => self.on_descriptor_read(peripheral_uuid, service_uuid, characteristic_uuid, characteristic_instance, descriptor_uuid, data).await,
                     CentralDelegateEvent::DescriptorWritten{
                         peripheral_uuid,
                         service_uuid,
                         characteristic_uuid,
+                        characteristic_instance,
                         descriptor_uuid,
-                    } => self.on_descriptor_written(peripheral_uuid, service_uuid, characteristic_uuid, descriptor_uuid),
+                    } => self.on_descriptor_written(peripheral_uuid, service_uuid, characteristic_uuid, characteristic_instance, descriptor_uuid),
                 };
             }
             adapter_msg = self.message_receiver.select_next_some() => {
@@ -1134,44 +1553,68 @@ impl CoreBluetoothInternal {
                     CoreBluetoothMessage::GetAdapterState { future } => {
                         self.get_adapter_state(future);
                     },
-                    CoreBluetoothMessage::StartScanning{filter} => self.start_discovery(filter),
-                    CoreBluetoothMessage::StopScanning => self.stop_discovery(),
-                    CoreBluetoothMessage::ConnectDevice{peripheral_uuid, future} => {
+                    CoreBluetoothMessage::IsScanning { future } => {
+                        let scanning = unsafe { self.manager.isScanning() };
+                        future.lock().unwrap().set_reply(CoreBluetoothReply::Scanning(scanning));
+                    },
+                    CoreBluetoothMessage::IsKnown { peripheral_uuid, future } => {
+                        self.is_known(peripheral_uuid, future);
+                    },
+                    CoreBluetoothMessage::StartScanning{filter} => self.start_discovery(filter).await,
+                    CoreBluetoothMessage::StopScanning => {
+                        self.stop_discovery();
+                        self.dispatch_event(CoreBluetoothEvent::ScanStopped).await
+                    }
+                    CoreBluetoothMessage::ConnectDevice{peripheral_uuid, discover_services, future} => {
                         trace!("got connectdevice msg!");
-                        self.connect_peripheral(peripheral_uuid, future);
+                        self.connect_peripheral(peripheral_uuid, discover_services, future);
                     }
                     CoreBluetoothMessage::DisconnectDevice{peripheral_uuid, future} => {
                         self.disconnect_peripheral(peripheral_uuid, future);
                     }
-                    CoreBluetoothMessage::ReadValue{peripheral_uuid, service_uuid,characteristic_uuid, future} => {
-                        self.read_value(peripheral_uuid, service_uuid,characteristic_uuid, future)
+                    CoreBluetoothMessage::DiscoverServices{peripheral_uuid, discover_descriptors, services, future} => {
+                        self.discover_services(peripheral_uuid, discover_descriptors, services, future);
+                    }
+                    CoreBluetoothMessage::DiscoverDescriptors{peripheral_uuid, service_uuid, characteristic_uuid, characteristic_instance, future} => {
+                        self.discover_descriptors(peripheral_uuid, service_uuid, characteristic_uuid, characteristic_instance, future);
+                    }
+                    CoreBluetoothMessage::ReadValue{peripheral_uuid, service_uuid,characteristic_uuid, characteristic_instance, future} => {
+                        self.read_value(peripheral_uuid, service_uuid,characteristic_uuid, characteristic_instance, future)
                     }
                     CoreBluetoothMessage::WriteValue{
                         peripheral_uuid,service_uuid,
                         characteristic_uuid,
+                        characteristic_instance,
                         data,
                         write_type,
                         future,
-                    } => self.write_value(peripheral_uuid, service_uuid,characteristic_uuid, data, write_type, future),
-                    CoreBluetoothMessage::Subscribe{peripheral_uuid, service_uuid,characteristic_uuid, future} => {
-                        self.subscribe(peripheral_uuid, service_uuid,characteristic_uuid, future)
+                    } => self.write_value(peripheral_uuid, service_uuid,characteristic_uuid, characteristic_instance, data, write_type, future),
+                    CoreBluetoothMessage::Subscribe{peripheral_uuid, service_uuid,characteristic_uuid, characteristic_instance, future} => {
+                        self.subscribe(peripheral_uuid, service_uuid,characteristic_uuid, characteristic_instance, future)
                     }
-                    CoreBluetoothMessage::Unsubscribe{peripheral_uuid, service_uuid,characteristic_uuid, future} => {
-                        self.unsubscribe(peripheral_uuid, service_uuid,characteristic_uuid, future)
+                    CoreBluetoothMessage::Unsubscribe{peripheral_uuid, service_uuid,characteristic_uuid, characteristic_instance, future} => {
+                        self.unsubscribe(peripheral_uuid, service_uuid,characteristic_uuid, characteristic_instance, future)
                     }
                     CoreBluetoothMessage::IsConnected{peripheral_uuid, future} => {
                         self.is_connected(peripheral_uuid, future);
                     },
-                    CoreBluetoothMessage::ReadDescriptorValue{peripheral_uuid, service_uuid, characteristic_uuid, descriptor_uuid, future} => {
-                        self.read_descriptor_value(peripheral_uuid, service_uuid, characteristic_uuid, descriptor_uuid, future)
+                    CoreBluetoothMessage::GetName{peripheral_uuid, future} => {
+                        self.get_name(peripheral_uuid, future);
+                    },
+                    CoreBluetoothMessage::ForgetPeripheral{peripheral_uuid} => {
+                        self.forget_peripheral(peripheral_uuid);
+                    },
+                    CoreBluetoothMessage::ReadDescriptorValue{peripheral_uuid, service_uuid, characteristic_uuid, characteristic_instance, descriptor_uuid, future} => {
+                        self.read_descriptor_value(peripheral_uuid, service_uuid, characteristic_uuid, characteristic_instance, descriptor_uuid, future)
                     }
                     CoreBluetoothMessage::WriteDescriptorValue{
                         peripheral_uuid,service_uuid,
                         characteristic_uuid,
+                        characteristic_instance,
                         descriptor_uuid,
                         data,
                         future,
-                    } => self.write_descriptor_value(peripheral_uuid, service_uuid, characteristic_uuid, descriptor_uuid, data, future),
+                    } => self.write_descriptor_value(peripheral_uuid, service_uuid, characteristic_uuid, characteristic_instance, descriptor_uuid, data, future),
                 };
             }
         }
@@ -1184,8 +1627,31 @@ impl CoreBluetoothInternal {
             .set_reply(CoreBluetoothReply::AdapterState(state))
     }
 
-    fn start_discovery(&mut self, filter: ScanFilter) {
+    async fn on_state_updated(&mut self, state: CBManagerState) {
+        self.dispatch_event(CoreBluetoothEvent::DidUpdateState { state })
+            .await;
+        if state == CBManagerState::PoweredOn {
+            if let Some(filter) = self.pending_scan.take() {
+                self.start_discovery(filter).await;
+            }
+        }
+    }
+
+    async fn start_discovery(&mut self, filter: ScanFilter) {
         trace!("BluetoothAdapter::start_discovery");
+        // CoreBluetooth silently drops a scan requested before the central is powered on, so hold
+        // on to it until `centralManagerDidUpdateState` says it is.
+        let state = unsafe { self.manager.state() };
+        if state != CBManagerState::PoweredOn {
+            warn!(
+                "Adapter state is {:?}, scan will start once it is powered on",
+                state
+            );
+            self.pending_scan = Some(filter);
+            return;
+        }
+        self.connectable_only = filter.connectable_only;
+        self.name_prefix = filter.name_prefix.clone();
         let service_uuids = scan_filter_to_service_uuids(filter);
         let mut options = NSMutableDictionary::new();
         // NOTE: If duplicates are not allowed then a peripheral will not show
@@ -1200,10 +1666,12 @@ impl CoreBluetoothInternal {
             self.manager
                 .scanForPeripheralsWithServices_options(service_uuids.as_deref(), Some(&options))
         };
+        self.dispatch_event(CoreBluetoothEvent::ScanStarted).await;
     }
 
     fn stop_discovery(&mut self) {
         trace!("BluetoothAdapter::stop_discovery");
+        self.pending_scan = None;
         unsafe { self.manager.stopScan() };
     }
 }
@@ -1211,15 +1679,17 @@ impl CoreBluetoothInternal {
 /// Convert a `ScanFilter` to the appropriate `NSArray<CBUUID *> *` to use for discovery. If the
 /// filter has an empty list of services then this will return `nil`, to discover all devices.
 fn scan_filter_to_service_uuids(filter: ScanFilter) -> Option<Retained<NSArray<CBUUID>>> {
-    if filter.services.is_empty() {
+    uuids_to_cbuuids(filter.services)
+}
+
+/// Convert UUIDs to an `NSArray<CBUUID *> *`, or `nil` if there are none, which CoreBluetooth
+/// takes to mean all of them.
+fn uuids_to_cbuuids(uuids: Vec<Uuid>) -> Option<Retained<NSArray<CBUUID>>> {
+    if uuids.is_empty() {
         None
     } else {
-        let service_uuids = filter
-            .services
-            .into_iter()
-            .map(uuid_to_cbuuid)
-            .collect::<Vec<_>>();
-        Some(NSArray::from_vec(service_uuids))
+        let uuids = uuids.into_iter().map(uuid_to_cbuuid).collect::<Vec<_>>();
+        Some(NSArray::from_vec(uuids))
     }
 }
 
@@ -1233,6 +1703,7 @@ impl Drop for CoreBluetoothInternal {
 
 pub fn run_corebluetooth_thread(
     event_sender: Sender<CoreBluetoothEvent>,
+    options: ManagerOptions,
 ) -> Result<Sender<CoreBluetoothMessage>, Error> {
     let authorization = unsafe { CBManager::authorization_class() };
     if authorization != CBManagerAuthorization::AllowedAlways
@@ -1246,9 +1717,8 @@ pub fn run_corebluetooth_thread(
     let (sender, receiver) = mpsc::channel::<CoreBluetoothMessage>(256);
     // CoreBluetoothInternal is !Send, so we need to keep it on a single thread.
     thread::spawn(move || {
-        let runtime = runtime::Builder::new_current_thread().build().unwrap();
-        runtime.block_on(async move {
-            let mut cbi = CoreBluetoothInternal::new(receiver, event_sender);
+        futures::executor::block_on(async move {
+            let mut cbi = CoreBluetoothInternal::new(receiver, event_sender, options);
             loop {
                 cbi.wait_for_message().await;
             }
@@ -9,13 +9,41 @@ use super::adapter::Adapter;
 use crate::{api, Result};
 use async_trait::async_trait;
 
+/// CoreBluetooth specific options for a [`Manager`].
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct ManagerOptions {
+    /// Opt out of App Nap (and idle display sleep throttling) for as long as the adapter is alive,
+    /// so that notifications from subscribed characteristics keep arriving while the app is in the
+    /// background or the display is asleep. The system may still go to sleep when idle.
+    ///
+    /// This is mostly useful for menu-bar agents and other background apps on macOS. It defaults
+    /// to `false`.
+    pub prevent_app_nap: bool,
+}
+
+impl ManagerOptions {
+    /// Sets [`ManagerOptions::prevent_app_nap`].
+    pub fn prevent_app_nap(mut self, prevent_app_nap: bool) -> Self {
+        self.prevent_app_nap = prevent_app_nap;
+        self
+    }
+}
+
 /// Implementation of [api::Manager](crate::api::Manager).
 #[derive(Clone, Debug)]
-pub struct Manager {}
+pub struct Manager {
+    options: ManagerOptions,
+}
 
 impl Manager {
     pub async fn new() -> Result<Self> {
-        Ok(Self {})
+        Self::with_options(ManagerOptions::default()).await
+    }
+
+    /// Creates a manager whose adapters use the given CoreBluetooth specific options.
+    pub async fn with_options(options: ManagerOptions) -> Result<Self> {
+        Ok(Self { options })
     }
 }
 
@@ -24,7 +52,7 @@ impl api::Manager for Manager {
     type Adapter = Adapter;
 
     async fn adapters(&self) -> Result<Vec<Adapter>> {
-        Ok(vec![Adapter::new().await?])
+        Ok(vec![Adapter::new(self.options.clone()).await?])
         // TODO What do we do if there is no bluetooth adapter, like on an older
         // macbook pro? Will BluetoothAdapter::init() fail?
     }
@@ -10,10 +10,23 @@ use super::internal::{
 };
 use crate::{
     api::{
-        self, BDAddr, CentralEvent, CharPropFlags, Characteristic, Descriptor,
-        PeripheralProperties, Service, ValueNotification, WriteType,
+        self, max_write_len_for_mtu,
+        trace::{self, traced_connect, traced_discover_services},
+        BDAddr, CentralEvent, CharPropFlags, Characteristic, ConnectOptions, Descriptor,
+        DisconnectOptions, DiscoverOptions, ParsePeripheralIdError, PeripheralMetrics,
+        PeripheralProperties, Service, SubscriptionKind, TraceEvent, Transport, ValueNotification,
+        WriteResult, WriteType, DEFAULT_ATT_MTU,
+    },
+    common::{
+        adapter_manager::AdapterManager,
+        connect_limit::ConnectLimit,
+        metrics::Metrics,
+        pending::PendingOperations,
+        rssi_history::RssiAverage,
+        runtime,
+        subscriptions::{auto_subscribe, Subscriptions},
+        util::{notifications_stream_from_broadcast_receiver, replace_descriptors},
     },
-    common::{adapter_manager::AdapterManager, util::notifications_stream_from_broadcast_receiver},
     Error, Result,
 };
 use async_trait::async_trait;
@@ -31,10 +44,12 @@ use std::{
     collections::{BTreeSet, HashMap},
     fmt::{self, Debug, Display, Formatter},
     pin::Pin,
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
     sync::{Arc, Mutex},
+    time::Instant,
 };
 use tokio::sync::broadcast;
-use tokio::task;
 use uuid::Uuid;
 
 #[cfg_attr(
@@ -43,7 +58,7 @@ use uuid::Uuid;
     serde(crate = "serde_cr")
 )]
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub struct PeripheralId(Uuid);
+pub struct PeripheralId(pub(super) Uuid);
 
 impl Display for PeripheralId {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
@@ -51,6 +66,17 @@ impl Display for PeripheralId {
     }
 }
 
+impl FromStr for PeripheralId {
+    type Err = ParsePeripheralIdError;
+
+    /// Parses an ID in the form it is displayed, the UUID CoreBluetooth identifies the device by.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Uuid::parse_str(s)
+            .map(PeripheralId)
+            .map_err(|_| ParsePeripheralIdError::new(s, "a CoreBluetooth peripheral UUID"))
+    }
+}
+
 /// Implementation of [api::Peripheral](crate::api::Peripheral).
 #[derive(Clone)]
 pub struct Peripheral {
@@ -60,9 +86,21 @@ pub struct Peripheral {
 struct Shared {
     notifications_channel: broadcast::Sender<ValueNotification>,
     manager: Weak<AdapterManager<Peripheral>>,
+    connect_limit: Arc<ConnectLimit>,
     uuid: Uuid,
     services: Mutex<BTreeSet<Service>>,
+    /// Whether the device is connected, as last reported by the CoreBluetooth thread.
+    connected: AtomicBool,
+    /// Whether the services were discovered by connecting, and haven't been asked for since.
+    services_from_connect: AtomicBool,
+    /// The maximum write lengths with and without response, as reported when last connected.
+    max_write_len: Mutex<Option<(usize, usize)>>,
     properties: Mutex<PeripheralProperties>,
+    rssi_average: Mutex<RssiAverage>,
+    pending: PendingOperations,
+    metrics: Metrics,
+    default_write_type: Mutex<Option<WriteType>>,
+    subscriptions: Subscriptions,
     message_sender: Sender<CoreBluetoothMessage>,
     // We're not actually holding a peripheral object here, that's held out in
     // the objc thread. We'll just communicate with it through our
@@ -77,14 +115,27 @@ impl Shared {
             trace!("Could not emit an event. AdapterManager has been dropped");
         }
     }
+
+    /// Records the signal strength of an advertisement, and when it was received.
+    fn advertised(&self, properties: &mut PeripheralProperties, rssi: i16) {
+        properties.rssi = Some(rssi);
+        if let Some(manager) = self.manager.upgrade() {
+            let weight = manager.rssi_smoothing().weight();
+            properties.smoothed_rssi = Some(self.rssi_average.lock().unwrap().add(rssi, weight));
+        }
+        properties.last_seen = Some(Instant::now());
+    }
 }
 
 impl Peripheral {
-    // This calls tokio::task::spawn, so it must be called from the context of a Tokio Runtime.
+    // With the tokio feature this spawns a Tokio task, so it must be called from the context of a
+    // Tokio Runtime.
     pub(crate) fn new(
         uuid: Uuid,
         local_name: Option<String>,
+        connectable: Option<bool>,
         manager: Weak<AdapterManager<Self>>,
+        connect_limit: Arc<ConnectLimit>,
         event_receiver: Receiver<PeripheralEventInternal>,
         message_sender: Sender<CoreBluetoothMessage>,
     ) -> Self {
@@ -96,30 +147,60 @@ impl Peripheral {
             local_name,
             tx_power_level: None,
             rssi: None,
+            smoothed_rssi: None,
             manufacturer_data: HashMap::new(),
             service_data: HashMap::new(),
-            services: Vec::new(),
+            advertised_services: Vec::new(),
             class: None,
+            transport: Transport::Le,
+            last_seen: Some(Instant::now()),
+            has_scan_response: None,
+            connectable,
+            advertising_sid: None,
         });
         let (notifications_channel, _) = broadcast::channel(16);
 
         let shared = Arc::new(Shared {
             properties,
+            rssi_average: Mutex::new(RssiAverage::default()),
             manager,
+            connect_limit,
             services: Mutex::new(BTreeSet::new()),
+            connected: AtomicBool::new(false),
+            services_from_connect: AtomicBool::new(false),
+            max_write_len: Mutex::new(None),
+            pending: PendingOperations::default(),
+            metrics: Metrics::default(),
+            default_write_type: Mutex::new(None),
+            subscriptions: Subscriptions::default(),
             notifications_channel,
             uuid,
             message_sender,
         });
         let shared_clone = shared.clone();
-        task::spawn(async move {
+        runtime::spawn(async move {
             let mut event_receiver = event_receiver;
             let shared = shared_clone;
 
             loop {
                 match event_receiver.next().await {
-                    Some(PeripheralEventInternal::Notification(uuid, data)) => {
-                        let notification = ValueNotification { uuid, value: data };
+                    Some(PeripheralEventInternal::Notification(
+                        service_uuid,
+                        uuid,
+                        instance_id,
+                        data,
+                    )) => {
+                        trace::emit(|| TraceEvent::NotificationDelivered {
+                            id: shared.uuid.into(),
+                            characteristic: uuid,
+                        });
+                        shared.metrics.notification();
+                        let notification = ValueNotification {
+                            uuid,
+                            service_uuid,
+                            instance_id,
+                            value: data,
+                        };
 
                         // Note: we ignore send errors here which may happen while there are no
                         // receivers...
@@ -131,7 +212,7 @@ impl Peripheral {
                         rssi,
                     )) => {
                         let mut properties = shared.properties.lock().unwrap();
-                        properties.rssi = Some(rssi);
+                        shared.advertised(&mut properties, rssi);
                         properties
                             .manufacturer_data
                             .insert(manufacturer_id, data.clone());
@@ -142,7 +223,7 @@ impl Peripheral {
                     }
                     Some(PeripheralEventInternal::ServiceData(service_data, rssi)) => {
                         let mut properties = shared.properties.lock().unwrap();
-                        properties.rssi = Some(rssi);
+                        shared.advertised(&mut properties, rssi);
                         properties.service_data.extend(service_data.clone());
 
                         shared.emit_event(CentralEvent::ServiceDataAdvertisement {
@@ -152,15 +233,18 @@ impl Peripheral {
                     }
                     Some(PeripheralEventInternal::Services(services, rssi)) => {
                         let mut properties = shared.properties.lock().unwrap();
-                        properties.rssi = Some(rssi);
-                        properties.services = services.clone();
+                        shared.advertised(&mut properties, rssi);
+                        properties.advertised_services = services.clone();
 
                         shared.emit_event(CentralEvent::ServicesAdvertisement {
                             id: shared.uuid.into(),
                             services,
                         });
                     }
-                    Some(PeripheralEventInternal::Disconnected) => (),
+                    Some(PeripheralEventInternal::Disconnected) => {
+                        shared.connected.store(false, Ordering::Relaxed);
+                        *shared.max_write_len.lock().unwrap() = None;
+                    }
                     None => {
                         info!("Event receiver died, breaking out of corebluetooth device loop.");
                         break;
@@ -172,7 +256,31 @@ impl Peripheral {
     }
 
     pub(super) fn update_name(&self, name: &str) {
-        self.shared.properties.lock().unwrap().local_name = Some(name.to_string());
+        let mut properties = self.shared.properties.lock().unwrap();
+        properties.local_name = Some(name.to_string());
+        properties.last_seen = Some(Instant::now());
+    }
+
+    pub(super) fn update_advertisement(&self, name: Option<String>, connectable: Option<bool>) {
+        let mut properties = self.shared.properties.lock().unwrap();
+        if name.is_some() {
+            properties.local_name = name;
+        }
+        if connectable.is_some() {
+            properties.connectable = connectable;
+        }
+        properties.last_seen = Some(Instant::now());
+    }
+
+    /// Returns [`Error::NotConnected`] unless the device is connected, so that an operation
+    /// attempted after it has disconnected fails the same way on every platform. This checks the
+    /// state last reported by the CoreBluetooth thread rather than asking it again.
+    fn ensure_connected(&self) -> Result<()> {
+        if self.shared.connected.load(Ordering::Relaxed) {
+            Ok(())
+        } else {
+            Err(Error::NotConnected)
+        }
     }
 }
 
@@ -207,6 +315,60 @@ impl api::Peripheral for Peripheral {
         BDAddr::default()
     }
 
+    fn max_write_len(&self, write_type: WriteType) -> usize {
+        match (*self.shared.max_write_len.lock().unwrap(), write_type) {
+            (Some((with_response, _)), WriteType::WithResponse) => with_response,
+            (Some((_, without_response)), WriteType::WithoutResponse) => without_response,
+            _ => max_write_len_for_mtu(DEFAULT_ATT_MTU, write_type),
+        }
+    }
+
+    async fn request_mtu(&self, _mtu: u16) -> Result<u16> {
+        // CoreBluetooth exchanges the largest MTU it can when connecting, and only tells us the
+        // resulting write length, which is 3 bytes less than the MTU.
+        match *self.shared.max_write_len.lock().unwrap() {
+            Some((_, without_response)) => {
+                Ok(u16::try_from(without_response + 3).unwrap_or(u16::MAX))
+            }
+            None => Err(Error::NotConnected),
+        }
+    }
+
+    fn pending_operations(&self) -> usize {
+        self.shared.pending.operations()
+    }
+
+    fn pending_writes(&self) -> usize {
+        self.shared.pending.writes()
+    }
+
+    fn abort_pending(&self) {
+        self.shared.pending.abort();
+    }
+
+    fn metrics(&self) -> PeripheralMetrics {
+        // As in request_mtu, the MTU is 3 bytes more than the longest write without response.
+        let mtu = self
+            .shared
+            .max_write_len
+            .lock()
+            .unwrap()
+            .map(|(_, without_response)| u16::try_from(without_response + 3).unwrap_or(u16::MAX));
+        self.shared.metrics.snapshot(mtu)
+    }
+
+    fn default_write_type(&self) -> Option<WriteType> {
+        *self.shared.default_write_type.lock().unwrap()
+    }
+
+    fn set_default_write_type(&self, write_type: WriteType) {
+        *self.shared.default_write_type.lock().unwrap() = Some(write_type);
+    }
+
+    fn subscriptions(&self) -> Vec<Characteristic> {
+        self.shared.subscriptions.get()
+    }
+
     async fn properties(&self) -> Result<Option<PeripheralProperties>> {
         Ok(Some(self.shared.properties.lock().unwrap().clone()))
     }
@@ -234,26 +396,72 @@ impl api::Peripheral for Peripheral {
         }
     }
 
-    async fn connect(&self) -> Result<()> {
+    async fn read_device_name(&self) -> Result<String> {
         let fut = CoreBluetoothReplyFuture::default();
         self.shared
             .message_sender
             .to_owned()
-            .send(CoreBluetoothMessage::ConnectDevice {
+            .send(CoreBluetoothMessage::GetName {
                 peripheral_uuid: self.shared.uuid,
                 future: fut.get_state_clone(),
             })
             .await?;
         match fut.await {
-            CoreBluetoothReply::Connected(services) => {
-                *(self.shared.services.lock().unwrap()) = services;
-                self.shared
-                    .emit_event(CentralEvent::DeviceConnected(self.shared.uuid.into()));
+            CoreBluetoothReply::Name(Some(name)) => {
+                self.update_name(&name);
+                Ok(name)
             }
-            CoreBluetoothReply::Err(msg) => return Err(Error::RuntimeError(msg)),
-            _ => panic!("Shouldn't get anything but connected or err!"),
+            CoreBluetoothReply::Name(None) => Err(Error::NoSuchCharacteristic),
+            _ => panic!("Shouldn't get anything but a Name!"),
         }
-        trace!("Device connected!");
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.connect_with_options(ConnectOptions::default()).await
+    }
+
+    async fn connect_with_options(&self, options: ConnectOptions) -> Result<()> {
+        let permit = self.shared.connect_limit.acquire().await;
+        traced_connect(self, async {
+            let fut = CoreBluetoothReplyFuture::default();
+            self.shared
+                .message_sender
+                .to_owned()
+                .send(CoreBluetoothMessage::ConnectDevice {
+                    peripheral_uuid: self.shared.uuid,
+                    discover_services: options.discover_services,
+                    future: fut.get_state_clone(),
+                })
+                .await?;
+            match fut.await {
+                CoreBluetoothReply::Connected(
+                    services,
+                    max_write_len,
+                    max_write_without_response_len,
+                ) => {
+                    *(self.shared.services.lock().unwrap()) = services;
+                    self.shared
+                        .services_from_connect
+                        .store(options.discover_services, Ordering::Relaxed);
+                    *(self.shared.max_write_len.lock().unwrap()) =
+                        Some((max_write_len, max_write_without_response_len));
+                    self.shared.connected.store(true, Ordering::Relaxed);
+                    self.shared.metrics.connected();
+                    self.shared
+                        .emit_event(CentralEvent::DeviceConnected(self.shared.uuid.into()));
+                }
+                CoreBluetoothReply::ConnectionFailed(reason, msg) => {
+                    return Err(Error::ConnectionFailed(reason, msg))
+                }
+                CoreBluetoothReply::Err(msg) => return Err(Error::RuntimeError(msg)),
+                _ => panic!("Shouldn't get anything but connected or err!"),
+            }
+            trace!("Device connected!");
+            Ok(())
+        })
+        .await?;
+        drop(permit);
+        auto_subscribe(self, &options.auto_subscribe).await;
         Ok(())
     }
 
@@ -269,6 +477,8 @@ impl api::Peripheral for Peripheral {
             .await?;
         match fut.await {
             CoreBluetoothReply::Ok => {
+                self.shared.connected.store(false, Ordering::Relaxed);
+                *self.shared.max_write_len.lock().unwrap() = None;
                 self.shared
                     .emit_event(CentralEvent::DeviceDisconnected(self.shared.uuid.into()));
                 trace!("Device disconnected!");
@@ -278,103 +488,253 @@ impl api::Peripheral for Peripheral {
         Ok(())
     }
 
-    async fn discover_services(&self) -> Result<()> {
-        // TODO: Actually discover on this, rather than on connection
+    async fn disconnect_with_options(&self, options: DisconnectOptions) -> Result<()> {
+        self.disconnect().await?;
+        if let Some(reason) = options.reason {
+            self.shared
+                .emit_event(CentralEvent::DeviceDisconnectedWithReason {
+                    id: self.id(),
+                    reason,
+                });
+        }
         Ok(())
     }
 
-    async fn write(
+    async fn discover_services(&self) -> Result<()> {
+        self.discover_services_with_options(DiscoverOptions::default())
+            .await
+    }
+
+    async fn discover_services_with_options(&self, options: DiscoverOptions) -> Result<()> {
+        traced_discover_services(self, async {
+            // Services are discovered as part of connecting, so there's no need to discover them again
+            // straight away. Later calls rediscover them, to pick up any the device has changed.
+            if self
+                .shared
+                .services_from_connect
+                .swap(false, Ordering::Relaxed)
+            {
+                return Ok(());
+            }
+            let fut = CoreBluetoothReplyFuture::default();
+            self.shared
+                .message_sender
+                .to_owned()
+                .send(CoreBluetoothMessage::DiscoverServices {
+                    peripheral_uuid: self.shared.uuid,
+                    discover_descriptors: options.descriptors,
+                    services: options.services,
+                    future: fut.get_state_clone(),
+                })
+                .await?;
+            match fut.await {
+                CoreBluetoothReply::Connected(services, _, _) => {
+                    *(self.shared.services.lock().unwrap()) = services;
+                }
+                CoreBluetoothReply::Err(msg) => return Err(Error::RuntimeError(msg)),
+                _ => panic!("Shouldn't get anything but connected or err!"),
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn discover_descriptors(
         &self,
         characteristic: &Characteristic,
-        data: &[u8],
-        mut write_type: WriteType,
-    ) -> Result<()> {
+    ) -> Result<Vec<Descriptor>> {
+        self.ensure_connected()?;
         let fut = CoreBluetoothReplyFuture::default();
-        // If we get WriteWithoutResponse for a characteristic that only
-        // supports WriteWithResponse, slam the type to WriteWithResponse.
-        // Otherwise we won't handle the future correctly.
-        if write_type == WriteType::WithoutResponse
-            && !characteristic
-                .properties
-                .contains(CharPropFlags::WRITE_WITHOUT_RESPONSE)
-        {
-            write_type = WriteType::WithResponse
-        }
         self.shared
             .message_sender
             .to_owned()
-            .send(CoreBluetoothMessage::WriteValue {
+            .send(CoreBluetoothMessage::DiscoverDescriptors {
                 peripheral_uuid: self.shared.uuid,
                 service_uuid: characteristic.service_uuid,
                 characteristic_uuid: characteristic.uuid,
-                data: Vec::from(data),
-                write_type,
+                characteristic_instance: characteristic.instance_id,
                 future: fut.get_state_clone(),
             })
             .await?;
-        match fut.await {
-            CoreBluetoothReply::Ok => {}
-            reply => panic!("Unexpected reply: {:?}", reply),
-        }
-        Ok(())
+        let uuids = match fut.await {
+            CoreBluetoothReply::Descriptors(uuids) => uuids,
+            CoreBluetoothReply::Err(msg) => return Err(Error::RuntimeError(msg)),
+            _ => panic!("Shouldn't get anything but descriptors or err!"),
+        };
+        let descriptors: BTreeSet<_> = uuids
+            .into_iter()
+            .map(|uuid| Descriptor {
+                uuid,
+                service_uuid: characteristic.service_uuid,
+                characteristic_uuid: characteristic.uuid,
+                characteristic_instance_id: characteristic.instance_id,
+            })
+            .collect();
+        replace_descriptors(
+            &mut self.shared.services.lock().unwrap(),
+            characteristic,
+            descriptors.clone(),
+        );
+        Ok(descriptors.into_iter().collect())
+    }
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        self.write_detailed(characteristic, data, write_type)
+            .await
+            .map(|_| ())
+    }
+
+    async fn write_detailed(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        mut write_type: WriteType,
+    ) -> Result<WriteResult> {
+        self.ensure_connected()?;
+        self.shared
+            .pending
+            .track_write(async {
+                if write_type == WriteType::SignedWithoutResponse {
+                    return Err(Error::NotSupported(
+                        "Signed writes are not supported by CoreBluetooth".to_string(),
+                    ));
+                }
+                let fut = CoreBluetoothReplyFuture::default();
+                // If we get WriteWithoutResponse for a characteristic that only
+                // supports WriteWithResponse, slam the type to WriteWithResponse.
+                // Otherwise we won't handle the future correctly.
+                if write_type == WriteType::WithoutResponse
+                    && !characteristic
+                        .properties
+                        .contains(CharPropFlags::WRITE_WITHOUT_RESPONSE)
+                {
+                    write_type = WriteType::WithResponse
+                }
+                self.shared
+                    .message_sender
+                    .to_owned()
+                    .send(CoreBluetoothMessage::WriteValue {
+                        peripheral_uuid: self.shared.uuid,
+                        service_uuid: characteristic.service_uuid,
+                        characteristic_uuid: characteristic.uuid,
+                        characteristic_instance: characteristic.instance_id,
+                        data: Vec::from(data),
+                        write_type,
+                        future: fut.get_state_clone(),
+                    })
+                    .await?;
+                let sent_immediately = match fut.await {
+                    CoreBluetoothReply::Ok => None,
+                    CoreBluetoothReply::WrittenWithoutResponse(sent) => Some(sent),
+                    CoreBluetoothReply::Err(msg) => return Err(Error::RuntimeError(msg)),
+                    reply => panic!("Unexpected reply: {:?}", reply),
+                };
+                Ok(WriteResult {
+                    write_type,
+                    sent_immediately,
+                })
+            })
+            .await
+            .inspect(|_| self.shared.metrics.written(data.len()))
     }
 
     async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
-        let fut = CoreBluetoothReplyFuture::default();
+        self.ensure_connected()?;
         self.shared
-            .message_sender
-            .to_owned()
-            .send(CoreBluetoothMessage::ReadValue {
-                peripheral_uuid: self.shared.uuid,
-                service_uuid: characteristic.service_uuid,
-                characteristic_uuid: characteristic.uuid,
-                future: fut.get_state_clone(),
+            .pending
+            .track(async {
+                let fut = CoreBluetoothReplyFuture::default();
+                self.shared
+                    .message_sender
+                    .to_owned()
+                    .send(CoreBluetoothMessage::ReadValue {
+                        peripheral_uuid: self.shared.uuid,
+                        service_uuid: characteristic.service_uuid,
+                        characteristic_uuid: characteristic.uuid,
+                        characteristic_instance: characteristic.instance_id,
+                        future: fut.get_state_clone(),
+                    })
+                    .await?;
+                match fut.await {
+                    CoreBluetoothReply::ReadResult(chars) => Ok(chars),
+                    _ => {
+                        panic!("Shouldn't get anything but read result!");
+                    }
+                }
             })
-            .await?;
-        match fut.await {
-            CoreBluetoothReply::ReadResult(chars) => Ok(chars),
-            _ => {
-                panic!("Shouldn't get anything but read result!");
-            }
-        }
+            .await
+            .inspect(|value| self.shared.metrics.read(value.len()))
+    }
+
+    async fn subscribe_with(
+        &self,
+        characteristic: &Characteristic,
+        kind: SubscriptionKind,
+    ) -> Result<()> {
+        api::subscribe_preferring_notify(self, characteristic, kind).await
     }
 
     async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        let fut = CoreBluetoothReplyFuture::default();
+        self.ensure_connected()?;
         self.shared
-            .message_sender
-            .to_owned()
-            .send(CoreBluetoothMessage::Subscribe {
-                peripheral_uuid: self.shared.uuid,
-                service_uuid: characteristic.service_uuid,
-                characteristic_uuid: characteristic.uuid,
-                future: fut.get_state_clone(),
-            })
-            .await?;
-        match fut.await {
-            CoreBluetoothReply::Ok => trace!("subscribed!"),
-            _ => panic!("Didn't subscribe!"),
-        }
-        Ok(())
+            .subscriptions
+            .subscribe(
+                characteristic,
+                self.shared.pending.track(async {
+                    let fut = CoreBluetoothReplyFuture::default();
+                    self.shared
+                        .message_sender
+                        .to_owned()
+                        .send(CoreBluetoothMessage::Subscribe {
+                            peripheral_uuid: self.shared.uuid,
+                            service_uuid: characteristic.service_uuid,
+                            characteristic_uuid: characteristic.uuid,
+                            characteristic_instance: characteristic.instance_id,
+                            future: fut.get_state_clone(),
+                        })
+                        .await?;
+                    match fut.await {
+                        CoreBluetoothReply::Ok => trace!("subscribed!"),
+                        _ => panic!("Didn't subscribe!"),
+                    }
+                    Ok(())
+                }),
+            )
+            .await
     }
 
     async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        let fut = CoreBluetoothReplyFuture::default();
+        self.ensure_connected()?;
         self.shared
-            .message_sender
-            .to_owned()
-            .send(CoreBluetoothMessage::Unsubscribe {
-                peripheral_uuid: self.shared.uuid,
-                service_uuid: characteristic.service_uuid,
-                characteristic_uuid: characteristic.uuid,
-                future: fut.get_state_clone(),
-            })
-            .await?;
-        match fut.await {
-            CoreBluetoothReply::Ok => {}
-            _ => panic!("Didn't unsubscribe!"),
-        }
-        Ok(())
+            .subscriptions
+            .unsubscribe(
+                characteristic,
+                self.shared.pending.track(async {
+                    let fut = CoreBluetoothReplyFuture::default();
+                    self.shared
+                        .message_sender
+                        .to_owned()
+                        .send(CoreBluetoothMessage::Unsubscribe {
+                            peripheral_uuid: self.shared.uuid,
+                            service_uuid: characteristic.service_uuid,
+                            characteristic_uuid: characteristic.uuid,
+                            characteristic_instance: characteristic.instance_id,
+                            future: fut.get_state_clone(),
+                        })
+                        .await?;
+                    match fut.await {
+                        CoreBluetoothReply::Ok => {}
+                        _ => panic!("Didn't unsubscribe!"),
+                    }
+                    Ok(())
+                }),
+            )
+            .await
     }
 
     async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
@@ -383,45 +743,59 @@ impl api::Peripheral for Peripheral {
     }
 
     async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
-        let fut = CoreBluetoothReplyFuture::default();
+        self.ensure_connected()?;
         self.shared
-            .message_sender
-            .to_owned()
-            .send(CoreBluetoothMessage::WriteDescriptorValue {
-                peripheral_uuid: self.shared.uuid,
-                service_uuid: descriptor.service_uuid,
-                characteristic_uuid: descriptor.characteristic_uuid,
-                descriptor_uuid: descriptor.uuid,
-                data: Vec::from(data),
-                future: fut.get_state_clone(),
+            .pending
+            .track(async {
+                let fut = CoreBluetoothReplyFuture::default();
+                self.shared
+                    .message_sender
+                    .to_owned()
+                    .send(CoreBluetoothMessage::WriteDescriptorValue {
+                        peripheral_uuid: self.shared.uuid,
+                        service_uuid: descriptor.service_uuid,
+                        characteristic_uuid: descriptor.characteristic_uuid,
+                        characteristic_instance: descriptor.characteristic_instance_id,
+                        descriptor_uuid: descriptor.uuid,
+                        data: Vec::from(data),
+                        future: fut.get_state_clone(),
+                    })
+                    .await?;
+                match fut.await {
+                    CoreBluetoothReply::Ok => {}
+                    reply => panic!("Unexpected reply: {:?}", reply),
+                }
+                Ok(())
             })
-            .await?;
-        match fut.await {
-            CoreBluetoothReply::Ok => {}
-            reply => panic!("Unexpected reply: {:?}", reply),
-        }
-        Ok(())
+            .await
     }
 
     async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
-        let fut = CoreBluetoothReplyFuture::default();
+        self.ensure_connected()?;
         self.shared
-            .message_sender
-            .to_owned()
-            .send(CoreBluetoothMessage::ReadDescriptorValue {
-                peripheral_uuid: self.shared.uuid,
-                service_uuid: descriptor.service_uuid,
-                characteristic_uuid: descriptor.characteristic_uuid,
-                descriptor_uuid: descriptor.uuid,
-                future: fut.get_state_clone(),
+            .pending
+            .track(async {
+                let fut = CoreBluetoothReplyFuture::default();
+                self.shared
+                    .message_sender
+                    .to_owned()
+                    .send(CoreBluetoothMessage::ReadDescriptorValue {
+                        peripheral_uuid: self.shared.uuid,
+                        service_uuid: descriptor.service_uuid,
+                        characteristic_uuid: descriptor.characteristic_uuid,
+                        characteristic_instance: descriptor.characteristic_instance_id,
+                        descriptor_uuid: descriptor.uuid,
+                        future: fut.get_state_clone(),
+                    })
+                    .await?;
+                match fut.await {
+                    CoreBluetoothReply::ReadResult(chars) => Ok(chars),
+                    _ => {
+                        panic!("Shouldn't get anything but read result!");
+                    }
+                }
             })
-            .await?;
-        match fut.await {
-            CoreBluetoothReply::ReadResult(chars) => Ok(chars),
-            _ => {
-                panic!("Shouldn't get anything but read result!");
-            }
-        }
+            .await
     }
 }
 
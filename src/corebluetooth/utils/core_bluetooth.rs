@@ -17,8 +17,9 @@
 // according to those terms.
 
 use objc2::rc::Retained;
-use objc2_core_bluetooth::CBUUID;
+use objc2_core_bluetooth::{CBCharacteristic, CBService, CBUUID};
 use objc2_foundation::NSString;
+use std::ptr;
 use uuid::Uuid;
 
 /// Convert a CBUUID object to the standard Uuid type.
@@ -43,6 +44,59 @@ pub fn uuid_to_cbuuid(uuid: Uuid) -> Retained<CBUUID> {
     unsafe { CBUUID::UUIDWithString(&NSString::from_str(&uuid.to_string())) }
 }
 
+/// Get the instance ID of a service, which tells it apart from others with the same UUID on the
+/// same peripheral. CoreBluetooth doesn't expose attribute handles, so this is the service's index
+/// among those.
+pub fn service_instance_id(service: &CBService) -> u16 {
+    let uuid = cbuuid_to_uuid(unsafe { &service.UUID() });
+    let Some(peripheral) = (unsafe { service.peripheral() }) else {
+        return 0;
+    };
+    let mut instance_id = 0;
+    for other in unsafe { peripheral.services() }.unwrap_or_default() {
+        if ptr::eq(&*other, service) {
+            break;
+        }
+        if cbuuid_to_uuid(unsafe { &other.UUID() }) == uuid {
+            instance_id += 1;
+        }
+    }
+    instance_id
+}
+
+/// Get the instance ID of a characteristic, which tells it apart from others with the same UUID in
+/// services with the same UUID, as a characteristic is only identified along with its service's
+/// UUID. CoreBluetooth doesn't expose attribute handles, so this is the characteristic's index
+/// among those.
+pub fn characteristic_instance_id(characteristic: &CBCharacteristic) -> u16 {
+    let uuid = cbuuid_to_uuid(unsafe { &characteristic.UUID() });
+    let Some(service) = (unsafe { characteristic.service() }) else {
+        return 0;
+    };
+    let service_uuid = cbuuid_to_uuid(unsafe { &service.UUID() });
+    let mut services: Vec<_> = unsafe { service.peripheral() }
+        .and_then(|peripheral| unsafe { peripheral.services() })
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|other| cbuuid_to_uuid(unsafe { &other.UUID() }) == service_uuid)
+        .collect();
+    if !services.iter().any(|other| ptr::eq(&**other, &*service)) {
+        services = vec![service];
+    }
+    let mut instance_id = 0;
+    for service in services {
+        for other in unsafe { service.characteristics() }.unwrap_or_default() {
+            if ptr::eq(&*other, characteristic) {
+                return instance_id;
+            }
+            if cbuuid_to_uuid(unsafe { &other.UUID() }) == uuid {
+                instance_id += 1;
+            }
+        }
+    }
+    instance_id
+}
+
 #[cfg(test)]
 mod tests {
     use objc2_foundation::ns_string;
@@ -18,6 +18,7 @@
 
 use std::ffi::CStr;
 
+use objc2::rc::Retained;
 use objc2_foundation::{NSString, NSUUID};
 use uuid::Uuid;
 
@@ -27,6 +28,10 @@ pub fn nsuuid_to_uuid(uuid: &NSUUID) -> Uuid {
     uuid.UUIDString().to_string().parse().unwrap()
 }
 
+pub fn uuid_to_nsuuid(uuid: Uuid) -> Retained<NSUUID> {
+    NSUUID::from_bytes(uuid.into_bytes())
+}
+
 pub unsafe fn nsstring_to_string(nsstring: *const NSString) -> Option<String> {
     nsstring
         .as_ref()
@@ -6,14 +6,21 @@ use super::{
     peripheral::{Peripheral, PeripheralId},
 };
 use crate::{
-    api::{BDAddr, Central, CentralEvent, CentralState, PeripheralProperties, ScanFilter},
-    common::adapter_manager::AdapterManager,
+    api::{
+        AdapterCapabilities, BDAddr, Central, CentralEvent, CentralState, PeripheralProperties,
+        ScanFilter, ScanOptions, ScanSubscription,
+    },
+    common::{
+        adapter_manager::AdapterManager,
+        scan_state::ScanState,
+        scan_subscriptions::{subscribe_scan, ScanSubscriptions},
+    },
     Error, Result,
 };
 use async_trait::async_trait;
 use futures::stream::Stream;
 use jni::{
-    objects::{GlobalRef, JObject, JString},
+    objects::{GlobalRef, JList, JObject, JString},
     strings::JavaStr,
     sys::jboolean,
     JNIEnv,
@@ -23,12 +30,15 @@ use std::{
     pin::Pin,
     str::FromStr,
     sync::Arc,
+    time::Duration,
 };
 
 #[derive(Clone)]
 pub struct Adapter {
     manager: Arc<AdapterManager<Peripheral>>,
     internal: GlobalRef,
+    scan_state: Arc<ScanState>,
+    scans: Arc<ScanSubscriptions>,
 }
 
 impl Debug for Adapter {
@@ -52,12 +62,34 @@ impl Adapter {
         let adapter = Self {
             manager: Arc::new(AdapterManager::default()),
             internal,
+            scan_state: Default::default(),
+            scans: Default::default(),
         };
         env.set_rust_field(obj, "handle", adapter.clone())?;
 
         Ok(adapter)
     }
 
+    fn start_java_scan(&self, filter: ScanFilter) -> Result<()> {
+        let env = global_jvm().get_env()?;
+        let filter = JScanFilter::new(&env, filter)?;
+        env.call_method(
+            &self.internal,
+            "startScan",
+            "(Lcom/nonpolynomial/btleplug/android/impl/ScanFilter;)V",
+            &[filter.into()],
+        )?;
+        self.manager.emit(CentralEvent::ScanStarted);
+        Ok(())
+    }
+
+    fn stop_java_scan(&self) -> Result<()> {
+        let env = global_jvm().get_env()?;
+        env.call_method(&self.internal, "stopScan", "()V", &[])?;
+        self.manager.emit(CentralEvent::ScanStopped);
+        Ok(())
+    }
+
     pub fn report_scan_result(&self, scan_result: JObject) -> Result<Peripheral> {
         use std::convert::TryInto;
 
@@ -90,7 +122,13 @@ impl Adapter {
 
     fn add(&self, address: BDAddr) -> Result<Peripheral> {
         let env = global_jvm().get_env()?;
-        let peripheral = Peripheral::new(&env, self.internal.as_obj(), address)?;
+        let peripheral = Peripheral::new(
+            &env,
+            self.internal.as_obj(),
+            address,
+            self.manager.connect_limit(),
+            Arc::downgrade(&self.manager),
+        )?;
         self.manager.add_peripheral(peripheral.clone());
         Ok(peripheral)
     }
@@ -101,7 +139,7 @@ impl Adapter {
         properties: PeripheralProperties,
         new: bool,
     ) {
-        peripheral.report_properties(properties.clone());
+        peripheral.report_properties(properties.clone(), self.manager.rssi_smoothing().weight());
         self.manager.emit(if new {
             CentralEvent::DeviceDiscovered(PeripheralId(properties.address))
         } else {
@@ -118,7 +156,7 @@ impl Adapter {
         });
         self.manager.emit(CentralEvent::ServicesAdvertisement {
             id: PeripheralId(properties.address),
-            services: properties.services,
+            services: properties.advertised_services,
         });
     }
 }
@@ -137,27 +175,79 @@ impl Central for Adapter {
     }
 
     async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
-        let env = global_jvm().get_env()?;
-        let filter = JScanFilter::new(&env, filter)?;
-        env.call_method(
-            &self.internal,
-            "startScan",
-            "(Lcom/nonpolynomial/btleplug/android/impl/ScanFilter;)V",
-            &[filter.into()],
-        )?;
+        self.start_java_scan(filter.clone())?;
+        self.scan_state.started(filter);
         Ok(())
     }
 
+    async fn start_scan_with_options(
+        &self,
+        filter: ScanFilter,
+        options: ScanOptions,
+    ) -> Result<()> {
+        options.check()?;
+        self.manager.rssi_smoothing().set(options.rssi_smoothing);
+        self.start_scan(filter).await
+    }
+
     async fn stop_scan(&self) -> Result<()> {
-        let env = global_jvm().get_env()?;
-        env.call_method(&self.internal, "stopScan", "()V", &[])?;
+        self.stop_java_scan()?;
+        self.scan_state.stopped();
+        Ok(())
+    }
+
+    async fn pause_scan(&self) -> Result<()> {
+        if self.scan_state.is_running() {
+            self.stop_java_scan()?;
+            self.scan_state.paused();
+        }
         Ok(())
     }
 
+    async fn resume_scan(&self) -> Result<()> {
+        if let Some(filter) = self.scan_state.paused_filter() {
+            self.start_java_scan(filter)?;
+            self.scan_state.resumed();
+        }
+        Ok(())
+    }
+
+    async fn subscribe_scan(&self, filter: ScanFilter) -> Result<ScanSubscription> {
+        subscribe_scan(self, &self.scans, filter).await
+    }
+
     async fn peripherals(&self) -> Result<Vec<Peripheral>> {
         Ok(self.manager.peripherals())
     }
 
+    async fn connected(&self) -> Result<Vec<Peripheral>> {
+        Ok(self.manager.connected_peripherals())
+    }
+
+    async fn bonded_devices(&self) -> Result<Vec<Peripheral>> {
+        let env = global_jvm().get_env()?;
+        let addresses = env
+            .call_method(
+                &self.internal,
+                "getBondedAddresses",
+                "()Ljava/util/List;",
+                &[],
+            )?
+            .l()?;
+        let addresses = JList::from_env(&env, addresses)?;
+        let mut bonded = Vec::new();
+        for address in addresses.iter()? {
+            let address = JavaStr::from_env(&env, address.into())?;
+            let address = address.to_str().map_err(|e| Error::Other(e.into()))?;
+            let address = BDAddr::from_str(address)?;
+            bonded.push(match self.manager.peripheral(&PeripheralId(address)) {
+                Some(peripheral) => peripheral,
+                None => self.add(address)?,
+            });
+        }
+        Ok(bonded)
+    }
+
     async fn peripheral(&self, address: &PeripheralId) -> Result<Peripheral> {
         self.manager
             .peripheral(address)
@@ -171,6 +261,33 @@ impl Central for Adapter {
     async fn adapter_state(&self) -> Result<CentralState> {
         Ok(CentralState::Unknown)
     }
+
+    async fn is_scanning(&self) -> Result<bool> {
+        let env = global_jvm().get_env()?;
+        Ok(env
+            .call_method(&self.internal, "isScanning", "()Z", &[])?
+            .z()?)
+    }
+
+    async fn adapter_capabilities(&self) -> Result<AdapterCapabilities> {
+        let env = global_jvm().get_env()?;
+        let peripheral_role = env
+            .call_method(&self.internal, "isPeripheralRoleSupported", "()Z", &[])?
+            .z()?;
+        Ok(AdapterCapabilities { peripheral_role })
+    }
+
+    fn set_peripheral_ttl(&self, ttl: Option<Duration>) {
+        self.manager.set_peripheral_ttl(ttl);
+    }
+
+    fn set_max_peripherals(&self, max: Option<usize>) {
+        self.manager.set_max_peripherals(max);
+    }
+
+    fn set_max_concurrent_connects(&self, max: usize) {
+        self.manager.connect_limit().set_max(max);
+    }
 }
 
 pub(crate) fn adapter_report_scan_result_internal(
@@ -200,3 +317,18 @@ pub(crate) fn adapter_on_connection_state_changed_internal(
     });
     Ok(())
 }
+
+pub(crate) fn adapter_on_services_changed_internal(
+    env: &JNIEnv,
+    obj: JObject,
+    addr: JString,
+) -> crate::Result<()> {
+    let adapter = env.get_rust_field::<_, _, Adapter>(obj, "handle")?;
+    let addr_str = JavaStr::from_env(env, addr)?;
+    let addr_str = addr_str.to_str().map_err(|e| Error::Other(e.into()))?;
+    let addr = BDAddr::from_str(addr_str)?;
+    adapter
+        .manager
+        .emit(CentralEvent::ServicesChanged(PeripheralId(addr)));
+    Ok(())
+}
@@ -2,6 +2,7 @@ package com.nonpolynomial.btleplug.android.impl;
 
 import android.annotation.SuppressLint;
 import android.bluetooth.BluetoothAdapter;
+import android.bluetooth.BluetoothDevice;
 import android.bluetooth.BluetoothManager;
 import android.bluetooth.le.BluetoothLeScanner;
 import android.bluetooth.le.ScanCallback;
@@ -17,6 +18,7 @@ import java.util.List;
 class Adapter {
     private long handle;
     private final Callback callback = new Callback();
+    private volatile boolean scanning = false;
 
     public Adapter() {}
 
@@ -43,6 +45,7 @@ class Adapter {
           throw new RuntimeException("No bluetooth scanner available for adapter");
         }
         scanner.startScan(filters, settings, this.callback);
+        this.scanning = true;
     }
 
     @SuppressLint("MissingPermission")
@@ -54,16 +57,49 @@ class Adapter {
                 scanner.stopScan(this.callback);
             }
         }
+        this.scanning = false;
+    }
+
+    public boolean isScanning() {
+        // A scan ends without any callback when Bluetooth is turned off.
+        BluetoothAdapter bluetoothAdapter = BluetoothAdapter.getDefaultAdapter();
+        return this.scanning && bluetoothAdapter != null && bluetoothAdapter.isEnabled();
+    }
+
+    @SuppressLint("MissingPermission")
+    public List<String> getBondedAddresses() {
+        ArrayList<String> addresses = new ArrayList<>();
+        BluetoothAdapter bluetoothAdapter = BluetoothAdapter.getDefaultAdapter();
+        if (bluetoothAdapter != null) {
+            for (BluetoothDevice device : bluetoothAdapter.getBondedDevices()) {
+                if (device.getType() != BluetoothDevice.DEVICE_TYPE_CLASSIC) {
+                    addresses.add(device.getAddress());
+                }
+            }
+        }
+        return addresses;
+    }
+
+    public boolean isPeripheralRoleSupported() {
+        BluetoothAdapter bluetoothAdapter = BluetoothAdapter.getDefaultAdapter();
+        return bluetoothAdapter != null && bluetoothAdapter.isMultipleAdvertisementSupported();
     }
 
     private native void reportScanResult(ScanResult result);
 
     public native void onConnectionStateChanged(String address, boolean connected);
 
+    public native void onServicesChanged(String address);
+
     private class Callback extends ScanCallback {
         @Override
         public void onScanResult(int callbackType, ScanResult result) {
             Adapter.this.reportScanResult(result);
         }
+
+        @Override
+        public void onScanFailed(int errorCode) {
+            Adapter.this.scanning = false;
+        }
     }
 }
@@ -0,0 +1,14 @@
+package com.nonpolynomial.btleplug.android.impl;
+
+class ConnectionFailedException extends BluetoothException {
+    private final int status;
+
+    public ConnectionFailedException(int status) {
+        super();
+        this.status = status;
+    }
+
+    public int getStatus() {
+        return this.status;
+    }
+}
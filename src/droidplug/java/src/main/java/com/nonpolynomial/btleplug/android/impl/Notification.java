@@ -0,0 +1,23 @@
+package com.nonpolynomial.btleplug.android.impl;
+
+import android.bluetooth.BluetoothGattCharacteristic;
+
+import java.util.Arrays;
+
+public class Notification {
+    private final BluetoothGattCharacteristic characteristic;
+    private final byte[] value;
+
+    public Notification(BluetoothGattCharacteristic characteristic, byte[] value) {
+        this.characteristic = characteristic;
+        this.value = Arrays.copyOf(value, value.length);
+    }
+
+    public BluetoothGattCharacteristic getCharacteristic() {
+        return characteristic;
+    }
+
+    public byte[] getValue() {
+        return Arrays.copyOf(value, value.length);
+    }
+}
@@ -11,6 +11,7 @@ import android.bluetooth.BluetoothGattService;
 
 import java.lang.ref.WeakReference;
 import java.util.ArrayList;
+import java.util.Arrays;
 import java.util.LinkedList;
 import java.util.List;
 import java.util.Queue;
@@ -24,15 +25,17 @@ import io.github.gedgygedgy.rust.stream.Stream;
 @SuppressWarnings("unused") // Native code uses this class.
 class Peripheral {
     private static final UUID CLIENT_CHARACTERISTIC_CONFIGURATION_DESCRIPTOR = new UUID(0x00002902_0000_1000L, 0x8000_00805f9b34fbL);
+    private static final int DEFAULT_MTU = 23;
 
     private final BluetoothDevice device;
     private final Adapter adapter;
     private BluetoothGatt gatt;
     private final Callback callback;
     private boolean connected = false;
+    private int mtu = DEFAULT_MTU;
 
     private final Queue<Runnable> commandQueue = new LinkedList<>();
-    private final LinkedList<WeakReference<QueueStream<BluetoothGattCharacteristic>>> notificationStreams = new LinkedList<>();
+    private final LinkedList<WeakReference<QueueStream<Notification>>> notificationStreams = new LinkedList<>();
     private boolean executingCommand = false;
     private CommandCallback commandCallback;
 
@@ -53,7 +56,7 @@ class Peripheral {
                         public void onConnectionStateChange(BluetoothGatt gatt, int status, int newState) {
                             Peripheral.this.asyncWithFuture(future, () -> {
                                 if (status != BluetoothGatt.GATT_SUCCESS) {
-                                    throw new NotConnectedException();
+                                    throw new ConnectionFailedException(status);
                                 }
 
                                 if (newState == BluetoothGatt.STATE_CONNECTED) {
@@ -121,8 +124,62 @@ class Peripheral {
         return this.connected;
     }
 
+    public int getMtu() {
+        return this.mtu;
+    }
+
+    @SuppressLint("MissingPermission")
+    public synchronized boolean requestConnectionPriority(int priority) {
+        return this.gatt != null && this.connected && this.gatt.requestConnectionPriority(priority);
+    }
+
+    @SuppressLint("MissingPermission")
+    public Future<Integer> requestMtu(int mtu) {
+        SimpleFuture<Integer> future = new SimpleFuture<>();
+        synchronized (this) {
+            this.queueCommand(() -> {
+                this.asyncWithFuture(future, () -> {
+                    if (!this.connected) {
+                        throw new NotConnectedException();
+                    }
+
+                    this.setCommandCallback(new CommandCallback() {
+                        @Override
+                        public void onMtuChanged(BluetoothGatt gatt, int mtu, int status) {
+                            Peripheral.this.asyncWithFuture(future, () -> {
+                                if (status != BluetoothGatt.GATT_SUCCESS) {
+                                    throw new RuntimeException("Unable to change MTU");
+                                }
+
+                                Peripheral.this.wakeCommand(future, mtu);
+                            });
+                        }
+                        @Override
+                        public void onConnectionStateChange(BluetoothGatt gatt, int status, int newState) {
+                            Peripheral.this.asyncWithFuture(future, () -> {
+                                if (status != BluetoothGatt.GATT_SUCCESS) {
+                                    throw new RuntimeException("Disconnected while requesting MTU");
+                                }
+
+                                if (newState == BluetoothGatt.STATE_DISCONNECTED) {
+                                    Peripheral.this.gatt.close();
+                                    Peripheral.this.gatt = null;
+                                    throw new NotConnectedException();
+                                }
+                            });
+                        }
+                    });
+                    if (!this.gatt.requestMtu(mtu)) {
+                        throw new RuntimeException("Unable to request MTU");
+                    }
+                });
+            });
+        }
+        return future;
+    }
+
     @SuppressLint("MissingPermission")
-    public Future<byte[]> read(UUID uuid) {
+    public Future<byte[]> read(UUID uuid, int instanceId) {
         SimpleFuture<byte[]> future = new SimpleFuture<>();
         synchronized (this) {
             this.queueCommand(() -> {
@@ -131,12 +188,12 @@ class Peripheral {
                         throw new NotConnectedException();
                     }
 
-                    BluetoothGattCharacteristic characteristic = this.getCharacteristicByUuid(uuid);
+                    BluetoothGattCharacteristic characteristic = this.getCharacteristicByUuid(uuid, instanceId);
                     this.setCommandCallback(new CommandCallback() {
                         @Override
                         public void onCharacteristicRead(BluetoothGatt gatt, BluetoothGattCharacteristic characteristic, int status) {
                             Peripheral.this.asyncWithFuture(future, () -> {
-                                if (!characteristic.getUuid().equals(uuid)) {
+                                if (!characteristic.getUuid().equals(uuid) || characteristic.getInstanceId() != instanceId) {
                                     throw new UnexpectedCharacteristicException();
                                 }
 
@@ -168,7 +225,7 @@ class Peripheral {
     }
 
     @SuppressLint("MissingPermission")
-    public Future<Void> write(UUID uuid, byte[] data, int writeType) {
+    public Future<Void> write(UUID uuid, int instanceId, byte[] data, int writeType) {
         SimpleFuture<Void> future = new SimpleFuture<>();
         synchronized (this) {
             this.queueCommand(() -> {
@@ -177,14 +234,18 @@ class Peripheral {
                         throw new NotConnectedException();
                     }
 
-                    BluetoothGattCharacteristic characteristic = this.getCharacteristicByUuid(uuid);
+                    BluetoothGattCharacteristic characteristic = this.getCharacteristicByUuid(uuid, instanceId);
                     characteristic.setValue(data);
                     characteristic.setWriteType(writeType);
                     this.setCommandCallback(new CommandCallback() {
                         @Override
                         public void onCharacteristicWrite(BluetoothGatt gatt, BluetoothGattCharacteristic characteristic, int status) {
                             Peripheral.this.asyncWithFuture(future, () -> {
-                                if (!characteristic.getUuid().equals(uuid)) {
+                                if (status != BluetoothGatt.GATT_SUCCESS) {
+                                    throw new RuntimeException("Unable to write characteristic");
+                                }
+
+                                if (!characteristic.getUuid().equals(uuid) || characteristic.getInstanceId() != instanceId) {
                                     throw new UnexpectedCharacteristicException();
                                 }
 
@@ -201,7 +262,7 @@ class Peripheral {
                                 if (newState == BluetoothGatt.STATE_DISCONNECTED) {
                                     Peripheral.this.gatt.close();
                                     Peripheral.this.gatt = null;
-                                    Peripheral.this.wakeCommand(future, null);
+                                    throw new NotConnectedException();
                                 }
                             });
                         }
@@ -215,6 +276,126 @@ class Peripheral {
         return future;
     }
 
+    @SuppressLint("MissingPermission")
+    public Future<Void> executeReliableWrite(UUID[] uuids, int[] instanceIds, byte[][] data) {
+        SimpleFuture<Void> future = new SimpleFuture<>();
+        synchronized (this) {
+            this.queueCommand(() -> {
+                this.asyncWithFuture(future, () -> {
+                    if (!this.connected) {
+                        throw new NotConnectedException();
+                    }
+
+                    class ReliableWriteCallback extends CommandCallback {
+                        private int index = 0;
+
+                        // Writes the next value, or executes the transaction once they have all
+                        // been written.
+                        private void writeNext(BluetoothGatt gatt) {
+                            if (this.index == uuids.length) {
+                                if (!gatt.executeReliableWrite()) {
+                                    throw new RuntimeException("Unable to execute reliable write");
+                                }
+                                return;
+                            }
+
+                            BluetoothGattCharacteristic characteristic = Peripheral.this.getCharacteristicByUuid(uuids[this.index], instanceIds[this.index]);
+                            characteristic.setValue(data[this.index]);
+                            characteristic.setWriteType(BluetoothGattCharacteristic.WRITE_TYPE_DEFAULT);
+                            if (!gatt.writeCharacteristic(characteristic)) {
+                                throw new RuntimeException("Unable to write characteristic");
+                            }
+                        }
+
+                        @Override
+                        public void onCharacteristicWrite(BluetoothGatt gatt, BluetoothGattCharacteristic characteristic, int status) {
+                            Peripheral.this.asyncWithFuture(future, () -> {
+                                Peripheral.this.abortReliableWriteOnError(() -> {
+                                    if (status != BluetoothGatt.GATT_SUCCESS) {
+                                        throw new RuntimeException("Unable to write characteristic");
+                                    }
+
+                                    if (!characteristic.getUuid().equals(uuids[this.index]) || characteristic.getInstanceId() != instanceIds[this.index]) {
+                                        throw new UnexpectedCharacteristicException();
+                                    }
+
+                                    // The device echoes each value back, and the transaction
+                                    // must be aborted if it didn't queue what was sent.
+                                    if (!Arrays.equals(characteristic.getValue(), data[this.index])) {
+                                        throw new RuntimeException("Reliable write value was not echoed back intact");
+                                    }
+
+                                    this.index++;
+                                    this.writeNext(gatt);
+                                });
+                            });
+                        }
+
+                        @Override
+                        public void onReliableWriteCompleted(BluetoothGatt gatt, int status) {
+                            Peripheral.this.asyncWithFuture(future, () -> {
+                                if (status != BluetoothGatt.GATT_SUCCESS) {
+                                    throw new RuntimeException("Unable to execute reliable write");
+                                }
+
+                                Peripheral.this.wakeCommand(future, null);
+                            });
+                        }
+
+                        @Override
+                        public void onConnectionStateChange(BluetoothGatt gatt, int status, int newState) {
+                            Peripheral.this.asyncWithFuture(future, () -> {
+                                if (status != BluetoothGatt.GATT_SUCCESS) {
+                                    throw new RuntimeException("Disconnected while in reliable write operation");
+                                }
+
+                                if (newState == BluetoothGatt.STATE_DISCONNECTED) {
+                                    Peripheral.this.gatt.close();
+                                    Peripheral.this.gatt = null;
+                                    throw new NotConnectedException();
+                                }
+                            });
+                        }
+                    }
+                    ReliableWriteCallback callback = new ReliableWriteCallback();
+                    this.setCommandCallback(callback);
+                    if (!this.gatt.beginReliableWrite()) {
+                        throw new RuntimeException("Unable to begin reliable write");
+                    }
+                    this.abortReliableWriteOnError(() -> callback.writeNext(this.gatt));
+                });
+            });
+        }
+        return future;
+    }
+
+    public Future<Void> clearGattCache() {
+        SimpleFuture<Void> future = new SimpleFuture<>();
+        synchronized (this) {
+            this.queueCommand(() -> {
+                this.asyncWithFuture(future, () -> {
+                    if (!this.connected) {
+                        throw new NotConnectedException();
+                    }
+
+                    // BluetoothGatt.refresh() is hidden, but has been there since Android 4.3 and
+                    // is the only way to make the next service discovery bypass the cache.
+                    boolean refreshed;
+                    try {
+                        refreshed = (Boolean) this.gatt.getClass().getMethod("refresh").invoke(this.gatt);
+                    } catch (ReflectiveOperationException ex) {
+                        throw new RuntimeException("Unable to clear GATT cache", ex);
+                    }
+                    if (!refreshed) {
+                        throw new RuntimeException("Unable to clear GATT cache");
+                    }
+                    this.wakeCommand(future, null);
+                });
+            });
+        }
+        return future;
+    }
+
     @SuppressLint("MissingPermission")
     public Future<List<BluetoothGattService>> discoverServices() {
         SimpleFuture<List<BluetoothGattService>> future = new SimpleFuture<>();
@@ -259,7 +440,7 @@ class Peripheral {
     }
 
     @SuppressLint("MissingPermission")
-    public Future<Void> setCharacteristicNotification(UUID uuid, boolean enable) {
+    public Future<Void> setCharacteristicNotification(UUID uuid, int instanceId, boolean enable) {
         SimpleFuture<Void> future = new SimpleFuture<>();
         synchronized (this) {
             this.queueCommand(() -> {
@@ -268,7 +449,7 @@ class Peripheral {
                         throw new NotConnectedException();
                     }
 
-                    BluetoothGattCharacteristic characteristic = this.getCharacteristicByUuid(uuid);
+                    BluetoothGattCharacteristic characteristic = this.getCharacteristicByUuid(uuid, instanceId);
                     if (!this.gatt.setCharacteristicNotification(characteristic, enable)) {
                         throw new RuntimeException("Unable to set characteristic notification");
                     }
@@ -315,8 +496,8 @@ class Peripheral {
         return future;
     }
 
-    public Stream<BluetoothGattCharacteristic> getNotifications() {
-        QueueStream<BluetoothGattCharacteristic> stream = new QueueStream<>();
+    public Stream<Notification> getNotifications() {
+        QueueStream<Notification> stream = new QueueStream<>();
         synchronized (this) {
             this.notificationStreams.add(new WeakReference<>(stream));
         }
@@ -324,7 +505,7 @@ class Peripheral {
     }
 
     @SuppressLint("MissingPermission")
-    public Future<byte[]> readDescriptor(UUID characteristic, UUID uuid) {
+    public Future<byte[]> readDescriptor(UUID characteristic, int characteristicInstanceId, UUID uuid) {
         SimpleFuture<byte[]> future = new SimpleFuture<>();
         synchronized (this) {
             this.queueCommand(() -> {
@@ -333,7 +514,7 @@ class Peripheral {
                         throw new NotConnectedException();
                     }
 
-                    BluetoothGattDescriptor descriptor = this.getDescriptorByUuid(characteristic, uuid);
+                    BluetoothGattDescriptor descriptor = this.getDescriptorByUuid(characteristic, characteristicInstanceId, uuid);
                     this.setCommandCallback(new CommandCallback() {
                         @Override
                         public void onDescriptorRead(BluetoothGatt gatt, BluetoothGattDescriptor descriptor, int status) {
@@ -356,7 +537,7 @@ class Peripheral {
     }
 
     @SuppressLint("MissingPermission")
-    public Future<Void> writeDescriptor(UUID characteristic, UUID uuid, byte[] data, int writeType) {
+    public Future<Void> writeDescriptor(UUID characteristic, int characteristicInstanceId, UUID uuid, byte[] data, int writeType) {
         SimpleFuture<Void> future = new SimpleFuture<>();
         synchronized (this) {
             this.queueCommand(() -> {
@@ -365,7 +546,7 @@ class Peripheral {
                         throw new NotConnectedException();
                     }
 
-                    BluetoothGattDescriptor descriptor = this.getDescriptorByUuid(characteristic, uuid);
+                    BluetoothGattDescriptor descriptor = this.getDescriptorByUuid(characteristic, characteristicInstanceId, uuid);
                     descriptor.setValue(data);
                     this.setCommandCallback(new CommandCallback() {
                         @Override
@@ -400,9 +581,9 @@ class Peripheral {
     }
 
     @SuppressLint("MissingPermission")
-    private BluetoothGattCharacteristic getCharacteristicByUuid(UUID uuid) {
+    private BluetoothGattCharacteristic getCharacteristicByUuid(UUID uuid, int instanceId) {
         for (BluetoothGattCharacteristic characteristic : this.getCharacteristics()) {
-            if (characteristic.getUuid().equals(uuid)) {
+            if (characteristic.getUuid().equals(uuid) && characteristic.getInstanceId() == instanceId) {
                 return characteristic;
             }
         }
@@ -411,8 +592,8 @@ class Peripheral {
     }
 
     @SuppressLint("MissingPermission")
-    private BluetoothGattDescriptor getDescriptorByUuid(UUID characteristicUuid, UUID uuid) {
-        BluetoothGattCharacteristic characteristic = getCharacteristicByUuid(characteristicUuid);
+    private BluetoothGattDescriptor getDescriptorByUuid(UUID characteristicUuid, int characteristicInstanceId, UUID uuid) {
+        BluetoothGattCharacteristic characteristic = getCharacteristicByUuid(characteristicUuid, characteristicInstanceId);
         for (BluetoothGattDescriptor descriptor : characteristic.getDescriptors()) {
             if (descriptor.getUuid().equals(uuid)) {
                 return descriptor;
@@ -422,6 +603,16 @@ class Peripheral {
         throw new NoSuchCharacteristicException();
     }
 
+    @SuppressLint("MissingPermission")
+    private void abortReliableWriteOnError(Runnable callback) {
+        try {
+            callback.run();
+        } catch (RuntimeException ex) {
+            this.gatt.abortReliableWrite();
+            throw ex;
+        }
+    }
+
     private void queueCommand(Runnable callback) {
         if (this.executingCommand) {
             this.commandQueue.add(callback);
@@ -471,6 +662,7 @@ class Peripheral {
                         break;
                     case BluetoothGatt.STATE_DISCONNECTED:
                         Peripheral.this.connected = false;
+                        Peripheral.this.mtu = DEFAULT_MTU;
                         break;
                 }
                 if (Peripheral.this.commandCallback != null) {
@@ -505,6 +697,15 @@ class Peripheral {
             }
         }
 
+        @Override
+        public void onReliableWriteCompleted(BluetoothGatt gatt, int status) {
+            synchronized (Peripheral.this) {
+                if (Peripheral.this.commandCallback != null) {
+                    Peripheral.this.commandCallback.onReliableWriteCompleted(gatt, status);
+                }
+            }
+        }
+
         @Override
         public void onServicesDiscovered(BluetoothGatt gatt, int status) {
             synchronized (Peripheral.this) {
@@ -516,13 +717,12 @@ class Peripheral {
 
         @Override
         public void onCharacteristicChanged(BluetoothGatt gatt, BluetoothGattCharacteristic characteristic) {
-            BluetoothGattCharacteristic characteristic2 = new BluetoothGattCharacteristic(characteristic.getUuid(), characteristic.getProperties(), characteristic.getPermissions());
-            characteristic2.setValue(characteristic.getValue());
+            Notification notification = new Notification(characteristic, characteristic.getValue());
             synchronized (Peripheral.this) {
-                for (WeakReference<QueueStream<BluetoothGattCharacteristic>> ref : Peripheral.this.notificationStreams) {
-                    QueueStream<BluetoothGattCharacteristic> stream = ref.get();
+                for (WeakReference<QueueStream<Notification>> ref : Peripheral.this.notificationStreams) {
+                    QueueStream<Notification> stream = ref.get();
                     if (stream != null) {
-                        stream.add(characteristic2);
+                        stream.add(notification);
                     }
                 }
             }
@@ -536,6 +736,24 @@ class Peripheral {
                 }
             }
         }
+
+        @Override
+        public void onMtuChanged(BluetoothGatt gatt, int mtu, int status) {
+            synchronized (Peripheral.this) {
+                if (status == BluetoothGatt.GATT_SUCCESS) {
+                    Peripheral.this.mtu = mtu;
+                }
+                if (Peripheral.this.commandCallback != null) {
+                    Peripheral.this.commandCallback.onMtuChanged(gatt, mtu, status);
+                }
+            }
+        }
+
+        // Only called from Android 12, where the Service Changed indication is passed on.
+        @Override
+        public void onServiceChanged(BluetoothGatt gatt) {
+            Peripheral.this.adapter.onServicesChanged(Peripheral.this.device.getAddress());
+        }
     }
 
     private static abstract class CommandCallback extends BluetoothGattCallback {
@@ -560,6 +778,11 @@ class Peripheral {
             throw new UnexpectedCallbackException();
         }
 
+        @Override
+        public void onReliableWriteCompleted(BluetoothGatt gatt, int status) {
+            throw new UnexpectedCallbackException();
+        }
+
         @Override
         public void onServicesDiscovered(BluetoothGatt gatt, int status) {
             throw new UnexpectedCallbackException();
@@ -569,5 +792,11 @@ class Peripheral {
         public void onDescriptorWrite(BluetoothGatt gatt, BluetoothGattDescriptor descriptor, int status) {
             throw new UnexpectedCallbackException();
         }
+
+        @Override
+        public void onMtuChanged(BluetoothGatt gatt, int mtu, int status) {
+            // The MTU can also be changed by an exchange the device or the OS started, so this
+            // isn't necessarily a reply to the current command.
+        }
     }
 }
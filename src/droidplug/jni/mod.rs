@@ -22,6 +22,11 @@ pub fn init(env: &JNIEnv) -> crate::Result<()> {
                     sig: "(Ljava/lang/String;Z)V".into(),
                     fn_ptr: adapter_on_connection_state_changed as *mut c_void,
                 },
+                NativeMethod {
+                    name: "onServicesChanged".into(),
+                    sig: "(Ljava/lang/String;)V".into(),
+                    fn_ptr: adapter_on_services_changed as *mut c_void,
+                },
             ],
         )?;
         jni_utils::classcache::find_add_class(
@@ -32,10 +37,18 @@ pub fn init(env: &JNIEnv) -> crate::Result<()> {
             env,
             "com/nonpolynomial/btleplug/android/impl/ScanFilter",
         )?;
+        jni_utils::classcache::find_add_class(
+            env,
+            "com/nonpolynomial/btleplug/android/impl/Notification",
+        )?;
         jni_utils::classcache::find_add_class(
             env,
             "com/nonpolynomial/btleplug/android/impl/NotConnectedException",
         )?;
+        jni_utils::classcache::find_add_class(
+            env,
+            "com/nonpolynomial/btleplug/android/impl/ConnectionFailedException",
+        )?;
         jni_utils::classcache::find_add_class(
             env,
             "com/nonpolynomial/btleplug/android/impl/PermissionDeniedException",
@@ -81,3 +94,7 @@ extern "C" fn adapter_on_connection_state_changed(
     let _ =
         super::adapter::adapter_on_connection_state_changed_internal(&env, obj, addr, connected);
 }
+
+extern "C" fn adapter_on_services_changed(env: JNIEnv, obj: JObject, addr: JString) {
+    let _ = super::adapter::adapter_on_services_changed_internal(&env, obj, addr);
+}
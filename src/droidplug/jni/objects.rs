@@ -7,19 +7,24 @@ use jni::{
     JNIEnv,
 };
 use jni_utils::{future::JFuture, stream::JStream, uuid::JUuid};
-use std::{collections::HashMap, convert::TryFrom, iter::Iterator};
+use std::{collections::HashMap, convert::TryFrom, iter::Iterator, time::Instant};
 use uuid::Uuid;
 
-use crate::api::{BDAddr, CharPropFlags, PeripheralProperties, ScanFilter};
+use crate::api::{BDAddr, CharPropFlags, PeripheralProperties, ScanFilter, Transport};
 
 pub struct JPeripheral<'a: 'b, 'b> {
     internal: JObject<'a>,
     connect: JMethodID<'a>,
     disconnect: JMethodID<'a>,
     is_connected: JMethodID<'a>,
+    get_mtu: JMethodID<'a>,
+    request_connection_priority: JMethodID<'a>,
+    request_mtu: JMethodID<'a>,
     discover_services: JMethodID<'a>,
+    clear_gatt_cache: JMethodID<'a>,
     read: JMethodID<'a>,
     write: JMethodID<'a>,
+    execute_reliable_write: JMethodID<'a>,
     set_characteristic_notification: JMethodID<'a>,
     get_notifications: JMethodID<'a>,
     read_descriptor: JMethodID<'a>,
@@ -67,25 +72,43 @@ impl<'a: 'b, 'b> JPeripheral<'a, 'b> {
             "()Lio/github/gedgygedgy/rust/future/Future;",
         )?;
         let is_connected = env.get_method_id(class, "isConnected", "()Z")?;
+        let get_mtu = env.get_method_id(class, "getMtu", "()I")?;
+        let request_connection_priority =
+            env.get_method_id(class, "requestConnectionPriority", "(I)Z")?;
+        let request_mtu = env.get_method_id(
+            class,
+            "requestMtu",
+            "(I)Lio/github/gedgygedgy/rust/future/Future;",
+        )?;
         let discover_services = env.get_method_id(
             class,
             "discoverServices",
             "()Lio/github/gedgygedgy/rust/future/Future;",
         )?;
+        let clear_gatt_cache = env.get_method_id(
+            class,
+            "clearGattCache",
+            "()Lio/github/gedgygedgy/rust/future/Future;",
+        )?;
         let read = env.get_method_id(
             class,
             "read",
-            "(Ljava/util/UUID;)Lio/github/gedgygedgy/rust/future/Future;",
+            "(Ljava/util/UUID;I)Lio/github/gedgygedgy/rust/future/Future;",
         )?;
         let write = env.get_method_id(
             class,
             "write",
-            "(Ljava/util/UUID;[BI)Lio/github/gedgygedgy/rust/future/Future;",
+            "(Ljava/util/UUID;I[BI)Lio/github/gedgygedgy/rust/future/Future;",
+        )?;
+        let execute_reliable_write = env.get_method_id(
+            class,
+            "executeReliableWrite",
+            "([Ljava/util/UUID;[I[[B)Lio/github/gedgygedgy/rust/future/Future;",
         )?;
         let set_characteristic_notification = env.get_method_id(
             class,
             "setCharacteristicNotification",
-            "(Ljava/util/UUID;Z)Lio/github/gedgygedgy/rust/future/Future;",
+            "(Ljava/util/UUID;IZ)Lio/github/gedgygedgy/rust/future/Future;",
         )?;
         let get_notifications = env.get_method_id(
             class,
@@ -95,21 +118,26 @@ impl<'a: 'b, 'b> JPeripheral<'a, 'b> {
         let read_descriptor = env.get_method_id(
             class,
             "readDescriptor",
-            "(Ljava/util/UUID;Ljava/util/UUID;)Lio/github/gedgygedgy/rust/future/Future;",
+            "(Ljava/util/UUID;ILjava/util/UUID;)Lio/github/gedgygedgy/rust/future/Future;",
         )?;
         let write_descriptor = env.get_method_id(
             class,
             "writeDescriptor",
-            "(Ljava/util/UUID;Ljava/util/UUID;[BI)Lio/github/gedgygedgy/rust/future/Future;",
+            "(Ljava/util/UUID;ILjava/util/UUID;[BI)Lio/github/gedgygedgy/rust/future/Future;",
         )?;
         Ok(Self {
             internal: obj,
             connect,
             disconnect,
             is_connected,
+            get_mtu,
+            request_connection_priority,
+            request_mtu,
             discover_services,
+            clear_gatt_cache,
             read,
             write,
+            execute_reliable_write,
             set_characteristic_notification,
             get_notifications,
             read_descriptor,
@@ -174,6 +202,41 @@ impl<'a: 'b, 'b> JPeripheral<'a, 'b> {
             .z()
     }
 
+    pub fn get_mtu(&self) -> Result<jint> {
+        self.env
+            .call_method_unchecked(
+                self.internal,
+                self.get_mtu,
+                JavaType::Primitive(Primitive::Int),
+                &[],
+            )?
+            .i()
+    }
+
+    pub fn request_connection_priority(&self, priority: jint) -> Result<bool> {
+        self.env
+            .call_method_unchecked(
+                self.internal,
+                self.request_connection_priority,
+                JavaType::Primitive(Primitive::Boolean),
+                &[priority.into()],
+            )?
+            .z()
+    }
+
+    pub fn request_mtu(&self, mtu: jint) -> Result<JFuture<'a, 'b>> {
+        let future_obj = self
+            .env
+            .call_method_unchecked(
+                self.internal,
+                self.request_mtu,
+                JavaType::Object("Lio/github/gedgygedgy/rust/future/Future;".to_string()),
+                &[mtu.into()],
+            )?
+            .l()?;
+        JFuture::from_env(self.env, future_obj)
+    }
+
     pub fn discover_services(&self) -> Result<JFuture<'a, 'b>> {
         let future_obj = self
             .env
@@ -187,14 +250,27 @@ impl<'a: 'b, 'b> JPeripheral<'a, 'b> {
         JFuture::from_env(self.env, future_obj)
     }
 
-    pub fn read(&self, uuid: JUuid<'a, 'b>) -> Result<JFuture<'a, 'b>> {
+    pub fn clear_gatt_cache(&self) -> Result<JFuture<'a, 'b>> {
+        let future_obj = self
+            .env
+            .call_method_unchecked(
+                self.internal,
+                self.clear_gatt_cache,
+                JavaType::Object("Lio/github/gedgygedgy/rust/future/Future;".to_string()),
+                &[],
+            )?
+            .l()?;
+        JFuture::from_env(self.env, future_obj)
+    }
+
+    pub fn read(&self, uuid: JUuid<'a, 'b>, instance_id: jint) -> Result<JFuture<'a, 'b>> {
         let future_obj = self
             .env
             .call_method_unchecked(
                 self.internal,
                 self.read,
                 JavaType::Object("Lio/github/gedgygedgy/rust/future/Future;".to_string()),
-                &[uuid.into()],
+                &[uuid.into(), instance_id.into()],
             )?
             .l()?;
         JFuture::from_env(self.env, future_obj)
@@ -203,6 +279,7 @@ impl<'a: 'b, 'b> JPeripheral<'a, 'b> {
     pub fn write(
         &self,
         uuid: JUuid<'a, 'b>,
+        instance_id: jint,
         data: JObject<'a>,
         write_type: jint,
     ) -> Result<JFuture<'a, 'b>> {
@@ -212,7 +289,30 @@ impl<'a: 'b, 'b> JPeripheral<'a, 'b> {
                 self.internal,
                 self.write,
                 JavaType::Object("Lio/github/gedgygedgy/rust/future/Future;".to_string()),
-                &[uuid.into(), data.into(), write_type.into()],
+                &[
+                    uuid.into(),
+                    instance_id.into(),
+                    data.into(),
+                    write_type.into(),
+                ],
+            )?
+            .l()?;
+        JFuture::from_env(self.env, future_obj)
+    }
+
+    pub fn execute_reliable_write(
+        &self,
+        uuids: JObject<'a>,
+        instance_ids: JObject<'a>,
+        data: JObject<'a>,
+    ) -> Result<JFuture<'a, 'b>> {
+        let future_obj = self
+            .env
+            .call_method_unchecked(
+                self.internal,
+                self.execute_reliable_write,
+                JavaType::Object("Lio/github/gedgygedgy/rust/future/Future;".to_string()),
+                &[uuids.into(), instance_ids.into(), data.into()],
             )?
             .l()?;
         JFuture::from_env(self.env, future_obj)
@@ -221,6 +321,7 @@ impl<'a: 'b, 'b> JPeripheral<'a, 'b> {
     pub fn set_characteristic_notification(
         &self,
         uuid: JUuid<'a, 'b>,
+        instance_id: jint,
         enable: bool,
     ) -> Result<JFuture<'a, 'b>> {
         let future_obj = self
@@ -229,7 +330,7 @@ impl<'a: 'b, 'b> JPeripheral<'a, 'b> {
                 self.internal,
                 self.set_characteristic_notification,
                 JavaType::Object("Lio/github/gedgygedgy/rust/future/Future;".to_string()),
-                &[uuid.into(), enable.into()],
+                &[uuid.into(), instance_id.into(), enable.into()],
             )?
             .l()?;
         JFuture::from_env(self.env, future_obj)
@@ -251,6 +352,7 @@ impl<'a: 'b, 'b> JPeripheral<'a, 'b> {
     pub fn read_descriptor(
         &self,
         characteristic: JUuid<'a, 'b>,
+        characteristic_instance_id: jint,
         uuid: JUuid<'a, 'b>,
     ) -> Result<JFuture<'a, 'b>> {
         let future_obj = self
@@ -259,7 +361,11 @@ impl<'a: 'b, 'b> JPeripheral<'a, 'b> {
                 self.internal,
                 self.read_descriptor,
                 JavaType::Object("Lio/github/gedgygedgy/rust/future/Future;".to_string()),
-                &[characteristic.into(), uuid.into()],
+                &[
+                    characteristic.into(),
+                    characteristic_instance_id.into(),
+                    uuid.into(),
+                ],
             )?
             .l()?;
         JFuture::from_env(self.env, future_obj)
@@ -268,6 +374,7 @@ impl<'a: 'b, 'b> JPeripheral<'a, 'b> {
     pub fn write_descriptor(
         &self,
         characteristic: JUuid<'a, 'b>,
+        characteristic_instance_id: jint,
         uuid: JUuid<'a, 'b>,
         data: JObject<'a>,
     ) -> Result<JFuture<'a, 'b>> {
@@ -277,7 +384,12 @@ impl<'a: 'b, 'b> JPeripheral<'a, 'b> {
                 self.internal,
                 self.write_descriptor,
                 JavaType::Object("Lio/github/gedgygedgy/rust/future/Future;".to_string()),
-                &[characteristic.into(), uuid.into(), data.into()],
+                &[
+                    characteristic.into(),
+                    characteristic_instance_id.into(),
+                    uuid.into(),
+                    data.into(),
+                ],
             )?
             .l()?;
         JFuture::from_env(self.env, future_obj)
@@ -287,6 +399,7 @@ impl<'a: 'b, 'b> JPeripheral<'a, 'b> {
 pub struct JBluetoothGattService<'a: 'b, 'b> {
     internal: JObject<'a>,
     get_uuid: JMethodID<'a>,
+    get_instance_id: JMethodID<'a>,
     //is_primary: JMethodID<'a>,
     get_characteristics: JMethodID<'a>,
     env: &'b JNIEnv<'a>,
@@ -297,12 +410,14 @@ impl<'a: 'b, 'b> JBluetoothGattService<'a, 'b> {
         let class = env.auto_local(env.find_class("android/bluetooth/BluetoothGattService")?);
 
         let get_uuid = env.get_method_id(&class, "getUuid", "()Ljava/util/UUID;")?;
+        let get_instance_id = env.get_method_id(&class, "getInstanceId", "()I")?;
         //let is_primary = env.get_method_id(&class, "isPrimary", "()Z;")?;
         let get_characteristics =
             env.get_method_id(&class, "getCharacteristics", "()Ljava/util/List;")?;
         Ok(Self {
             internal: obj,
             get_uuid,
+            get_instance_id,
             //is_primary,
             get_characteristics,
             env,
@@ -337,6 +452,19 @@ impl<'a: 'b, 'b> JBluetoothGattService<'a, 'b> {
         Ok(uuid_obj.as_uuid()?)
     }
 
+    pub fn get_instance_id(&self) -> Result<u16> {
+        let instance_id = self
+            .env
+            .call_method_unchecked(
+                self.internal,
+                self.get_instance_id,
+                JavaType::Primitive(Primitive::Int),
+                &[],
+            )?
+            .i()?;
+        Ok(instance_id as u16)
+    }
+
     pub fn get_characteristics(&self) -> Result<Vec<JBluetoothGattCharacteristic>> {
         let obj = self
             .env
@@ -359,9 +487,11 @@ impl<'a: 'b, 'b> JBluetoothGattService<'a, 'b> {
 pub struct JBluetoothGattCharacteristic<'a: 'b, 'b> {
     internal: JObject<'a>,
     get_uuid: JMethodID<'a>,
+    get_instance_id: JMethodID<'a>,
     get_properties: JMethodID<'a>,
     get_value: JMethodID<'a>,
     get_descriptors: JMethodID<'a>,
+    get_service: JMethodID<'a>,
     env: &'b JNIEnv<'a>,
 }
 
@@ -371,19 +501,40 @@ impl<'a: 'b, 'b> JBluetoothGattCharacteristic<'a, 'b> {
             env.auto_local(env.find_class("android/bluetooth/BluetoothGattCharacteristic")?);
 
         let get_uuid = env.get_method_id(&class, "getUuid", "()Ljava/util/UUID;")?;
+        let get_instance_id = env.get_method_id(&class, "getInstanceId", "()I")?;
         let get_properties = env.get_method_id(&class, "getProperties", "()I")?;
         let get_descriptors = env.get_method_id(&class, "getDescriptors", "()Ljava/util/List;")?;
         let get_value = env.get_method_id(&class, "getValue", "()[B")?;
+        let get_service = env.get_method_id(
+            &class,
+            "getService",
+            "()Landroid/bluetooth/BluetoothGattService;",
+        )?;
         Ok(Self {
             internal: obj,
             get_uuid,
+            get_instance_id,
             get_properties,
             get_value,
             get_descriptors,
+            get_service,
             env,
         })
     }
 
+    pub fn get_service(&self) -> Result<JBluetoothGattService<'a, 'b>> {
+        let obj = self
+            .env
+            .call_method_unchecked(
+                self.internal,
+                self.get_service,
+                JavaType::Object("Landroid/bluetooth/BluetoothGattService;".to_string()),
+                &[],
+            )?
+            .l()?;
+        JBluetoothGattService::from_env(self.env, obj)
+    }
+
     pub fn get_uuid(&self) -> Result<Uuid> {
         let obj = self
             .env
@@ -398,6 +549,19 @@ impl<'a: 'b, 'b> JBluetoothGattCharacteristic<'a, 'b> {
         Ok(uuid_obj.as_uuid()?)
     }
 
+    pub fn get_instance_id(&self) -> Result<u16> {
+        let instance_id = self
+            .env
+            .call_method_unchecked(
+                self.internal,
+                self.get_instance_id,
+                JavaType::Primitive(Primitive::Int),
+                &[],
+            )?
+            .i()?;
+        Ok(instance_id as u16)
+    }
+
     pub fn get_properties(&self) -> Result<CharPropFlags> {
         let flags = self
             .env
@@ -443,6 +607,61 @@ impl<'a: 'b, 'b> JBluetoothGattCharacteristic<'a, 'b> {
     }
 }
 
+pub struct JNotification<'a: 'b, 'b> {
+    internal: JObject<'a>,
+    get_characteristic: JMethodID<'a>,
+    get_value: JMethodID<'a>,
+    env: &'b JNIEnv<'a>,
+}
+
+impl<'a: 'b, 'b> JNotification<'a, 'b> {
+    pub fn from_env(env: &'b JNIEnv<'a>, obj: JObject<'a>) -> Result<Self> {
+        let class_static = jni_utils::classcache::get_class(
+            "com/nonpolynomial/btleplug/android/impl/Notification",
+        )
+        .unwrap();
+        let class = JClass::from(class_static.as_obj());
+        let get_characteristic = env.get_method_id(
+            class,
+            "getCharacteristic",
+            "()Landroid/bluetooth/BluetoothGattCharacteristic;",
+        )?;
+        let get_value = env.get_method_id(class, "getValue", "()[B")?;
+        Ok(Self {
+            internal: obj,
+            get_characteristic,
+            get_value,
+            env,
+        })
+    }
+
+    pub fn get_characteristic(&self) -> Result<JBluetoothGattCharacteristic<'a, 'b>> {
+        let obj = self
+            .env
+            .call_method_unchecked(
+                self.internal,
+                self.get_characteristic,
+                JavaType::Object("Landroid/bluetooth/BluetoothGattCharacteristic;".to_string()),
+                &[],
+            )?
+            .l()?;
+        JBluetoothGattCharacteristic::from_env(self.env, obj)
+    }
+
+    pub fn get_value(&self) -> Result<Vec<u8>> {
+        let value = self
+            .env
+            .call_method_unchecked(
+                self.internal,
+                self.get_value,
+                JavaType::Array(JavaType::Primitive(Primitive::Byte).into()),
+                &[],
+            )?
+            .l()?;
+        jni_utils::arrays::byte_array_to_vec(self.env, value.into_inner())
+    }
+}
+
 pub struct JBluetoothGattDescriptor<'a: 'b, 'b> {
     internal: JObject<'a>,
     get_uuid: JMethodID<'a>,
@@ -479,6 +698,7 @@ impl<'a: 'b, 'b> JBluetoothGattDescriptor<'a, 'b> {
 pub struct JBluetoothDevice<'a: 'b, 'b> {
     internal: JObject<'a>,
     get_address: JMethodID<'a>,
+    get_type: JMethodID<'a>,
     env: &'b JNIEnv<'a>,
 }
 
@@ -487,9 +707,11 @@ impl<'a: 'b, 'b> JBluetoothDevice<'a, 'b> {
         let class = env.auto_local(env.find_class("android/bluetooth/BluetoothDevice")?);
 
         let get_address = env.get_method_id(&class, "getAddress", "()Ljava/lang/String;")?;
+        let get_type = env.get_method_id(&class, "getType", "()I")?;
         Ok(Self {
             internal: obj,
             get_address,
+            get_type,
             env,
         })
     }
@@ -506,6 +728,17 @@ impl<'a: 'b, 'b> JBluetoothDevice<'a, 'b> {
             .l()?;
         Ok(obj.into())
     }
+
+    pub fn get_type(&self) -> Result<jint> {
+        self.env
+            .call_method_unchecked(
+                self.internal,
+                self.get_type,
+                JavaType::Primitive(Primitive::Int),
+                &[],
+            )?
+            .i()
+    }
 }
 
 pub struct JScanFilter<'a> {
@@ -642,6 +875,16 @@ impl<'a: 'b, 'b> TryFrom<JScanResult<'a, 'b>> for (BDAddr, Option<PeripheralProp
                 .map_err(|e| Self::Error::Other(e.into()))?,
         )?;
 
+        // Constants from BluetoothDevice documentation. The device got here through an LE scan, so
+        // treat an unknown type as LE.
+        const DEVICE_TYPE_CLASSIC: jint = 1;
+        const DEVICE_TYPE_DUAL: jint = 3;
+        let transport = match device.get_type()? {
+            DEVICE_TYPE_CLASSIC => Transport::BrEdr,
+            DEVICE_TYPE_DUAL => Transport::DualMode,
+            _ => Transport::Le,
+        };
+
         let record = result.get_scan_record()?;
         let record_obj: &JObject = &record;
         let properties = if result
@@ -736,9 +979,15 @@ impl<'a: 'b, 'b> TryFrom<JScanResult<'a, 'b>> for (BDAddr, Option<PeripheralProp
                 tx_power_level,
                 manufacturer_data,
                 service_data,
-                services,
+                advertised_services: services,
                 rssi,
+                smoothed_rssi: rssi,
                 class: None,
+                transport,
+                last_seen: Some(Instant::now()),
+                has_scan_response: None,
+                connectable: None,
+                advertising_sid: None,
             })
         };
         Ok((addr, properties))
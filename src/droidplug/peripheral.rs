@@ -1,21 +1,35 @@
 use crate::{
     api::{
-        self, BDAddr, Characteristic, Descriptor, PeripheralProperties, Service, ValueNotification,
-        WriteType,
+        self, max_write_len_for_mtu, read_gap_device_name,
+        trace::{self, traced_connect, traced_discover_services},
+        BDAddr, CentralEvent, Characteristic, ConnectOptions, Descriptor, DisconnectOptions,
+        ParsePeripheralIdError, PeripheralMetrics, PeripheralProperties,
+        PreferredConnectionParameters, Service, TraceEvent, ValueNotification, WriteType,
+        DEFAULT_ATT_MTU,
     },
-    Error, Result,
+    common::{
+        adapter_manager::AdapterManager,
+        connect_limit::ConnectLimit,
+        metrics::Metrics,
+        pending::PendingOperations,
+        rssi_history::RssiAverage,
+        subscriptions::{auto_subscribe, Subscriptions},
+    },
+    ConnectFailureReason, Error, Result,
 };
 use async_trait::async_trait;
 use futures::stream::Stream;
 use jni::{
     descriptors,
     objects::{GlobalRef, JList, JObject},
+    sys::jint,
     JNIEnv,
 };
 use jni_utils::{
     arrays::byte_array_to_vec, exceptions::try_block, future::JSendFuture, stream::JSendStream,
     task::JPollResult, uuid::JUuid,
 };
+use log::{debug, trace};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "serde")]
@@ -25,12 +39,13 @@ use std::{
     convert::TryFrom,
     fmt::{self, Debug, Display, Formatter},
     pin::Pin,
-    sync::{Arc, Mutex},
+    str::FromStr,
+    sync::{Arc, Mutex, Weak},
 };
 
 use super::jni::{
     global_jvm,
-    objects::{JBluetoothGattCharacteristic, JBluetoothGattService, JPeripheral},
+    objects::{JBluetoothGattService, JNotification, JPeripheral},
 };
 use jni::objects::JClass;
 #[cfg_attr(
@@ -39,13 +54,24 @@ use jni::objects::JClass;
     serde(crate = "serde_cr")
 )]
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub struct PeripheralId(pub(super) BDAddr);
+pub struct PeripheralId(pub(crate) BDAddr);
 impl Display for PeripheralId {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         Display::fmt(&self.0, f)
     }
 }
 
+impl FromStr for PeripheralId {
+    type Err = ParsePeripheralIdError;
+
+    /// Parses an ID in the form it is displayed, the device's address like `00:11:22:33:44:55`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        BDAddr::from_str_delim(s)
+            .map(PeripheralId)
+            .map_err(|_| ParsePeripheralIdError::new(s, "an address like 00:11:22:33:44:55"))
+    }
+}
+
 fn get_poll_result<'a: 'b, 'b>(
     env: &'b JNIEnv<'a>,
     result: JPollResult<'a, 'b>,
@@ -74,6 +100,18 @@ fn get_poll_result<'a: 'b, 'b>(
                     ),
                 )? {
                     Ok(Err(Error::NotConnected))
+                } else if env.is_instance_of(
+                    cause,
+                    JClass::from(
+                        jni_utils::classcache::get_class(
+                            "com/nonpolynomial/btleplug/android/impl/ConnectionFailedException",
+                        )
+                        .unwrap()
+                        .as_obj(),
+                    ),
+                )? {
+                    let status = env.call_method(cause, "getStatus", "()I", &[])?.i()?;
+                    Ok(Err(connection_failed(status)))
                 } else if env.is_instance_of(
                     cause,
                     JClass::from(
@@ -138,10 +176,29 @@ fn get_poll_result<'a: 'b, 'b>(
         .result()?
 }
 
+/// Converts the status `BluetoothGattCallback.onConnectionStateChange` gave for a failed connection,
+/// which is either one of the GATT status codes or an HCI status code passed on from the
+/// controller.
+fn connection_failed(status: jint) -> Error {
+    // BluetoothGatt.GATT_CONNECTION_TIMEOUT
+    const GATT_CONNECTION_TIMEOUT: jint = 0x93;
+    let reason = match status {
+        GATT_CONNECTION_TIMEOUT => ConnectFailureReason::Timeout,
+        0..=0xff => ConnectFailureReason::from_hci_status(status as u8),
+        _ => ConnectFailureReason::Unknown,
+    };
+    Error::ConnectionFailed(
+        reason,
+        format!("Connection failed with status {:#04x}", status),
+    )
+}
+
 struct PeripheralShared {
     services: BTreeSet<Service>,
     characteristics: BTreeSet<Characteristic>,
     properties: Option<PeripheralProperties>,
+    default_write_type: Option<WriteType>,
+    rssi_average: RssiAverage,
 }
 
 #[derive(Clone)]
@@ -149,10 +206,21 @@ pub struct Peripheral {
     addr: BDAddr,
     internal: GlobalRef,
     shared: Arc<Mutex<PeripheralShared>>,
+    pending: Arc<PendingOperations>,
+    metrics: Arc<Metrics>,
+    subscriptions: Arc<Subscriptions>,
+    connect_limit: Arc<ConnectLimit>,
+    manager: Weak<AdapterManager<Peripheral>>,
 }
 
 impl Peripheral {
-    pub(crate) fn new(env: &JNIEnv, adapter: JObject, addr: BDAddr) -> Result<Self> {
+    pub(crate) fn new(
+        env: &JNIEnv,
+        adapter: JObject,
+        addr: BDAddr,
+        connect_limit: Arc<ConnectLimit>,
+        manager: Weak<AdapterManager<Peripheral>>,
+    ) -> Result<Self> {
         let obj = JPeripheral::new(env, adapter, addr)?;
         Ok(Self {
             addr,
@@ -161,13 +229,33 @@ impl Peripheral {
                 services: BTreeSet::new(),
                 characteristics: BTreeSet::new(),
                 properties: None,
+                default_write_type: None,
+                rssi_average: RssiAverage::default(),
             })),
+            pending: Default::default(),
+            metrics: Default::default(),
+            subscriptions: Default::default(),
+            connect_limit,
+            manager,
         })
     }
 
-    pub(crate) fn report_properties(&self, mut properties: PeripheralProperties) {
+    fn emit_event(&self, event: CentralEvent) {
+        if let Some(manager) = self.manager.upgrade() {
+            manager.emit(event);
+        } else {
+            trace!("Could not emit an event. AdapterManager has been dropped");
+        }
+    }
+
+    /// Records newly advertised properties, adding their signal strength to the smoothed one with
+    /// the given weight in percent.
+    pub(crate) fn report_properties(&self, mut properties: PeripheralProperties, rssi_weight: u8) {
         let mut guard = self.shared.lock().unwrap();
 
+        if let Some(rssi) = properties.rssi {
+            properties.smoothed_rssi = Some(guard.rssi_average.add(rssi, rssi_weight));
+        }
         guard.properties = Some(properties);
     }
 
@@ -183,6 +271,16 @@ impl Peripheral {
         f(&env, obj)
     }
 
+    /// Returns [`Error::NotConnected`] unless the device is connected, so that an operation
+    /// attempted after it has disconnected fails the same way on every platform.
+    fn ensure_connected(&self) -> Result<()> {
+        if self.with_obj(|_env, obj| obj.is_connected())? {
+            Ok(())
+        } else {
+            Err(Error::NotConnected)
+        }
+    }
+
     async fn set_characteristic_notification(
         &self,
         characteristic: &Characteristic,
@@ -190,7 +288,11 @@ impl Peripheral {
     ) -> Result<()> {
         let future = self.with_obj(|env, obj| {
             let uuid_obj = JUuid::new(env, characteristic.uuid)?;
-            JSendFuture::try_from(obj.set_characteristic_notification(uuid_obj, enable)?)
+            JSendFuture::try_from(obj.set_characteristic_notification(
+                uuid_obj,
+                characteristic.instance_id.into(),
+                enable,
+            )?)
         })?;
         let result_ref = future.await?;
         self.with_obj(|env, _obj| {
@@ -217,6 +319,62 @@ impl api::Peripheral for Peripheral {
         self.addr
     }
 
+    fn max_write_len(&self, write_type: WriteType) -> usize {
+        let mtu = self
+            .with_obj(|_env, obj| obj.get_mtu())
+            .ok()
+            .and_then(|mtu| u16::try_from(mtu).ok())
+            .unwrap_or(DEFAULT_ATT_MTU);
+        max_write_len_for_mtu(mtu, write_type)
+    }
+
+    async fn request_mtu(&self, mtu: u16) -> Result<u16> {
+        let future =
+            self.with_obj(|_env, obj| JSendFuture::try_from(obj.request_mtu(mtu.into())?))?;
+        let result_ref = future.await?;
+        self.with_obj(|env, _obj| {
+            let result = JPollResult::from_env(env, result_ref.as_obj())?;
+            let mtu = get_poll_result(env, result)?;
+            let mtu = env.call_method(mtu, "intValue", "()I", &[])?.i()?;
+            u16::try_from(mtu).map_err(|_| Error::RuntimeError(format!("Invalid MTU {}", mtu)))
+        })
+    }
+
+    fn pending_operations(&self) -> usize {
+        self.pending.operations()
+    }
+
+    fn pending_writes(&self) -> usize {
+        self.pending.writes()
+    }
+
+    fn abort_pending(&self) {
+        self.pending.abort();
+    }
+
+    fn metrics(&self) -> PeripheralMetrics {
+        let connected = self
+            .with_obj(|_env, obj| obj.is_connected())
+            .unwrap_or(false);
+        let mtu = connected
+            .then(|| self.with_obj(|_env, obj| obj.get_mtu()).ok())
+            .flatten()
+            .and_then(|mtu| u16::try_from(mtu).ok());
+        self.metrics.snapshot(mtu)
+    }
+
+    fn default_write_type(&self) -> Option<WriteType> {
+        self.shared.lock().unwrap().default_write_type
+    }
+
+    fn set_default_write_type(&self, write_type: WriteType) {
+        self.shared.lock().unwrap().default_write_type = Some(write_type);
+    }
+
+    fn subscriptions(&self) -> Vec<Characteristic> {
+        self.subscriptions.get()
+    }
+
     async fn properties(&self) -> Result<Option<PeripheralProperties>> {
         let guard = self.shared.lock().unwrap();
         Ok((&guard.properties).clone())
@@ -232,7 +390,40 @@ impl api::Peripheral for Peripheral {
     }
 
     async fn connect(&self) -> Result<()> {
-        let future = self.with_obj(|_env, obj| JSendFuture::try_from(obj.connect()?))?;
+        let _permit = self.connect_limit.acquire().await;
+        traced_connect(self, async {
+            let future = self.with_obj(|_env, obj| JSendFuture::try_from(obj.connect()?))?;
+            let result_ref = future.await?;
+            self.with_obj(|env, _obj| {
+                let result = JPollResult::from_env(env, result_ref.as_obj())?;
+                get_poll_result(env, result).map(|_| {})
+            })
+        })
+        .await
+        .inspect(|()| self.metrics.connected())
+    }
+
+    async fn connect_with_options(&self, options: ConnectOptions) -> Result<()> {
+        self.connect().await?;
+        if let Some(parameters) = options.preferred_parameters {
+            // The values of BluetoothGatt's CONNECTION_PRIORITY_* constants.
+            let priority: jint = match parameters {
+                PreferredConnectionParameters::Balanced => 0,
+                PreferredConnectionParameters::ThroughputOptimized => 1,
+                PreferredConnectionParameters::PowerOptimized => 2,
+            };
+            let requested =
+                self.with_obj(|_env, obj| Ok(obj.request_connection_priority(priority)?))?;
+            if !requested {
+                debug!("Could not request connection priority {:?}", parameters);
+            }
+        }
+        auto_subscribe(self, &options.auto_subscribe).await;
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        let future = self.with_obj(|_env, obj| JSendFuture::try_from(obj.disconnect()?))?;
         let result_ref = future.await?;
         self.with_obj(|env, _obj| {
             let result = JPollResult::from_env(env, result_ref.as_obj())?;
@@ -240,8 +431,19 @@ impl api::Peripheral for Peripheral {
         })
     }
 
-    async fn disconnect(&self) -> Result<()> {
-        let future = self.with_obj(|_env, obj| JSendFuture::try_from(obj.disconnect()?))?;
+    async fn disconnect_with_options(&self, options: DisconnectOptions) -> Result<()> {
+        self.disconnect().await?;
+        if let Some(reason) = options.reason {
+            self.emit_event(CentralEvent::DeviceDisconnectedWithReason {
+                id: self.id(),
+                reason,
+            });
+        }
+        Ok(())
+    }
+
+    async fn clear_gatt_cache(&self) -> Result<()> {
+        let future = self.with_obj(|_env, obj| JSendFuture::try_from(obj.clear_gatt_cache()?))?;
         let result_ref = future.await?;
         self.with_obj(|env, _obj| {
             let result = JPollResult::from_env(env, result_ref.as_obj())?;
@@ -257,53 +459,63 @@ impl api::Peripheral for Peripheral {
     }
 
     async fn discover_services(&self) -> Result<()> {
-        let future = self.with_obj(|_env, obj| JSendFuture::try_from(obj.discover_services()?))?;
-        let result_ref = future.await?;
-        self.with_obj(|env, _obj| {
-            use std::iter::FromIterator;
+        traced_discover_services(self, async {
+            let future =
+                self.with_obj(|_env, obj| JSendFuture::try_from(obj.discover_services()?))?;
+            let result_ref = future.await?;
+            self.with_obj(|env, _obj| {
+                use std::iter::FromIterator;
 
-            let result = JPollResult::from_env(env, result_ref.as_obj())?;
-            let obj = get_poll_result(env, result)?;
-            let list = JList::from_env(env, obj)?;
-            let mut peripheral_services = Vec::new();
-            let mut peripheral_characteristics = Vec::new();
-
-            for service in list.iter()? {
-                let service = JBluetoothGattService::from_env(env, service)?;
-                let mut characteristics = BTreeSet::new();
-                for characteristic in service.get_characteristics()? {
-                    let mut descriptors = BTreeSet::new();
-                    for descriptor in characteristic.get_descriptors()? {
-                        descriptors.insert(Descriptor {
-                            uuid: descriptor.get_uuid()?,
+                let result = JPollResult::from_env(env, result_ref.as_obj())?;
+                let obj = get_poll_result(env, result)?;
+                let list = JList::from_env(env, obj)?;
+                let mut peripheral_services = Vec::new();
+                let mut peripheral_characteristics = Vec::new();
+
+                for service in list.iter()? {
+                    let service = JBluetoothGattService::from_env(env, service)?;
+                    let mut characteristics = BTreeSet::new();
+                    for characteristic in service.get_characteristics()? {
+                        let mut descriptors = BTreeSet::new();
+                        let instance_id = characteristic.get_instance_id()?;
+                        for descriptor in characteristic.get_descriptors()? {
+                            descriptors.insert(Descriptor {
+                                uuid: descriptor.get_uuid()?,
+                                service_uuid: service.get_uuid()?,
+                                characteristic_uuid: characteristic.get_uuid()?,
+                                characteristic_instance_id: instance_id,
+                            });
+                        }
+                        characteristics.insert(Characteristic {
+                            service_uuid: service.get_uuid()?,
+                            uuid: characteristic.get_uuid()?,
+                            instance_id,
+                            properties: characteristic.get_properties()?,
+                            descriptors: descriptors.clone(),
+                        });
+                        peripheral_characteristics.push(Characteristic {
                             service_uuid: service.get_uuid()?,
-                            characteristic_uuid: characteristic.get_uuid()?,
+                            uuid: characteristic.get_uuid()?,
+                            instance_id,
+                            properties: characteristic.get_properties()?,
+                            descriptors: descriptors,
                         });
                     }
-                    characteristics.insert(Characteristic {
-                        service_uuid: service.get_uuid()?,
-                        uuid: characteristic.get_uuid()?,
-                        properties: characteristic.get_properties()?,
-                        descriptors: descriptors.clone(),
-                    });
-                    peripheral_characteristics.push(Characteristic {
-                        service_uuid: service.get_uuid()?,
-                        uuid: characteristic.get_uuid()?,
-                        properties: characteristic.get_properties()?,
-                        descriptors: descriptors,
-                    });
+                    peripheral_services.push(Service {
+                        uuid: service.get_uuid()?,
+                        primary: service.is_primary()?,
+                        start_handle: Some(service.get_instance_id()?),
+                        end_handle: None,
+                        characteristics,
+                    })
                 }
-                peripheral_services.push(Service {
-                    uuid: service.get_uuid()?,
-                    primary: service.is_primary()?,
-                    characteristics,
-                })
-            }
-            let mut guard = self.shared.lock().unwrap();
-            guard.services = BTreeSet::from_iter(peripheral_services.clone());
-            guard.characteristics = BTreeSet::from_iter(peripheral_characteristics.clone());
-            Ok(())
+                let mut guard = self.shared.lock().unwrap();
+                guard.services = BTreeSet::from_iter(peripheral_services.clone());
+                guard.characteristics = BTreeSet::from_iter(peripheral_characteristics.clone());
+                Ok(())
+            })
         })
+        .await
     }
 
     async fn write(
@@ -312,57 +524,156 @@ impl api::Peripheral for Peripheral {
         data: &[u8],
         write_type: WriteType,
     ) -> Result<()> {
-        let future = self.with_obj(|env, obj| {
-            let uuid = JUuid::new(env, characteristic.uuid)?;
-            let data_obj = jni_utils::arrays::slice_to_byte_array(env, data)?;
-            let write_type = match write_type {
-                WriteType::WithResponse => 2,
-                WriteType::WithoutResponse => 1,
-            };
-            JSendFuture::try_from(obj.write(uuid, data_obj.into(), write_type)?)
-        })?;
-        let result_ref = future.await?;
-        self.with_obj(|env, _obj| {
-            let result = JPollResult::from_env(env, result_ref.as_obj())?;
-            get_poll_result(env, result).map(|_| {})
-        })
+        self.ensure_connected()?;
+        self.pending
+            .track_write(async {
+                let future = self.with_obj(|env, obj| {
+                    let uuid = JUuid::new(env, characteristic.uuid)?;
+                    let data_obj = jni_utils::arrays::slice_to_byte_array(env, data)?;
+                    let write_type = match write_type {
+                        // BluetoothGattCharacteristic.WRITE_TYPE_*
+                        WriteType::WithResponse => 2,
+                        WriteType::WithoutResponse => 1,
+                        WriteType::SignedWithoutResponse => 4,
+                    };
+                    JSendFuture::try_from(obj.write(
+                        uuid,
+                        characteristic.instance_id.into(),
+                        data_obj.into(),
+                        write_type,
+                    )?)
+                })?;
+                let result_ref = future.await?;
+                self.with_obj(|env, _obj| {
+                    let result = JPollResult::from_env(env, result_ref.as_obj())?;
+                    get_poll_result(env, result).map(|_| {})
+                })
+            })
+            .await
+            .inspect(|()| self.metrics.written(data.len()))
+    }
+
+    async fn execute_reliable_write(&self, writes: &[(Characteristic, Vec<u8>)]) -> Result<()> {
+        self.ensure_connected()?;
+        self.pending
+            .track_write(async {
+                let future = self.with_obj(|env, obj| {
+                    let len = writes.len() as jint;
+                    let uuids = env.new_object_array(len, "java/util/UUID", JObject::null())?;
+                    let instance_ids = env.new_int_array(len)?;
+                    let data = env.new_object_array(len, "[B", JObject::null())?;
+                    for (idx, (characteristic, value)) in writes.iter().enumerate() {
+                        let idx = idx as jint;
+                        let uuid = JUuid::new(env, characteristic.uuid)?;
+                        env.set_object_array_element(uuids, idx, uuid)?;
+                        env.set_int_array_region(
+                            instance_ids,
+                            idx,
+                            &[characteristic.instance_id.into()],
+                        )?;
+                        let value = jni_utils::arrays::slice_to_byte_array(env, value)?;
+                        env.set_object_array_element(data, idx, value)?;
+                    }
+                    JSendFuture::try_from(obj.execute_reliable_write(
+                        uuids.into(),
+                        instance_ids.into(),
+                        data.into(),
+                    )?)
+                })?;
+                let result_ref = future.await?;
+                self.with_obj(|env, _obj| {
+                    let result = JPollResult::from_env(env, result_ref.as_obj())?;
+                    get_poll_result(env, result).map(|_| {})
+                })
+            })
+            .await
+            .inspect(|()| {
+                let len = writes.iter().map(|(_, value)| value.len()).sum();
+                self.metrics.written(len)
+            })
     }
 
     async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
-        let future = self.with_obj(|env, obj| {
-            let uuid = JUuid::new(env, characteristic.uuid)?;
-            JSendFuture::try_from(obj.read(uuid)?)
-        })?;
-        let result_ref = future.await?;
-        self.with_obj(|env, _obj| {
-            let result = JPollResult::from_env(env, result_ref.as_obj())?;
-            let bytes = get_poll_result(env, result)?;
-            Ok(byte_array_to_vec(env, bytes.into_inner())?)
-        })
+        self.ensure_connected()?;
+        self.pending
+            .track(async {
+                let future = self.with_obj(|env, obj| {
+                    let uuid = JUuid::new(env, characteristic.uuid)?;
+                    JSendFuture::try_from(obj.read(uuid, characteristic.instance_id.into())?)
+                })?;
+                let result_ref = future.await?;
+                self.with_obj(|env, _obj| {
+                    let result = JPollResult::from_env(env, result_ref.as_obj())?;
+                    let bytes = get_poll_result(env, result)?;
+                    Ok(byte_array_to_vec(env, bytes.into_inner())?)
+                })
+            })
+            .await
+            .inspect(|value| self.metrics.read(value.len()))
+    }
+
+    async fn read_device_name(&self) -> Result<String> {
+        let name = read_gap_device_name(self).await?;
+        let mut guard = self.shared.lock().unwrap();
+        if let Some(properties) = &mut guard.properties {
+            properties.local_name = Some(name.clone());
+        }
+        Ok(name)
     }
 
     async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        self.set_characteristic_notification(characteristic, true)
+        self.ensure_connected()?;
+        self.subscriptions
+            .subscribe(
+                characteristic,
+                self.pending.track(async {
+                    self.set_characteristic_notification(characteristic, true)
+                        .await
+                }),
+            )
             .await
     }
 
     async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        self.set_characteristic_notification(characteristic, false)
+        self.ensure_connected()?;
+        self.subscriptions
+            .unsubscribe(
+                characteristic,
+                self.pending.track(async {
+                    self.set_characteristic_notification(characteristic, false)
+                        .await
+                }),
+            )
             .await
     }
 
     async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
         use futures::stream::StreamExt;
         let stream = self.with_obj(|_env, obj| JSendStream::try_from(obj.get_notifications()?))?;
+        let addr = self.addr;
+        let metrics = self.metrics.clone();
         let stream = stream
-            .map(|item| match item {
+            .map(move |item| match item {
                 Ok(item) => {
                     let env = global_jvm().get_env()?;
                     let item = item.as_obj();
-                    let characteristic = JBluetoothGattCharacteristic::from_env(&env, item)?;
+                    let notification = JNotification::from_env(&env, item)?;
+                    let characteristic = notification.get_characteristic()?;
                     let uuid = characteristic.get_uuid()?;
-                    let value = characteristic.get_value()?;
-                    Ok(ValueNotification { uuid, value })
+                    let service_uuid = characteristic.get_service()?.get_uuid()?;
+                    let instance_id = characteristic.get_instance_id()?;
+                    let value = notification.get_value()?;
+                    trace::emit(|| TraceEvent::NotificationDelivered {
+                        id: PeripheralId(addr),
+                        characteristic: uuid,
+                    });
+                    metrics.notification();
+                    Ok(ValueNotification {
+                        uuid,
+                        service_uuid,
+                        instance_id,
+                        value,
+                    })
                 }
                 Err(err) => Err(err),
             })
@@ -371,30 +682,49 @@ impl api::Peripheral for Peripheral {
     }
 
     async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
-        let future = self.with_obj(|env, obj| {
-            let characteristic = JUuid::new(env, descriptor.characteristic_uuid)?;
-            let uuid = JUuid::new(env, descriptor.uuid)?;
-            let data_obj = jni_utils::arrays::slice_to_byte_array(env, data)?;
-            JSendFuture::try_from(obj.write_descriptor(characteristic, uuid, data_obj.into())?)
-        })?;
-        let result_ref = future.await?;
-        self.with_obj(|env, _obj| {
-            let result = JPollResult::from_env(env, result_ref.as_obj())?;
-            get_poll_result(env, result).map(|_| {})
-        })
+        self.ensure_connected()?;
+        self.pending
+            .track(async {
+                let future = self.with_obj(|env, obj| {
+                    let characteristic = JUuid::new(env, descriptor.characteristic_uuid)?;
+                    let uuid = JUuid::new(env, descriptor.uuid)?;
+                    let data_obj = jni_utils::arrays::slice_to_byte_array(env, data)?;
+                    JSendFuture::try_from(obj.write_descriptor(
+                        characteristic,
+                        descriptor.characteristic_instance_id.into(),
+                        uuid,
+                        data_obj.into(),
+                    )?)
+                })?;
+                let result_ref = future.await?;
+                self.with_obj(|env, _obj| {
+                    let result = JPollResult::from_env(env, result_ref.as_obj())?;
+                    get_poll_result(env, result).map(|_| {})
+                })
+            })
+            .await
     }
 
     async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
-        let future = self.with_obj(|env, obj| {
-            let characteristic = JUuid::new(env, descriptor.characteristic_uuid)?;
-            let uuid = JUuid::new(env, descriptor.uuid)?;
-            JSendFuture::try_from(obj.read_descriptor(characteristic, uuid)?)
-        })?;
-        let result_ref = future.await?;
-        self.with_obj(|env, _obj| {
-            let result = JPollResult::from_env(env, result_ref.as_obj())?;
-            let bytes = get_poll_result(env, result)?;
-            Ok(byte_array_to_vec(env, bytes.into_inner())?)
-        })
+        self.ensure_connected()?;
+        self.pending
+            .track(async {
+                let future = self.with_obj(|env, obj| {
+                    let characteristic = JUuid::new(env, descriptor.characteristic_uuid)?;
+                    let uuid = JUuid::new(env, descriptor.uuid)?;
+                    JSendFuture::try_from(obj.read_descriptor(
+                        characteristic,
+                        descriptor.characteristic_instance_id.into(),
+                        uuid,
+                    )?)
+                })?;
+                let result_ref = future.await?;
+                self.with_obj(|env, _obj| {
+                    let result = JPollResult::from_env(env, result_ref.as_obj())?;
+                    let bytes = get_poll_result(env, result)?;
+                    Ok(byte_array_to_vec(env, bytes.into_inner())?)
+                })
+            })
+            .await
     }
 }
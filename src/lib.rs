@@ -92,13 +92,16 @@ use std::time::Duration;
 pub mod api;
 #[cfg(target_os = "linux")]
 mod bluez;
-#[cfg(not(target_os = "linux"))]
 mod common;
 #[cfg(target_vendor = "apple")]
 mod corebluetooth;
 #[cfg(target_os = "android")]
 mod droidplug;
+#[cfg(feature = "mock")]
+pub mod mock;
 pub mod platform;
+#[cfg(feature = "profiles")]
+pub mod profiles;
 #[cfg(feature = "serde")]
 pub mod serde;
 #[cfg(target_os = "windows")]
@@ -110,6 +113,11 @@ pub enum Error {
     #[error("Permission denied")]
     PermissionDenied,
 
+    /// The device refused the operation because it requires an authenticated or encrypted link.
+    /// Pairing with the device and then retrying may succeed.
+    #[error("Device not paired")]
+    NotPaired,
+
     #[error("Device not found")]
     DeviceNotFound,
 
@@ -131,6 +139,16 @@ pub enum Error {
     #[error("Timed out after {:?}", _0)]
     TimedOut(Duration),
 
+    /// The operation was abandoned by [`Peripheral::abort_pending`](api::Peripheral::abort_pending)
+    /// before it completed.
+    #[error("Operation cancelled")]
+    Cancelled,
+
+    /// A connection attempt failed before the link was established. The reason is as specific as
+    /// the platform makes it, and the message has whatever detail it gave.
+    #[error("Connection failed ({:?}): {}", _0, _1)]
+    ConnectionFailed(ConnectFailureReason, String),
+
     #[error("Error parsing UUID: {0}")]
     Uuid(#[from] uuid::Error),
 
@@ -144,5 +162,44 @@ pub enum Error {
     Other(Box<dyn std::error::Error + Send + Sync>),
 }
 
+/// Why a connection attempt failed, for [`Error::ConnectionFailed`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum ConnectFailureReason {
+    /// The device didn't answer in time, e.g. because it is out of range or has stopped
+    /// advertising. Retrying later may succeed.
+    Timeout,
+    /// The device refused the connection, e.g. because it has no room for another or doesn't
+    /// accept this central. Retrying is unlikely to help.
+    Rejected,
+    /// The device or the local controller doesn't support something the connection needs.
+    UnsupportedFeature,
+    /// The attempt was cancelled on this side, e.g. by disconnecting while it was in progress.
+    Cancelled,
+    /// The platform gave no reason, or one which btleplug doesn't recognise.
+    Unknown,
+}
+
+impl ConnectFailureReason {
+    /// Classifies an HCI status code, as defined in Core Specification Vol 1, Part F, from a
+    /// controller event for the failed connection.
+    pub fn from_hci_status(status: u8) -> Self {
+        match status {
+            // Page Timeout, Connection Timeout, LMP/LL Response Timeout, Advertising Timeout and
+            // Connection Failed to be Established.
+            0x04 | 0x08 | 0x22 | 0x3c | 0x3e => ConnectFailureReason::Timeout,
+            // Connection Rejected due to Limited Resources, Security Reasons or Unacceptable
+            // BD_ADDR, and Remote User Terminated Connection, Low Resources or Power Off.
+            0x0d..=0x0f | 0x13..=0x15 => ConnectFailureReason::Rejected,
+            // Unsupported Feature or Parameter Value, Unsupported Remote Feature, Unsupported
+            // LMP/LL Parameter Value and Unacceptable Connection Parameters.
+            0x11 | 0x1a | 0x20 | 0x3b => ConnectFailureReason::UnsupportedFeature,
+            // Connection Terminated by Local Host and Operation Cancelled by Host.
+            0x16 | 0x44 => ConnectFailureReason::Cancelled,
+            _ => ConnectFailureReason::Unknown,
+        }
+    }
+}
+
 /// Convenience type for a result using the btleplug [`Error`] type.
 pub type Result<T> = result::Result<T, Error>;
@@ -0,0 +1,241 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{peripheral::MockPeripheral, peripheral_id};
+use crate::{
+    api::{
+        BDAddr, Central, CentralEvent, CentralState, PeripheralProperties, ScanFilter, ScanOptions,
+        ScanSubscription,
+    },
+    common::{
+        adapter_manager::AdapterManager,
+        scan_state::ScanState,
+        scan_subscriptions::{subscribe_scan, ScanSubscriptions},
+    },
+    platform::PeripheralId,
+    Error, Result,
+};
+use async_trait::async_trait;
+use futures::stream::Stream;
+use std::convert::TryFrom;
+use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+
+/// Implementation of [api::Central](crate::api::Central) backed by in-memory peripherals.
+#[derive(Clone, Debug)]
+pub struct MockCentral {
+    manager: Arc<AdapterManager<MockPeripheral>>,
+    state: Arc<Mutex<CentralState>>,
+    scan_filter: Arc<Mutex<Option<ScanFilter>>>,
+    scan_state: Arc<ScanState>,
+    next_address: Arc<AtomicU64>,
+    scans: Arc<ScanSubscriptions>,
+    /// Some while the adapter is discoverable, with when it stops being discoverable if it does.
+    discoverable: Arc<Mutex<Option<Option<Instant>>>>,
+    connectable: Arc<AtomicBool>,
+}
+
+impl MockCentral {
+    /// Creates a powered on central with no peripherals.
+    pub fn new() -> Self {
+        MockCentral {
+            manager: Arc::new(AdapterManager::default()),
+            state: Arc::new(Mutex::new(CentralState::PoweredOn)),
+            scan_filter: Arc::new(Mutex::new(None)),
+            scan_state: Default::default(),
+            next_address: Arc::new(AtomicU64::new(1)),
+            scans: Default::default(),
+            discoverable: Default::default(),
+            connectable: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Adds a peripheral with the given advertised properties and emits
+    /// [`CentralEvent::DeviceDiscovered`] for it, unless the current scan leaves it out: a scan for
+    /// [`ScanFilter::connectable_only`] devices leaves out those which aren't connectable, and one
+    /// with a [`ScanFilter::name_prefix`] those whose name doesn't start with it. If
+    /// `properties.address` is left at its default, a unique address is assigned.
+    ///
+    /// Panics if a peripheral with the same address has already been added.
+    pub fn add_mock_peripheral(&self, mut properties: PeripheralProperties) -> MockPeripheral {
+        if properties.address == BDAddr::default() {
+            let address = self.next_address.fetch_add(1, Ordering::Relaxed);
+            properties.address = BDAddr::try_from(address).unwrap();
+        }
+        let id = peripheral_id(properties.address);
+        let dropped = self.scan_filter().is_some_and(|filter| {
+            (filter.connectable_only && properties.connectable == Some(false))
+                || filter.name_prefix.is_some_and(|prefix| {
+                    !properties
+                        .local_name
+                        .as_ref()
+                        .is_some_and(|name| name.starts_with(&prefix))
+                })
+        });
+        let peripheral = MockPeripheral::new(
+            Arc::downgrade(&self.manager),
+            self.manager.connect_limit(),
+            id.clone(),
+            properties,
+        );
+        self.manager.add_peripheral(peripheral.clone());
+        if !dropped {
+            self.manager.emit(CentralEvent::DeviceDiscovered(id));
+        }
+        peripheral
+    }
+
+    /// Changes the state reported by [`Central::adapter_state`] and emits
+    /// [`CentralEvent::StateUpdate`].
+    pub fn set_adapter_state(&self, state: CentralState) {
+        *self.state.lock().unwrap() = state.clone();
+        self.manager.emit(CentralEvent::StateUpdate(state));
+    }
+
+    /// Emits an arbitrary event on this central's event stream.
+    pub fn emit(&self, event: CentralEvent) {
+        self.manager.emit(event);
+    }
+
+    /// The filter passed to the current scan, or `None` if not scanning.
+    pub fn scan_filter(&self) -> Option<ScanFilter> {
+        self.scan_filter.lock().unwrap().clone()
+    }
+}
+
+impl Default for MockCentral {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Central for MockCentral {
+    type Peripheral = MockPeripheral;
+
+    async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
+        Ok(self.manager.event_stream())
+    }
+
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        *self.scan_filter.lock().unwrap() = Some(filter.clone());
+        self.scan_state.started(filter);
+        self.manager.emit(CentralEvent::ScanStarted);
+        Ok(())
+    }
+
+    async fn start_scan_with_options(
+        &self,
+        filter: ScanFilter,
+        options: ScanOptions,
+    ) -> Result<()> {
+        options.check()?;
+        self.manager.rssi_smoothing().set(options.rssi_smoothing);
+        self.start_scan(filter).await
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        *self.scan_filter.lock().unwrap() = None;
+        self.scan_state.stopped();
+        self.manager.emit(CentralEvent::ScanStopped);
+        Ok(())
+    }
+
+    async fn pause_scan(&self) -> Result<()> {
+        if self.scan_state.is_running() {
+            self.scan_state.paused();
+            *self.scan_filter.lock().unwrap() = None;
+            self.manager.emit(CentralEvent::ScanStopped);
+        }
+        Ok(())
+    }
+
+    async fn resume_scan(&self) -> Result<()> {
+        if let Some(filter) = self.scan_state.paused_filter() {
+            self.scan_state.resumed();
+            *self.scan_filter.lock().unwrap() = Some(filter);
+            self.manager.emit(CentralEvent::ScanStarted);
+        }
+        Ok(())
+    }
+
+    async fn subscribe_scan(&self, filter: ScanFilter) -> Result<ScanSubscription> {
+        subscribe_scan(self, &self.scans, filter).await
+    }
+
+    async fn peripherals(&self) -> Result<Vec<MockPeripheral>> {
+        Ok(self.manager.peripherals())
+    }
+
+    async fn connected(&self) -> Result<Vec<MockPeripheral>> {
+        Ok(self.manager.connected_peripherals())
+    }
+
+    async fn peripheral(&self, id: &PeripheralId) -> Result<MockPeripheral> {
+        self.manager.peripheral(id).ok_or(Error::DeviceNotFound)
+    }
+
+    async fn add_peripheral(&self, id: &PeripheralId) -> Result<MockPeripheral> {
+        self.manager.peripheral(id).ok_or(Error::DeviceNotFound)
+    }
+
+    async fn adapter_info(&self) -> Result<String> {
+        Ok("Mock".to_string())
+    }
+
+    async fn adapter_state(&self) -> Result<CentralState> {
+        Ok(self.state.lock().unwrap().clone())
+    }
+
+    async fn is_scanning(&self) -> Result<bool> {
+        Ok(self.scan_filter.lock().unwrap().is_some())
+    }
+
+    async fn is_discoverable(&self) -> Result<bool> {
+        let discoverable = *self.discoverable.lock().unwrap();
+        Ok(match discoverable {
+            Some(Some(until)) => Instant::now() < until,
+            Some(None) => true,
+            None => false,
+        })
+    }
+
+    async fn set_discoverable(&self, discoverable: bool, timeout: Option<Duration>) -> Result<()> {
+        // Unlike BlueZ, the timeout isn't rounded to whole seconds, to keep tests quick.
+        *self.discoverable.lock().unwrap() =
+            discoverable.then(|| timeout.map(|timeout| Instant::now() + timeout));
+        Ok(())
+    }
+
+    async fn is_connectable(&self) -> Result<bool> {
+        Ok(self.connectable.load(Ordering::Relaxed))
+    }
+
+    async fn set_connectable(&self, connectable: bool) -> Result<()> {
+        self.connectable.store(connectable, Ordering::Relaxed);
+        if !connectable {
+            *self.discoverable.lock().unwrap() = None;
+        }
+        Ok(())
+    }
+
+    fn set_peripheral_ttl(&self, ttl: Option<Duration>) {
+        self.manager.set_peripheral_ttl(ttl);
+    }
+
+    fn set_max_peripherals(&self, max: Option<usize>) {
+        self.manager.set_max_peripherals(max);
+    }
+
+    fn set_max_concurrent_connects(&self, max: usize) {
+        self.manager.connect_limit().set_max(max);
+    }
+}
@@ -0,0 +1,43 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::adapter::MockCentral;
+use crate::{api, Result};
+use async_trait::async_trait;
+
+/// Implementation of [api::Manager](crate::api::Manager) which hands out [`MockCentral`]s.
+#[derive(Clone, Debug)]
+pub struct MockManager {
+    adapters: Vec<MockCentral>,
+}
+
+impl MockManager {
+    /// Creates a manager with a single, empty adapter.
+    pub fn new() -> Self {
+        Self::with_adapters(vec![MockCentral::new()])
+    }
+
+    /// Creates a manager which returns the given adapters.
+    pub fn with_adapters(adapters: Vec<MockCentral>) -> Self {
+        MockManager { adapters }
+    }
+}
+
+impl Default for MockManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl api::Manager for MockManager {
+    type Adapter = MockCentral;
+
+    async fn adapters(&self) -> Result<Vec<MockCentral>> {
+        Ok(self.adapters.clone())
+    }
+}
@@ -0,0 +1,2140 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! An in-memory backend for testing code built on btleplug without real hardware.
+//!
+//! A [`MockCentral`] implements [`Central`](crate::api::Central) and hands out
+//! [`MockPeripheral`]s, which implement [`Peripheral`](crate::api::Peripheral). Tests populate the
+//! central with peripherals, give each peripheral a GATT table and characteristic values, and then
+//! drive the code under test against them. Notifications and disconnects can be injected at any
+//! point, and the writes and subscriptions made by the code under test can be inspected afterwards.
+//!
+//! ```
+//! use btleplug::api::{
+//!     Central, CharPropFlags, Characteristic, Peripheral, PeripheralProperties, Service,
+//! };
+//! use btleplug::mock::MockCentral;
+//! use std::collections::BTreeSet;
+//! # use std::error::Error;
+//! use uuid::Uuid;
+//!
+//! # async fn example() -> Result<(), Box<dyn Error>> {
+//! const SERVICE: Uuid = Uuid::from_u128(0x4fafc201_1fb5_459e_8fcc_c5c9c331914b);
+//! const CHARACTERISTIC: Uuid = Uuid::from_u128(0xbeb5483e_36e1_4688_b7f5_ea07361b26a8);
+//!
+//! let central = MockCentral::new();
+//! let device = central.add_mock_peripheral(PeripheralProperties {
+//!     local_name: Some("Sensor".to_string()),
+//!     ..Default::default()
+//! });
+//! let characteristic = Characteristic {
+//!     uuid: CHARACTERISTIC,
+//!     service_uuid: SERVICE,
+//!     instance_id: 0,
+//!     properties: CharPropFlags::READ | CharPropFlags::NOTIFY,
+//!     descriptors: BTreeSet::new(),
+//! };
+//! device.add_service(Service {
+//!     uuid: SERVICE,
+//!     primary: true,
+//!     start_handle: None,
+//!     end_handle: None,
+//!     characteristics: [characteristic.clone()].into(),
+//! });
+//! device.set_value(&characteristic, vec![42]);
+//!
+//! // The code under test sees an ordinary central and peripheral.
+//! let peripheral = central.peripheral(&device.id()).await?;
+//! peripheral.connect().await?;
+//! peripheral.discover_services().await?;
+//! assert_eq!(peripheral.read(&characteristic).await?, vec![42]);
+//! # Ok(())
+//! # }
+//! ```
+
+mod adapter;
+mod manager;
+mod peripheral;
+
+pub use self::adapter::MockCentral;
+pub use self::manager::MockManager;
+pub use self::peripheral::{MockPeripheral, MockWrite};
+
+use crate::{api::BDAddr, platform::PeripheralId};
+
+/// Builds a [`PeripheralId`] for a mock device from its address, in whatever form the current
+/// platform uses for its IDs.
+#[cfg(target_os = "linux")]
+fn peripheral_id(address: BDAddr) -> PeripheralId {
+    format!("mock/dev_{}", address.to_string().replace(':', "_"))
+        .parse()
+        .expect("Mock device path should be a valid PeripheralId")
+}
+
+#[cfg(target_os = "windows")]
+fn peripheral_id(address: BDAddr) -> PeripheralId {
+    address.into()
+}
+
+#[cfg(target_vendor = "apple")]
+fn peripheral_id(address: BDAddr) -> PeripheralId {
+    uuid::Uuid::from_u128(u64::from(address).into()).into()
+}
+
+#[cfg(target_os = "android")]
+fn peripheral_id(address: BDAddr) -> PeripheralId {
+    PeripheralId(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{
+        bleuuid::uuid_from_u16, Central, CentralEvent, CentralState, CharPropFlags, Characteristic,
+        ClientSupportedFeatures, ConnectOptions, Descriptor, DisconnectReason, DiscoverOptions,
+        Manager as _, Peripheral, PeripheralMetrics, PeripheralProperties, ScanFilter, ScanOptions,
+        Service, SubscriptionKind, ValueNotification, WriteType,
+    };
+    use crate::Error;
+    use futures::stream::StreamExt;
+    use std::collections::BTreeSet;
+    use std::time::{Duration, Instant};
+    use uuid::Uuid;
+
+    const SERVICE: Uuid = Uuid::from_u128(0x4fafc201_1fb5_459e_8fcc_c5c9c331914b);
+    const CHARACTERISTIC: Uuid = Uuid::from_u128(0xbeb5483e_36e1_4688_b7f5_ea07361b26a8);
+    const DESCRIPTOR: Uuid = Uuid::from_u128(0x00002902_0000_1000_8000_00805f9b34fb);
+
+    fn characteristic(properties: CharPropFlags) -> Characteristic {
+        Characteristic {
+            uuid: CHARACTERISTIC,
+            service_uuid: SERVICE,
+            instance_id: 0,
+            properties,
+            descriptors: [Descriptor {
+                uuid: DESCRIPTOR,
+                service_uuid: SERVICE,
+                characteristic_uuid: CHARACTERISTIC,
+                characteristic_instance_id: 0,
+            }]
+            .into(),
+        }
+    }
+
+    fn device(central: &MockCentral, characteristic: &Characteristic) -> MockPeripheral {
+        let device = central.add_mock_peripheral(PeripheralProperties::default());
+        device.add_service(Service {
+            uuid: SERVICE,
+            primary: true,
+            start_handle: Some(0x0010),
+            end_handle: Some(0x0013),
+            characteristics: [characteristic.clone()].into(),
+        });
+        device
+    }
+
+    #[test]
+    fn peripheral_ids_are_unique_per_address() {
+        let a = peripheral_id(BDAddr::from([1, 2, 3, 4, 5, 6]));
+        let b = peripheral_id(BDAddr::from([1, 2, 3, 4, 5, 7]));
+        assert_eq!(a, peripheral_id(BDAddr::from([1, 2, 3, 4, 5, 6])));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn peripheral_ids_round_trip_through_strings() {
+        let id = peripheral_id(BDAddr::from([1, 2, 3, 4, 5, 6]));
+        assert_eq!(id.to_string().parse::<PeripheralId>(), Ok(id));
+        assert!("not an id".parse::<PeripheralId>().is_err());
+    }
+
+    #[tokio::test]
+    async fn manager_lists_adapters() {
+        let manager = MockManager::new();
+        let adapters = manager.adapters().await.unwrap();
+        assert_eq!(adapters.len(), 1);
+        assert_eq!(adapters[0].adapter_info().await.unwrap(), "Mock");
+    }
+
+    #[tokio::test]
+    async fn central_events() {
+        let central = MockCentral::new();
+        let mut events = central.events().await.unwrap();
+
+        central.start_scan(ScanFilter::default()).await.unwrap();
+        let device = central.add_mock_peripheral(PeripheralProperties::default());
+        central.stop_scan().await.unwrap();
+        central.set_adapter_state(CentralState::PoweredOff);
+
+        assert!(matches!(
+            events.next().await,
+            Some(CentralEvent::ScanStarted)
+        ));
+        assert!(
+            matches!(events.next().await, Some(CentralEvent::DeviceDiscovered(id)) if id == device.id())
+        );
+        assert!(matches!(
+            events.next().await,
+            Some(CentralEvent::ScanStopped)
+        ));
+        assert!(matches!(
+            events.next().await,
+            Some(CentralEvent::StateUpdate(CentralState::PoweredOff))
+        ));
+        assert_eq!(
+            central.adapter_state().await.unwrap(),
+            CentralState::PoweredOff
+        );
+        assert_eq!(central.scan_filter(), None);
+    }
+
+    #[tokio::test]
+    async fn discoveries_yield_each_device_once_per_scan() {
+        let central = MockCentral::new();
+        let mut discoveries = central.discoveries().await.unwrap();
+
+        central.start_scan(ScanFilter::default()).await.unwrap();
+        let first = central.add_mock_peripheral(PeripheralProperties::default());
+        first.set_properties(PeripheralProperties::default());
+        let second = central.add_mock_peripheral(PeripheralProperties::default());
+        central.stop_scan().await.unwrap();
+        central.start_scan(ScanFilter::default()).await.unwrap();
+        first.set_properties(PeripheralProperties {
+            rssi: Some(-40),
+            ..Default::default()
+        });
+
+        let (id, _) = discoveries.next().await.unwrap();
+        assert_eq!(id, first.id());
+        let (id, _) = discoveries.next().await.unwrap();
+        assert_eq!(id, second.id());
+        let (id, properties) = discoveries.next().await.unwrap();
+        assert_eq!(id, first.id());
+        assert_eq!(properties.rssi, Some(-40));
+    }
+
+    #[tokio::test]
+    async fn last_seen_is_updated_by_advertisements() {
+        let central = MockCentral::new();
+        let device = central.add_mock_peripheral(PeripheralProperties::default());
+        let first = device
+            .properties()
+            .await
+            .unwrap()
+            .unwrap()
+            .last_seen
+            .unwrap();
+
+        device.set_properties(PeripheralProperties {
+            rssi: Some(-40),
+            ..Default::default()
+        });
+        let second = device
+            .properties()
+            .await
+            .unwrap()
+            .unwrap()
+            .last_seen
+            .unwrap();
+        assert!(second >= first);
+
+        // A timestamp given explicitly is kept, so tests can simulate stale devices.
+        let stale = first - Duration::from_secs(60);
+        device.set_properties(PeripheralProperties {
+            last_seen: Some(stale),
+            ..Default::default()
+        });
+        assert_eq!(
+            device.properties().await.unwrap().unwrap().last_seen,
+            Some(stale)
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_peripherals_are_evicted() {
+        let central = MockCentral::new();
+        let stale = Some(Instant::now() - Duration::from_secs(60));
+        let gone = central.add_mock_peripheral(PeripheralProperties {
+            last_seen: stale,
+            ..Default::default()
+        });
+        let connected = central.add_mock_peripheral(PeripheralProperties {
+            last_seen: stale,
+            ..Default::default()
+        });
+        connected.connect().await.unwrap();
+        let fresh = central.add_mock_peripheral(PeripheralProperties::default());
+        let mut events = central.events().await.unwrap();
+
+        central.set_peripheral_ttl(Some(Duration::from_secs(30)));
+        let lost = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(CentralEvent::DeviceLost(id)) = events.next().await {
+                    break id;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(lost, gone.id());
+
+        let mut ids: Vec<_> = central
+            .peripherals()
+            .await
+            .unwrap()
+            .iter()
+            .map(|p| p.id())
+            .collect();
+        ids.sort();
+        let mut expected = vec![connected.id(), fresh.id()];
+        expected.sort();
+        assert_eq!(ids, expected);
+        central.set_peripheral_ttl(None);
+    }
+
+    #[tokio::test]
+    async fn max_peripherals_evicts_least_recently_seen() {
+        let central = MockCentral::new();
+        let updated = central.add_mock_peripheral(PeripheralProperties::default());
+        let connected = central.add_mock_peripheral(PeripheralProperties::default());
+        connected.connect().await.unwrap();
+        let stale = central.add_mock_peripheral(PeripheralProperties::default());
+        updated.set_properties(PeripheralProperties::default());
+        let mut events = central.events().await.unwrap();
+
+        central.set_max_peripherals(Some(2));
+        assert!(matches!(
+            events.next().await,
+            Some(CentralEvent::DeviceLost(id)) if id == stale.id()
+        ));
+        assert_eq!(central.peripherals().await.unwrap().len(), 2);
+
+        // Connected peripherals are kept even when they alone are over the limit.
+        central.set_max_peripherals(Some(0));
+        assert!(matches!(
+            events.next().await,
+            Some(CentralEvent::DeviceLost(id)) if id == updated.id()
+        ));
+        let ids: Vec<_> = central
+            .peripherals()
+            .await
+            .unwrap()
+            .iter()
+            .map(|p| p.id())
+            .collect();
+        assert_eq!(ids, vec![connected.id()]);
+    }
+
+    #[tokio::test]
+    async fn connect_first_with_service_picks_strongest() {
+        let central = MockCentral::new();
+        let advertising = |rssi| PeripheralProperties {
+            rssi: Some(rssi),
+            advertised_services: vec![SERVICE],
+            ..Default::default()
+        };
+        central.add_mock_peripheral(PeripheralProperties {
+            rssi: Some(-30),
+            ..Default::default()
+        });
+        let far = central.add_mock_peripheral(advertising(-80));
+
+        let connect = central.connect_first_with_service(SERVICE, Duration::from_secs(5));
+        let advertise = async {
+            tokio::task::yield_now().await;
+            central.add_mock_peripheral(advertising(-50))
+        };
+        let (connected, near) = tokio::join!(connect, advertise);
+        let connected = connected.unwrap();
+
+        assert_eq!(connected.id(), near.id());
+        assert!(connected.is_connected().await.unwrap());
+        assert!(!far.is_connected().await.unwrap());
+        assert_eq!(central.scan_filter(), None);
+    }
+
+    #[tokio::test]
+    async fn connect_first_with_service_times_out() {
+        let central = MockCentral::new();
+        central.add_mock_peripheral(PeripheralProperties::default());
+
+        let result = central
+            .connect_first_with_service(SERVICE, Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(Error::TimedOut(_))));
+        assert_eq!(central.scan_filter(), None);
+    }
+
+    #[tokio::test]
+    async fn connect_first_with_service_leaves_a_running_scan() {
+        let central = MockCentral::new();
+        central.start_scan(ScanFilter::default()).await.unwrap();
+
+        let result = central
+            .connect_first_with_service(SERVICE, Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(Error::TimedOut(_))));
+        assert_eq!(central.scan_filter(), Some(ScanFilter::default()));
+    }
+
+    #[tokio::test]
+    async fn scan_until_sees_updated_properties() {
+        let central = MockCentral::new();
+        central.add_mock_peripheral(PeripheralProperties::default());
+        let device = central.add_mock_peripheral(PeripheralProperties::default());
+
+        let is_ready = |properties: &PeripheralProperties| {
+            properties.manufacturer_data.get(&0x1234) == Some(&vec![1])
+        };
+        let scan = central.scan_until(ScanFilter::default(), is_ready, Duration::from_secs(5));
+        let update = async {
+            tokio::task::yield_now().await;
+            device.set_properties(PeripheralProperties {
+                manufacturer_data: [(0x1234, vec![1])].into(),
+                ..Default::default()
+            });
+        };
+        let (found, ()) = tokio::join!(scan, update);
+
+        assert_eq!(found.unwrap().id(), device.id());
+        assert_eq!(central.scan_filter(), None);
+    }
+
+    #[tokio::test]
+    async fn scan_until_times_out() {
+        let central = MockCentral::new();
+        central.add_mock_peripheral(PeripheralProperties::default());
+
+        let result = central
+            .scan_until(
+                ScanFilter::default(),
+                |properties| properties.local_name.is_some(),
+                Duration::from_millis(50),
+            )
+            .await;
+        assert!(matches!(result, Err(Error::TimedOut(_))));
+        assert_eq!(central.scan_filter(), None);
+    }
+
+    #[tokio::test]
+    async fn refresh_properties_waits_for_the_next_advertisement() {
+        let central = MockCentral::new();
+        let other = central.add_mock_peripheral(PeripheralProperties::default());
+        let device = central.add_mock_peripheral(PeripheralProperties::default());
+        let id = device.id();
+
+        let advertise = |name: &'static str| {
+            let (other, device) = (&other, &device);
+            async move {
+                tokio::task::yield_now().await;
+                other.set_properties(PeripheralProperties::default());
+                device.set_properties(PeripheralProperties {
+                    local_name: Some(name.to_string()),
+                    ..Default::default()
+                });
+            }
+        };
+        let refresh = central.refresh_properties(&id, Duration::from_secs(5));
+        let (properties, ()) = tokio::join!(refresh, advertise("first"));
+        assert_eq!(properties.unwrap().local_name.as_deref(), Some("first"));
+        assert_eq!(central.scan_filter(), None);
+
+        // A scan which was already running is left running.
+        let filter = ScanFilter {
+            name_prefix: Some("f".to_string()),
+            ..Default::default()
+        };
+        central.start_scan(filter.clone()).await.unwrap();
+        let refresh = central.refresh_properties(&id, Duration::from_secs(5));
+        let (properties, ()) = tokio::join!(refresh, advertise("second"));
+        assert_eq!(properties.unwrap().local_name.as_deref(), Some("second"));
+        assert_eq!(central.scan_filter(), Some(filter));
+
+        let result = central
+            .refresh_properties(&id, Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(Error::TimedOut(_))));
+    }
+
+    #[tokio::test]
+    async fn discoverability_times_out() {
+        let central = MockCentral::new();
+        assert!(!central.is_discoverable().await.unwrap());
+        assert!(central.is_connectable().await.unwrap());
+
+        central
+            .set_discoverable(true, Some(Duration::from_millis(20)))
+            .await
+            .unwrap();
+        assert!(central.is_discoverable().await.unwrap());
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(!central.is_discoverable().await.unwrap());
+
+        central.set_discoverable(true, None).await.unwrap();
+        central.set_connectable(false).await.unwrap();
+        assert!(!central.is_discoverable().await.unwrap());
+        assert!(!central.is_connectable().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn rssi_samples_are_smoothed_per_device() {
+        let central = MockCentral::new();
+        let device = central.add_mock_peripheral(PeripheralProperties::default());
+        let other = central.add_mock_peripheral(PeripheralProperties::default());
+
+        let mut samples = central
+            .rssi_samples(ScanFilter::default(), vec![device.id()], 2)
+            .await
+            .unwrap();
+        let mut smoothed = vec![];
+        for rssi in [-50, -60, -70] {
+            other.set_properties(PeripheralProperties {
+                rssi: Some(-10),
+                ..Default::default()
+            });
+            device.set_properties(PeripheralProperties {
+                rssi: Some(rssi),
+                ..Default::default()
+            });
+            let sample = samples.next().await.unwrap();
+            assert_eq!(sample.id, device.id());
+            smoothed.push((sample.rssi, sample.smoothed_rssi));
+        }
+        assert_eq!(smoothed, vec![(-50, -50), (-60, -55), (-70, -65)]);
+    }
+
+    #[tokio::test]
+    async fn smoothed_rssi_follows_scan_options() {
+        let central = MockCentral::new();
+        let device = central.add_mock_peripheral(PeripheralProperties::default());
+        assert!(central
+            .start_scan_with_options(
+                ScanFilter::default(),
+                ScanOptions::default().rssi_smoothing(0)
+            )
+            .await
+            .is_err());
+        central
+            .start_scan_with_options(
+                ScanFilter::default(),
+                ScanOptions::default().rssi_smoothing(50),
+            )
+            .await
+            .unwrap();
+
+        let mut smoothed = vec![];
+        for rssi in [-50, -70, -70] {
+            device.set_properties(PeripheralProperties {
+                rssi: Some(rssi),
+                ..Default::default()
+            });
+            let properties = device.properties().await.unwrap().unwrap();
+            smoothed.push(properties.smoothed_rssi);
+        }
+        assert_eq!(smoothed, vec![Some(-50), Some(-60), Some(-65)]);
+    }
+
+    #[tokio::test]
+    async fn wait_for_event_matches_predicate() {
+        let central = MockCentral::new();
+        let device = central.add_mock_peripheral(PeripheralProperties::default());
+        let id = device.id();
+
+        let wait = central.wait_for_event(
+            |event| matches!(event, CentralEvent::DeviceConnected(i) if *i == id),
+            Duration::from_secs(5),
+        );
+        let connect = async {
+            tokio::task::yield_now().await;
+            device.connect().await.unwrap();
+        };
+        let (event, ()) = tokio::join!(wait, connect);
+        assert!(matches!(event, Ok(CentralEvent::DeviceConnected(_))));
+
+        let result = central
+            .wait_for_event(
+                |event| matches!(event, CentralEvent::DeviceLost(_)),
+                Duration::from_millis(50),
+            )
+            .await;
+        assert!(matches!(result, Err(Error::TimedOut(_))));
+    }
+
+    #[tokio::test]
+    async fn is_scanning_follows_the_scan() {
+        let central = MockCentral::new();
+        assert!(!central.is_scanning().await.unwrap());
+        central.start_scan(ScanFilter::default()).await.unwrap();
+        assert!(central.is_scanning().await.unwrap());
+        central.stop_scan().await.unwrap();
+        assert!(!central.is_scanning().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn is_known_until_lost() {
+        let central = MockCentral::new();
+        let device = central.add_mock_peripheral(PeripheralProperties::default());
+        assert!(central.is_known(&device.id()).await.unwrap());
+        central.emit(CentralEvent::DeviceLost(device.id()));
+        assert!(!central.is_known(&device.id()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn peripherals_snapshot_has_every_peripheral() {
+        let central = MockCentral::new();
+        let first = central.add_mock_peripheral(PeripheralProperties {
+            local_name: Some("first".to_string()),
+            ..Default::default()
+        });
+        let second = central.add_mock_peripheral(PeripheralProperties::default());
+        let mut snapshot = central.peripherals_snapshot().await.unwrap();
+        snapshot.sort_by_key(|(_, properties)| properties.address);
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].0, first.id());
+        assert_eq!(snapshot[0].1.local_name.as_deref(), Some("first"));
+        assert_eq!(snapshot[1].0, second.id());
+        assert_eq!(snapshot[1].1.address, second.address());
+    }
+
+    #[tokio::test]
+    async fn scan_options_are_checked() {
+        let central = MockCentral::new();
+        let too_long_window = ScanOptions::default()
+            .window(Duration::from_millis(200))
+            .interval(Duration::from_millis(100));
+        assert!(central
+            .start_scan_with_options(ScanFilter::default(), too_long_window)
+            .await
+            .is_err());
+        let too_short_interval = ScanOptions::default().interval(Duration::from_millis(1));
+        assert!(central
+            .start_scan_with_options(ScanFilter::default(), too_short_interval)
+            .await
+            .is_err());
+        assert!(!central.is_scanning().await.unwrap());
+
+        let continuous = ScanOptions::default()
+            .window(Duration::from_millis(100))
+            .interval(Duration::from_millis(100));
+        central
+            .start_scan_with_options(ScanFilter::default(), continuous)
+            .await
+            .unwrap();
+        assert!(central.is_scanning().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn resumed_scan_keeps_its_filter() {
+        let central = MockCentral::new();
+        let filter = ScanFilter {
+            services: vec![SERVICE],
+            ..Default::default()
+        };
+        central.resume_scan().await.unwrap();
+        assert!(!central.is_scanning().await.unwrap());
+
+        central.start_scan(filter.clone()).await.unwrap();
+        central.pause_scan().await.unwrap();
+        assert!(!central.is_scanning().await.unwrap());
+        central.resume_scan().await.unwrap();
+        assert_eq!(central.scan_filter(), Some(filter));
+
+        central.pause_scan().await.unwrap();
+        central.stop_scan().await.unwrap();
+        central.resume_scan().await.unwrap();
+        assert!(!central.is_scanning().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn scan_subscriptions_share_the_scan() {
+        const OTHER_SERVICE: Uuid = Uuid::from_u128(0x0000180d_0000_1000_8000_00805f9b34fb);
+        let central = MockCentral::new();
+        let filter = |service| ScanFilter {
+            services: vec![service],
+            ..Default::default()
+        };
+        let mut first = central.subscribe_scan(filter(SERVICE)).await.unwrap();
+        let second = central.subscribe_scan(filter(OTHER_SERVICE)).await.unwrap();
+        assert_eq!(
+            central.scan_filter().unwrap().services,
+            vec![SERVICE, OTHER_SERVICE]
+        );
+
+        central.add_mock_peripheral(PeripheralProperties {
+            advertised_services: vec![OTHER_SERVICE],
+            ..Default::default()
+        });
+        let wanted = central.add_mock_peripheral(PeripheralProperties {
+            advertised_services: vec![SERVICE],
+            ..Default::default()
+        });
+        assert!(matches!(
+            first.next().await,
+            Some(CentralEvent::DeviceDiscovered(id)) if id == wanted.id()
+        ));
+
+        second.stop().await.unwrap();
+        assert_eq!(central.scan_filter(), Some(filter(SERVICE)));
+        first.stop().await.unwrap();
+        assert_eq!(central.scan_filter(), None);
+    }
+
+    #[tokio::test]
+    async fn name_prefix_scans_match_the_start_of_the_name() {
+        let central = MockCentral::new();
+        let named = |name: &str| PeripheralProperties {
+            local_name: Some(name.to_string()),
+            ..Default::default()
+        };
+        let mut sensors = central
+            .subscribe_scan(ScanFilter::builder().name_prefix("Acme-Sensor").build())
+            .await
+            .unwrap();
+        let _switches = central
+            .subscribe_scan(ScanFilter::builder().name_prefix("Acme-Switch").build())
+            .await
+            .unwrap();
+        // The shared scan only requires what the names have in common.
+        assert_eq!(
+            central.scan_filter().unwrap().name_prefix.as_deref(),
+            Some("Acme-S")
+        );
+
+        central.add_mock_peripheral(named("acme-sensor 1"));
+        central.add_mock_peripheral(PeripheralProperties::default());
+        central.add_mock_peripheral(named("Acme-Switch 1"));
+        let sensor = central.add_mock_peripheral(named("Acme-Sensor 1"));
+        assert!(matches!(
+            sensors.next().await,
+            Some(CentralEvent::DeviceDiscovered(id)) if id == sensor.id()
+        ));
+    }
+
+    #[tokio::test]
+    async fn observe_reports_advertisements() {
+        let central = MockCentral::new();
+        let mut advertisements = central
+            .observe(ScanFilter {
+                services: vec![SERVICE],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        central.add_mock_peripheral(PeripheralProperties::default());
+        let beacon = central.add_mock_peripheral(PeripheralProperties {
+            local_name: Some("Beacon".to_string()),
+            advertised_services: vec![SERVICE],
+            ..Default::default()
+        });
+
+        let advertisement = advertisements.next().await.unwrap();
+        assert_eq!(advertisement.id, beacon.id());
+        assert_eq!(advertisement.local_name.as_deref(), Some("Beacon"));
+        assert_eq!(advertisement.advertised_services, vec![SERVICE]);
+        // Dropping the stream stops the scan in the background, so wait to hear that it has.
+        let mut events = central.events().await.unwrap();
+        drop(advertisements);
+        while !matches!(events.next().await, Some(CentralEvent::ScanStopped)) {}
+        assert_eq!(central.scan_filter(), None);
+    }
+
+    #[tokio::test]
+    async fn read_device_name_updates_local_name() {
+        let central = MockCentral::new();
+        let device = central.add_mock_peripheral(PeripheralProperties {
+            local_name: Some("Sens".to_string()),
+            ..Default::default()
+        });
+        device.connect().await.unwrap();
+        assert!(matches!(
+            device.read_device_name().await,
+            Err(Error::NoSuchCharacteristic)
+        ));
+
+        let device_name = Characteristic {
+            uuid: uuid_from_u16(0x2a00),
+            service_uuid: uuid_from_u16(0x1800),
+            instance_id: 0,
+            properties: CharPropFlags::READ,
+            descriptors: BTreeSet::new(),
+        };
+        device.add_service(Service {
+            uuid: uuid_from_u16(0x1800),
+            primary: true,
+            start_handle: None,
+            end_handle: None,
+            characteristics: [device_name.clone()].into(),
+        });
+        device.set_value(&device_name, b"Sensor 42\0\0".to_vec());
+        device.discover_services().await.unwrap();
+        assert_eq!(device.read_device_name().await.unwrap(), "Sensor 42");
+        assert_eq!(
+            device.properties().await.unwrap().unwrap().local_name,
+            Some("Sensor 42".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn database_hash_and_client_supported_features() {
+        let central = MockCentral::new();
+        let device = central.add_mock_peripheral(PeripheralProperties::default());
+        device.connect().await.unwrap();
+        assert!(matches!(
+            device.read_database_hash().await,
+            Err(Error::NoSuchCharacteristic)
+        ));
+
+        let gatt_characteristic = |uuid, properties| Characteristic {
+            uuid: uuid_from_u16(uuid),
+            service_uuid: uuid_from_u16(0x1801),
+            instance_id: 0,
+            properties,
+            descriptors: BTreeSet::new(),
+        };
+        let features = gatt_characteristic(0x2b29, CharPropFlags::READ | CharPropFlags::WRITE);
+        let hash = gatt_characteristic(0x2b2a, CharPropFlags::READ);
+        device.add_service(Service {
+            uuid: uuid_from_u16(0x1801),
+            primary: true,
+            start_handle: None,
+            end_handle: None,
+            characteristics: [features.clone(), hash.clone()].into(),
+        });
+        device.set_value(&hash, (0..16).collect());
+        device.discover_services().await.unwrap();
+
+        assert_eq!(
+            device.read_database_hash().await.unwrap(),
+            std::array::from_fn::<u8, 16, _>(|i| i as u8)
+        );
+        device
+            .write_client_supported_features(ClientSupportedFeatures::ROBUST_CACHING)
+            .await
+            .unwrap();
+        assert_eq!(device.writes().last().unwrap().value, vec![0x01]);
+
+        device.set_value(&hash, vec![1, 2, 3]);
+        assert!(device.read_database_hash().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn gatt_tree_nests_discovered_services() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::READ | CharPropFlags::NOTIFY);
+        let device = device(&central, &characteristic);
+        assert!(device.gatt_tree().services.is_empty());
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+
+        let tree = device.gatt_tree();
+        assert_eq!(tree.services.len(), 1);
+        let service = &tree.services[0];
+        assert_eq!(service.uuid, SERVICE);
+        assert!(service.primary);
+        assert_eq!(service.start_handle, Some(0x0010));
+        assert_eq!(service.end_handle, Some(0x0013));
+        assert_eq!(service.characteristics.len(), 1);
+        let tree_characteristic = &service.characteristics[0];
+        assert_eq!(tree_characteristic.uuid, CHARACTERISTIC);
+        if !cfg!(target_vendor = "apple") {
+            assert_eq!(tree_characteristic.handle, Some(characteristic.instance_id));
+        }
+        assert_eq!(tree_characteristic.properties, characteristic.properties);
+        assert_eq!(tree_characteristic.descriptors.len(), 1);
+        assert_eq!(tree_characteristic.descriptors[0].uuid, DESCRIPTOR);
+    }
+
+    #[tokio::test]
+    async fn write_chunked_reports_progress() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::WRITE_WITHOUT_RESPONSE);
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+
+        // The default MTU of 23 allows 20 bytes per write without response.
+        let data: Vec<u8> = (0..50).collect();
+        let mut progress = Vec::new();
+        device
+            .write_chunked_with_progress(
+                &characteristic,
+                &data,
+                WriteType::WithoutResponse,
+                |written, total| progress.push((written, total)),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(progress, vec![(20, 50), (40, 50), (50, 50)]);
+        let writes: Vec<_> = device.writes().into_iter().map(|w| w.value).collect();
+        assert_eq!(
+            writes,
+            vec![
+                data[..20].to_vec(),
+                data[20..40].to_vec(),
+                data[40..].to_vec()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reliable_write_is_all_or_nothing() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::WRITE);
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+
+        device
+            .reliable_write()
+            .write(&characteristic, &[1])
+            .write(&characteristic, &[2])
+            .execute()
+            .await
+            .unwrap();
+        let writes: Vec<_> = device.writes().into_iter().map(|w| w.value).collect();
+        assert_eq!(writes, vec![vec![1], vec![2]]);
+
+        let unknown = Characteristic {
+            uuid: DESCRIPTOR,
+            ..characteristic.clone()
+        };
+        assert!(device
+            .reliable_write()
+            .write(&characteristic, &[3])
+            .write(&unknown, &[4])
+            .execute()
+            .await
+            .is_err());
+        assert_eq!(device.writes().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn read_and_write() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::READ | CharPropFlags::WRITE);
+        let descriptor = characteristic.descriptors.iter().next().unwrap().clone();
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+
+        device.set_value(&characteristic, vec![1, 2]);
+        assert_eq!(device.read(&characteristic).await.unwrap(), vec![1, 2]);
+        device
+            .write(&characteristic, &[3], WriteType::WithResponse)
+            .await
+            .unwrap();
+        assert_eq!(device.read(&characteristic).await.unwrap(), vec![3]);
+        assert!(matches!(
+            device
+                .write(&characteristic, &[4], WriteType::WithoutResponse)
+                .await,
+            Err(Error::NotSupported(_))
+        ));
+        assert_eq!(
+            device.writes(),
+            vec![MockWrite {
+                characteristic: characteristic.clone(),
+                value: vec![3],
+                write_type: WriteType::WithResponse,
+            }]
+        );
+
+        device.write_descriptor(&descriptor, &[1, 0]).await.unwrap();
+        assert_eq!(
+            device.read_descriptor(&descriptor).await.unwrap(),
+            vec![1, 0]
+        );
+
+        let unknown = Characteristic {
+            uuid: Uuid::nil(),
+            ..characteristic
+        };
+        assert!(matches!(
+            device.read(&unknown).await,
+            Err(Error::NoSuchCharacteristic)
+        ));
+    }
+
+    #[tokio::test]
+    async fn notifications() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::NOTIFY);
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        let mut notifications = device.notifications().await.unwrap();
+
+        // Not subscribed yet, so this is dropped.
+        device.notify(&characteristic, vec![1]);
+        device.subscribe(&characteristic).await.unwrap();
+        assert!(device.is_subscribed(&characteristic));
+        device.notify(&characteristic, vec![2]);
+        device.unsubscribe(&characteristic).await.unwrap();
+        device.notify(&characteristic, vec![3]);
+        device.subscribe(&characteristic).await.unwrap();
+        device.notify(&characteristic, vec![4]);
+
+        for value in [vec![2], vec![4]] {
+            assert_eq!(
+                notifications.next().await,
+                Some(ValueNotification {
+                    uuid: CHARACTERISTIC,
+                    service_uuid: SERVICE,
+                    instance_id: 0,
+                    value,
+                })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_and_get_finds_characteristic() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::WRITE);
+        let device = device(&central, &characteristic);
+
+        assert_eq!(
+            device
+                .connect_and_get(SERVICE, CHARACTERISTIC)
+                .await
+                .unwrap(),
+            characteristic
+        );
+        assert!(device.is_connected().await.unwrap());
+        assert!(matches!(
+            device.connect_and_get(SERVICE, SERVICE).await,
+            Err(Error::NoSuchCharacteristic)
+        ));
+    }
+
+    #[tokio::test]
+    async fn connect_and_get_discovers_only_its_service() {
+        const OTHER_SERVICE: Uuid = Uuid::from_u128(0x0000180d_0000_1000_8000_00805f9b34fb);
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::WRITE);
+        let device = device(&central, &characteristic);
+        device.add_service(Service {
+            uuid: OTHER_SERVICE,
+            primary: true,
+            start_handle: Some(0x0020),
+            end_handle: Some(0x0023),
+            characteristics: BTreeSet::new(),
+        });
+
+        device
+            .connect_and_get(SERVICE, CHARACTERISTIC)
+            .await
+            .unwrap();
+        let services: Vec<_> = device.services().into_iter().map(|s| s.uuid).collect();
+        assert_eq!(services, vec![SERVICE]);
+
+        device
+            .discover_services_with_options(DiscoverOptions::default().services([OTHER_SERVICE]))
+            .await
+            .unwrap();
+        assert_eq!(device.services().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn resubscribe_all_after_reconnecting() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::NOTIFY);
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        device.subscribe(&characteristic).await.unwrap();
+        device.disconnect().await.unwrap();
+        assert!(!device.is_subscribed(&characteristic));
+        assert_eq!(device.subscriptions(), vec![characteristic.clone()]);
+
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        assert!(device.resubscribe_all().await.is_empty());
+        assert!(device.is_subscribed(&characteristic));
+
+        device.unsubscribe(&characteristic).await.unwrap();
+        assert!(device.subscriptions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn auto_subscribe_on_connect() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::NOTIFY);
+        let device = device(&central, &characteristic);
+        // The missing characteristic is only logged, and doesn't fail the connection.
+        device
+            .connect_with_options(
+                ConnectOptions::default().auto_subscribe([CHARACTERISTIC, SERVICE]),
+            )
+            .await
+            .unwrap();
+        assert!(device.is_connected().await.unwrap());
+        assert!(device.is_subscribed(&characteristic));
+        assert_eq!(device.subscriptions(), vec![characteristic]);
+    }
+
+    #[tokio::test]
+    async fn read_and_subscribe_yields_current_value_then_updates() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::READ | CharPropFlags::NOTIFY);
+        let device = device(&central, &characteristic);
+        device.set_value(&characteristic, vec![5]);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+
+        let (value, mut updates) = device.read_and_subscribe(&characteristic).await.unwrap();
+        assert_eq!(value, Some(vec![5]));
+        device.notify(&characteristic, vec![6]);
+        assert_eq!(updates.next().await, Some(vec![6]));
+    }
+
+    #[tokio::test]
+    async fn read_and_subscribe_handles_read_or_notify_only() {
+        let central = MockCentral::new();
+        let read_only = characteristic(CharPropFlags::READ);
+        let device = device(&central, &read_only);
+        device.set_value(&read_only, vec![5]);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        let (value, mut updates) = device.read_and_subscribe(&read_only).await.unwrap();
+        assert_eq!(value, Some(vec![5]));
+        assert_eq!(updates.next().await, None);
+
+        let notify_only = characteristic(CharPropFlags::NOTIFY);
+        let device = self::device(&central, &notify_only);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        let (value, _updates) = device.read_and_subscribe(&notify_only).await.unwrap();
+        assert_eq!(value, None);
+        assert!(device.is_subscribed(&notify_only));
+
+        assert!(matches!(
+            device
+                .read_and_subscribe(&characteristic(CharPropFlags::WRITE))
+                .await,
+            Err(Error::NotSupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn liveness_probe_disconnects_a_device_which_stops_answering() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::READ);
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        let mut events = central.events().await.unwrap();
+
+        let _probe = device.set_liveness_probe(Duration::from_millis(10), &characteristic);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(device.is_connected().await.unwrap());
+        device.set_stalled(true);
+
+        let disconnected = tokio::time::timeout(Duration::from_secs(5), events.next()).await;
+        assert!(matches!(
+            disconnected,
+            Ok(Some(CentralEvent::DeviceDisconnected(id))) if id == device.id()
+        ));
+        assert!(matches!(
+            events.next().await,
+            Some(CentralEvent::DeviceDisconnectedWithReason {
+                id,
+                reason: DisconnectReason::ProbeTimeout,
+            }) if id == device.id()
+        ));
+        assert!(!device.is_connected().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn abort_pending_cancels_stalled_operations() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::READ | CharPropFlags::WRITE);
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        device.set_stalled(true);
+
+        let read = device.read(&characteristic);
+        let write = device.write(&characteristic, &[1], WriteType::WithResponse);
+        let abort = async {
+            while device.pending_operations() < 2 {
+                tokio::task::yield_now().await;
+            }
+            assert_eq!(device.pending_writes(), 1);
+            device.abort_pending();
+        };
+        let (read, write, ()) = tokio::join!(read, write, abort);
+        assert!(matches!(read, Err(Error::Cancelled)));
+        assert!(matches!(write, Err(Error::Cancelled)));
+        assert_eq!(device.pending_operations(), 0);
+        assert!(device.writes().is_empty());
+
+        // Operations started afterwards carry on as normal.
+        device.set_stalled(false);
+        assert_eq!(
+            device.read(&characteristic).await.unwrap(),
+            Vec::<u8>::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn subscriptions_and_descriptor_accesses_are_pending() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::NOTIFY);
+        let cccd = characteristic.descriptors.first().unwrap().clone();
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        device.set_stalled(true);
+
+        let subscribe = device.subscribe(&characteristic);
+        let read_descriptor = device.read_descriptor(&cccd);
+        let abort = async {
+            while device.pending_operations() < 2 {
+                tokio::task::yield_now().await;
+            }
+            assert_eq!(device.pending_writes(), 0);
+            device.abort_pending();
+        };
+        let (subscribe, read_descriptor, ()) = tokio::join!(subscribe, read_descriptor, abort);
+        assert!(matches!(subscribe, Err(Error::Cancelled)));
+        assert!(matches!(read_descriptor, Err(Error::Cancelled)));
+        assert_eq!(device.pending_operations(), 0);
+        assert!(!device.is_subscribed(&characteristic));
+        assert!(device.subscriptions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn write_default_prefers_the_default_write_type() {
+        let central = MockCentral::new();
+        let both = characteristic(CharPropFlags::WRITE | CharPropFlags::WRITE_WITHOUT_RESPONSE);
+        let device = device(&central, &both);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+
+        device.write_default(&both, &[1]).await.unwrap();
+        device.set_default_write_type(WriteType::WithoutResponse);
+        device.write_default(&both, &[2]).await.unwrap();
+        let with_response_only = characteristic(CharPropFlags::WRITE);
+        device
+            .write_default(&with_response_only, &[3])
+            .await
+            .unwrap();
+
+        let write_types: Vec<_> = device
+            .writes()
+            .into_iter()
+            .map(|write| write.write_type)
+            .collect();
+        assert_eq!(
+            write_types,
+            vec![
+                WriteType::WithResponse,
+                WriteType::WithoutResponse,
+                WriteType::WithResponse
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn write_detailed_reports_the_write_type() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::WRITE_WITHOUT_RESPONSE);
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+
+        let result = device
+            .write_detailed(&characteristic, &[1], WriteType::WithoutResponse)
+            .await
+            .unwrap();
+        assert_eq!(result.write_type, WriteType::WithoutResponse);
+        assert_eq!(result.sent_immediately, None);
+        assert!(device
+            .write_detailed(&characteristic, &[2], WriteType::WithResponse)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn metrics_count_traffic() {
+        let central = MockCentral::new();
+        let characteristic =
+            characteristic(CharPropFlags::READ | CharPropFlags::WRITE | CharPropFlags::NOTIFY);
+        let device = device(&central, &characteristic);
+        device.set_mtu(247);
+        assert_eq!(device.metrics(), PeripheralMetrics::default());
+
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        device.subscribe(&characteristic).await.unwrap();
+        device
+            .write(&characteristic, &[1, 2, 3], WriteType::WithResponse)
+            .await
+            .unwrap();
+        assert_eq!(device.read(&characteristic).await.unwrap().len(), 3);
+        device.notify(&characteristic, vec![4]);
+        // Failed operations aren't counted.
+        device
+            .write(&characteristic, &[5], WriteType::WithoutResponse)
+            .await
+            .unwrap_err();
+
+        let metrics = device.metrics();
+        assert_eq!(metrics.bytes_read, 3);
+        assert_eq!(metrics.bytes_written, 3);
+        assert_eq!(metrics.notifications_received, 1);
+        assert_eq!(metrics.reconnects, 0);
+        assert_eq!(metrics.mtu, Some(247));
+
+        // The counters carry on across connections.
+        device.disconnect().await.unwrap();
+        assert_eq!(device.metrics().mtu, None);
+        device.connect().await.unwrap();
+        let metrics = device.metrics();
+        assert_eq!(metrics.bytes_written, 3);
+        assert_eq!(metrics.reconnects, 1);
+    }
+
+    #[tokio::test]
+    async fn connectable_only_scan_leaves_out_beacons() {
+        let central = MockCentral::new();
+        let mut events = central.events().await.unwrap();
+        central
+            .start_scan(ScanFilter::builder().connectable_only(true).build())
+            .await
+            .unwrap();
+        assert!(matches!(
+            events.next().await,
+            Some(CentralEvent::ScanStarted)
+        ));
+
+        central.add_mock_peripheral(PeripheralProperties {
+            connectable: Some(false),
+            ..Default::default()
+        });
+        let unknown = central.add_mock_peripheral(PeripheralProperties::default());
+        let connectable = central.add_mock_peripheral(PeripheralProperties {
+            connectable: Some(true),
+            ..Default::default()
+        });
+        assert!(matches!(
+            events.next().await,
+            Some(CentralEvent::DeviceDiscovered(id)) if id == unknown.id()
+        ));
+        assert!(matches!(
+            events.next().await,
+            Some(CentralEvent::DeviceDiscovered(id)) if id == connectable.id()
+        ));
+    }
+
+    #[tokio::test]
+    async fn notification_receiver_polls_without_waiting() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::NOTIFY);
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        device.subscribe(&characteristic).await.unwrap();
+        let mut receiver = device.notification_receiver().await.unwrap();
+        assert_eq!(receiver.try_next_notification(), None);
+
+        device.notify(&characteristic, vec![1]);
+        device.notify(&characteristic, vec![2]);
+        assert_eq!(
+            receiver.try_next_notification().map(|n| n.value),
+            Some(vec![1])
+        );
+        assert_eq!(receiver.next().await.map(|n| n.value), Some(vec![2]));
+        assert_eq!(receiver.try_next_notification(), None);
+    }
+
+    #[tokio::test]
+    async fn coalesced_notifications() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::NOTIFY);
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        let mut notifications = device
+            .subscribe_coalesced(&characteristic, Duration::from_millis(50))
+            .await
+            .unwrap();
+
+        for value in 1..=3 {
+            device.notify(&characteristic, vec![value]);
+        }
+        assert_eq!(notifications.next().await.unwrap().value, vec![3]);
+        device.notify(&characteristic, vec![4]);
+        assert_eq!(notifications.next().await.unwrap().value, vec![4]);
+    }
+
+    #[tokio::test]
+    async fn subscribe_requires_notify_or_indicate() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::READ);
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        assert!(matches!(
+            device.subscribe(&characteristic).await,
+            Err(Error::NotSupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn connects_beyond_the_limit_wait_their_turn() {
+        let central = MockCentral::new();
+        central.set_max_concurrent_connects(1);
+        let first = central.add_mock_peripheral(PeripheralProperties::default());
+        let second = central.add_mock_peripheral(PeripheralProperties::default());
+
+        let (a, b) = futures::join!(first.connect(), second.connect());
+        a.unwrap();
+        b.unwrap();
+        assert!(first.is_connected().await.unwrap());
+        assert!(second.is_connected().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn subscribe_with_notify_and_indicate() {
+        let central = MockCentral::new();
+        let notify_only = characteristic(CharPropFlags::NOTIFY);
+        let characteristic = characteristic(CharPropFlags::NOTIFY | CharPropFlags::INDICATE);
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+
+        device
+            .subscribe_with(&characteristic, SubscriptionKind::NotifyAndIndicate)
+            .await
+            .unwrap();
+        assert!(device.is_subscribed(&characteristic));
+        let cccd = characteristic.descriptors.first().unwrap();
+        assert_eq!(
+            device.read_descriptor(cccd).await.unwrap(),
+            vec![0x03, 0x00]
+        );
+
+        assert!(matches!(
+            device
+                .subscribe_with(&notify_only, SubscriptionKind::NotifyAndIndicate)
+                .await,
+            Err(Error::NotSupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn is_notifying_reads_the_device_state() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::NOTIFY);
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        assert!(!device.is_notifying(&characteristic).await.unwrap());
+
+        device.subscribe(&characteristic).await.unwrap();
+        assert!(device.is_notifying(&characteristic).await.unwrap());
+        // Another client turns notifications off again.
+        let cccd = characteristic.descriptors.first().unwrap();
+        device.set_descriptor_value(cccd, vec![0x00, 0x00]);
+        assert!(!device.is_notifying(&characteristic).await.unwrap());
+        assert!(device.is_subscribed(&characteristic));
+
+        let without_cccd = Characteristic {
+            descriptors: BTreeSet::new(),
+            ..characteristic
+        };
+        assert!(matches!(
+            device.is_notifying(&without_cccd).await,
+            Err(Error::NotSupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn connected_peripherals() {
+        let central = MockCentral::new();
+        let a = central.add_mock_peripheral(PeripheralProperties::default());
+        let b = central.add_mock_peripheral(PeripheralProperties::default());
+        assert!(central.connected().await.unwrap().is_empty());
+
+        a.connect().await.unwrap();
+        let connected = central.connected().await.unwrap();
+        assert_eq!(connected.len(), 1);
+        assert_eq!(connected[0].id(), a.id());
+
+        a.disconnect().await.unwrap();
+        b.connect().await.unwrap();
+        let connected = central.connected().await.unwrap();
+        assert_eq!(connected.len(), 1);
+        assert_eq!(connected[0].id(), b.id());
+    }
+
+    #[tokio::test]
+    async fn all_notifications() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::NOTIFY);
+        let a = device(&central, &characteristic);
+        let b = device(&central, &characteristic);
+        a.connect().await.unwrap();
+        let mut notifications = central.all_notifications().await.unwrap();
+        b.connect().await.unwrap();
+        for device in [&a, &b] {
+            device.discover_services().await.unwrap();
+            device.subscribe(&characteristic).await.unwrap();
+        }
+        // Let the stream pick up both peripherals.
+        assert!(futures::poll!(notifications.next()).is_pending());
+
+        a.notify(&characteristic, vec![1]);
+        b.notify(&characteristic, vec![2]);
+        let mut received = vec![
+            notifications.next().await.unwrap(),
+            notifications.next().await.unwrap(),
+        ];
+        received.sort_by_key(|(_, notification)| notification.value.clone());
+        assert_eq!(
+            received,
+            vec![
+                (
+                    a.id(),
+                    ValueNotification {
+                        uuid: CHARACTERISTIC,
+                        service_uuid: SERVICE,
+                        instance_id: 0,
+                        value: vec![1]
+                    }
+                ),
+                (
+                    b.id(),
+                    ValueNotification {
+                        uuid: CHARACTERISTIC,
+                        service_uuid: SERVICE,
+                        instance_id: 0,
+                        value: vec![2]
+                    }
+                ),
+            ]
+        );
+
+        a.disconnect().await.unwrap();
+        assert!(futures::poll!(notifications.next()).is_pending());
+        b.notify(&characteristic, vec![3]);
+        let (id, notification) = notifications.next().await.unwrap();
+        assert_eq!(id, b.id());
+        assert_eq!(notification.value, vec![3]);
+
+        // The central forgets a peripheral when it disconnects, but its notifications are merged
+        // again when it reconnects.
+        a.connect().await.unwrap();
+        a.discover_services().await.unwrap();
+        a.subscribe(&characteristic).await.unwrap();
+        assert!(central.peripheral(&a.id()).await.is_err());
+        assert!(futures::poll!(notifications.next()).is_pending());
+        a.notify(&characteristic, vec![4]);
+        let (id, notification) = notifications.next().await.unwrap();
+        assert_eq!(id, a.id());
+        assert_eq!(notification.value, vec![4]);
+        // Dropping the stream leaves subscriptions alone.
+        drop(notifications);
+        assert!(b.is_subscribed(&characteristic));
+    }
+
+    #[tokio::test]
+    async fn subscribe_after_rediscovery() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::NOTIFY);
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        device.subscribe(&characteristic).await.unwrap();
+        let mut notifications = device.notifications().await.unwrap();
+
+        // The device adds a service, as it would before indicating Service Changed.
+        let added = Characteristic {
+            uuid: Uuid::from_u128(0x6e400003_b5a3_f393_e0a9_e50e24dcca9e),
+            service_uuid: Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e),
+            instance_id: 0,
+            properties: CharPropFlags::INDICATE,
+            descriptors: BTreeSet::new(),
+        };
+        device.add_service(Service {
+            uuid: added.service_uuid,
+            primary: true,
+            start_handle: None,
+            end_handle: None,
+            characteristics: [added.clone()].into(),
+        });
+        assert!(matches!(
+            device.subscribe(&added).await,
+            Err(Error::NoSuchCharacteristic)
+        ));
+
+        device.discover_services().await.unwrap();
+        device.subscribe(&added).await.unwrap();
+        assert!(device.is_subscribed(&characteristic));
+        device.notify(&added, vec![1]);
+        device.notify(&characteristic, vec![2]);
+        assert_eq!(notifications.next().await.unwrap().uuid, added.uuid);
+        assert_eq!(notifications.next().await.unwrap().uuid, CHARACTERISTIC);
+    }
+
+    #[tokio::test]
+    async fn trace_events() {
+        use crate::api::TraceEvent;
+        use std::sync::{Arc, Mutex};
+
+        let manager = MockManager::new();
+        let central = manager.adapters().await.unwrap().remove(0);
+        let characteristic = characteristic(CharPropFlags::NOTIFY);
+        let device = device(&central, &characteristic);
+        let id = device.id();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = events.clone();
+        // The callback is global, so only keep events for this test's peripheral.
+        manager.set_trace_callback(move |event| match &event {
+            TraceEvent::ConnectStarted { id: event_id }
+            | TraceEvent::ConnectFinished { id: event_id, .. }
+            | TraceEvent::ServicesDiscovered { id: event_id, .. }
+            | TraceEvent::NotificationDelivered { id: event_id, .. }
+                if *event_id == id =>
+            {
+                events_clone.lock().unwrap().push(event)
+            }
+            _ => {}
+        });
+
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        device.subscribe(&characteristic).await.unwrap();
+        device.notify(&characteristic, vec![1]);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                TraceEvent::ConnectStarted { id: device.id() },
+                TraceEvent::ConnectFinished {
+                    id: device.id(),
+                    error: None
+                },
+                TraceEvent::ServicesDiscovered {
+                    id: device.id(),
+                    result: Ok(1)
+                },
+                TraceEvent::NotificationDelivered {
+                    id: device.id(),
+                    characteristic: CHARACTERISTIC
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn subscribe_by_uuid_discovers_services() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::NOTIFY);
+        let device = device(&central, &characteristic);
+        device.connect().await.unwrap();
+        assert!(device.characteristics().is_empty());
+
+        device
+            .subscribe_by_uuid(SERVICE, CHARACTERISTIC)
+            .await
+            .unwrap();
+        assert!(device.is_subscribed(&characteristic));
+        assert!(matches!(
+            device.subscribe_by_uuid(SERVICE, DESCRIPTOR).await,
+            Err(Error::NoSuchCharacteristic)
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_all_descriptors() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::READ);
+        let descriptor = characteristic.descriptors.first().unwrap().clone();
+        let device = device(&central, &characteristic);
+        device.set_descriptor_value(&descriptor, vec![1, 0]);
+        assert!(matches!(
+            device.read_all_descriptors().await,
+            Err(Error::NotConnected)
+        ));
+
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        let values: Vec<_> = device
+            .read_all_descriptors()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(characteristic, descriptor, value)| (characteristic, descriptor, value.unwrap()))
+            .collect();
+        assert_eq!(values, vec![(characteristic, descriptor, vec![1, 0])]);
+    }
+
+    #[tokio::test]
+    async fn read_descriptors_reports_each_result() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::READ);
+        let cccd = characteristic.descriptors.first().unwrap().clone();
+        let device = device(&central, &characteristic);
+        device.set_descriptor_value(&cccd, vec![1, 0]);
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+
+        // A descriptor the device doesn't have fails on its own.
+        let user_description = Descriptor {
+            uuid: uuid_from_u16(0x2901),
+            ..cccd.clone()
+        };
+        let mut with_missing = characteristic.clone();
+        with_missing.descriptors.insert(user_description.clone());
+        let values = device.read_descriptors(&with_missing).await.unwrap();
+        assert_eq!(values.len(), 2);
+        for (descriptor, value) in values {
+            if descriptor == cccd {
+                assert_eq!(value.unwrap(), vec![1, 0]);
+            } else {
+                assert_eq!(descriptor, user_description);
+                assert!(value.is_err());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn read_all_readable() {
+        let central = MockCentral::new();
+        let readable = characteristic(CharPropFlags::READ);
+        let unset = Characteristic {
+            uuid: Uuid::from_u128(0x2a19),
+            ..readable.clone()
+        };
+        let write_only = Characteristic {
+            uuid: Uuid::from_u128(0x2a1a),
+            properties: CharPropFlags::WRITE,
+            ..readable.clone()
+        };
+        let device = central.add_mock_peripheral(PeripheralProperties::default());
+        device.add_service(Service {
+            uuid: SERVICE,
+            primary: true,
+            start_handle: None,
+            end_handle: None,
+            characteristics: [readable.clone(), unset.clone(), write_only].into(),
+        });
+        device.set_value(&readable, vec![42]);
+        assert!(matches!(
+            device.read_all_readable().await,
+            Err(Error::NotConnected)
+        ));
+
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        let mut values: Vec<_> = device
+            .read_all_readable()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(characteristic, value)| (characteristic, value.unwrap()))
+            .collect();
+        values.sort();
+        let mut expected = vec![(readable, vec![42]), (unset, vec![])];
+        expected.sort();
+        assert_eq!(values, expected);
+    }
+
+    #[tokio::test]
+    async fn unexpected_disconnect() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::NOTIFY);
+        let device = device(&central, &characteristic);
+        let mut events = central.events().await.unwrap();
+
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        device.subscribe(&characteristic).await.unwrap();
+        device.disconnect_unexpectedly();
+
+        assert!(
+            matches!(events.next().await, Some(CentralEvent::DeviceConnected(id)) if id == device.id())
+        );
+        assert!(
+            matches!(events.next().await, Some(CentralEvent::DeviceDisconnected(id)) if id == device.id())
+        );
+        assert!(!device.is_connected().await.unwrap());
+        assert!(!device.is_subscribed(&characteristic));
+        assert!(device.services().is_empty());
+        // The common adapter layer forgets about disconnected peripherals.
+        assert!(central.peripherals().await.unwrap().is_empty());
+        assert!(matches!(
+            central.peripheral(&device.id()).await,
+            Err(Error::DeviceNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn disconnecting_twice_emits_one_event() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::READ);
+        let device = device(&central, &characteristic);
+        let mut events = central.events().await.unwrap();
+
+        device.connect().await.unwrap();
+        device.disconnect().await.unwrap();
+        device.disconnect().await.unwrap();
+        device.disconnect_unexpectedly();
+        device.connect().await.unwrap();
+
+        assert!(
+            matches!(events.next().await, Some(CentralEvent::DeviceConnected(id)) if id == device.id())
+        );
+        assert!(
+            matches!(events.next().await, Some(CentralEvent::DeviceDisconnected(id)) if id == device.id())
+        );
+        assert!(
+            matches!(events.next().await, Some(CentralEvent::DeviceConnected(id)) if id == device.id())
+        );
+    }
+
+    #[tokio::test]
+    async fn services_changed_while_connected() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::READ);
+        let device = device(&central, &characteristic);
+        let mut events = central.events().await.unwrap();
+
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        let service_uuid = Uuid::from_u128(0x5678);
+        device.add_service(Service {
+            uuid: service_uuid,
+            primary: true,
+            start_handle: None,
+            end_handle: None,
+            characteristics: BTreeSet::new(),
+        });
+
+        assert!(
+            matches!(events.next().await, Some(CentralEvent::DeviceConnected(id)) if id == device.id())
+        );
+        assert!(
+            matches!(events.next().await, Some(CentralEvent::ServicesChanged(id)) if id == device.id())
+        );
+        device.discover_services().await.unwrap();
+        assert!(device.services().iter().any(|s| s.uuid == service_uuid));
+    }
+
+    #[tokio::test]
+    async fn wait_services_resolved_discovers_services() {
+        let central = MockCentral::new();
+        let characteristic = characteristic(CharPropFlags::READ);
+        let device = device(&central, &characteristic);
+
+        device.connect().await.unwrap();
+        device
+            .wait_services_resolved(Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(device.characteristics().contains(&characteristic));
+    }
+
+    #[tokio::test]
+    async fn characteristics_without_services() {
+        let central = MockCentral::new();
+        let device = central.add_mock_peripheral(PeripheralProperties::default());
+        device.connect().await.unwrap();
+        device.discover_services().await.unwrap();
+        assert_eq!(device.characteristics(), BTreeSet::new());
+    }
+
+    #[tokio::test]
+    async fn max_write_len_follows_mtu() {
+        let central = MockCentral::new();
+        let device = central.add_mock_peripheral(PeripheralProperties::default());
+        device.set_mtu(247);
+        assert_eq!(device.max_write_len(WriteType::WithoutResponse), 20);
+        device.connect().await.unwrap();
+        assert_eq!(device.max_write_len(WriteType::WithoutResponse), 244);
+        assert_eq!(device.max_write_len(WriteType::SignedWithoutResponse), 232);
+        assert_eq!(device.max_write_len(WriteType::WithResponse), 512);
+    }
+
+    #[tokio::test]
+    async fn request_mtu() {
+        let central = MockCentral::new();
+        let device = central.add_mock_peripheral(PeripheralProperties::default());
+        device.set_mtu(185);
+        assert!(matches!(
+            device.request_mtu(247).await,
+            Err(Error::NotConnected)
+        ));
+        device.connect().await.unwrap();
+        assert_eq!(device.request_mtu(247).await.unwrap(), 185);
+        assert_eq!(device.request_mtu(100).await.unwrap(), 100);
+        assert_eq!(device.max_write_len(WriteType::WithoutResponse), 97);
+        assert_eq!(device.request_mtu(10).await.unwrap(), 23);
+    }
+
+    mod not_connected {
+        use super::*;
+        use crate::Result;
+        use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+
+        #[tokio::test]
+        async fn before_connecting() {
+            let central = MockCentral::new();
+            let characteristic = characteristic(CharPropFlags::READ);
+            let device = device(&central, &characteristic);
+
+            assert!(matches!(
+                device.read(&characteristic).await,
+                Err(Error::NotConnected)
+            ));
+            assert!(matches!(
+                device.discover_services().await,
+                Err(Error::NotConnected)
+            ));
+            assert!(device.services().is_empty());
+            assert!(matches!(
+                device.require_encryption().await,
+                Err(Error::NotConnected)
+            ));
+
+            device.connect().await.unwrap();
+            device.require_encryption().await.unwrap();
+            device.discover_services().await.unwrap();
+            assert_eq!(device.characteristics(), [characteristic].into());
+        }
+
+        #[tokio::test]
+        async fn after_disconnecting() {
+            let central = MockCentral::new();
+            let characteristic =
+                characteristic(CharPropFlags::READ | CharPropFlags::WRITE | CharPropFlags::NOTIFY);
+            let descriptor = characteristic.descriptors.first().unwrap().clone();
+            let device = device(&central, &characteristic);
+            device.connect().await.unwrap();
+            device.discover_services().await.unwrap();
+            device.subscribe(&characteristic).await.unwrap();
+            device.disconnect_unexpectedly();
+
+            let operations: [(&str, BoxFuture<'_, Result<()>>); 6] = [
+                ("read", device.read(&characteristic).map_ok(drop).boxed()),
+                (
+                    "write",
+                    device.write(&characteristic, &[1], WriteType::WithResponse),
+                ),
+                ("subscribe", device.subscribe(&characteristic)),
+                ("unsubscribe", device.unsubscribe(&characteristic)),
+                (
+                    "read_descriptor",
+                    device.read_descriptor(&descriptor).map_ok(drop).boxed(),
+                ),
+                (
+                    "write_descriptor",
+                    device.write_descriptor(&descriptor, &[1, 0]),
+                ),
+            ];
+            for (name, operation) in operations {
+                assert!(
+                    matches!(operation.await, Err(Error::NotConnected)),
+                    "{name} should fail"
+                );
+            }
+            assert!(device.writes().is_empty());
+        }
+    }
+
+    mod duplicate_uuids {
+        use super::*;
+
+        /// A connected device with two instances of the same characteristic in one service,
+        /// holding the values 1 and 2.
+        async fn device_with_two_instances(
+            central: &MockCentral,
+        ) -> (MockPeripheral, Characteristic, Characteristic) {
+            let properties = CharPropFlags::READ | CharPropFlags::WRITE | CharPropFlags::NOTIFY;
+            let first = Characteristic {
+                instance_id: 0x10,
+                ..characteristic(properties)
+            };
+            let second = Characteristic {
+                instance_id: 0x20,
+                ..characteristic(properties)
+            };
+            let device = central.add_mock_peripheral(PeripheralProperties::default());
+            device.add_service(Service {
+                uuid: SERVICE,
+                primary: true,
+                start_handle: None,
+                end_handle: None,
+                characteristics: [first.clone(), second.clone()].into(),
+            });
+            device.set_value(&first, vec![1]);
+            device.set_value(&second, vec![2]);
+            device.connect().await.unwrap();
+            device.discover_services().await.unwrap();
+            assert_eq!(device.characteristics().len(), 2);
+            (device, first, second)
+        }
+
+        #[tokio::test]
+        async fn reads_and_writes_reach_their_instance() {
+            let central = MockCentral::new();
+            let (device, first, second) = device_with_two_instances(&central).await;
+
+            assert_eq!(device.read(&first).await.unwrap(), vec![1]);
+            assert_eq!(device.read(&second).await.unwrap(), vec![2]);
+            device
+                .write(&second, &[3], WriteType::WithResponse)
+                .await
+                .unwrap();
+            assert_eq!(device.read(&first).await.unwrap(), vec![1]);
+            assert_eq!(device.read(&second).await.unwrap(), vec![3]);
+        }
+
+        #[tokio::test]
+        async fn notifications_name_their_instance() {
+            let central = MockCentral::new();
+            let (device, first, second) = device_with_two_instances(&central).await;
+
+            let mut notifications = device.notifications().await.unwrap();
+            device.subscribe(&first).await.unwrap();
+            assert!(device.is_subscribed(&first));
+            assert!(!device.is_subscribed(&second));
+            device.subscribe(&second).await.unwrap();
+            device.notify(&first, vec![4]);
+            device.notify(&second, vec![5]);
+            let notification = notifications.next().await.unwrap();
+            assert!(notification.is_from(&first) && !notification.is_from(&second));
+            assert_eq!(notification.value, vec![4]);
+            assert!(notifications.next().await.unwrap().is_from(&second));
+        }
+
+        #[tokio::test]
+        async fn unknown_instance_is_rejected() {
+            let central = MockCentral::new();
+            let (device, first, _) = device_with_two_instances(&central).await;
+
+            let unknown = Characteristic {
+                instance_id: 0x30,
+                ..first
+            };
+            assert!(matches!(
+                device.read(&unknown).await,
+                Err(Error::NoSuchCharacteristic)
+            ));
+        }
+
+        #[tokio::test]
+        async fn services_with_the_same_uuid_are_kept_apart() {
+            let central = MockCentral::new();
+            let first = Characteristic {
+                instance_id: 0x12,
+                ..characteristic(CharPropFlags::READ)
+            };
+            let second = Characteristic {
+                instance_id: 0x22,
+                ..characteristic(CharPropFlags::READ)
+            };
+            let device = central.add_mock_peripheral(PeripheralProperties::default());
+            for (start_handle, characteristic) in [(0x0010, &first), (0x0020, &second)] {
+                device.add_service(Service {
+                    uuid: SERVICE,
+                    primary: true,
+                    start_handle: Some(start_handle),
+                    end_handle: Some(start_handle + 3),
+                    characteristics: [characteristic.clone()].into(),
+                });
+                device.set_value(characteristic, vec![characteristic.instance_id as u8]);
+            }
+            device.connect().await.unwrap();
+            device.discover_services().await.unwrap();
+
+            let services = device.services();
+            assert_eq!(services.len(), 2);
+            assert!(services.iter().all(|service| service.uuid == SERVICE));
+            assert_eq!(device.characteristics().len(), 2);
+            assert_eq!(device.read(&first).await.unwrap(), vec![0x12]);
+            assert_eq!(device.read(&second).await.unwrap(), vec![0x22]);
+        }
+
+        #[tokio::test]
+        async fn bootstrap_keeps_them_across_services() {
+            let central = MockCentral::new();
+            let characteristic = characteristic(CharPropFlags::READ);
+            let device = device(&central, &characteristic);
+            let other_service = uuid_from_u16(0x180f);
+            let duplicate = Characteristic {
+                service_uuid: other_service,
+                descriptors: BTreeSet::new(),
+                ..characteristic.clone()
+            };
+            device.add_service(Service {
+                uuid: other_service,
+                primary: true,
+                start_handle: Some(0x0020),
+                end_handle: Some(0x0021),
+                characteristics: [duplicate.clone()].into(),
+            });
+
+            let map = device.bootstrap().await.unwrap();
+            assert!(device.is_connected().await.unwrap());
+            assert_eq!(map.len(), 2);
+            assert_eq!(map.get_all(CHARACTERISTIC).len(), 2);
+            assert_eq!(map.get_in(SERVICE, CHARACTERISTIC), Some(&characteristic));
+            assert_eq!(map.get_in(other_service, CHARACTERISTIC), Some(&duplicate));
+            assert_eq!(
+                map.service(other_service).collect::<Vec<_>>(),
+                vec![&duplicate]
+            );
+            assert_eq!(map.get(SERVICE), None);
+        }
+    }
+
+    mod descriptor_discovery {
+        use super::*;
+
+        #[tokio::test]
+        async fn with_the_services() {
+            let central = MockCentral::new();
+            let characteristic = characteristic(CharPropFlags::READ);
+            let device = device(&central, &characteristic);
+            let mut stale = characteristic.clone();
+            stale.descriptors.clear();
+            device.connect().await.unwrap();
+            assert!(device.descriptors(&stale).is_empty());
+
+            device.discover_services().await.unwrap();
+            assert_eq!(
+                device.descriptors(&stale),
+                Vec::from_iter(characteristic.descriptors.clone())
+            );
+        }
+
+        #[tokio::test]
+        async fn after_skipping_them() {
+            let central = MockCentral::new();
+            let characteristic = characteristic(CharPropFlags::NOTIFY);
+            let device = device(&central, &characteristic);
+            device.connect().await.unwrap();
+            device
+                .discover_services_with_options(DiscoverOptions::default().descriptors(false))
+                .await
+                .unwrap();
+            assert_eq!(device.characteristics().len(), 1);
+            assert!(device.descriptors(&characteristic).is_empty());
+
+            let descriptors: Vec<_> = characteristic.descriptors.iter().cloned().collect();
+            assert_eq!(
+                device.discover_descriptors(&characteristic).await.unwrap(),
+                descriptors
+            );
+            assert_eq!(device.descriptors(&characteristic), descriptors);
+        }
+    }
+}
@@ -0,0 +1,745 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+    api::{
+        max_write_len_for_mtu, read_gap_device_name,
+        trace::{self, traced_connect, traced_discover_services},
+        BDAddr, CentralEvent, CharPropFlags, Characteristic, Descriptor, DisconnectOptions,
+        DiscoverOptions, Peripheral, PeripheralMetrics, PeripheralProperties, Service,
+        SubscriptionKind, TraceEvent, ValueNotification, WriteType,
+        CLIENT_CHARACTERISTIC_CONFIGURATION, DEFAULT_ATT_MTU,
+    },
+    common::{
+        adapter_manager::AdapterManager,
+        connect_limit::ConnectLimit,
+        metrics::Metrics,
+        pending::PendingOperations,
+        rssi_history::RssiAverage,
+        subscriptions::Subscriptions,
+        util::{notifications_stream_from_broadcast_receiver, replace_descriptors},
+    },
+    platform::PeripheralId,
+    Error, Result,
+};
+use async_trait::async_trait;
+use futures::stream::Stream;
+use log::trace;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt::{self, Debug, Display, Formatter},
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU16, Ordering},
+    sync::{Arc, Mutex, Weak},
+    time::Instant,
+};
+use tokio::sync::{broadcast, watch};
+use uuid::Uuid;
+
+/// A write made to a [`MockPeripheral`] through [`Peripheral::write`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MockWrite {
+    /// The characteristic that was written to.
+    pub characteristic: Characteristic,
+    /// The value that was written.
+    pub value: Vec<u8>,
+    /// The type of write that was requested.
+    pub write_type: WriteType,
+}
+
+/// Implementation of [api::Peripheral](crate::api::Peripheral) backed by an in-memory GATT table.
+///
+/// Clones share the same state, so a test can keep one handle to script the device while the code
+/// under test uses another.
+#[derive(Clone)]
+pub struct MockPeripheral {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    adapter: Weak<AdapterManager<MockPeripheral>>,
+    connect_limit: Arc<ConnectLimit>,
+    id: PeripheralId,
+    properties: Mutex<PeripheralProperties>,
+    connected: AtomicBool,
+    // The largest MTU the device supports, and the one last requested on this connection.
+    mtu: AtomicU16,
+    requested_mtu: AtomicU16,
+    // The services the device has, and the subset of them which has been discovered so far.
+    gatt: Mutex<BTreeSet<Service>>,
+    services: Mutex<BTreeSet<Service>>,
+    values: Mutex<HashMap<CharacteristicKey, Vec<u8>>>,
+    descriptor_values: Mutex<HashMap<DescriptorKey, Vec<u8>>>,
+    subscriptions: Mutex<HashSet<CharacteristicKey>>,
+    // What the client has subscribed to, which unlike the device's subscriptions outlives a
+    // connection.
+    remembered_subscriptions: Subscriptions,
+    writes: Mutex<Vec<MockWrite>>,
+    notifications_channel: broadcast::Sender<ValueNotification>,
+    // Whether reads and writes wait rather than completing.
+    stalled: watch::Sender<bool>,
+    pending: PendingOperations,
+    metrics: Metrics,
+    default_write_type: Mutex<Option<WriteType>>,
+    rssi_average: Mutex<RssiAverage>,
+}
+
+impl MockPeripheral {
+    pub(crate) fn new(
+        adapter: Weak<AdapterManager<Self>>,
+        connect_limit: Arc<ConnectLimit>,
+        id: PeripheralId,
+        mut properties: PeripheralProperties,
+    ) -> Self {
+        properties.last_seen.get_or_insert_with(Instant::now);
+        let mut rssi_average = RssiAverage::default();
+        smooth_rssi(&adapter, &mut rssi_average, &mut properties);
+        let (broadcast_sender, _) = broadcast::channel(16);
+        MockPeripheral {
+            shared: Arc::new(Shared {
+                adapter,
+                connect_limit,
+                id,
+                properties: Mutex::new(properties),
+                connected: AtomicBool::new(false),
+                mtu: AtomicU16::new(DEFAULT_ATT_MTU),
+                requested_mtu: AtomicU16::new(u16::MAX),
+                gatt: Mutex::new(BTreeSet::new()),
+                services: Mutex::new(BTreeSet::new()),
+                values: Mutex::new(HashMap::new()),
+                descriptor_values: Mutex::new(HashMap::new()),
+                subscriptions: Mutex::new(HashSet::new()),
+                remembered_subscriptions: Subscriptions::default(),
+                writes: Mutex::new(Vec::new()),
+                notifications_channel: broadcast_sender,
+                stalled: watch::Sender::new(false),
+                pending: PendingOperations::default(),
+                metrics: Metrics::default(),
+                default_write_type: Mutex::new(None),
+                rssi_average: Mutex::new(rssi_average),
+            }),
+        }
+    }
+
+    /// Adds a service to the device's GATT table. It will be returned by
+    /// [`Peripheral::services`] once [`Peripheral::discover_services`] has been called. While
+    /// connected, this emits [`CentralEvent::ServicesChanged`], as the device's Service Changed
+    /// indication would.
+    pub fn add_service(&self, service: Service) {
+        self.shared.gatt.lock().unwrap().insert(service);
+        if self.shared.connected.load(Ordering::Relaxed) {
+            self.emit_event(CentralEvent::ServicesChanged(self.id()));
+        }
+    }
+
+    /// Sets the value returned when reading the given characteristic.
+    pub fn set_value(&self, characteristic: &Characteristic, value: Vec<u8>) {
+        self.shared
+            .values
+            .lock()
+            .unwrap()
+            .insert(characteristic_key(characteristic), value);
+    }
+
+    /// Sets the value returned when reading the given descriptor.
+    pub fn set_descriptor_value(&self, descriptor: &Descriptor, value: Vec<u8>) {
+        self.shared
+            .descriptor_values
+            .lock()
+            .unwrap()
+            .insert(descriptor_key(descriptor), value);
+    }
+
+    /// Replaces the advertised properties of the device, as if it had advertised them, and emits
+    /// [`CentralEvent::DeviceUpdated`]. [`PeripheralProperties::last_seen`] is set to now, and
+    /// [`PeripheralProperties::smoothed_rssi`] to the average including `rssi`, unless `properties`
+    /// already has values for them.
+    pub fn set_properties(&self, mut properties: PeripheralProperties) {
+        properties.last_seen.get_or_insert_with(Instant::now);
+        smooth_rssi(
+            &self.shared.adapter,
+            &mut self.shared.rssi_average.lock().unwrap(),
+            &mut properties,
+        );
+        *self.shared.properties.lock().unwrap() = properties;
+        self.emit_event(CentralEvent::DeviceUpdated(self.id()));
+    }
+
+    /// Sends a notification for the given characteristic, as if the device had changed its value.
+    /// Notifications for characteristics which aren't currently subscribed to are dropped, as they
+    /// would be by a real device.
+    pub fn notify(&self, characteristic: &Characteristic, value: Vec<u8>) {
+        let key = characteristic_key(characteristic);
+        if !self.shared.subscriptions.lock().unwrap().contains(&key) {
+            trace!("Dropping notification for unsubscribed {}", characteristic);
+            return;
+        }
+        self.shared
+            .values
+            .lock()
+            .unwrap()
+            .insert(key, value.clone());
+        trace::emit(|| TraceEvent::NotificationDelivered {
+            id: self.id(),
+            characteristic: characteristic.uuid,
+        });
+        self.shared.metrics.notification();
+        if let Err(lost) = self.shared.notifications_channel.send(ValueNotification {
+            uuid: characteristic.uuid,
+            service_uuid: characteristic.service_uuid,
+            instance_id: characteristic.instance_id,
+            value,
+        }) {
+            trace!("Lost notification, while nothing subscribed: {:?}", lost);
+        }
+    }
+
+    /// Sets the largest ATT MTU the device supports, which is what gets negotiated unless
+    /// [`Peripheral::request_mtu`] asks for a smaller one. The MTU determines
+    /// [`Peripheral::max_write_len`] while connected. Defaults to the minimum of 23.
+    pub fn set_mtu(&self, mtu: u16) {
+        self.shared.mtu.store(mtu, Ordering::Relaxed);
+    }
+
+    /// Drops the connection as if the device had gone out of range, emitting
+    /// [`CentralEvent::DeviceDisconnected`] if it was connected.
+    pub fn disconnect_unexpectedly(&self) {
+        self.on_disconnected();
+    }
+
+    /// Makes the device stop answering reads, writes and subscription changes, which wait until this
+    /// is called again with `false`, as they would for a device which has gone quiet.
+    pub fn set_stalled(&self, stalled: bool) {
+        self.shared.stalled.send_replace(stalled);
+    }
+
+    /// Returns whether the given characteristic is currently subscribed to.
+    pub fn is_subscribed(&self, characteristic: &Characteristic) -> bool {
+        self.shared
+            .subscriptions
+            .lock()
+            .unwrap()
+            .contains(&characteristic_key(characteristic))
+    }
+
+    /// Returns all writes made to the device so far, oldest first.
+    pub fn writes(&self) -> Vec<MockWrite> {
+        self.shared.writes.lock().unwrap().clone()
+    }
+
+    /// Drops the connection, returning whether there was one. Only then is
+    /// [`CentralEvent::DeviceDisconnected`] emitted.
+    fn on_disconnected(&self) -> bool {
+        // Like a real device, discovered services and subscriptions don't survive a reconnect.
+        self.shared.services.lock().unwrap().clear();
+        self.shared.subscriptions.lock().unwrap().clear();
+        self.shared
+            .descriptor_values
+            .lock()
+            .unwrap()
+            .retain(|key, _| key.3 != CLIENT_CHARACTERISTIC_CONFIGURATION);
+        self.shared.requested_mtu.store(u16::MAX, Ordering::Relaxed);
+        let was_connected = self.shared.connected.swap(false, Ordering::Relaxed);
+        if was_connected {
+            self.emit_event(CentralEvent::DeviceDisconnected(self.id()));
+        }
+        was_connected
+    }
+
+    /// Sets the value of the characteristic's Client Characteristic Configuration descriptor, if it
+    /// has one, as a device does when it is subscribed to or unsubscribed from.
+    fn set_client_configuration(&self, characteristic: &Characteristic, value: u16) {
+        if let Some(cccd) = characteristic
+            .descriptors
+            .iter()
+            .find(|descriptor| descriptor.uuid == CLIENT_CHARACTERISTIC_CONFIGURATION)
+        {
+            self.set_descriptor_value(cccd, value.to_le_bytes().to_vec());
+        }
+    }
+
+    fn negotiated_mtu(&self) -> u16 {
+        self.shared
+            .mtu
+            .load(Ordering::Relaxed)
+            .min(self.shared.requested_mtu.load(Ordering::Relaxed))
+    }
+
+    async fn wait_until_responding(&self) {
+        // The sender lives as long as the peripheral, so waiting can't fail.
+        let _ = self
+            .shared
+            .stalled
+            .subscribe()
+            .wait_for(|stalled| !stalled)
+            .await;
+    }
+
+    fn ensure_connected(&self) -> Result<()> {
+        if self.shared.connected.load(Ordering::Relaxed) {
+            Ok(())
+        } else {
+            Err(Error::NotConnected)
+        }
+    }
+
+    /// Looks up a discovered characteristic, returning it as the device describes it.
+    fn find_characteristic(&self, characteristic: &Characteristic) -> Result<Characteristic> {
+        self.ensure_connected()?;
+        self.characteristics()
+            .into_iter()
+            .find(|c| characteristic_key(c) == characteristic_key(characteristic))
+            .ok_or(Error::NoSuchCharacteristic)
+    }
+
+    fn find_descriptor(&self, descriptor: &Descriptor) -> Result<()> {
+        self.ensure_connected()?;
+        self.characteristics()
+            .iter()
+            .flat_map(|c| c.descriptors.iter())
+            .find(|d| *d == descriptor)
+            .map(|_| ())
+            .ok_or(Error::NoSuchCharacteristic)
+    }
+
+    fn emit_event(&self, event: CentralEvent) {
+        if let Some(manager) = self.shared.adapter.upgrade() {
+            manager.emit(event);
+        } else {
+            trace!("Could not emit an event. AdapterManager has been dropped");
+        }
+    }
+}
+
+/// Identifies a characteristic by service UUID, UUID and instance ID.
+type CharacteristicKey = (Uuid, Uuid, u16);
+/// Identifies a descriptor by its characteristic's key and its own UUID.
+type DescriptorKey = (Uuid, Uuid, u16, Uuid);
+
+fn characteristic_key(characteristic: &Characteristic) -> CharacteristicKey {
+    (
+        characteristic.service_uuid,
+        characteristic.uuid,
+        characteristic.instance_id,
+    )
+}
+
+fn descriptor_key(descriptor: &Descriptor) -> DescriptorKey {
+    (
+        descriptor.service_uuid,
+        descriptor.characteristic_uuid,
+        descriptor.characteristic_instance_id,
+        descriptor.uuid,
+    )
+}
+
+/// Adds the signal strength of newly advertised properties to the average, unless they already
+/// have a smoothed one.
+fn smooth_rssi(
+    adapter: &Weak<AdapterManager<MockPeripheral>>,
+    average: &mut RssiAverage,
+    properties: &mut PeripheralProperties,
+) {
+    if properties.smoothed_rssi.is_some() {
+        return;
+    }
+    if let (Some(rssi), Some(adapter)) = (properties.rssi, adapter.upgrade()) {
+        properties.smoothed_rssi = Some(average.add(rssi, adapter.rssi_smoothing().weight()));
+    }
+}
+
+impl Display for MockPeripheral {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let connected = if self.shared.connected.load(Ordering::Relaxed) {
+            " connected"
+        } else {
+            ""
+        };
+        write!(
+            f,
+            "{} {}{}",
+            self.address(),
+            self.shared
+                .properties
+                .lock()
+                .unwrap()
+                .local_name
+                .clone()
+                .unwrap_or_else(|| "(unknown)".to_string()),
+            connected
+        )
+    }
+}
+
+impl Debug for MockPeripheral {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("MockPeripheral")
+            .field("id", &self.shared.id)
+            .field("properties", &self.shared.properties.lock().unwrap())
+            .field("services", &self.shared.services.lock().unwrap())
+            .field("connected", &self.shared.connected.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+#[async_trait]
+impl Peripheral for MockPeripheral {
+    fn id(&self) -> PeripheralId {
+        self.shared.id.clone()
+    }
+
+    fn address(&self) -> BDAddr {
+        self.shared.properties.lock().unwrap().address
+    }
+
+    fn max_write_len(&self, write_type: WriteType) -> usize {
+        let mtu = if self.shared.connected.load(Ordering::Relaxed) {
+            self.negotiated_mtu()
+        } else {
+            DEFAULT_ATT_MTU
+        };
+        max_write_len_for_mtu(mtu, write_type)
+    }
+
+    async fn request_mtu(&self, mtu: u16) -> Result<u16> {
+        self.ensure_connected()?;
+        self.shared
+            .requested_mtu
+            .store(mtu.max(DEFAULT_ATT_MTU), Ordering::Relaxed);
+        Ok(self.negotiated_mtu())
+    }
+
+    fn pending_operations(&self) -> usize {
+        self.shared.pending.operations()
+    }
+
+    fn pending_writes(&self) -> usize {
+        self.shared.pending.writes()
+    }
+
+    fn abort_pending(&self) {
+        self.shared.pending.abort();
+    }
+
+    fn metrics(&self) -> PeripheralMetrics {
+        let mtu = self
+            .shared
+            .connected
+            .load(Ordering::Relaxed)
+            .then(|| self.negotiated_mtu());
+        self.shared.metrics.snapshot(mtu)
+    }
+
+    fn default_write_type(&self) -> Option<WriteType> {
+        *self.shared.default_write_type.lock().unwrap()
+    }
+
+    fn set_default_write_type(&self, write_type: WriteType) {
+        *self.shared.default_write_type.lock().unwrap() = Some(write_type);
+    }
+
+    fn subscriptions(&self) -> Vec<Characteristic> {
+        self.shared.remembered_subscriptions.get()
+    }
+
+    async fn properties(&self) -> Result<Option<PeripheralProperties>> {
+        Ok(Some(self.shared.properties.lock().unwrap().clone()))
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        self.shared.services.lock().unwrap().clone()
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        Ok(self.shared.connected.load(Ordering::Relaxed))
+    }
+
+    async fn connect(&self) -> Result<()> {
+        let _permit = self.shared.connect_limit.acquire().await;
+        traced_connect(self, async {
+            self.shared.connected.store(true, Ordering::Relaxed);
+            self.shared.metrics.connected();
+            self.emit_event(CentralEvent::DeviceConnected(self.id()));
+            Ok(())
+        })
+        .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.on_disconnected();
+        Ok(())
+    }
+
+    async fn disconnect_with_options(&self, options: DisconnectOptions) -> Result<()> {
+        if !self.on_disconnected() {
+            return Ok(());
+        }
+        if let Some(reason) = options.reason {
+            self.emit_event(CentralEvent::DeviceDisconnectedWithReason {
+                id: self.id(),
+                reason,
+            });
+        }
+        Ok(())
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        self.discover_services_with_options(DiscoverOptions::default())
+            .await
+    }
+
+    async fn discover_services_with_options(&self, options: DiscoverOptions) -> Result<()> {
+        traced_discover_services(self, async {
+            self.ensure_connected()?;
+            let mut gatt = self.shared.gatt.lock().unwrap().clone();
+            if !options.descriptors {
+                gatt = gatt
+                    .into_iter()
+                    .map(|mut service| {
+                        service.characteristics = service
+                            .characteristics
+                            .into_iter()
+                            .map(|mut characteristic| {
+                                characteristic.descriptors.clear();
+                                characteristic
+                            })
+                            .collect();
+                        service
+                    })
+                    .collect();
+            }
+            if !options.services.is_empty() {
+                // Like CoreBluetooth and WinRT, ask the device for just these services.
+                gatt.retain(|service| options.services.contains(&service.uuid));
+                let services = self.shared.services.lock().unwrap();
+                gatt.extend(
+                    services
+                        .iter()
+                        .filter(|service| !options.services.contains(&service.uuid))
+                        .cloned(),
+                );
+            }
+            // Subscriptions survive rediscovery, unless the characteristic has gone away.
+            let characteristics: HashSet<_> = gatt
+                .iter()
+                .flat_map(|service| service.characteristics.iter().map(characteristic_key))
+                .collect();
+            self.shared
+                .subscriptions
+                .lock()
+                .unwrap()
+                .retain(|key| characteristics.contains(key));
+            *self.shared.services.lock().unwrap() = gatt;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn discover_descriptors(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Vec<Descriptor>> {
+        let characteristic = self.find_characteristic(characteristic)?;
+        let descriptors = self
+            .shared
+            .gatt
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|service| service.characteristics.iter())
+            .find(|c| characteristic_key(c) == characteristic_key(&characteristic))
+            .map(|c| c.descriptors.clone())
+            .unwrap_or_default();
+        replace_descriptors(
+            &mut self.shared.services.lock().unwrap(),
+            &characteristic,
+            descriptors.clone(),
+        );
+        Ok(descriptors.into_iter().collect())
+    }
+
+    async fn require_encryption(&self) -> Result<()> {
+        // Mock links are taken to be encrypted.
+        self.ensure_connected()
+    }
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        self.shared
+            .pending
+            .track_write(async {
+                let characteristic = self.find_characteristic(characteristic)?;
+                if !characteristic
+                    .properties
+                    .contains(write_type.required_property())
+                {
+                    return Err(Error::NotSupported(format!(
+                        "{:?} on characteristic {}",
+                        write_type, characteristic.uuid
+                    )));
+                }
+                self.wait_until_responding().await;
+                self.shared
+                    .values
+                    .lock()
+                    .unwrap()
+                    .insert(characteristic_key(&characteristic), data.to_vec());
+                self.shared.writes.lock().unwrap().push(MockWrite {
+                    characteristic,
+                    value: data.to_vec(),
+                    write_type,
+                });
+                Ok(())
+            })
+            .await
+            .inspect(|()| self.shared.metrics.written(data.len()))
+    }
+
+    async fn execute_reliable_write(&self, writes: &[(Characteristic, Vec<u8>)]) -> Result<()> {
+        // Check every write before making any, so that the transaction is all or nothing.
+        let mut checked = Vec::with_capacity(writes.len());
+        for (characteristic, data) in writes {
+            let characteristic = self.find_characteristic(characteristic)?;
+            if !characteristic.properties.contains(CharPropFlags::WRITE) {
+                return Err(Error::NotSupported(format!(
+                    "Reliable write on characteristic {}",
+                    characteristic.uuid
+                )));
+            }
+            checked.push((characteristic, data));
+        }
+        for (characteristic, data) in checked {
+            self.write(&characteristic, data, WriteType::WithResponse)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.shared
+            .pending
+            .track(async {
+                let characteristic = self.find_characteristic(characteristic)?;
+                if !characteristic.properties.contains(CharPropFlags::READ) {
+                    return Err(Error::NotSupported(format!(
+                        "Reading characteristic {}",
+                        characteristic.uuid
+                    )));
+                }
+                self.wait_until_responding().await;
+                Ok(self
+                    .shared
+                    .values
+                    .lock()
+                    .unwrap()
+                    .get(&characteristic_key(&characteristic))
+                    .cloned()
+                    .unwrap_or_default())
+            })
+            .await
+            .inspect(|value| self.shared.metrics.read(value.len()))
+    }
+
+    async fn read_device_name(&self) -> Result<String> {
+        let name = read_gap_device_name(self).await?;
+        self.shared.properties.lock().unwrap().local_name = Some(name.clone());
+        Ok(name)
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let subscribe = async {
+            let characteristic = self.find_characteristic(characteristic)?;
+            if !characteristic
+                .properties
+                .intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE)
+            {
+                return Err(Error::NotSupported(format!(
+                    "Subscribing to characteristic {}",
+                    characteristic.uuid
+                )));
+            }
+            self.wait_until_responding().await;
+            self.shared
+                .subscriptions
+                .lock()
+                .unwrap()
+                .insert(characteristic_key(&characteristic));
+            let kind = if characteristic.properties.contains(CharPropFlags::NOTIFY) {
+                SubscriptionKind::Notify
+            } else {
+                SubscriptionKind::Indicate
+            };
+            self.set_client_configuration(&characteristic, kind.cccd_value());
+            Ok(())
+        };
+        self.shared
+            .remembered_subscriptions
+            .subscribe(characteristic, self.shared.pending.track(subscribe))
+            .await
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let unsubscribe = async {
+            let characteristic = self.find_characteristic(characteristic)?;
+            self.wait_until_responding().await;
+            self.shared
+                .subscriptions
+                .lock()
+                .unwrap()
+                .remove(&characteristic_key(&characteristic));
+            self.set_client_configuration(&characteristic, 0);
+            Ok(())
+        };
+        self.shared
+            .remembered_subscriptions
+            .unsubscribe(characteristic, self.shared.pending.track(unsubscribe))
+            .await
+    }
+
+    async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        let receiver = self.shared.notifications_channel.subscribe();
+        Ok(notifications_stream_from_broadcast_receiver(receiver))
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        self.shared
+            .pending
+            .track(async {
+                self.find_descriptor(descriptor)?;
+                self.wait_until_responding().await;
+                self.shared
+                    .descriptor_values
+                    .lock()
+                    .unwrap()
+                    .insert(descriptor_key(descriptor), data.to_vec());
+                Ok(())
+            })
+            .await
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        self.shared
+            .pending
+            .track(async {
+                self.find_descriptor(descriptor)?;
+                self.wait_until_responding().await;
+                Ok(self
+                    .shared
+                    .descriptor_values
+                    .lock()
+                    .unwrap()
+                    .get(&descriptor_key(descriptor))
+                    .cloned()
+                    .unwrap_or_default())
+            })
+            .await
+    }
+}
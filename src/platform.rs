@@ -5,14 +5,26 @@
 pub use crate::bluez::{
     adapter::Adapter, manager::Manager, peripheral::Peripheral, peripheral::PeripheralId,
 };
+/// Conversions between `Uuid` and CoreBluetooth's `CBUUID`, for code which mixes btleplug with
+/// direct CoreBluetooth calls. These use the `objc2-core-bluetooth` version btleplug depends on.
+#[cfg(target_vendor = "apple")]
+pub use crate::corebluetooth::utils::core_bluetooth::{cbuuid_to_uuid, uuid_to_cbuuid};
 #[cfg(target_vendor = "apple")]
 pub use crate::corebluetooth::{
-    adapter::Adapter, manager::Manager, peripheral::Peripheral, peripheral::PeripheralId,
+    adapter::Adapter,
+    manager::{Manager, ManagerOptions},
+    peripheral::Peripheral,
+    peripheral::PeripheralId,
 };
 #[cfg(target_os = "android")]
 pub use crate::droidplug::{
     adapter::Adapter, init, manager::Manager, peripheral::Peripheral, peripheral::PeripheralId,
 };
+/// Conversions between `Uuid` and the Windows `GUID`, for code which mixes btleplug with direct
+/// WinRT calls. The byte order of the two differs, so converting through the raw bytes gets the
+/// first three fields backwards. These use the `windows` crate version btleplug depends on.
+#[cfg(target_os = "windows")]
+pub use crate::winrtble::utils::{to_guid as uuid_to_guid, to_uuid as guid_to_uuid};
 #[cfg(target_os = "windows")]
 pub use crate::winrtble::{
     adapter::Adapter, manager::Manager, peripheral::Peripheral, peripheral::PeripheralId,
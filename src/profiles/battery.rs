@@ -0,0 +1,57 @@
+//! The Battery service.
+
+use crate::api::{bleuuid::uuid_from_u16, Peripheral};
+use crate::{Error, Result};
+use futures::future::ready;
+use futures::stream::{self, Stream, StreamExt};
+use log::warn;
+use std::pin::Pin;
+use uuid::Uuid;
+
+/// The UUID of the Battery service.
+pub const BATTERY_SERVICE: Uuid = uuid_from_u16(0x180F);
+/// The UUID of the Battery Level characteristic.
+pub const BATTERY_LEVEL: Uuid = uuid_from_u16(0x2A19);
+
+/// Parses a value of the Battery Level characteristic, as read or notified, into a percentage.
+/// Returns `None` if the value is empty or more than 100.
+pub fn parse_battery_level(data: &[u8]) -> Option<u8> {
+    data.first().copied().filter(|&level| level <= 100)
+}
+
+/// Reads the Battery Level characteristic of the given peripheral and subscribes to it, and returns
+/// a stream of the current level followed by its updates, as percentages. Services must already
+/// have been discovered. Devices whose battery level can only be read give a stream of just the
+/// current level, and those whose level can only be notified give just the updates. Values which
+/// can't be parsed are logged and skipped.
+pub async fn watch_battery_level<P: Peripheral>(
+    peripheral: &P,
+) -> Result<Pin<Box<dyn Stream<Item = u8> + Send>>> {
+    let characteristic = peripheral
+        .characteristics()
+        .into_iter()
+        .find(|c| c.service_uuid == BATTERY_SERVICE && c.uuid == BATTERY_LEVEL)
+        .ok_or(Error::NoSuchCharacteristic)?;
+    let (current, updates) = peripheral.read_and_subscribe(&characteristic).await?;
+    Ok(Box::pin(stream::iter(current).chain(updates).filter_map(
+        |value| {
+            ready(parse_battery_level(&value).or_else(|| {
+                warn!("Ignoring battery level {:?}", value);
+                None
+            }))
+        },
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_battery_level(&[0]), Some(0));
+        assert_eq!(parse_battery_level(&[100]), Some(100));
+        assert_eq!(parse_battery_level(&[101]), None);
+        assert_eq!(parse_battery_level(&[]), None);
+    }
+}
@@ -0,0 +1,205 @@
+//! The Heart Rate profile.
+
+use crate::api::{bleuuid::uuid_from_u16, Peripheral};
+use crate::{Error, Result};
+use futures::future::ready;
+use futures::stream::{Stream, StreamExt};
+use log::warn;
+use std::pin::Pin;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// The UUID of the Heart Rate service.
+pub const HEART_RATE_SERVICE: Uuid = uuid_from_u16(0x180D);
+/// The UUID of the Heart Rate Measurement characteristic.
+pub const HEART_RATE_MEASUREMENT: Uuid = uuid_from_u16(0x2A37);
+
+const FLAG_BPM_16_BIT: u8 = 0x01;
+const FLAG_CONTACT_SUPPORTED: u8 = 0x04;
+const FLAG_CONTACT_DETECTED: u8 = 0x02;
+const FLAG_ENERGY_EXPENDED: u8 = 0x08;
+const FLAG_RR_INTERVALS: u8 = 0x10;
+
+/// Whether the sensor is in contact with the skin.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SensorContact {
+    /// The sensor doesn't report whether it is in contact.
+    NotSupported,
+    NotDetected,
+    Detected,
+}
+
+/// A value of the Heart Rate Measurement characteristic.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HeartRateMeasurement {
+    /// The heart rate, in beats per minute.
+    pub bpm: u16,
+    pub sensor_contact: SensorContact,
+    /// The energy expended since it was last reset, in kilojoules, if the sensor included it.
+    pub energy_expended: Option<u16>,
+    /// The intervals between successive beats since the last measurement, in units of 1/1024
+    /// seconds. See [`HeartRateMeasurement::rr_interval_durations`].
+    pub rr_intervals: Vec<u16>,
+}
+
+/// An error parsing a [`HeartRateMeasurement`].
+#[derive(Debug, thiserror::Error, Clone, PartialEq)]
+pub enum ParseHeartRateError {
+    #[error("Heart rate measurement is truncated")]
+    Truncated,
+    #[error("Heart rate measurement has an incomplete RR interval")]
+    IncompleteRrInterval,
+}
+
+impl HeartRateMeasurement {
+    /// Parses a value of the Heart Rate Measurement characteristic, as read or notified.
+    pub fn parse(data: &[u8]) -> std::result::Result<Self, ParseHeartRateError> {
+        let (&flags, mut rest) = data.split_first().ok_or(ParseHeartRateError::Truncated)?;
+        let bpm = if flags & FLAG_BPM_16_BIT != 0 {
+            take_u16(&mut rest)?
+        } else {
+            let (&bpm, remaining) = rest.split_first().ok_or(ParseHeartRateError::Truncated)?;
+            rest = remaining;
+            bpm.into()
+        };
+        let sensor_contact = match (
+            flags & FLAG_CONTACT_SUPPORTED != 0,
+            flags & FLAG_CONTACT_DETECTED != 0,
+        ) {
+            (false, _) => SensorContact::NotSupported,
+            (true, false) => SensorContact::NotDetected,
+            (true, true) => SensorContact::Detected,
+        };
+        let energy_expended = if flags & FLAG_ENERGY_EXPENDED != 0 {
+            Some(take_u16(&mut rest)?)
+        } else {
+            None
+        };
+        let mut rr_intervals = Vec::new();
+        if flags & FLAG_RR_INTERVALS != 0 {
+            if rest.len() % 2 != 0 {
+                return Err(ParseHeartRateError::IncompleteRrInterval);
+            }
+            while !rest.is_empty() {
+                rr_intervals.push(take_u16(&mut rest)?);
+            }
+        }
+        Ok(Self {
+            bpm,
+            sensor_contact,
+            energy_expended,
+            rr_intervals,
+        })
+    }
+
+    /// The intervals between successive beats since the last measurement.
+    pub fn rr_interval_durations(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.rr_intervals
+            .iter()
+            .map(|&interval| Duration::from_secs_f64(f64::from(interval) / 1024.0))
+    }
+}
+
+fn take_u16(data: &mut &[u8]) -> std::result::Result<u16, ParseHeartRateError> {
+    match *data {
+        [low, high, rest @ ..] => {
+            *data = rest;
+            Ok(u16::from_le_bytes([*low, *high]))
+        }
+        _ => Err(ParseHeartRateError::Truncated),
+    }
+}
+
+/// Subscribes to the Heart Rate Measurement characteristic of the given peripheral, and returns a
+/// stream of its measurements. Services must already have been discovered. Notifications which
+/// can't be parsed are logged and skipped.
+pub async fn subscribe_heart_rate<P: Peripheral>(
+    peripheral: &P,
+) -> Result<Pin<Box<dyn Stream<Item = HeartRateMeasurement> + Send>>> {
+    let characteristic = peripheral
+        .characteristics()
+        .into_iter()
+        .find(|c| c.service_uuid == HEART_RATE_SERVICE && c.uuid == HEART_RATE_MEASUREMENT)
+        .ok_or(Error::NoSuchCharacteristic)?;
+    // Get the stream before subscribing so that the first measurement isn't missed.
+    let notifications = peripheral.notifications().await?;
+    peripheral.subscribe(&characteristic).await?;
+    Ok(Box::pin(notifications.filter_map(|notification| {
+        ready(if notification.uuid == HEART_RATE_MEASUREMENT {
+            HeartRateMeasurement::parse(&notification.value)
+                .map_err(|e| warn!("Ignoring heart rate measurement: {}", e))
+                .ok()
+        } else {
+            None
+        })
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_8_bit_bpm() {
+        assert_eq!(
+            HeartRateMeasurement::parse(&[0x00, 72]),
+            Ok(HeartRateMeasurement {
+                bpm: 72,
+                sensor_contact: SensorContact::NotSupported,
+                energy_expended: None,
+                rr_intervals: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn parse_all_fields() {
+        let data = [0x1f, 0x2c, 0x01, 0x10, 0x00, 0x00, 0x04, 0x01, 0x04];
+        let measurement = HeartRateMeasurement::parse(&data).unwrap();
+        assert_eq!(
+            measurement,
+            HeartRateMeasurement {
+                bpm: 300,
+                sensor_contact: SensorContact::Detected,
+                energy_expended: Some(16),
+                rr_intervals: vec![1024, 1025],
+            }
+        );
+        assert_eq!(
+            measurement.rr_interval_durations().next(),
+            Some(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn parse_sensor_contact() {
+        let contact = |flags| {
+            HeartRateMeasurement::parse(&[flags, 60])
+                .unwrap()
+                .sensor_contact
+        };
+        assert_eq!(contact(0x02), SensorContact::NotSupported);
+        assert_eq!(contact(0x04), SensorContact::NotDetected);
+        assert_eq!(contact(0x06), SensorContact::Detected);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(
+            HeartRateMeasurement::parse(&[]),
+            Err(ParseHeartRateError::Truncated)
+        );
+        assert_eq!(
+            HeartRateMeasurement::parse(&[0x01, 60]),
+            Err(ParseHeartRateError::Truncated)
+        );
+        assert_eq!(
+            HeartRateMeasurement::parse(&[0x08, 60, 1]),
+            Err(ParseHeartRateError::Truncated)
+        );
+        assert_eq!(
+            HeartRateMeasurement::parse(&[0x10, 60, 0, 4, 1]),
+            Err(ParseHeartRateError::IncompleteRrInterval)
+        );
+    }
+}
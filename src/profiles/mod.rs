@@ -0,0 +1,6 @@
+//! Helpers for standard GATT profiles, which parse the values of their characteristics into typed
+//! structures. Enabled by the `profiles` feature.
+
+pub mod battery;
+pub mod heart_rate;
+pub mod presentation_format;
@@ -0,0 +1,162 @@
+//! The Characteristic Presentation Format descriptor, which says how to interpret the value of the
+//! characteristic it belongs to.
+
+use crate::api::{
+    bleuuid::{uuid_from_u16, BleUuid},
+    Characteristic, Peripheral,
+};
+use crate::{Error, Result};
+use uuid::Uuid;
+
+/// The UUID of the Characteristic Presentation Format descriptor.
+pub const CHARACTERISTIC_PRESENTATION_FORMAT: Uuid = uuid_from_u16(0x2904);
+
+/// The namespace of descriptions assigned by the Bluetooth SIG.
+pub const BLUETOOTH_SIG_NAMESPACE: u8 = 0x01;
+
+/// A value of the Characteristic Presentation Format descriptor.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PresentationFormat {
+    /// The format of the characteristic's value, from the Bluetooth SIG's Assigned Numbers, such as
+    /// 0x04 for an unsigned 8-bit integer or 0x0e for a signed 16-bit one.
+    pub format: u8,
+    /// The power of ten the value is to be multiplied by, for integer formats. See
+    /// [`PresentationFormat::scale`].
+    pub exponent: i8,
+    /// The unit of the value, as a UUID from the Bluetooth SIG's Assigned Numbers. See
+    /// [`PresentationFormat::unit_name`].
+    pub unit: Uuid,
+    /// The organisation which assigned `description`, which is [`BLUETOOTH_SIG_NAMESPACE`] for
+    /// standard descriptions.
+    pub namespace: u8,
+    /// Which of several characteristics of the same type this is, such as 0x0001 for "first" or
+    /// 0x0106 for "left" in the Bluetooth SIG namespace.
+    pub description: u16,
+}
+
+impl PresentationFormat {
+    /// Parses a value of the Characteristic Presentation Format descriptor. Returns `None` if it is
+    /// shorter than the 7 bytes it should be.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let data: &[u8; 7] = data.get(..7)?.try_into().ok()?;
+        Some(PresentationFormat {
+            format: data[0],
+            exponent: data[1] as i8,
+            unit: uuid_from_u16(u16::from_le_bytes([data[2], data[3]])),
+            namespace: data[4],
+            description: u16::from_le_bytes([data[5], data[6]]),
+        })
+    }
+
+    /// Applies the exponent to a raw integer value of the characteristic, giving the value in the
+    /// unit.
+    pub fn scale(&self, raw: f64) -> f64 {
+        raw * 10f64.powi(self.exponent.into())
+    }
+
+    /// Returns the name of the unit, like "degree Celsius", if it is one of the more common units.
+    pub fn unit_name(&self) -> Option<&'static str> {
+        unit_name(self.unit.to_ble_u16()?)
+    }
+}
+
+/// Parses a value of the Characteristic Presentation Format descriptor, like
+/// [`PresentationFormat::parse`].
+pub fn parse_presentation_format(data: &[u8]) -> Option<PresentationFormat> {
+    PresentationFormat::parse(data)
+}
+
+/// Reads the Characteristic Presentation Format descriptor of the given characteristic, if it has
+/// one. The peripheral must be connected and its services discovered.
+pub async fn read_presentation_format<P: Peripheral>(
+    peripheral: &P,
+    characteristic: &Characteristic,
+) -> Result<Option<PresentationFormat>> {
+    let Some(descriptor) = characteristic
+        .descriptors
+        .iter()
+        .find(|descriptor| descriptor.uuid == CHARACTERISTIC_PRESENTATION_FORMAT)
+    else {
+        return Ok(None);
+    };
+    let value = peripheral.read_descriptor(descriptor).await?;
+    PresentationFormat::parse(&value)
+        .map(Some)
+        .ok_or_else(|| Error::Other(format!("Invalid presentation format {:?}", value).into()))
+}
+
+/// The names of the more common units from the Bluetooth SIG's Assigned Numbers document.
+fn unit_name(unit: u16) -> Option<&'static str> {
+    Some(match unit {
+        0x2700 => "unitless",
+        0x2701 => "metre",
+        0x2702 => "kilogram",
+        0x2703 => "second",
+        0x2704 => "ampere",
+        0x2705 => "kelvin",
+        0x2706 => "mole",
+        0x2707 => "candela",
+        0x2710 => "square metre",
+        0x2711 => "cubic metre",
+        0x2712 => "metre per second",
+        0x2713 => "metre per second squared",
+        0x2720 => "radian",
+        0x2722 => "hertz",
+        0x2723 => "newton",
+        0x2724 => "pascal",
+        0x2725 => "joule",
+        0x2726 => "watt",
+        0x2727 => "coulomb",
+        0x2728 => "volt",
+        0x2729 => "farad",
+        0x272a => "ohm",
+        0x272f => "degree Celsius",
+        0x2731 => "lux",
+        0x2760 => "minute",
+        0x2761 => "hour",
+        0x2762 => "day",
+        0x2763 => "degree",
+        0x2767 => "litre",
+        0x2780 => "bar",
+        0x27a6 => "kilometre per hour",
+        0x27a7 => "mile per hour",
+        0x27a8 => "revolution per minute",
+        0x27ab => "kilowatt hour",
+        0x27ac => "degree Fahrenheit",
+        0x27ad => "percent",
+        0x27af => "beats per minute",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        // A signed 16-bit temperature in hundredths of a degree Celsius.
+        let format =
+            parse_presentation_format(&[0x0e, 0xfe, 0x2f, 0x27, 0x01, 0x00, 0x00]).unwrap();
+        assert_eq!(
+            format,
+            PresentationFormat {
+                format: 0x0e,
+                exponent: -2,
+                unit: uuid_from_u16(0x272f),
+                namespace: BLUETOOTH_SIG_NAMESPACE,
+                description: 0,
+            }
+        );
+        assert_eq!(format.unit_name(), Some("degree Celsius"));
+        assert!((format.scale(2150.0) - 21.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parse_truncated() {
+        assert_eq!(
+            parse_presentation_format(&[0x0e, 0xfe, 0x2f, 0x27, 0x01, 0x00]),
+            None
+        );
+    }
+}
@@ -11,20 +11,43 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use super::{ble::watcher::BLEWatcher, peripheral::Peripheral, peripheral::PeripheralId};
+use super::{
+    ble::watcher::BLEWatcher,
+    peripheral::{
+        advertisement_from_args, connectable_from, local_name_from_args, Peripheral, PeripheralId,
+    },
+    utils,
+};
 use crate::{
-    api::{BDAddr, Central, CentralEvent, CentralState, ScanFilter},
-    common::adapter_manager::AdapterManager,
+    api::{
+        AdapterCapabilities, Advertisement, BDAddr, Central, CentralEvent, CentralState,
+        LeFeatures, ScanFilter, ScanOptions, ScanSubscription,
+    },
+    common::{
+        adapter_manager::AdapterManager,
+        scan_state::ScanState,
+        scan_subscriptions::{subscribe_scan, ScanSubscriptions},
+    },
     Error, Result,
 };
 use async_trait::async_trait;
-use futures::stream::Stream;
+use futures::channel::mpsc::{self, UnboundedReceiver};
+use futures::stream::{Stream, StreamExt};
+use log::{debug, error};
 use std::convert::TryInto;
 use std::fmt::{self, Debug, Formatter};
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
 use windows::{
-    Devices::Radios::{Radio, RadioState},
+    Devices::{
+        Bluetooth::{
+            Advertisement::BluetoothLEAdvertisementType, BluetoothAdapter, BluetoothLEDevice,
+        },
+        Enumeration::DeviceInformation,
+        Radios::{Radio, RadioState},
+    },
     Foundation::TypedEventHandler,
 };
 
@@ -34,6 +57,8 @@ pub struct Adapter {
     watcher: Arc<Mutex<BLEWatcher>>,
     manager: Arc<AdapterManager<Peripheral>>,
     radio: Radio,
+    scan_state: Arc<ScanState>,
+    scans: Arc<ScanSubscriptions>,
 }
 
 // https://github.com/microsoft/windows-rs/blob/master/crates/libs/windows/src/Windows/Devices/Radios/mod.rs
@@ -62,10 +87,106 @@ impl Adapter {
             eprintln!("radio.StateChanged error: {}", err);
         }
 
+        let manager_clone = manager.clone();
+        if let Err(err) = watcher.lock().unwrap().on_stopped(Box::new(move || {
+            manager_clone.emit(CentralEvent::ScanStopped);
+        })) {
+            error!("watcher.Stopped error: {:?}", err);
+        }
+
         Adapter {
             watcher,
             manager,
             radio,
+            scan_state: Default::default(),
+            scans: Default::default(),
+        }
+    }
+
+    fn start_watcher(&self, filter: ScanFilter) -> Result<()> {
+        let watcher = self.watcher.lock().unwrap();
+        let manager = self.manager.clone();
+        let connectable_only = filter.connectable_only;
+        let name_prefix = filter.name_prefix.clone();
+        watcher.start(
+            filter,
+            Box::new(move |args| {
+                let bluetooth_address = args.BluetoothAddress().unwrap();
+                let address: BDAddr = bluetooth_address.try_into().unwrap();
+                if connectable_only {
+                    let connectable = if args.AdvertisementType()
+                        == Ok(BluetoothLEAdvertisementType::ScanResponse)
+                    {
+                        // A scan response doesn't say, so goes by the advertisement it followed,
+                        // which left the device unknown if it was dropped.
+                        manager
+                            .peripheral(&address.into())
+                            .is_some_and(|peripheral| peripheral.connectable() != Some(false))
+                    } else {
+                        connectable_from(args) != Some(false)
+                    };
+                    if !connectable {
+                        return;
+                    }
+                }
+                if let Some(prefix) = &name_prefix {
+                    // Windows can only filter on the whole name. A report without a name goes by
+                    // the one the device has already advertised.
+                    let name = local_name_from_args(args).or_else(|| {
+                        manager
+                            .peripheral(&address.into())
+                            .and_then(|peripheral| peripheral.local_name())
+                    });
+                    if !name.is_some_and(|name| name.starts_with(prefix.as_str())) {
+                        return;
+                    }
+                }
+                if let Some(mut entry) = manager.peripheral_mut(&address.into()) {
+                    let events = entry.value_mut().update_properties(args);
+                    drop(entry);
+                    for event in events {
+                        manager.emit(event);
+                    }
+                    manager.emit(CentralEvent::DeviceUpdated(address.into()));
+                } else {
+                    // Fill in everything from this first report before announcing the device, so
+                    // that its properties are complete as soon as it is discovered.
+                    let peripheral =
+                        Peripheral::new(Arc::downgrade(&manager), manager.connect_limit(), address);
+                    let events = peripheral.update_properties(args);
+                    manager.add_peripheral(peripheral);
+                    manager.emit(CentralEvent::DeviceDiscovered(address.into()));
+                    for event in events {
+                        manager.emit(event);
+                    }
+                }
+            }),
+        )?;
+        if watcher.is_started() {
+            self.manager.emit(CentralEvent::ScanStarted);
+        }
+        Ok(())
+    }
+}
+
+/// The stream returned by `Adapter::observe`, which stops its watcher when dropped.
+struct Observation {
+    watcher: BLEWatcher,
+    advertisements: UnboundedReceiver<Advertisement>,
+}
+
+impl Stream for Observation {
+    type Item = Advertisement;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Advertisement>> {
+        self.advertisements.poll_next_unpin(cx)
+    }
+}
+
+impl Drop for Observation {
+    fn drop(&mut self) {
+        if let Err(e) = self.watcher.stop() {
+            debug!("Couldn't stop observing: {:?}", e);
         }
     }
 }
@@ -87,44 +208,143 @@ impl Central for Adapter {
     }
 
     async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        self.start_watcher(filter.clone())?;
+        self.scan_state.started(filter);
+        Ok(())
+    }
+
+    async fn start_scan_with_options(
+        &self,
+        filter: ScanFilter,
+        options: ScanOptions,
+    ) -> Result<()> {
+        options.check()?;
+        self.manager.rssi_smoothing().set(options.rssi_smoothing);
+        self.start_scan(filter).await
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
         let watcher = self.watcher.lock().unwrap();
-        let manager = self.manager.clone();
+        watcher.stop().unwrap();
+        self.scan_state.stopped();
+        Ok(())
+    }
+
+    async fn pause_scan(&self) -> Result<()> {
+        if self.scan_state.is_running() {
+            self.watcher.lock().unwrap().stop()?;
+            self.scan_state.paused();
+        }
+        Ok(())
+    }
+
+    async fn resume_scan(&self) -> Result<()> {
+        if let Some(filter) = self.scan_state.paused_filter() {
+            self.start_watcher(filter)?;
+            self.scan_state.resumed();
+        }
+        Ok(())
+    }
+
+    async fn observe(
+        &self,
+        filter: ScanFilter,
+    ) -> Result<Pin<Box<dyn Stream<Item = Advertisement> + Send>>>
+    where
+        Self: 'static,
+    {
+        // A watcher of its own means that several can run at once without touching the shared
+        // scan, and what it reports needn't go through the peripheral registry.
+        let watcher = BLEWatcher::new();
+        let (sender, receiver) = mpsc::unbounded();
         watcher.start(
             filter,
-            Box::new(move |args| {
-                let bluetooth_address = args.BluetoothAddress().unwrap();
-                let address: BDAddr = bluetooth_address.try_into().unwrap();
-                if let Some(mut entry) = manager.peripheral_mut(&address.into()) {
-                    entry.value_mut().update_properties(args);
-                    manager.emit(CentralEvent::DeviceUpdated(address.into()));
-                } else {
-                    let peripheral = Peripheral::new(Arc::downgrade(&manager), address);
-                    peripheral.update_properties(args);
-                    manager.add_peripheral(peripheral);
-                    manager.emit(CentralEvent::DeviceDiscovered(address.into()));
+            Box::new(move |args| match advertisement_from_args(args) {
+                Ok(advertisement) => {
+                    let _ = sender.unbounded_send(advertisement);
                 }
+                Err(e) => debug!("Couldn't read advertisement: {:?}", e),
             }),
-        )
+        )?;
+        Ok(Box::pin(Observation {
+            watcher,
+            advertisements: receiver,
+        }))
     }
 
-    async fn stop_scan(&self) -> Result<()> {
-        let watcher = self.watcher.lock().unwrap();
-        watcher.stop().unwrap();
-        Ok(())
+    async fn subscribe_scan(&self, filter: ScanFilter) -> Result<ScanSubscription> {
+        subscribe_scan(self, &self.scans, filter).await
     }
 
     async fn peripherals(&self) -> Result<Vec<Peripheral>> {
         Ok(self.manager.peripherals())
     }
 
+    async fn connected(&self) -> Result<Vec<Peripheral>> {
+        Ok(self.manager.connected_peripherals())
+    }
+
+    async fn bonded_devices(&self) -> Result<Vec<Peripheral>> {
+        let selector = BluetoothLEDevice::GetDeviceSelectorFromPairingState(true)?;
+        let infos = DeviceInformation::FindAllAsyncAqsFilter(&selector)?.await?;
+        let mut bonded = Vec::new();
+        for info in infos {
+            let device = BluetoothLEDevice::FromIdAsync(&info.Id()?)?.await?;
+            let address: BDAddr = device.BluetoothAddress()?.try_into()?;
+            let address_type = device.BluetoothAddressType().ok();
+            device.Close()?;
+            let peripheral = match self.manager.peripheral(&address.into()) {
+                Some(peripheral) => peripheral,
+                None => {
+                    let peripheral = Peripheral::new(
+                        Arc::downgrade(&self.manager),
+                        self.manager.connect_limit(),
+                        address,
+                    );
+                    peripheral.set_local_name(info.Name()?.to_string());
+                    if let Some(address_type) = address_type.and_then(utils::to_address_type) {
+                        peripheral.set_address_type(address_type);
+                    }
+                    self.manager.add_peripheral(peripheral.clone());
+                    peripheral
+                }
+            };
+            bonded.push(peripheral);
+        }
+        Ok(bonded)
+    }
+
     async fn peripheral(&self, id: &PeripheralId) -> Result<Peripheral> {
         self.manager.peripheral(id).ok_or(Error::DeviceNotFound)
     }
 
-    async fn add_peripheral(&self, _address: &PeripheralId) -> Result<Peripheral> {
-        Err(Error::NotSupported(
-            "Can't add a Peripheral from a BDAddr".to_string(),
-        ))
+    async fn is_known(&self, id: &PeripheralId) -> Result<bool> {
+        if self.manager.peripheral(id).is_some() {
+            return Ok(true);
+        }
+        // Windows only resolves addresses it has seen or paired with.
+        match BluetoothLEDevice::FromBluetoothAddressAsync(id.0.into())?.await {
+            Ok(device) => {
+                let _ = device.Close();
+                Ok(true)
+            }
+            Err(_) => Ok(false),
+        }
+    }
+
+    async fn add_peripheral(&self, address: &PeripheralId) -> Result<Peripheral> {
+        if let Some(peripheral) = self.manager.peripheral(address) {
+            return Ok(peripheral);
+        }
+        // The device is only looked up when connecting, with the address type given in the
+        // `ConnectOptions` or public by default.
+        let peripheral = Peripheral::new(
+            Arc::downgrade(&self.manager),
+            self.manager.connect_limit(),
+            address.0,
+        );
+        self.manager.add_peripheral(peripheral.clone());
+        Ok(peripheral)
     }
 
     async fn adapter_info(&self) -> Result<String> {
@@ -135,4 +355,37 @@ impl Central for Adapter {
     async fn adapter_state(&self) -> Result<CentralState> {
         Ok(get_central_state(&self.radio))
     }
+
+    async fn is_scanning(&self) -> Result<bool> {
+        Ok(self.watcher.lock().unwrap().is_started())
+    }
+
+    async fn adapter_capabilities(&self) -> Result<AdapterCapabilities> {
+        let adapter = BluetoothAdapter::GetDefaultAsync()?.await?;
+        Ok(AdapterCapabilities {
+            peripheral_role: adapter.IsPeripheralRoleSupported()?,
+        })
+    }
+
+    async fn le_features(&self) -> Result<LeFeatures> {
+        let adapter = BluetoothAdapter::GetDefaultAsync()?.await?;
+        let mut features = LeFeatures::empty();
+        // Only Windows 10 2004 and later can tell.
+        if adapter.IsExtendedAdvertisingSupported().unwrap_or(false) {
+            features |= LeFeatures::EXTENDED_ADVERTISING;
+        }
+        Ok(features)
+    }
+
+    fn set_peripheral_ttl(&self, ttl: Option<Duration>) {
+        self.manager.set_peripheral_ttl(ttl);
+    }
+
+    fn set_max_peripherals(&self, max: Option<usize>) {
+        self.manager.set_max_peripherals(max);
+    }
+
+    fn set_max_concurrent_connects(&self, max: usize) {
+        self.manager.connect_limit().set_max(max);
+    }
 }
@@ -26,7 +26,8 @@ use windows::{
         BluetoothCacheMode,
         GenericAttributeProfile::{
             GattCharacteristic, GattClientCharacteristicConfigurationDescriptorValue,
-            GattCommunicationStatus, GattValueChangedEventArgs, GattWriteOption,
+            GattCommunicationStatus, GattReliableWriteTransaction, GattValueChangedEventArgs,
+            GattWriteOption,
         },
     },
     Foundation::{EventRegistrationToken, TypedEventHandler},
@@ -35,11 +36,16 @@ use windows::{
 
 pub type NotifiyEventHandler = Box<dyn Fn(Vec<u8>) + Send>;
 
-impl From<WriteType> for GattWriteOption {
-    fn from(val: WriteType) -> Self {
+impl TryFrom<WriteType> for GattWriteOption {
+    type Error = Error;
+
+    fn try_from(val: WriteType) -> Result<Self> {
         match val {
-            WriteType::WithoutResponse => GattWriteOption::WriteWithoutResponse,
-            WriteType::WithResponse => GattWriteOption::WriteWithResponse,
+            WriteType::WithoutResponse => Ok(GattWriteOption::WriteWithoutResponse),
+            WriteType::WithResponse => Ok(GattWriteOption::WriteWithResponse),
+            WriteType::SignedWithoutResponse => Err(Error::NotSupported(
+                "Signed writes are not supported on Windows".to_string(),
+            )),
         }
     }
 }
@@ -68,23 +74,34 @@ impl BLECharacteristic {
         writer.WriteBytes(data)?;
         let operation = self
             .characteristic
-            .WriteValueWithOptionAsync(&writer.DetachBuffer()?, write_type.into())?;
+            .WriteValueWithOptionAsync(&writer.DetachBuffer()?, write_type.try_into()?)?;
         let result = operation.await?;
         if result == GattCommunicationStatus::Success {
             Ok(())
         } else {
-            Err(Error::Other(
-                format!("Windows UWP threw error on write: {:?}", result).into(),
-            ))
+            Err(utils::to_operation_error("write", result))
         }
     }
 
-    pub async fn read_value(&self) -> Result<Vec<u8>> {
+    /// Adds a write of `data` to this characteristic to the given Reliable Write transaction.
+    pub fn add_to_transaction(
+        &self,
+        transaction: &GattReliableWriteTransaction,
+        data: &[u8],
+    ) -> Result<()> {
+        let writer = DataWriter::new()?;
+        writer.WriteBytes(data)?;
+        transaction.WriteValue(&self.characteristic, &writer.DetachBuffer()?)?;
+        Ok(())
+    }
+
+    pub async fn read_value(&self, cache_mode: BluetoothCacheMode) -> Result<Vec<u8>> {
         let result = self
             .characteristic
-            .ReadValueWithCacheModeAsync(BluetoothCacheMode::Uncached)?
+            .ReadValueWithCacheModeAsync(cache_mode)?
             .await?;
-        if result.Status()? == GattCommunicationStatus::Success {
+        let status = result.Status()?;
+        if status == GattCommunicationStatus::Success {
             let value = result.Value()?;
             let reader = DataReader::FromBuffer(&value)?;
             let len = reader.UnconsumedBufferLength()? as usize;
@@ -92,13 +109,21 @@ impl BLECharacteristic {
             reader.ReadBytes(&mut input[0..len])?;
             Ok(input)
         } else {
-            Err(Error::Other(
-                format!("Windows UWP threw error on read: {:?}", result).into(),
-            ))
+            Err(utils::to_operation_error("read", status))
         }
     }
 
     pub async fn subscribe(&mut self, on_value_changed: NotifiyEventHandler) -> Result<()> {
+        let config = to_descriptor_value(self.characteristic.CharacteristicProperties()?);
+        self.subscribe_with_config(config, on_value_changed).await
+    }
+
+    /// Subscribes, writing the given value to the Client Characteristic Configuration descriptor.
+    pub async fn subscribe_with_config(
+        &mut self,
+        config: GattClientCharacteristicConfigurationDescriptorValue,
+        on_value_changed: NotifiyEventHandler,
+    ) -> Result<()> {
         {
             let value_handler = TypedEventHandler::new(
                 move |_: &Option<GattCharacteristic>, args: &Option<GattValueChangedEventArgs>| {
@@ -117,7 +142,6 @@ impl BLECharacteristic {
             let token = self.characteristic.ValueChanged(&value_handler)?;
             self.notify_token = Some(token);
         }
-        let config = to_descriptor_value(self.characteristic.CharacteristicProperties()?);
         if config == GattClientCharacteristicConfigurationDescriptorValue::None {
             return Err(Error::NotSupported("Can not subscribe to attribute".into()));
         }
@@ -130,9 +154,7 @@ impl BLECharacteristic {
         if status == GattCommunicationStatus::Success {
             Ok(())
         } else {
-            Err(Error::Other(
-                format!("Windows UWP threw error on subscribe: {:?}", status).into(),
-            ))
+            Err(utils::to_operation_error("subscribe", status))
         }
     }
 
@@ -150,9 +172,7 @@ impl BLECharacteristic {
         if status == GattCommunicationStatus::Success {
             Ok(())
         } else {
-            Err(Error::Other(
-                format!("Windows UWP threw error on unsubscribe: {:?}", status).into(),
-            ))
+            Err(utils::to_operation_error("unsubscribe", status))
         }
     }
 
@@ -160,18 +180,37 @@ impl BLECharacteristic {
         utils::to_uuid(&self.characteristic.Uuid().unwrap())
     }
 
+    pub fn gatt_characteristic(&self) -> GattCharacteristic {
+        self.characteristic.clone()
+    }
+
+    pub fn handle(&self) -> u16 {
+        self.characteristic.AttributeHandle().unwrap()
+    }
+
+    /// Identifies the characteristic within its service, which may have several with the same UUID.
+    pub fn key(&self) -> (Uuid, u16) {
+        (self.uuid(), self.handle())
+    }
+
+    pub fn is_subscribed(&self) -> bool {
+        self.notify_token.is_some()
+    }
+
     pub fn to_characteristic(&self, service_uuid: Uuid) -> Characteristic {
         let uuid = self.uuid();
+        let instance_id = self.handle();
         let properties =
             utils::to_char_props(&self.characteristic.CharacteristicProperties().unwrap());
         let descriptors = self
             .descriptors
             .values()
-            .map(|descriptor| descriptor.to_descriptor(service_uuid, uuid))
+            .map(|descriptor| descriptor.to_descriptor(service_uuid, uuid, instance_id))
             .collect();
         Characteristic {
             uuid,
             service_uuid,
+            instance_id,
             descriptors,
             properties,
         }
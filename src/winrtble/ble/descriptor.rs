@@ -12,7 +12,7 @@
 // Copyright (c) 2014 The Rust Project Developers
 
 use super::super::utils;
-use crate::{api::Descriptor, Error, Result};
+use crate::{api::Descriptor, Result};
 
 use uuid::Uuid;
 use windows::{
@@ -37,12 +37,22 @@ impl BLEDescriptor {
         utils::to_uuid(&self.descriptor.Uuid().unwrap())
     }
 
-    pub fn to_descriptor(&self, service_uuid: Uuid, characteristic_uuid: Uuid) -> Descriptor {
+    pub fn handle(&self) -> u16 {
+        self.descriptor.AttributeHandle().unwrap()
+    }
+
+    pub fn to_descriptor(
+        &self,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        characteristic_instance_id: u16,
+    ) -> Descriptor {
         let uuid = self.uuid();
         Descriptor {
             uuid,
             service_uuid,
             characteristic_uuid,
+            characteristic_instance_id,
         }
     }
 
@@ -54,9 +64,7 @@ impl BLEDescriptor {
         if result == GattCommunicationStatus::Success {
             Ok(())
         } else {
-            Err(Error::Other(
-                format!("Windows UWP threw error on write descriptor: {:?}", result).into(),
-            ))
+            Err(utils::to_operation_error("write descriptor", result))
         }
     }
 
@@ -65,7 +73,8 @@ impl BLEDescriptor {
             .descriptor
             .ReadValueWithCacheModeAsync(BluetoothCacheMode::Uncached)?
             .await?;
-        if result.Status()? == GattCommunicationStatus::Success {
+        let status = result.Status()?;
+        if status == GattCommunicationStatus::Success {
             let value = result.Value()?;
             let reader = DataReader::FromBuffer(&value)?;
             let len = reader.UnconsumedBufferLength()? as usize;
@@ -73,9 +82,7 @@ impl BLEDescriptor {
             reader.ReadBytes(&mut input[0..len])?;
             Ok(input)
         } else {
-            Err(Error::Other(
-                format!("Windows UWP threw error on read: {:?}", result).into(),
-            ))
+            Err(utils::to_operation_error("read descriptor", status))
         }
     }
 }
@@ -11,34 +11,59 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use crate::{api::BDAddr, winrtble::utils, Error, Result};
+use crate::{
+    api::{AddressType, BDAddr, PreferredConnectionParameters},
+    common::runtime,
+    winrtble::utils,
+    ConnectFailureReason, Error, Result,
+};
+use futures::channel::oneshot;
 use log::{debug, trace};
+use std::sync::Mutex;
+use uuid::Uuid;
 use windows::{
     Devices::Bluetooth::{
         BluetoothCacheMode, BluetoothConnectionStatus, BluetoothLEDevice,
+        BluetoothLEPreferredConnectionParameters, BluetoothLEPreferredConnectionParametersRequest,
         GenericAttributeProfile::{
             GattCharacteristic, GattCommunicationStatus, GattDescriptor, GattDeviceService,
-            GattDeviceServicesResult,
+            GattDeviceServicesResult, GattSession, GattSessionStatus,
+            GattSessionStatusChangedEventArgs,
         },
     },
+    Devices::Enumeration::{DevicePairingProtectionLevel, DevicePairingResultStatus},
     Foundation::{EventRegistrationToken, TypedEventHandler},
 };
 
 pub type ConnectedEventHandler = Box<dyn Fn(bool) + Send>;
+pub type ServicesChangedEventHandler = Box<dyn Fn() + Send>;
+pub type MaxPduSizeChangedEventHandler = Box<dyn Fn(u16) + Send>;
 
 pub struct BLEDevice {
     device: BluetoothLEDevice,
     connection_token: EventRegistrationToken,
+    services_changed_token: EventRegistrationToken,
     services: Vec<GattDeviceService>,
+    session: Option<(GattSession, EventRegistrationToken)>,
+    /// The preferred connection parameters only apply for as long as this request is open.
+    preferred_parameters_request: Option<BluetoothLEPreferredConnectionParametersRequest>,
+    closed: bool,
+    bypass_cache: bool,
 }
 
 impl BLEDevice {
     pub async fn new(
         address: BDAddr,
+        address_type: AddressType,
         connection_status_changed: ConnectedEventHandler,
+        services_changed: ServicesChangedEventHandler,
     ) -> Result<Self> {
-        let async_op = BluetoothLEDevice::FromBluetoothAddressAsync(address.into())
-            .map_err(|_| Error::DeviceNotFound)?;
+        // Windows can't always resolve a random address without being told that it is one.
+        let async_op = BluetoothLEDevice::FromBluetoothAddressWithBluetoothAddressTypeAsync(
+            address.into(),
+            utils::to_bluetooth_address_type(address_type),
+        )
+        .map_err(|_| Error::DeviceNotFound)?;
         let device = async_op.await.map_err(|_| Error::DeviceNotFound)?;
         let connection_status_handler =
             TypedEventHandler::new(move |sender: &Option<BluetoothLEDevice>, _| {
@@ -56,23 +81,47 @@ impl BLEDevice {
         let connection_token = device
             .ConnectionStatusChanged(&connection_status_handler)
             .map_err(|_| Error::Other("Could not add connection status handler".into()))?;
+        let services_changed_handler = TypedEventHandler::new(move |_, _| {
+            trace!("gatt services changed");
+            services_changed();
+            Ok(())
+        });
+        let services_changed_token = match device.GattServicesChanged(&services_changed_handler) {
+            Ok(token) => token,
+            Err(_) => {
+                let _ = device.RemoveConnectionStatusChanged(connection_token);
+                return Err(Error::Other(
+                    "Could not add GATT services changed handler".into(),
+                ));
+            }
+        };
 
         Ok(BLEDevice {
             device,
             connection_token,
+            services_changed_token,
             services: vec![],
+            session: None,
+            preferred_parameters_request: None,
+            closed: false,
+            bypass_cache: false,
         })
     }
 
+    /// Gets the device's services with the given UUID, or all of them.
     async fn get_gatt_services(
         &self,
+        uuid: Option<Uuid>,
         cache_mode: BluetoothCacheMode,
     ) -> Result<GattDeviceServicesResult> {
         let winrt_error = |e| Error::Other(format!("{:?}", e).into());
-        let async_op = self
-            .device
-            .GetGattServicesWithCacheModeAsync(cache_mode)
-            .map_err(winrt_error)?;
+        let async_op = match uuid {
+            Some(uuid) => self
+                .device
+                .GetGattServicesForUuidWithCacheModeAsync(utils::to_guid(&uuid), cache_mode),
+            None => self.device.GetGattServicesWithCacheModeAsync(cache_mode),
+        }
+        .map_err(winrt_error)?;
         let service_result = async_op.await.map_err(winrt_error)?;
         Ok(service_result)
     }
@@ -82,11 +131,125 @@ impl BLEDevice {
             return Ok(());
         }
 
-        let service_result = self.get_gatt_services(BluetoothCacheMode::Uncached).await?;
+        let service_result = self
+            .get_gatt_services(None, BluetoothCacheMode::Uncached)
+            .await?;
         let status = service_result.Status().map_err(|_| Error::DeviceNotFound)?;
+        // Windows gives no more detail than this, which is what it reports when the device didn't
+        // answer, e.g. because it is out of range.
+        if status == GattCommunicationStatus::Unreachable {
+            return Err(Error::ConnectionFailed(
+                ConnectFailureReason::Timeout,
+                "The device is unreachable".to_string(),
+            ));
+        }
         utils::to_error(status)
     }
 
+    /// Asks Windows to use the given connection parameters for the device, from the next time it
+    /// connects until the device is closed. This needs Windows 11, so on older versions it fails.
+    pub fn request_preferred_parameters(
+        &mut self,
+        parameters: PreferredConnectionParameters,
+    ) -> Result<()> {
+        let parameters = match parameters {
+            PreferredConnectionParameters::ThroughputOptimized => {
+                BluetoothLEPreferredConnectionParameters::ThroughputOptimized()?
+            }
+            PreferredConnectionParameters::PowerOptimized => {
+                BluetoothLEPreferredConnectionParameters::PowerOptimized()?
+            }
+            PreferredConnectionParameters::Balanced => {
+                BluetoothLEPreferredConnectionParameters::Balanced()?
+            }
+        };
+        let request = self
+            .device
+            .RequestPreferredConnectionParameters(&parameters)?;
+        trace!("preferred connection parameters {:?}", request.Status());
+        if let Some(previous) = self.preferred_parameters_request.replace(request) {
+            previous.Close()?;
+        }
+        Ok(())
+    }
+
+    /// Opens a GATT session for the device, so that the MTU negotiated for the connection can be
+    /// tracked. The handler is called with the current maximum PDU size, and again whenever it
+    /// changes.
+    pub async fn open_session(
+        &mut self,
+        max_pdu_size_changed: MaxPduSizeChangedEventHandler,
+    ) -> Result<()> {
+        let session = GattSession::FromDeviceIdAsync(&self.device.BluetoothDeviceId()?)?.await?;
+        max_pdu_size_changed(session.MaxPduSize()?);
+        let max_pdu_size_handler =
+            TypedEventHandler::new(move |sender: &Option<GattSession>, _| {
+                if let Some(sender) = sender {
+                    if let Ok(max_pdu_size) = sender.MaxPduSize() {
+                        trace!("max pdu size {:?}", max_pdu_size);
+                        max_pdu_size_changed(max_pdu_size);
+                    }
+                }
+                Ok(())
+            });
+        let max_pdu_size_token = session
+            .MaxPduSizeChanged(&max_pdu_size_handler)
+            .map_err(|_| Error::Other("Could not add max PDU size handler".into()))?;
+        self.session = Some((session, max_pdu_size_token));
+        Ok(())
+    }
+
+    /// Connects to the device without querying its services, by asking the GATT session to keep a
+    /// connection open. [`Self::open_session`] must have been called first.
+    pub async fn maintain_connection(&self) -> Result<()> {
+        let (session, _) = self
+            .session
+            .as_ref()
+            .ok_or_else(|| Error::Other("No GATT session to connect with".into()))?;
+        let (sender, receiver) = oneshot::channel();
+        let sender = Mutex::new(Some(sender));
+        let session_status_handler = TypedEventHandler::new(
+            move |_: &Option<GattSession>, args: &Option<GattSessionStatusChangedEventArgs>| {
+                if let Some(args) = args {
+                    if args.Status()? == GattSessionStatus::Active {
+                        if let Some(sender) = sender.lock().unwrap().take() {
+                            let _ = sender.send(());
+                        }
+                    }
+                }
+                Ok(())
+            },
+        );
+        let session_status_token = session.SessionStatusChanged(&session_status_handler)?;
+        session.SetMaintainConnection(true)?;
+        if session.SessionStatus()? != GattSessionStatus::Active {
+            let _ = receiver.await;
+        }
+        session.RemoveSessionStatusChanged(session_status_token)?;
+        Ok(())
+    }
+
+    /// Pairs with the device unless it is already paired with encryption, which Windows then uses
+    /// for the link.
+    pub async fn require_encryption(&self) -> Result<()> {
+        let pairing = self.device.DeviceInformation()?.Pairing()?;
+        if pairing.IsPaired()?
+            && pairing.ProtectionLevel()?.0 >= DevicePairingProtectionLevel::Encryption.0
+        {
+            return Ok(());
+        }
+        let result = pairing
+            .PairWithProtectionLevelAsync(DevicePairingProtectionLevel::Encryption)?
+            .await?;
+        match result.Status()? {
+            DevicePairingResultStatus::Paired | DevicePairingResultStatus::AlreadyPaired => Ok(()),
+            status => {
+                debug!("Pairing failed: {:?}", status);
+                Err(Error::NotPaired)
+            }
+        }
+    }
+
     async fn is_connected(&self) -> Result<bool> {
         let winrt_error = |e| Error::Other(format!("{:?}", e).into());
         let status = self.device.ConnectionStatus().map_err(winrt_error)?;
@@ -114,10 +277,12 @@ impl BLEDevice {
                 )
                 .into(),
             )),
-            Ok(status) => {
-                debug!("characteristic read failed due to {:?}", status);
-                Ok(vec![])
-            }
+            Ok(GattCommunicationStatus::AccessDenied) => Err(Error::NotPaired),
+            // Report this rather than returning no characteristics, so that the caller can tell an
+            // empty service from one that failed.
+            Ok(status) => Err(Error::Other(
+                format!("get_characteristics for {:?} failed: {:?}", service, status).into(),
+            )),
             Err(e) => Err(Error::Other(
                 format!("get_characteristics for {:?} failed: {:?}", service, e).into(),
             )),
@@ -135,6 +300,8 @@ impl BLEDevice {
             let results = async_result.Descriptors()?;
             debug!("descriptors {:?}", results.Size());
             Ok(results.into_iter().collect())
+        } else if status == Ok(GattCommunicationStatus::AccessDenied) {
+            Err(Error::NotPaired)
         } else {
             Err(Error::Other(
                 format!(
@@ -146,27 +313,81 @@ impl BLEDevice {
         }
     }
 
-    pub async fn discover_services(&mut self) -> Result<&[GattDeviceService]> {
+    /// Makes the next call to [`Self::discover_services`] query the device rather than using the
+    /// services Windows has cached for it.
+    pub fn clear_cache(&mut self) {
+        self.bypass_cache = true;
+    }
+
+    /// Discovers the services with the given UUIDs, or all of them if there are none. If the
+    /// device can't be asked, the services found last time are returned instead.
+    pub async fn discover_services(&mut self, uuids: &[Uuid]) -> Result<&[GattDeviceService]> {
         let winrt_error = |e| Error::Other(format!("{:?}", e).into());
-        let service_result = self.get_gatt_services(BluetoothCacheMode::Cached).await?;
-        let status = service_result.Status().map_err(winrt_error)?;
-        if status == GattCommunicationStatus::Success {
+        let cache_mode = if self.bypass_cache {
+            BluetoothCacheMode::Uncached
+        } else {
+            BluetoothCacheMode::Cached
+        };
+        let uuids: Vec<_> = if uuids.is_empty() {
+            vec![None]
+        } else {
+            uuids.iter().copied().map(Some).collect()
+        };
+        let mut services = Vec::new();
+        for uuid in uuids {
+            let service_result = self.get_gatt_services(uuid, cache_mode).await?;
+            let status = service_result.Status().map_err(winrt_error)?;
+            if status == GattCommunicationStatus::AccessDenied {
+                return Err(Error::NotPaired);
+            } else if status != GattCommunicationStatus::Success {
+                return Ok(self.services.as_slice());
+            }
             // We need to convert the IVectorView to a Vec, because IVectorView is not Send and so
             // can't be help past the await point below.
-            let services: Vec<_> = service_result
-                .Services()
-                .map_err(winrt_error)?
-                .into_iter()
-                .collect();
-            self.services = services;
-            debug!("services {:?}", self.services.len());
+            services.extend(service_result.Services().map_err(winrt_error)?);
         }
+        self.bypass_cache = false;
+        self.services = services;
+        debug!("services {:?}", self.services.len());
         Ok(self.services.as_slice())
     }
+
+    /// Does the same teardown as dropping the device, but on a blocking thread so as not to stall
+    /// the executor, and reports the first error encountered.
+    pub async fn close(mut self) -> Result<()> {
+        self.closed = true;
+        let device = self.device.clone();
+        let connection_token = self.connection_token;
+        let services_changed_token = self.services_changed_token;
+        let services = std::mem::take(&mut self.services);
+        let session = self.session.take();
+        let preferred_parameters_request = self.preferred_parameters_request.take();
+        runtime::spawn_blocking(move || {
+            device.RemoveConnectionStatusChanged(connection_token)?;
+            device.RemoveGattServicesChanged(services_changed_token)?;
+            if let Some((session, max_pdu_size_token)) = session {
+                session.RemoveMaxPduSizeChanged(max_pdu_size_token)?;
+                session.Close()?;
+            }
+            if let Some(request) = preferred_parameters_request {
+                request.Close()?;
+            }
+            for service in services {
+                service.Close()?;
+            }
+            device.Close()?;
+            Ok(())
+        })
+        .await?
+    }
 }
 
 impl Drop for BLEDevice {
     fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+
         let result = self
             .device
             .RemoveConnectionStatusChanged(self.connection_token);
@@ -174,6 +395,28 @@ impl Drop for BLEDevice {
             debug!("Drop:remove_connection_status_changed {:?}", err);
         }
 
+        let result = self
+            .device
+            .RemoveGattServicesChanged(self.services_changed_token);
+        if let Err(err) = result {
+            debug!("Drop:remove_gatt_services_changed {:?}", err);
+        }
+
+        if let Some((session, max_pdu_size_token)) = &self.session {
+            if let Err(err) = session.RemoveMaxPduSizeChanged(*max_pdu_size_token) {
+                debug!("Drop:remove_max_pdu_size_changed {:?}", err);
+            }
+            if let Err(err) = session.Close() {
+                debug!("Drop:close_gatt_session {:?}", err);
+            }
+        }
+
+        if let Some(request) = &self.preferred_parameters_request {
+            if let Err(err) = request.Close() {
+                debug!("Drop:close_preferred_parameters_request {:?}", err);
+            }
+        }
+
         self.services.iter().for_each(|service| {
             if let Err(err) = service.Close() {
                 debug!("Drop:remove_gatt_Service {:?}", err);
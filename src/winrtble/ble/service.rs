@@ -1,12 +1,17 @@
 use super::characteristic::BLECharacteristic;
 use crate::api::Service;
 use std::collections::HashMap;
+use std::iter;
 use uuid::Uuid;
 
 #[derive(Debug)]
 pub struct BLEService {
     pub uuid: Uuid,
-    pub characteristics: HashMap<Uuid, BLECharacteristic>,
+    /// The attribute handle of the service's declaration.
+    pub handle: u16,
+    /// Keyed by UUID and attribute handle, as a service may have several characteristics with the
+    /// same UUID.
+    pub characteristics: HashMap<(Uuid, u16), BLECharacteristic>,
 }
 
 impl BLEService {
@@ -16,9 +21,23 @@ impl BLEService {
             .values()
             .map(|ble_characteristic| ble_characteristic.to_characteristic(self.uuid))
             .collect();
+        let end_handle = self
+            .characteristics
+            .values()
+            .flat_map(|characteristic| {
+                characteristic
+                    .descriptors
+                    .values()
+                    .map(|descriptor| descriptor.handle())
+                    .chain(iter::once(characteristic.handle()))
+            })
+            .chain(iter::once(self.handle))
+            .max();
         Service {
             uuid: self.uuid,
             primary: true,
+            start_handle: Some(self.handle),
+            end_handle,
             characteristics,
         }
     }
@@ -11,10 +11,19 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use crate::{api::ScanFilter, Error, Result};
-use windows::{Devices::Bluetooth::Advertisement::*, Foundation::TypedEventHandler};
+use crate::{
+    api::{ScanFilter, ScanType},
+    Error, Result,
+};
+use windows::{
+    core::Interface,
+    Devices::Bluetooth::Advertisement::*,
+    Foundation::{IReference, PropertyValue, TypedEventHandler},
+    Storage::Streams::DataWriter,
+};
 
 pub type AdvertismentEventHandler = Box<dyn Fn(&BluetoothLEAdvertisementReceivedEventArgs) + Send>;
+pub type StoppedEventHandler = Box<dyn Fn() + Send>;
 
 pub struct BLEWatcher {
     watcher: BluetoothLEAdvertisementWatcher,
@@ -34,7 +43,12 @@ impl BLEWatcher {
     }
 
     pub fn start(&self, filter: ScanFilter, on_received: AdvertismentEventHandler) -> Result<()> {
-        let ScanFilter { services } = filter;
+        let ScanFilter {
+            services,
+            manufacturer_data,
+            min_rssi,
+            scan_type,
+        } = filter;
         let ad = self
             .watcher
             .AdvertisementFilter()
@@ -48,8 +62,28 @@ impl BLEWatcher {
                 .Append(windows::core::GUID::from(service.as_u128()))
                 .unwrap();
         }
+        let ad_manufacturer_data = ad.ManufacturerData()?;
+        ad_manufacturer_data.Clear()?;
+        for (company_id, data_prefix) in manufacturer_data {
+            let writer = DataWriter::new()?;
+            writer.WriteBytes(&data_prefix)?;
+            ad_manufacturer_data.Append(&BluetoothLEManufacturerData::Create(
+                company_id,
+                &writer.DetachBuffer()?,
+            )?)?;
+        }
+        let in_range_threshold = match min_rssi {
+            Some(rssi) => Some(PropertyValue::CreateInt16(rssi)?.cast::<IReference<i16>>()?),
+            None => None,
+        };
+        self.watcher
+            .SignalStrengthFilter()?
+            .SetInRangeThresholdInDBm(in_range_threshold.as_ref())?;
         self.watcher
-            .SetScanningMode(BluetoothLEScanningMode::Active)
+            .SetScanningMode(match scan_type {
+                ScanType::Active => BluetoothLEScanningMode::Active,
+                ScanType::Passive => BluetoothLEScanningMode::Passive,
+            })
             .unwrap();
         self.watcher.SetAllowExtendedAdvertisements(true)?;
         let handler: TypedEventHandler<
@@ -69,6 +103,24 @@ impl BLEWatcher {
         Ok(())
     }
 
+    /// Registers a handler that fires whenever the watcher stops, whether that was requested or
+    /// the OS aborted the scan (e.g. because the radio was turned off).
+    pub fn on_stopped(&self, on_stopped: StoppedEventHandler) -> Result<()> {
+        let handler: TypedEventHandler<
+            BluetoothLEAdvertisementWatcher,
+            BluetoothLEAdvertisementWatcherStoppedEventArgs,
+        > = TypedEventHandler::new(move |_sender, _args| {
+            on_stopped();
+            Ok(())
+        });
+        self.watcher.Stopped(&handler)?;
+        Ok(())
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.watcher.Status() == Ok(BluetoothLEAdvertisementWatcherStatus::Started)
+    }
+
     pub fn stop(&self) -> Result<()> {
         self.watcher.Stop()?;
         Ok(())
@@ -15,10 +15,12 @@ pub mod adapter;
 mod ble;
 pub mod manager;
 pub mod peripheral;
-mod utils;
+pub(crate) mod utils;
 
 /// Only some of the assigned numbers are populated here as needed from https://www.bluetooth.com/specifications/assigned-numbers/generic-access-profile/
 mod advertisement_data_type {
+    pub const SHORTENED_LOCAL_NAME: u8 = 0x08;
+    pub const COMPLETE_LOCAL_NAME: u8 = 0x09;
     pub const SERVICE_DATA_16_BIT_UUID: u8 = 0x16;
     pub const SERVICE_DATA_32_BIT_UUID: u8 = 0x20;
     pub const SERVICE_DATA_128_BIT_UUID: u8 = 0x21;
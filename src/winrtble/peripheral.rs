@@ -18,16 +18,29 @@ use super::{
 use crate::{
     api::{
         bleuuid::{uuid_from_u16, uuid_from_u32},
-        AddressType, BDAddr, CentralEvent, Characteristic, Descriptor, Peripheral as ApiPeripheral,
-        PeripheralProperties, Service, ValueNotification, WriteType,
+        max_write_len_for_mtu, read_gap_device_name,
+        trace::{self, traced_connect, traced_discover_services},
+        AddressType, Advertisement, BDAddr, CentralEvent, Characteristic, ConnectOptions,
+        Descriptor, DisconnectOptions, DiscoverOptions, ParsePeripheralIdError,
+        Peripheral as ApiPeripheral, PeripheralMetrics, PeripheralProperties, Service,
+        SubscriptionKind, TraceEvent, Transport, ValueNotification, WriteType, DEFAULT_ATT_MTU,
+    },
+    common::{
+        adapter_manager::AdapterManager,
+        connect_limit::ConnectLimit,
+        metrics::Metrics,
+        pending::PendingOperations,
+        rssi_history::RssiAverage,
+        runtime,
+        subscriptions::{auto_subscribe, Subscriptions},
+        util::notifications_stream_from_broadcast_receiver,
     },
-    common::{adapter_manager::AdapterManager, util::notifications_stream_from_broadcast_receiver},
     Error, Result,
 };
 use async_trait::async_trait;
 use dashmap::DashMap;
 use futures::stream::Stream;
-use log::{trace, warn};
+use log::{debug, trace, warn};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "serde")]
@@ -37,14 +50,23 @@ use std::{
     convert::TryInto,
     fmt::{self, Debug, Display, Formatter},
     pin::Pin,
-    sync::atomic::{AtomicBool, Ordering},
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicU16, Ordering},
     sync::{Arc, RwLock},
+    time::Instant,
 };
 use tokio::sync::broadcast;
 use uuid::Uuid;
 
 use std::sync::Weak;
-use windows::Devices::Bluetooth::{Advertisement::*, BluetoothAddressType};
+use windows::Devices::Bluetooth::{
+    Advertisement::*,
+    BluetoothCacheMode,
+    GenericAttributeProfile::{
+        GattClientCharacteristicConfigurationDescriptorValue, GattCommunicationStatus,
+        GattReliableWriteTransaction,
+    },
+};
 
 #[cfg_attr(
     feature = "serde",
@@ -52,7 +74,7 @@ use windows::Devices::Bluetooth::{Advertisement::*, BluetoothAddressType};
     serde(crate = "serde_cr")
 )]
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub struct PeripheralId(BDAddr);
+pub struct PeripheralId(pub(super) BDAddr);
 
 impl Display for PeripheralId {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
@@ -60,6 +82,17 @@ impl Display for PeripheralId {
     }
 }
 
+impl FromStr for PeripheralId {
+    type Err = ParsePeripheralIdError;
+
+    /// Parses an ID in the form it is displayed, the device's address like `00:11:22:33:44:55`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        BDAddr::from_str_delim(s)
+            .map(PeripheralId)
+            .map_err(|_| ParsePeripheralIdError::new(s, "an address like 00:11:22:33:44:55"))
+    }
+}
+
 /// Implementation of [api::Peripheral](crate::api::Peripheral).
 #[derive(Clone)]
 pub struct Peripheral {
@@ -67,43 +100,72 @@ pub struct Peripheral {
 }
 
 struct Shared {
+    // Only opened on connecting, along with its connection status and PDU size handlers, so that
+    // the many devices which are only ever seen advertising don't hold any OS resources.
     device: tokio::sync::Mutex<Option<BLEDevice>>,
     adapter: Weak<AdapterManager<Peripheral>>,
+    connect_limit: Arc<ConnectLimit>,
     address: BDAddr,
     connected: AtomicBool,
-    ble_services: DashMap<Uuid, BLEService>,
+    mtu: AtomicU16,
+    /// Keyed by UUID and handle, as a device may have several services with the same UUID.
+    ble_services: DashMap<(Uuid, u16), BLEService>,
+    failed_services: RwLock<BTreeSet<Uuid>>,
     notifications_channel: broadcast::Sender<ValueNotification>,
+    pending: PendingOperations,
+    metrics: Metrics,
+    default_write_type: RwLock<Option<WriteType>>,
+    subscriptions: Subscriptions,
 
     // Mutable, advertised, state...
     address_type: RwLock<Option<AddressType>>,
     local_name: RwLock<Option<String>>,
     last_tx_power_level: RwLock<Option<i16>>, // XXX: would be nice to avoid lock here!
     last_rssi: RwLock<Option<i16>>,           // XXX: would be nice to avoid lock here!
+    rssi_average: RwLock<RssiAverage>,
     latest_manufacturer_data: RwLock<HashMap<u16, Vec<u8>>>,
     latest_service_data: RwLock<HashMap<Uuid, Vec<u8>>>,
     services: RwLock<HashSet<Uuid>>,
     class: RwLock<Option<u32>>,
+    last_seen: RwLock<Option<Instant>>,
+    has_scan_response: AtomicBool,
+    connectable: RwLock<Option<bool>>,
 }
 
 impl Peripheral {
-    pub(crate) fn new(adapter: Weak<AdapterManager<Self>>, address: BDAddr) -> Self {
+    pub(crate) fn new(
+        adapter: Weak<AdapterManager<Self>>,
+        connect_limit: Arc<ConnectLimit>,
+        address: BDAddr,
+    ) -> Self {
         let (broadcast_sender, _) = broadcast::channel(16);
         Peripheral {
             shared: Arc::new(Shared {
                 adapter,
+                connect_limit,
                 device: tokio::sync::Mutex::new(None),
                 address,
                 connected: AtomicBool::new(false),
+                mtu: AtomicU16::new(DEFAULT_ATT_MTU),
                 ble_services: DashMap::new(),
+                failed_services: RwLock::new(BTreeSet::new()),
                 notifications_channel: broadcast_sender,
+                pending: PendingOperations::default(),
+                metrics: Metrics::default(),
+                default_write_type: RwLock::new(None),
+                subscriptions: Subscriptions::default(),
                 address_type: RwLock::new(None),
                 local_name: RwLock::new(None),
                 last_tx_power_level: RwLock::new(None),
                 last_rssi: RwLock::new(None),
+                rssi_average: RwLock::new(RssiAverage::default()),
                 latest_manufacturer_data: RwLock::new(HashMap::new()),
                 latest_service_data: RwLock::new(HashMap::new()),
                 services: RwLock::new(HashSet::new()),
                 class: RwLock::new(None),
+                last_seen: RwLock::new(None),
+                has_scan_response: AtomicBool::new(false),
+                connectable: RwLock::new(None),
             }),
         }
     }
@@ -117,9 +179,10 @@ impl Peripheral {
             local_name: self.shared.local_name.read().unwrap().clone(),
             tx_power_level: *self.shared.last_tx_power_level.read().unwrap(),
             rssi: *self.shared.last_rssi.read().unwrap(),
+            smoothed_rssi: self.shared.rssi_average.read().unwrap().get(),
             manufacturer_data: self.shared.latest_manufacturer_data.read().unwrap().clone(),
             service_data: self.shared.latest_service_data.read().unwrap().clone(),
-            services: self
+            advertised_services: self
                 .shared
                 .services
                 .read()
@@ -128,98 +191,92 @@ impl Peripheral {
                 .copied()
                 .collect(),
             class: *self.shared.class.read().unwrap(),
+            // The advertisement watcher only reports LE advertisements.
+            transport: Transport::Le,
+            last_seen: *self.shared.last_seen.read().unwrap(),
+            has_scan_response: Some(self.shared.has_scan_response.load(Ordering::Relaxed)),
+            connectable: self.connectable(),
+            advertising_sid: None,
         }
     }
 
-    pub(crate) fn update_properties(&self, args: &BluetoothLEAdvertisementReceivedEventArgs) {
-        let advertisement = args.Advertisement().unwrap();
-
-        // Advertisements are cumulative: set/replace data only if it's set
-        if let Ok(name) = advertisement.LocalName() {
-            if !name.is_empty() {
-                // XXX: we could probably also assume that we've seen the
-                // advertisement before and speculatively take a read lock
-                // to confirm that the name hasn't changed...
-
-                let mut local_name_guard = self.shared.local_name.write().unwrap();
-                *local_name_guard = Some(name.to_string());
-            }
+    /// Returns [`Error::NotConnected`] unless the device is connected, so that an operation
+    /// attempted after it has disconnected fails the same way on every platform.
+    fn ensure_connected(&self) -> Result<()> {
+        if self.shared.connected.load(Ordering::Relaxed) {
+            Ok(())
+        } else {
+            Err(Error::NotConnected)
         }
-        if let Ok(manufacturer_data) = advertisement.ManufacturerData() {
-            if manufacturer_data.Size().unwrap() > 0 {
-                let mut manufacturer_data_guard =
-                    self.shared.latest_manufacturer_data.write().unwrap();
-                *manufacturer_data_guard = manufacturer_data
-                    .into_iter()
-                    .map(|d| {
-                        let manufacturer_id = d.CompanyId().unwrap();
-                        let data = utils::to_vec(&d.Data().unwrap());
+    }
 
-                        (manufacturer_id, data)
-                    })
-                    .collect();
+    /// Whether the peripheral's advertisements have said it accepts connections.
+    pub(crate) fn connectable(&self) -> Option<bool> {
+        *self.shared.connectable.read().unwrap()
+    }
 
-                // Emit event of newly received advertisement
-                self.emit_event(CentralEvent::ManufacturerDataAdvertisement {
-                    id: self.shared.address.into(),
-                    manufacturer_data: manufacturer_data_guard.clone(),
-                });
-            }
+    /// The name the peripheral has advertised, if any.
+    pub(crate) fn local_name(&self) -> Option<String> {
+        self.shared.local_name.read().unwrap().clone()
+    }
+
+    /// Sets the name of a peripheral which hasn't been seen advertising, such as a bonded device.
+    pub(crate) fn set_local_name(&self, name: String) {
+        *self.shared.local_name.write().unwrap() = Some(name);
+    }
+
+    /// Sets the address type of a peripheral which hasn't been seen advertising, such as a bonded
+    /// device, so that it can be found again to connect to.
+    pub(crate) fn set_address_type(&self, address_type: AddressType) {
+        *self.shared.address_type.write().unwrap() = Some(address_type);
+    }
+
+    /// Updates the advertised state of the peripheral from an advertisement report, returning the
+    /// advertisement events to emit for it. These aren't emitted here, so that for a newly found
+    /// peripheral they can follow its `DeviceDiscovered` event.
+    #[must_use]
+    pub(crate) fn update_properties(
+        &self,
+        args: &BluetoothLEAdvertisementReceivedEventArgs,
+    ) -> Vec<CentralEvent> {
+        *self.shared.last_seen.write().unwrap() = Some(Instant::now());
+        if args.AdvertisementType() == Ok(BluetoothLEAdvertisementType::ScanResponse) {
+            self.shared.has_scan_response.store(true, Ordering::Relaxed);
+        }
+        if let Some(connectable) = connectable_from(args) {
+            *self.shared.connectable.write().unwrap() = Some(connectable);
         }
+        let advertisement = args.Advertisement().unwrap();
+        let mut events = Vec::new();
 
-        // The Windows Runtime API (as of 19041) does not directly expose Service Data as a friendly API (like Manufacturer Data above)
-        // Instead they provide data sections for access to raw advertising data. That is processed here.
-        if let Ok(data_sections) = advertisement.DataSections() {
-            // See if we have any advertised service data before taking a lock to update...
-            let mut found_service_data = false;
-            for section in &data_sections {
-                match section.DataType().unwrap() {
-                    advertisement_data_type::SERVICE_DATA_16_BIT_UUID
-                    | advertisement_data_type::SERVICE_DATA_32_BIT_UUID
-                    | advertisement_data_type::SERVICE_DATA_128_BIT_UUID => {
-                        found_service_data = true;
-                        break;
-                    }
-                    _ => {}
-                }
-            }
-            if found_service_data {
-                let mut service_data_guard = self.shared.latest_service_data.write().unwrap();
+        // Advertisements are cumulative: set/replace data only if it's set
+        let name = local_name_from(&advertisement);
+        if let Some(name) = name {
+            // XXX: we could probably also assume that we've seen the
+            // advertisement before and speculatively take a read lock
+            // to confirm that the name hasn't changed...
 
-                *service_data_guard = data_sections
-                    .into_iter()
-                    .filter_map(|d| {
-                        let data = utils::to_vec(&d.Data().unwrap());
-
-                        match d.DataType().unwrap() {
-                            advertisement_data_type::SERVICE_DATA_16_BIT_UUID => {
-                                let (uuid, data) = data.split_at(2);
-                                let uuid =
-                                    uuid_from_u16(u16::from_le_bytes(uuid.try_into().unwrap()));
-                                Some((uuid, data.to_owned()))
-                            }
-                            advertisement_data_type::SERVICE_DATA_32_BIT_UUID => {
-                                let (uuid, data) = data.split_at(4);
-                                let uuid =
-                                    uuid_from_u32(u32::from_le_bytes(uuid.try_into().unwrap()));
-                                Some((uuid, data.to_owned()))
-                            }
-                            advertisement_data_type::SERVICE_DATA_128_BIT_UUID => {
-                                let (uuid, data) = data.split_at(16);
-                                let uuid = Uuid::from_slice(uuid).unwrap();
-                                Some((uuid, data.to_owned()))
-                            }
-                            _ => None,
-                        }
-                    })
-                    .collect();
+            let mut local_name_guard = self.shared.local_name.write().unwrap();
+            *local_name_guard = Some(name);
+        }
+        let manufacturer_data = manufacturer_data_from(&advertisement);
+        if !manufacturer_data.is_empty() {
+            *self.shared.latest_manufacturer_data.write().unwrap() = manufacturer_data.clone();
+            // Emit event of newly received advertisement
+            events.push(CentralEvent::ManufacturerDataAdvertisement {
+                id: self.shared.address.into(),
+                manufacturer_data,
+            });
+        }
 
-                // Emit event of newly received advertisement
-                self.emit_event(CentralEvent::ServiceDataAdvertisement {
-                    id: self.shared.address.into(),
-                    service_data: service_data_guard.clone(),
-                });
-            }
+        let service_data = service_data_from(&advertisement);
+        if !service_data.is_empty() {
+            *self.shared.latest_service_data.write().unwrap() = service_data.clone();
+            // Emit event of newly received advertisement
+            events.push(CentralEvent::ServiceDataAdvertisement {
+                id: self.shared.address.into(),
+                service_data,
+            });
         }
 
         if let Ok(services) = advertisement.ServiceUuids() {
@@ -251,7 +308,7 @@ impl Peripheral {
                     services_guard.insert(utils::to_uuid(&uuid));
                 }
 
-                self.emit_event(CentralEvent::ServicesAdvertisement {
+                events.push(CentralEvent::ServicesAdvertisement {
                     id: self.shared.address.into(),
                     services: services_guard.iter().copied().collect(),
                 });
@@ -259,12 +316,7 @@ impl Peripheral {
         }
 
         if let Ok(address_type) = args.BluetoothAddressType() {
-            let mut address_type_guard = self.shared.address_type.write().unwrap();
-            *address_type_guard = match address_type {
-                BluetoothAddressType::Public => Some(AddressType::Public),
-                BluetoothAddressType::Random => Some(AddressType::Random),
-                _ => None,
-            };
+            *self.shared.address_type.write().unwrap() = utils::to_address_type(address_type);
         }
 
         if let Ok(tx_reference) = args.TransmitPowerLevelInDBm() {
@@ -280,7 +332,101 @@ impl Peripheral {
         if let Ok(rssi) = args.RawSignalStrengthInDBm() {
             let mut rssi_guard = self.shared.last_rssi.write().unwrap();
             *rssi_guard = Some(rssi);
+            if let Some(manager) = self.shared.adapter.upgrade() {
+                let weight = manager.rssi_smoothing().weight();
+                self.shared.rssi_average.write().unwrap().add(rssi, weight);
+            }
         }
+        events
+    }
+
+    /// Subscribes to the characteristic, writing `config` to its Client Characteristic
+    /// Configuration descriptor, or whatever its properties call for if that is `None`.
+    async fn subscribe_with_config(
+        &self,
+        characteristic: &Characteristic,
+        config: Option<GattClientCharacteristicConfigurationDescriptorValue>,
+    ) -> Result<()> {
+        self.ensure_connected()?;
+        self.shared
+            .subscriptions
+            .subscribe(
+                characteristic,
+                self.shared.pending.track(async {
+                    let ble_service = &mut *self
+                        .shared
+                        .ble_services
+                        .get_mut(&self.service_key(
+                            characteristic.service_uuid,
+                            characteristic.uuid,
+                            characteristic.instance_id,
+                        ))
+                        .ok_or_else(|| {
+                            Error::NotSupported("Service not found for subscribe".into())
+                        })?;
+                    let ble_characteristic = ble_service
+                        .characteristics
+                        .get_mut(&(characteristic.uuid, characteristic.instance_id))
+                        .ok_or_else(|| {
+                            Error::NotSupported("Characteristic not found for subscribe".into())
+                        })?;
+                    let notifications_sender = self.shared.notifications_channel.clone();
+                    let uuid = characteristic.uuid;
+                    let service_uuid = characteristic.service_uuid;
+                    let instance_id = characteristic.instance_id;
+                    let address = self.shared.address;
+                    let shared_clone = Arc::downgrade(&self.shared);
+                    let on_value_changed = Box::new(move |value| {
+                        trace::emit(|| TraceEvent::NotificationDelivered {
+                            id: address.into(),
+                            characteristic: uuid,
+                        });
+                        if let Some(shared) = shared_clone.upgrade() {
+                            shared.metrics.notification();
+                        }
+                        let notification = ValueNotification {
+                            uuid,
+                            service_uuid,
+                            instance_id,
+                            value,
+                        };
+                        // Note: we ignore send errors here which may happen while there are no
+                        // receivers...
+                        let _ = notifications_sender.send(notification);
+                    });
+                    match config {
+                        Some(config) => {
+                            ble_characteristic
+                                .subscribe_with_config(config, on_value_changed)
+                                .await
+                        }
+                        None => ble_characteristic.subscribe(on_value_changed).await,
+                    }
+                }),
+            )
+            .await
+    }
+
+    /// Finds the key of the service with the given UUID which has the given characteristic, or one
+    /// which matches no service if there isn't one. A device may have several services with the
+    /// same UUID, but characteristics' handles are unique across them, and never 0.
+    fn service_key(
+        &self,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        instance_id: u16,
+    ) -> (Uuid, u16) {
+        self.shared
+            .ble_services
+            .iter()
+            .find(|item| {
+                item.key().0 == service_uuid
+                    && item
+                        .characteristics
+                        .contains_key(&(characteristic_uuid, instance_id))
+            })
+            .map(|item| *item.key())
+            .unwrap_or((service_uuid, 0))
     }
 
     fn emit_event(&self, event: CentralEvent) {
@@ -292,6 +438,140 @@ impl Peripheral {
     }
 }
 
+/// Builds a standalone report of a single advertisement, for [`Central::observe`].
+///
+/// [`Central::observe`]: crate::api::Central::observe
+pub(crate) fn advertisement_from_args(
+    args: &BluetoothLEAdvertisementReceivedEventArgs,
+) -> Result<Advertisement> {
+    let address: BDAddr = args.BluetoothAddress()?.try_into()?;
+    let advertisement = args.Advertisement()?;
+    Ok(Advertisement {
+        id: address.into(),
+        address,
+        local_name: local_name_from(&advertisement),
+        rssi: args.RawSignalStrengthInDBm().ok(),
+        tx_power_level: args
+            .TransmitPowerLevelInDBm()
+            .ok()
+            .and_then(|tx| tx.Value().ok()),
+        manufacturer_data: manufacturer_data_from(&advertisement),
+        service_data: service_data_from(&advertisement),
+        advertised_services: advertisement
+            .ServiceUuids()
+            .map(|services| {
+                services
+                    .into_iter()
+                    .map(|uuid| utils::to_uuid(&uuid))
+                    .collect()
+            })
+            .unwrap_or_default(),
+    })
+}
+
+/// Whether an advertisement report says the device accepts connections, or `None` for a scan
+/// response, which doesn't say.
+pub(crate) fn connectable_from(args: &BluetoothLEAdvertisementReceivedEventArgs) -> Option<bool> {
+    match args.AdvertisementType().ok()? {
+        BluetoothLEAdvertisementType::ConnectableUndirected
+        | BluetoothLEAdvertisementType::ConnectableDirected => Some(true),
+        BluetoothLEAdvertisementType::ScannableUndirected
+        | BluetoothLEAdvertisementType::NonConnectableUndirected => Some(false),
+        // Extended advertisements say separately, on Windows 10 2004 and later.
+        BluetoothLEAdvertisementType::Extended => args.IsConnectable().ok(),
+        _ => None,
+    }
+}
+
+/// The local name in an advertisement report, if it has one.
+pub(crate) fn local_name_from_args(
+    args: &BluetoothLEAdvertisementReceivedEventArgs,
+) -> Option<String> {
+    local_name_from(&args.Advertisement().ok()?)
+}
+
+fn local_name_from(advertisement: &BluetoothLEAdvertisement) -> Option<String> {
+    advertisement
+        .LocalName()
+        .ok()
+        .map(|name| name.to_string())
+        .filter(|name| !name.is_empty())
+        .or_else(|| local_name_from_sections(advertisement))
+}
+
+fn manufacturer_data_from(advertisement: &BluetoothLEAdvertisement) -> HashMap<u16, Vec<u8>> {
+    let Ok(manufacturer_data) = advertisement.ManufacturerData() else {
+        return HashMap::new();
+    };
+    manufacturer_data
+        .into_iter()
+        .map(|d| {
+            let manufacturer_id = d.CompanyId().unwrap();
+            let data = utils::to_vec(&d.Data().unwrap());
+
+            (manufacturer_id, data)
+        })
+        .collect()
+}
+
+/// The Windows Runtime API (as of 19041) does not directly expose Service Data as a friendly API
+/// (like Manufacturer Data) Instead they provide data sections for access to raw advertising data.
+/// That is processed here.
+fn service_data_from(advertisement: &BluetoothLEAdvertisement) -> HashMap<Uuid, Vec<u8>> {
+    let Ok(data_sections) = advertisement.DataSections() else {
+        return HashMap::new();
+    };
+    data_sections
+        .into_iter()
+        .filter_map(|d| {
+            let data = utils::to_vec(&d.Data().unwrap());
+
+            match d.DataType().unwrap() {
+                advertisement_data_type::SERVICE_DATA_16_BIT_UUID => {
+                    let (uuid, data) = data.split_at(2);
+                    let uuid = uuid_from_u16(u16::from_le_bytes(uuid.try_into().unwrap()));
+                    Some((uuid, data.to_owned()))
+                }
+                advertisement_data_type::SERVICE_DATA_32_BIT_UUID => {
+                    let (uuid, data) = data.split_at(4);
+                    let uuid = uuid_from_u32(u32::from_le_bytes(uuid.try_into().unwrap()));
+                    Some((uuid, data.to_owned()))
+                }
+                advertisement_data_type::SERVICE_DATA_128_BIT_UUID => {
+                    let (uuid, data) = data.split_at(16);
+                    let uuid = Uuid::from_slice(uuid).unwrap();
+                    Some((uuid, data.to_owned()))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Finds the local name in the raw sections of an advertisement, preferring the complete name to a
+/// shortened one. Windows doesn't always fill in `LocalName` from the first report for a device,
+/// particularly for non-scannable advertisers which never send a scan response.
+fn local_name_from_sections(advertisement: &BluetoothLEAdvertisement) -> Option<String> {
+    let mut shortened = None;
+    for section in &advertisement.DataSections().ok()? {
+        let data_type = section.DataType().ok()?;
+        if data_type != advertisement_data_type::COMPLETE_LOCAL_NAME
+            && data_type != advertisement_data_type::SHORTENED_LOCAL_NAME
+        {
+            continue;
+        }
+        let name = String::from_utf8_lossy(&utils::to_vec(&section.Data().ok()?)).into_owned();
+        if name.is_empty() {
+            continue;
+        }
+        if data_type == advertisement_data_type::COMPLETE_LOCAL_NAME {
+            return Some(name);
+        }
+        shortened = Some(name);
+    }
+    shortened
+}
+
 impl Display for Peripheral {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         let connected = if self.shared.connected.load(Ordering::Relaxed) {
@@ -341,6 +621,52 @@ impl ApiPeripheral for Peripheral {
         self.shared.address
     }
 
+    fn max_write_len(&self, write_type: WriteType) -> usize {
+        max_write_len_for_mtu(self.shared.mtu.load(Ordering::Relaxed), write_type)
+    }
+
+    async fn request_mtu(&self, _mtu: u16) -> Result<u16> {
+        // Windows exchanges the largest MTU it can as soon as the GATT session is opened, and
+        // offers no way to ask for a particular one.
+        if !self.shared.connected.load(Ordering::Relaxed) {
+            return Err(Error::NotConnected);
+        }
+        Ok(self.shared.mtu.load(Ordering::Relaxed))
+    }
+
+    fn pending_operations(&self) -> usize {
+        self.shared.pending.operations()
+    }
+
+    fn pending_writes(&self) -> usize {
+        self.shared.pending.writes()
+    }
+
+    fn abort_pending(&self) {
+        self.shared.pending.abort();
+    }
+
+    fn metrics(&self) -> PeripheralMetrics {
+        let mtu = self
+            .shared
+            .connected
+            .load(Ordering::Relaxed)
+            .then(|| self.shared.mtu.load(Ordering::Relaxed));
+        self.shared.metrics.snapshot(mtu)
+    }
+
+    fn default_write_type(&self) -> Option<WriteType> {
+        *self.shared.default_write_type.read().unwrap()
+    }
+
+    fn set_default_write_type(&self, write_type: WriteType) {
+        *self.shared.default_write_type.write().unwrap() = Some(write_type);
+    }
+
+    fn subscriptions(&self) -> Vec<Characteristic> {
+        self.shared.subscriptions.get()
+    }
+
     /// Returns the set of properties associated with the peripheral. These may be updated over time
     /// as additional advertising reports are received.
     async fn properties(&self) -> Result<Option<PeripheralProperties>> {
@@ -355,6 +681,10 @@ impl ApiPeripheral for Peripheral {
             .collect()
     }
 
+    fn failed_services(&self) -> BTreeSet<Uuid> {
+        self.shared.failed_services.read().unwrap().clone()
+    }
+
     /// Returns true iff we are currently connected to the device.
     async fn is_connected(&self) -> Result<bool> {
         Ok(self.shared.connected.load(Ordering::Relaxed))
@@ -364,30 +694,90 @@ impl ApiPeripheral for Peripheral {
     /// Ok there has been successful connection. Note that peripherals allow only one connection at
     /// a time. Operations that attempt to communicate with a device will fail until it is connected.
     async fn connect(&self) -> Result<()> {
-        let shared_clone = Arc::downgrade(&self.shared);
-        let adapter_clone = self.shared.adapter.clone();
-        let address = self.shared.address;
-        let device = BLEDevice::new(
-            self.shared.address,
-            Box::new(move |is_connected| {
-                if let Some(shared) = shared_clone.upgrade() {
-                    shared.connected.store(is_connected, Ordering::Relaxed);
+        self.connect_with_options(ConnectOptions::default()).await
+    }
+
+    async fn connect_with_options(&self, options: ConnectOptions) -> Result<()> {
+        let permit = self.shared.connect_limit.acquire().await;
+        traced_connect(self, async {
+            let shared_clone = Arc::downgrade(&self.shared);
+            let adapter_clone = self.shared.adapter.clone();
+            let address = self.shared.address;
+            let address_type = options
+                .address_type
+                .or(*self.shared.address_type.read().unwrap())
+                .unwrap_or(AddressType::Public);
+            let mut device = BLEDevice::new(
+                self.shared.address,
+                address_type,
+                Box::new(move |is_connected| {
+                    if let Some(shared) = shared_clone.upgrade() {
+                        shared.connected.store(is_connected, Ordering::Relaxed);
+                        if !is_connected {
+                            shared.mtu.store(DEFAULT_ATT_MTU, Ordering::Relaxed);
+                        }
+                    }
+
+                    if !is_connected {
+                        if let Some(adapter) = adapter_clone.upgrade() {
+                            adapter.emit(CentralEvent::DeviceDisconnected(address.into()));
+                        }
+                    }
+                }),
+                {
+                    let adapter_clone = self.shared.adapter.clone();
+                    Box::new(move || {
+                        if let Some(adapter) = adapter_clone.upgrade() {
+                            adapter.emit(CentralEvent::ServicesChanged(address.into()));
+                        }
+                    })
+                },
+            )
+            .await?;
+
+            if let Some(parameters) = options.preferred_parameters {
+                // Only an optimisation, so connect anyway on versions of Windows without it.
+                if let Err(err) = device.request_preferred_parameters(parameters) {
+                    debug!(
+                        "Could not request preferred connection parameters: {:?}",
+                        err
+                    );
                 }
+            }
 
-                if !is_connected {
-                    if let Some(adapter) = adapter_clone.upgrade() {
-                        adapter.emit(CentralEvent::DeviceDisconnected(address.into()));
+            // Querying the services is what makes Windows connect, unless the GATT session is
+            // asked to keep a connection open instead.
+            if options.discover_services {
+                device.connect().await?;
+            }
+            let shared_clone = Arc::downgrade(&self.shared);
+            let result = device
+                .open_session(Box::new(move |max_pdu_size| {
+                    if let Some(shared) = shared_clone.upgrade() {
+                        shared.mtu.store(max_pdu_size, Ordering::Relaxed);
                     }
+                }))
+                .await;
+            if options.discover_services {
+                if let Err(err) = result {
+                    // Not being able to track the MTU isn't a reason to fail the connection; writes
+                    // will just be limited to what the minimum MTU allows.
+                    warn!("Could not open GATT session: {:?}", err);
                 }
-            }),
-        )
+            } else {
+                result?;
+                device.maintain_connection().await?;
+            }
+            let mut d = self.shared.device.lock().await;
+            *d = Some(device);
+            self.shared.connected.store(true, Ordering::Relaxed);
+            self.shared.metrics.connected();
+            self.emit_event(CentralEvent::DeviceConnected(self.shared.address.into()));
+            Ok(())
+        })
         .await?;
-
-        device.connect().await?;
-        let mut d = self.shared.device.lock().await;
-        *d = Some(device);
-        self.shared.connected.store(true, Ordering::Relaxed);
-        self.emit_event(CentralEvent::DeviceConnected(self.shared.address.into()));
+        drop(permit);
+        auto_subscribe(self, &options.auto_subscribe).await;
         Ok(())
     }
 
@@ -396,31 +786,101 @@ impl ApiPeripheral for Peripheral {
         // We need to clear the services because if this device is re-connected,
         // the cached service objects will no longer be valid (they must be refreshed).
         self.shared.ble_services.clear();
-        let mut device = self.shared.device.lock().await;
-        *device = None;
+        let device = self.shared.device.lock().await.take();
         self.shared.connected.store(false, Ordering::Relaxed);
+        self.shared.mtu.store(DEFAULT_ATT_MTU, Ordering::Relaxed);
         self.emit_event(CentralEvent::DeviceDisconnected(self.shared.address.into()));
+        // Close the session and device here rather than leaving it to the drop, so that any error
+        // is reported and the executor isn't blocked.
+        if let Some(device) = device {
+            device.close().await?;
+        }
+        Ok(())
+    }
+
+    async fn disconnect_with_options(&self, options: DisconnectOptions) -> Result<()> {
+        self.disconnect().await?;
+        if let Some(reason) = options.reason {
+            self.emit_event(CentralEvent::DeviceDisconnectedWithReason {
+                id: self.id(),
+                reason,
+            });
+        }
+        Ok(())
+    }
+
+    async fn close(self) -> Result<()> {
+        // Dropping the services removes their notification handlers, which is another blocking
+        // call, so do that off the executor too.
+        let keys: Vec<_> = self
+            .shared
+            .ble_services
+            .iter()
+            .map(|item| *item.key())
+            .collect();
+        let ble_services: Vec<_> = keys
+            .iter()
+            .filter_map(|key| self.shared.ble_services.remove(key))
+            .collect();
+        runtime::spawn_blocking(move || drop(ble_services)).await?;
+        let device = self.shared.device.lock().await.take();
+        if self.shared.connected.swap(false, Ordering::Relaxed) {
+            self.emit_event(CentralEvent::DeviceDisconnected(self.shared.address.into()));
+        }
+        if let Some(device) = device {
+            device.close().await?;
+        }
+        Ok(())
+    }
+
+    async fn require_encryption(&self) -> Result<()> {
+        match *self.shared.device.lock().await {
+            Some(ref device) => device.require_encryption().await,
+            None => Err(Error::NotConnected),
+        }
+    }
+
+    async fn clear_gatt_cache(&self) -> Result<()> {
+        // Connecting always queries the device, so there's only anything to do while connected.
+        if let Some(ref mut device) = *self.shared.device.lock().await {
+            device.clear_cache();
+        }
         Ok(())
     }
 
     /// Discovers all characteristics for the device. This is a synchronous operation.
     async fn discover_services(&self) -> Result<()> {
-        let mut device = self.shared.device.lock().await;
-        if let Some(ref mut device) = *device {
-            let gatt_services = device.discover_services().await?;
-            for service in gatt_services {
-                let uuid = utils::to_uuid(&service.Uuid().unwrap());
-                if !self.shared.ble_services.contains_key(&uuid) {
+        self.discover_services_with_options(DiscoverOptions::default())
+            .await
+    }
+
+    async fn discover_services_with_options(&self, options: DiscoverOptions) -> Result<()> {
+        traced_discover_services(self, async {
+            let mut device = self.shared.device.lock().await;
+            if let Some(ref mut device) = *device {
+                let gatt_services = device.discover_services(&options.services).await?;
+                let mut failed_services = BTreeSet::new();
+                let mut service_keys = HashSet::new();
+                for service in gatt_services {
+                    let uuid = utils::to_uuid(&service.Uuid().unwrap());
+                    let handle = service.AttributeHandle().unwrap();
+                    service_keys.insert((uuid, handle));
                     match BLEDevice::get_characteristics(service).await {
                         Ok(characteristics) => {
                             let characteristics =
                                 characteristics.into_iter().map(|characteristic| async {
+                                    if !options.descriptors {
+                                        return (characteristic, HashMap::new());
+                                    }
                                     let c = characteristic.clone();
                                     (
                                         characteristic,
                                         BLEDevice::get_characteristic_descriptors(&c)
                                             .await
-                                            .unwrap_or(Vec::new())
+                                            .unwrap_or_else(|e| {
+                                                warn!("Failed to get descriptors: {:?}", e);
+                                                Vec::new()
+                                            })
                                             .into_iter()
                                             .map(|descriptor| {
                                                 let descriptor = BLEDescriptor::new(descriptor);
@@ -430,33 +890,116 @@ impl ApiPeripheral for Peripheral {
                                     )
                                 });
 
-                            let characteristics = futures::future::join_all(characteristics)
-                                .await
-                                .into_iter()
-                                .map(|(characteristic, descriptors)| {
-                                    let characteristic =
-                                        BLECharacteristic::new(characteristic, descriptors);
-                                    (characteristic.uuid(), characteristic)
-                                })
-                                .collect();
+                            let mut characteristics: HashMap<_, _> =
+                                futures::future::join_all(characteristics)
+                                    .await
+                                    .into_iter()
+                                    .map(|(characteristic, descriptors)| {
+                                        let characteristic =
+                                            BLECharacteristic::new(characteristic, descriptors);
+                                        (characteristic.key(), characteristic)
+                                    })
+                                    .collect();
+
+                            // When discovering again, e.g. after the device's services have
+                            // changed, the fresh characteristics replace the ones we had, except
+                            // for those still subscribed to, which hold the handler delivering
+                            // their notifications.
+                            if let Some((_, old_service)) =
+                                self.shared.ble_services.remove(&(uuid, handle))
+                            {
+                                for (key, characteristic) in old_service.characteristics {
+                                    if characteristic.is_subscribed()
+                                        && characteristics.contains_key(&key)
+                                    {
+                                        characteristics.insert(key, characteristic);
+                                    }
+                                }
+                            }
 
                             self.shared.ble_services.insert(
-                                uuid,
+                                (uuid, handle),
                                 BLEService {
                                     uuid,
+                                    handle,
                                     characteristics,
                                 },
                             );
                         }
+                        // Otherwise an unpaired device just looks like it has no characteristics.
+                        Err(Error::NotPaired) => return Err(Error::NotPaired),
                         Err(e) => {
-                            warn!("get_characteristics_async {:?}", e);
+                            warn!(
+                                "Skipping service {:?}, failed to get its characteristics: {:?}",
+                                uuid, e
+                            );
+                            failed_services.insert(uuid);
                         }
                     }
                 }
+                // Forget about any services the device no longer has, of those asked for.
+                self.shared.ble_services.retain(|key, _| {
+                    service_keys.contains(key)
+                        || !(options.services.is_empty() || options.services.contains(&key.0))
+                });
+                *self.shared.failed_services.write().unwrap() = failed_services;
+                return Ok(());
             }
-            return Ok(());
-        }
-        Err(Error::NotConnected)
+            Err(Error::NotConnected)
+        })
+        .await
+    }
+
+    async fn discover_descriptors(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Vec<Descriptor>> {
+        self.ensure_connected()?;
+        let not_found = || Error::NotSupported("Characteristic not found for discovery".into());
+        let gatt_characteristic = self
+            .shared
+            .ble_services
+            .get(&self.service_key(
+                characteristic.service_uuid,
+                characteristic.uuid,
+                characteristic.instance_id,
+            ))
+            .and_then(|ble_service| {
+                ble_service
+                    .characteristics
+                    .get(&(characteristic.uuid, characteristic.instance_id))
+                    .map(BLECharacteristic::gatt_characteristic)
+            })
+            .ok_or_else(not_found)?;
+        let descriptors = BLEDevice::get_characteristic_descriptors(&gatt_characteristic)
+            .await?
+            .into_iter()
+            .map(|descriptor| {
+                let descriptor = BLEDescriptor::new(descriptor);
+                (descriptor.uuid(), descriptor)
+            })
+            .collect();
+        let mut ble_service = self
+            .shared
+            .ble_services
+            .get_mut(&self.service_key(
+                characteristic.service_uuid,
+                characteristic.uuid,
+                characteristic.instance_id,
+            ))
+            .ok_or_else(not_found)?;
+        let ble_characteristic = ble_service
+            .characteristics
+            .get_mut(&(characteristic.uuid, characteristic.instance_id))
+            .ok_or_else(not_found)?;
+        ble_characteristic.descriptors = descriptors;
+        let mut descriptors: Vec<_> = ble_characteristic
+            .to_characteristic(characteristic.service_uuid)
+            .descriptors
+            .into_iter()
+            .collect();
+        descriptors.sort();
+        Ok(descriptors)
     }
 
     /// Write some data to the characteristic. Returns an error if the write couldn't be send or (in
@@ -467,70 +1010,172 @@ impl ApiPeripheral for Peripheral {
         data: &[u8],
         write_type: WriteType,
     ) -> Result<()> {
-        let ble_service = &*self
-            .shared
-            .ble_services
-            .get(&characteristic.service_uuid)
-            .ok_or_else(|| Error::NotSupported("Service not found for write".into()))?;
-        let ble_characteristic = ble_service
-            .characteristics
-            .get(&characteristic.uuid)
-            .ok_or_else(|| Error::NotSupported("Characteristic not found for write".into()))?;
-        ble_characteristic.write_value(data, write_type).await
+        self.ensure_connected()?;
+        self.shared
+            .pending
+            .track_write(async {
+                let ble_service = &*self
+                    .shared
+                    .ble_services
+                    .get(&self.service_key(
+                        characteristic.service_uuid,
+                        characteristic.uuid,
+                        characteristic.instance_id,
+                    ))
+                    .ok_or_else(|| Error::NotSupported("Service not found for write".into()))?;
+                let ble_characteristic = ble_service
+                    .characteristics
+                    .get(&(characteristic.uuid, characteristic.instance_id))
+                    .ok_or_else(|| {
+                        Error::NotSupported("Characteristic not found for write".into())
+                    })?;
+                ble_characteristic.write_value(data, write_type).await
+            })
+            .await
+            .inspect(|()| self.shared.metrics.written(data.len()))
+    }
+
+    async fn execute_reliable_write(&self, writes: &[(Characteristic, Vec<u8>)]) -> Result<()> {
+        self.shared
+            .pending
+            .track_write(async {
+                let transaction = GattReliableWriteTransaction::new()?;
+                for (characteristic, data) in writes {
+                    let ble_service = &*self
+                        .shared
+                        .ble_services
+                        .get(&self.service_key(
+                            characteristic.service_uuid,
+                            characteristic.uuid,
+                            characteristic.instance_id,
+                        ))
+                        .ok_or_else(|| Error::NotSupported("Service not found for write".into()))?;
+                    let ble_characteristic = ble_service
+                        .characteristics
+                        .get(&(characteristic.uuid, characteristic.instance_id))
+                        .ok_or_else(|| {
+                            Error::NotSupported("Characteristic not found for write".into())
+                        })?;
+                    ble_characteristic.add_to_transaction(&transaction, data)?;
+                }
+                // Windows cancels the whole transaction if any of the writes fails.
+                let status = transaction.CommitAsync()?.await?;
+                if status == GattCommunicationStatus::Success {
+                    Ok(())
+                } else {
+                    Err(utils::to_operation_error("reliable write", status))
+                }
+            })
+            .await
     }
 
     /// Enables either notify or indicate (depending on support) for the specified characteristic.
     /// This is a synchronous call.
     async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        let ble_service = &mut *self
-            .shared
-            .ble_services
-            .get_mut(&characteristic.service_uuid)
-            .ok_or_else(|| Error::NotSupported("Service not found for subscribe".into()))?;
-        let ble_characteristic = ble_service
-            .characteristics
-            .get_mut(&characteristic.uuid)
-            .ok_or_else(|| Error::NotSupported("Characteristic not found for subscribe".into()))?;
-        let notifications_sender = self.shared.notifications_channel.clone();
-        let uuid = characteristic.uuid;
-        ble_characteristic
-            .subscribe(Box::new(move |value| {
-                let notification = ValueNotification { uuid, value };
-                // Note: we ignore send errors here which may happen while there are no
-                // receivers...
-                let _ = notifications_sender.send(notification);
-            }))
+        self.subscribe_with_config(characteristic, None).await
+    }
+
+    async fn subscribe_with(
+        &self,
+        characteristic: &Characteristic,
+        kind: SubscriptionKind,
+    ) -> Result<()> {
+        kind.check(characteristic)?;
+        let config = GattClientCharacteristicConfigurationDescriptorValue(kind.cccd_value().into());
+        self.subscribe_with_config(characteristic, Some(config))
             .await
     }
 
     /// Disables either notify or indicate (depending on support) for the specified characteristic.
     /// This is a synchronous call.
     async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        let ble_service = &mut *self
-            .shared
-            .ble_services
-            .get_mut(&characteristic.service_uuid)
-            .ok_or_else(|| Error::NotSupported("Service not found for unsubscribe".into()))?;
-        let ble_characteristic = ble_service
-            .characteristics
-            .get_mut(&characteristic.uuid)
-            .ok_or_else(|| {
-                Error::NotSupported("Characteristic not found for unsubscribe".into())
-            })?;
-        ble_characteristic.unsubscribe().await
+        self.ensure_connected()?;
+        self.shared
+            .subscriptions
+            .unsubscribe(
+                characteristic,
+                self.shared.pending.track(async {
+                    let ble_service = &mut *self
+                        .shared
+                        .ble_services
+                        .get_mut(&self.service_key(
+                            characteristic.service_uuid,
+                            characteristic.uuid,
+                            characteristic.instance_id,
+                        ))
+                        .ok_or_else(|| {
+                            Error::NotSupported("Service not found for unsubscribe".into())
+                        })?;
+                    let ble_characteristic = ble_service
+                        .characteristics
+                        .get_mut(&(characteristic.uuid, characteristic.instance_id))
+                        .ok_or_else(|| {
+                            Error::NotSupported("Characteristic not found for unsubscribe".into())
+                        })?;
+                    ble_characteristic.unsubscribe().await
+                }),
+            )
+            .await
     }
 
     async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
-        let ble_service = &*self
-            .shared
-            .ble_services
-            .get(&characteristic.service_uuid)
-            .ok_or_else(|| Error::NotSupported("Service not found for read".into()))?;
-        let ble_characteristic = ble_service
-            .characteristics
-            .get(&characteristic.uuid)
-            .ok_or_else(|| Error::NotSupported("Characteristic not found for read".into()))?;
-        ble_characteristic.read_value().await
+        self.ensure_connected()?;
+        self.shared
+            .pending
+            .track(async {
+                let ble_service = &*self
+                    .shared
+                    .ble_services
+                    .get(&self.service_key(
+                        characteristic.service_uuid,
+                        characteristic.uuid,
+                        characteristic.instance_id,
+                    ))
+                    .ok_or_else(|| Error::NotSupported("Service not found for read".into()))?;
+                let ble_characteristic = ble_service
+                    .characteristics
+                    .get(&(characteristic.uuid, characteristic.instance_id))
+                    .ok_or_else(|| {
+                        Error::NotSupported("Characteristic not found for read".into())
+                    })?;
+                ble_characteristic
+                    .read_value(BluetoothCacheMode::Uncached)
+                    .await
+            })
+            .await
+            .inspect(|value| self.shared.metrics.read(value.len()))
+    }
+
+    async fn read_cached(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.shared
+            .pending
+            .track(async {
+                let ble_service = &*self
+                    .shared
+                    .ble_services
+                    .get(&self.service_key(
+                        characteristic.service_uuid,
+                        characteristic.uuid,
+                        characteristic.instance_id,
+                    ))
+                    .ok_or_else(|| Error::NotSupported("Service not found for read".into()))?;
+                let ble_characteristic = ble_service
+                    .characteristics
+                    .get(&(characteristic.uuid, characteristic.instance_id))
+                    .ok_or_else(|| {
+                        Error::NotSupported("Characteristic not found for read".into())
+                    })?;
+                ble_characteristic
+                    .read_value(BluetoothCacheMode::Cached)
+                    .await
+            })
+            .await
+    }
+
+    async fn read_device_name(&self) -> Result<String> {
+        let name = read_gap_device_name(self).await?;
+        *self.shared.local_name.write().unwrap() = Some(name.clone());
+        Ok(name)
     }
 
     async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
@@ -539,37 +1184,67 @@ impl ApiPeripheral for Peripheral {
     }
 
     async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
-        let ble_service = &*self
-            .shared
-            .ble_services
-            .get(&descriptor.service_uuid)
-            .ok_or_else(|| Error::NotSupported("Service not found for write".into()))?;
-        let ble_characteristic = ble_service
-            .characteristics
-            .get(&descriptor.characteristic_uuid)
-            .ok_or_else(|| Error::NotSupported("Characteristic not found for write".into()))?;
-        let ble_descriptor = ble_characteristic
-            .descriptors
-            .get(&descriptor.uuid)
-            .ok_or_else(|| Error::NotSupported("Descriptor not found for write".into()))?;
-        ble_descriptor.write_value(data).await
+        self.ensure_connected()?;
+        self.shared
+            .pending
+            .track(async {
+                let ble_service = &*self
+                    .shared
+                    .ble_services
+                    .get(&self.service_key(
+                        descriptor.service_uuid,
+                        descriptor.characteristic_uuid,
+                        descriptor.characteristic_instance_id,
+                    ))
+                    .ok_or_else(|| Error::NotSupported("Service not found for write".into()))?;
+                let ble_characteristic = ble_service
+                    .characteristics
+                    .get(&(
+                        descriptor.characteristic_uuid,
+                        descriptor.characteristic_instance_id,
+                    ))
+                    .ok_or_else(|| {
+                        Error::NotSupported("Characteristic not found for write".into())
+                    })?;
+                let ble_descriptor = ble_characteristic
+                    .descriptors
+                    .get(&descriptor.uuid)
+                    .ok_or_else(|| Error::NotSupported("Descriptor not found for write".into()))?;
+                ble_descriptor.write_value(data).await
+            })
+            .await
     }
 
     async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
-        let ble_service = &*self
-            .shared
-            .ble_services
-            .get(&descriptor.service_uuid)
-            .ok_or_else(|| Error::NotSupported("Service not found for read".into()))?;
-        let ble_characteristic = ble_service
-            .characteristics
-            .get(&descriptor.characteristic_uuid)
-            .ok_or_else(|| Error::NotSupported("Characteristic not found for read".into()))?;
-        let ble_descriptor = ble_characteristic
-            .descriptors
-            .get(&descriptor.uuid)
-            .ok_or_else(|| Error::NotSupported("Descriptor not found for write".into()))?;
-        ble_descriptor.read_value().await
+        self.ensure_connected()?;
+        self.shared
+            .pending
+            .track(async {
+                let ble_service = &*self
+                    .shared
+                    .ble_services
+                    .get(&self.service_key(
+                        descriptor.service_uuid,
+                        descriptor.characteristic_uuid,
+                        descriptor.characteristic_instance_id,
+                    ))
+                    .ok_or_else(|| Error::NotSupported("Service not found for read".into()))?;
+                let ble_characteristic = ble_service
+                    .characteristics
+                    .get(&(
+                        descriptor.characteristic_uuid,
+                        descriptor.characteristic_instance_id,
+                    ))
+                    .ok_or_else(|| {
+                        Error::NotSupported("Characteristic not found for read".into())
+                    })?;
+                let ble_descriptor = ble_characteristic
+                    .descriptors
+                    .get(&descriptor.uuid)
+                    .ok_or_else(|| Error::NotSupported("Descriptor not found for write".into()))?;
+                ble_descriptor.read_value().await
+            })
+            .await
     }
 }
 
@@ -11,21 +11,26 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use crate::{api::CharPropFlags, Error, Result};
-use std::str::FromStr;
+use crate::{
+    api::{AddressType, CharPropFlags},
+    Error, Result,
+};
 use uuid::Uuid;
 use windows::core::GUID;
 use windows::{
-    Devices::Bluetooth::GenericAttributeProfile::{
-        GattCharacteristicProperties, GattClientCharacteristicConfigurationDescriptorValue,
-        GattCommunicationStatus,
+    Devices::Bluetooth::{
+        BluetoothAddressType,
+        GenericAttributeProfile::{
+            GattCharacteristicProperties, GattClientCharacteristicConfigurationDescriptorValue,
+            GattCommunicationStatus,
+        },
     },
     Storage::Streams::{DataReader, IBuffer},
 };
 
 pub fn to_error(status: GattCommunicationStatus) -> Result<()> {
     if status == GattCommunicationStatus::AccessDenied {
-        Err(Error::PermissionDenied)
+        Err(Error::NotPaired)
     } else if status == GattCommunicationStatus::Unreachable {
         Err(Error::NotConnected)
     } else if status == GattCommunicationStatus::Success {
@@ -37,6 +42,16 @@ pub fn to_error(status: GattCommunicationStatus) -> Result<()> {
     }
 }
 
+/// Converts the status of a failed GATT operation into an error. `AccessDenied` gets its own
+/// error, as it usually means the device has to be paired before the operation will succeed.
+pub fn to_operation_error(operation: &str, status: GattCommunicationStatus) -> Error {
+    if status == GattCommunicationStatus::AccessDenied {
+        Error::NotPaired
+    } else {
+        Error::Other(format!("Windows UWP threw error on {}: {:?}", operation, status).into())
+    }
+}
+
 pub fn to_descriptor_value(
     properties: GattCharacteristicProperties,
 ) -> GattClientCharacteristicConfigurationDescriptorValue {
@@ -51,9 +66,26 @@ pub fn to_descriptor_value(
     }
 }
 
+pub fn to_address_type(address_type: BluetoothAddressType) -> Option<AddressType> {
+    match address_type {
+        BluetoothAddressType::Public => Some(AddressType::Public),
+        BluetoothAddressType::Random => Some(AddressType::Random),
+        _ => None,
+    }
+}
+
+pub fn to_bluetooth_address_type(address_type: AddressType) -> BluetoothAddressType {
+    match address_type {
+        AddressType::Public => BluetoothAddressType::Public,
+        AddressType::Random => BluetoothAddressType::Random,
+    }
+}
+
+/// Converts a Windows `GUID` to the standard `Uuid` type. The first three fields of a `GUID` are
+/// integers, which are laid out in memory little-endian rather than in the order they're written,
+/// so this goes by the fields rather than the bytes.
 pub fn to_uuid(uuid: &GUID) -> Uuid {
-    let guid_s = format!("{:?}", uuid);
-    Uuid::from_str(&guid_s).unwrap()
+    Uuid::from_fields(uuid.data1, uuid.data2, uuid.data3, &uuid.data4)
 }
 
 pub fn to_vec(buffer: &IBuffer) -> Vec<u8> {
@@ -64,7 +96,7 @@ pub fn to_vec(buffer: &IBuffer) -> Vec<u8> {
     data
 }
 
-#[allow(dead_code)]
+/// Converts a `Uuid` to a Windows `GUID`, the inverse of [`to_uuid`].
 pub fn to_guid(uuid: &Uuid) -> GUID {
     let (data1, data2, data3, data4) = uuid.as_fields();
     GUID::from_values(data1, data2, data3, data4.to_owned())
@@ -108,6 +140,7 @@ pub fn to_char_props(props: &GattCharacteristicProperties) -> CharPropFlags {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::str::FromStr;
 
     #[test]
     fn check_uuid_to_guid_conversion() {
@@ -130,4 +163,20 @@ mod tests {
         let uuid_expected = Uuid::from_str(uuid_str).unwrap();
         assert_eq!(uuid_converted, uuid_expected);
     }
+
+    #[test]
+    fn check_access_denied_is_not_paired() {
+        assert!(matches!(
+            to_operation_error("read", GattCommunicationStatus::AccessDenied),
+            Error::NotPaired
+        ));
+        assert!(matches!(
+            to_error(GattCommunicationStatus::AccessDenied),
+            Err(Error::NotPaired)
+        ));
+        assert!(matches!(
+            to_operation_error("read", GattCommunicationStatus::Unreachable),
+            Error::Other(_)
+        ));
+    }
 }